@@ -0,0 +1,324 @@
+//! Derives for `protocol::payload`. See that module for the wire format; the
+//! generated code refers to the traits as `::protocol::payload::*`.
+//!
+//! - `#[derive(Payload)]` on a struct encodes its fields in declaration
+//!   order. On an enum it writes a tag byte (the variant's discriminant,
+//!   counting up from 0 like Rust does) followed by the variant's fields.
+//! - `#[payload(range = LO..=HI)]` on a field makes decoding reject values
+//!   outside the range with BAD_ARG.
+//! - `#[derive(Command)]` with `#[command(cmd = 0x04)]` adds the CMD code.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{
+    Data, DeriveInput, Error, Expr, ExprLit, Fields, Generics, Lit, Result, parse_macro_input,
+    parse_quote,
+};
+
+#[proc_macro_derive(Payload, attributes(payload))]
+pub fn derive_payload(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    payload(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(Command, attributes(command))]
+pub fn derive_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    command(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn command(input: DeriveInput) -> Result<TokenStream2> {
+    let mut cmd = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("command")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("cmd") {
+                cmd = Some(meta.value()?.parse::<Expr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `cmd = ...`"))
+            }
+        })?;
+    }
+    let Some(cmd) = cmd else {
+        return Err(Error::new(
+            input.ident.span(),
+            "#[derive(Command)] needs #[command(cmd = ...)]",
+        ));
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::protocol::payload::Command for #name #ty_generics #where_clause {
+            const CMD: u8 = #cmd;
+        }
+    })
+}
+
+/// One field of a struct or variant.
+struct Field {
+    /// `self.name` / `self.0` for structs, a binding for enum variants.
+    access: TokenStream2,
+    /// The local the decoder reads it into.
+    local: syn::Ident,
+    ty: syn::Type,
+    range: Option<Expr>,
+}
+
+fn fields(fields: &Fields, bind: bool) -> Result<Vec<Field>> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let local = match &field.ident {
+                Some(ident) => format_ident!("__{}", ident),
+                None => format_ident!("__{}", i),
+            };
+            let access = match (&field.ident, bind) {
+                (_, true) => quote!(#local),
+                (Some(ident), false) => quote!(&self.#ident),
+                (None, false) => {
+                    let index = syn::Index::from(i);
+                    quote!(&self.#index)
+                }
+            };
+            let mut range = None;
+            for attr in field.attrs.iter().filter(|a| a.path().is_ident("payload")) {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("range") {
+                        range = Some(meta.value()?.parse::<Expr>()?);
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `range = ...`"))
+                    }
+                })?;
+            }
+            Ok(Field {
+                access,
+                local,
+                ty: field.ty.clone(),
+                range,
+            })
+        })
+        .collect()
+}
+
+/// `MIN_LEN` or `MAX_LEN` of a field list.
+fn sum_len(fields: &[Field], bound: &syn::Ident) -> TokenStream2 {
+    let tys = fields.iter().map(|f| &f.ty);
+    quote!(0 #(+ <#tys as ::protocol::payload::Payload>::#bound)*)
+}
+
+fn encode_fields(fields: &[Field]) -> TokenStream2 {
+    let access = fields.iter().map(|f| &f.access);
+    quote! {
+        #(::protocol::payload::Payload::encode(#access, out)?;)*
+    }
+}
+
+fn encoded_len(fields: &[Field]) -> TokenStream2 {
+    let access = fields.iter().map(|f| &f.access);
+    quote!(0 #(+ ::protocol::payload::Payload::encoded_len(#access))*)
+}
+
+/// Decode every field into its local, checking ranges.
+fn decode_fields(fields: &[Field]) -> TokenStream2 {
+    let decode = fields.iter().map(|f| {
+        let Field {
+            local, ty, range, ..
+        } = f;
+        let check = range.as_ref().map(|range| {
+            quote! {
+                if !(#range).contains(&#local) {
+                    return Err(::protocol::Status::BadArg);
+                }
+            }
+        });
+        quote! {
+            let #local = <#ty as ::protocol::payload::Payload>::decode(input)?;
+            #check
+        }
+    });
+    quote!(#(#decode)*)
+}
+
+/// `Self { a: __a, .. }`, `Self(__0, ..)` or `Self`: a constructor, or a
+/// pattern binding every field to its local.
+fn construct(path: TokenStream2, fields: &Fields, parsed: &[Field]) -> TokenStream2 {
+    let locals = parsed.iter().map(|f| &f.local);
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote!(#path { #(#names: #locals),* })
+        }
+        Fields::Unnamed(_) => quote!(#path(#(#locals),*)),
+        Fields::Unit => path,
+    }
+}
+
+fn add_bounds(generics: &mut Generics) {
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::protocol::payload::Payload));
+    }
+}
+
+fn payload(mut input: DeriveInput) -> Result<TokenStream2> {
+    let (min, max) = (format_ident!("MIN_LEN"), format_ident!("MAX_LEN"));
+    let (min_len, max_len, encoded_len, encode, decode) = match &input.data {
+        Data::Struct(data) => {
+            let parsed = fields(&data.fields, false)?;
+            let decode = decode_fields(&parsed);
+            let build = construct(quote!(Self), &data.fields, &parsed);
+            (
+                sum_len(&parsed, &min),
+                sum_len(&parsed, &max),
+                encoded_len(&parsed),
+                encode_fields(&parsed),
+                quote! {
+                    #decode
+                    Ok(#build)
+                },
+            )
+        }
+        Data::Enum(data) => {
+            if data.variants.is_empty() {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "#[derive(Payload)] needs at least one variant",
+                ));
+            }
+            let mut min_len = Vec::new();
+            let mut max_len = Vec::new();
+            let mut len_arms = Vec::new();
+            let mut encode_arms = Vec::new();
+            let mut decode_arms = Vec::new();
+            let mut next_tag = 0u16;
+            for variant in &data.variants {
+                let tag = match &variant.discriminant {
+                    Some((
+                        _,
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Int(lit), ..
+                        }),
+                    )) => lit.base10_parse::<u16>()?,
+                    Some((_, expr)) => {
+                        return Err(Error::new(
+                            expr.span(),
+                            "payload tags must be integer literals",
+                        ));
+                    }
+                    None => next_tag,
+                };
+                let Ok(tag) = u8::try_from(tag) else {
+                    return Err(Error::new(variant.span(), "payload tags must fit in a u8"));
+                };
+                next_tag = u16::from(tag) + 1;
+
+                let ident = &variant.ident;
+                let parsed = fields(&variant.fields, true)?;
+                let pattern = construct(quote!(Self::#ident), &variant.fields, &parsed);
+                let (len, enc, dec) = (
+                    encoded_len(&parsed),
+                    encode_fields(&parsed),
+                    decode_fields(&parsed),
+                );
+
+                min_len.push(sum_len(&parsed, &min));
+                max_len.push(sum_len(&parsed, &max));
+                len_arms.push(quote!(#pattern => 1 + #len,));
+                encode_arms.push(quote! {
+                    #pattern => {
+                        ::protocol::payload::Sink::put(out, &[#tag])?;
+                        #enc
+                    }
+                });
+                decode_arms.push(quote! {
+                    #tag => {
+                        #dec
+                        Ok(#pattern)
+                    }
+                });
+            }
+            let first_min = &min_len[0];
+            let first_max = &max_len[0];
+            let rest_min = &min_len[1..];
+            let rest_max = &max_len[1..];
+            (
+                quote! {
+                    1 + {
+                        let len = #first_min;
+                        #(let len = ::protocol::payload::min_len(len, #rest_min);)*
+                        len
+                    }
+                },
+                quote! {
+                    1 + {
+                        let len = #first_max;
+                        #(let len = ::protocol::payload::max_len(len, #rest_max);)*
+                        len
+                    }
+                },
+                quote! {
+                    match self {
+                        #(#len_arms)*
+                    }
+                },
+                quote! {
+                    match self {
+                        #(#encode_arms)*
+                    }
+                },
+                quote! {
+                    match <u8 as ::protocol::payload::Payload>::decode(input)? {
+                        #(#decode_arms)*
+                        _ => Err(::protocol::Status::BadArg),
+                    }
+                },
+            )
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                input.ident.span(),
+                "#[derive(Payload)] does not support unions",
+            ));
+        }
+    };
+
+    add_bounds(&mut input.generics);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::protocol::payload::Payload for #name #ty_generics #where_clause {
+            const MIN_LEN: usize = #min_len;
+            const MAX_LEN: usize = #max_len;
+
+            fn encoded_len(&self) -> usize {
+                #encoded_len
+            }
+
+            #[allow(unused_variables)] // fieldless types
+            fn encode<S: ::protocol::payload::Sink + ?Sized>(
+                &self,
+                out: &mut S,
+            ) -> Result<(), ::protocol::BuildError> {
+                #encode
+                Ok(())
+            }
+
+            #[allow(unused_variables)] // fieldless types
+            fn decode(
+                input: &mut ::protocol::payload::Reader<'_>,
+            ) -> Result<Self, ::protocol::Status> {
+                #decode
+            }
+        }
+    })
+}
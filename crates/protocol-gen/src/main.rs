@@ -0,0 +1,152 @@
+//! Generates the protocol modules from `crates/protocol/protocol.toml`.
+//!
+//! ```text
+//! cargo protocol-gen                       # rewrite the generated files
+//! cargo protocol-gen --check               # exit 1 if any of them is out of date
+//! cargo protocol-gen --stubs GET_ADDRESS   # print firmware handler skeletons
+//! ```
+//!
+//! Paths are relative to the repository root (the current directory unless
+//! `--root DIR` is given).
+
+mod python;
+mod readme;
+mod rust;
+mod schema;
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+
+use schema::{Result, Schema};
+
+const SCHEMA: &str = "crates/protocol/protocol.toml";
+const SCHEMA_RS: &str = "crates/protocol/src/schema.rs";
+const CLIENT_RS: &str = "crates/protocol/src/client.rs";
+const PYTHON: &str = "tools/serial_client/protocol_schema.py";
+const README: &str = "README.md";
+
+const USAGE: &str = "usage: protocol-gen [--root DIR] [--check | --stubs [COMMAND...]]";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1)) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("protocol-gen: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut root = PathBuf::from(".");
+    let mut check = false;
+    let mut stubs = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => root = args.next().ok_or("--root needs a directory")?.into(),
+            "--check" => check = true,
+            "--stubs" => stubs = Some(args.by_ref().collect::<Vec<_>>()),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(ExitCode::SUCCESS);
+            }
+            other => return Err(format!("unknown argument {other}\n{USAGE}")),
+        }
+    }
+
+    let schema = load(&root)?;
+    if let Some(names) = stubs {
+        print!("{}", rustfmt(&rust::stubs(&schema, &names)?)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut stale = 0;
+    for (path, contents) in generate(&root, &schema)? {
+        let full = root.join(path);
+        if fs::read_to_string(&full).is_ok_and(|current| current == contents) {
+            continue;
+        }
+        if check {
+            eprintln!("{path} is out of date");
+            stale += 1;
+        } else {
+            fs::write(&full, contents).map_err(|err| format!("{path}: {err}"))?;
+            println!("wrote {path}");
+        }
+    }
+    if stale > 0 {
+        eprintln!("run `cargo protocol-gen` and commit the result");
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn load(root: &Path) -> Result<Schema> {
+    schema::parse(&read(root, SCHEMA)?).map_err(|err| format!("{SCHEMA}: {err}"))
+}
+
+/// Every generated file, by path relative to `root`.
+fn generate(root: &Path, schema: &Schema) -> Result<Vec<(&'static str, String)>> {
+    let mut files = rust_files(schema)?;
+    files.push((PYTHON, python::module(schema)));
+    files.push((README, readme::update(&read(root, README)?, schema)?));
+    Ok(files)
+}
+
+fn rust_files(schema: &Schema) -> Result<Vec<(&'static str, String)>> {
+    Ok(vec![
+        (SCHEMA_RS, rustfmt(&rust::schema(schema))?),
+        (CLIENT_RS, rustfmt(&rust::client(schema))?),
+    ])
+}
+
+fn read(root: &Path, path: &str) -> Result<String> {
+    fs::read_to_string(root.join(path)).map_err(|err| format!("{path}: {err}"))
+}
+
+/// The generators only get the tokens right; rustfmt does the layout.
+fn rustfmt(source: &str) -> Result<String> {
+    let mut child = Command::new("rustfmt")
+        .args(["--edition", "2024", "--emit", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("rustfmt: {err}"))?;
+    child
+        .stdin
+        .take()
+        .expect("piped stdin")
+        .write_all(source.as_bytes())
+        .map_err(|err| format!("rustfmt: {err}"))?;
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("rustfmt: {err}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "rustfmt rejected the generated code:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|err| format!("rustfmt: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_rust_is_up_to_date() {
+        // crates/protocol-gen -> the repository root
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+        let schema = load(&root).unwrap();
+        for (path, contents) in rust_files(&schema).unwrap() {
+            assert!(
+                read(&root, path).unwrap() == contents,
+                "{path} is out of date: run `cargo protocol-gen`"
+            );
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! `tools/serial_client/protocol_schema.py`.
+
+use std::fmt::Write;
+
+use crate::schema::{Command, Direction, Field, Kind, Schema, Type};
+
+pub fn module(schema: &Schema) -> String {
+    let mut out = String::from(
+        "\"\"\"Command table, payload codecs, status codes, event classes and feature bits.\n\
+         \n\
+         @generated by `protocol-gen` from crates/protocol/protocol.toml: edit that file and\n\
+         run `cargo protocol-gen` instead of changing this one.\n\
+         \"\"\"\n\n\
+         import struct\n\
+         from enum import IntEnum\n\
+         from typing import NamedTuple, Optional\n\n\n",
+    );
+
+    out.push_str("class Status(IntEnum):\n");
+    out.push_str("    \"\"\"Response status byte (first payload byte). Mirrors `protocol::Status`.\"\"\"\n\n");
+    for status in &schema.statuses {
+        let _ = writeln!(
+            out,
+            "    {} = 0x{:02X}  # {}",
+            status.name, status.code, status.doc
+        );
+    }
+
+    out.push_str("\n\nclass Cmd(IntEnum):\n    \"\"\"CMD codes.\"\"\"\n\n");
+    for command in &schema.commands {
+        let _ = writeln!(
+            out,
+            "    {} = 0x{:02X}  # {}",
+            command.name, command.cmd, command.doc
+        );
+    }
+
+    out.push_str(
+        "\n\nclass CommandInfo(NamedTuple):\n    \
+         getter: bool\n    \
+         min_payload: int\n    \
+         max_payload: int\n\n\n\
+         # Request limits the device checks before running a handler.\n\
+         COMMANDS = {\n",
+    );
+    for command in schema.commands.iter().filter(|c| c.has_spec()) {
+        let (min, max) = payload_limits(command);
+        let _ = writeln!(
+            out,
+            "    Cmd.{}: CommandInfo({}, {min}, {max}),",
+            command.name,
+            python_bool(command.kind == Kind::Getter)
+        );
+    }
+    out.push_str("}\n\n");
+
+    let _ = writeln!(
+        out,
+        "# Unsolicited events: CMD = 0x80 + class.\nEVENT_CLASSES = {{{}}}",
+        schema
+            .events
+            .iter()
+            .map(|e| format!("{}: \"{}\"", e.class, e.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    out.push_str("\n# (struct format, field names) of each event payload.\nEVENT_FIELDS = {\n");
+    for event in &schema.events {
+        let _ = writeln!(
+            out,
+            "    \"{}\": (\"{}\", {}),",
+            event.name,
+            struct_format(&event.fields),
+            names(&event.fields)
+        );
+    }
+    out.push_str("}\n\n");
+    let _ = writeln!(
+        out,
+        "# GET_CAPABILITIES feature bits.\nFEATURE_FLAGS = {{\n{}}}",
+        schema
+            .features
+            .iter()
+            .map(|f| format!("    0x{:02X}: \"{}\",\n", 1u16 << f.bit, f.name))
+            .collect::<String>()
+    );
+
+    out.push_str(
+        "\n\ndef decode_fields(fmt: str, names: tuple, data: bytes) -> dict:\n    \
+         if len(data) != struct.calcsize(fmt):\n        \
+         raise ValueError(f\"expected {struct.calcsize(fmt)} bytes, got {len(data)}\")\n    \
+         return dict(zip(names, struct.unpack(fmt, data)))\n",
+    );
+
+    for command in &schema.commands {
+        let Some(message) = &command.message else {
+            continue;
+        };
+        match message.direction {
+            Direction::Request => encoder(&mut out, command, &message.fields),
+            Direction::Response => {
+                let _ = write!(
+                    out,
+                    "\n\ndef decode_{}(data: bytes) -> dict:\n    \
+                     \"\"\"0x{:02X} {} data.\"\"\"\n    \
+                     return decode_fields(\"{}\", {}, data)\n",
+                    command.name.to_lowercase(),
+                    command.cmd,
+                    command.name,
+                    struct_format(&message.fields),
+                    names(&message.fields)
+                );
+            }
+        }
+    }
+    out
+}
+
+fn encoder(out: &mut String, command: &Command, fields: &[Field]) {
+    let params = fields
+        .iter()
+        .map(|f| match f.optional {
+            true => format!("{}: Optional[{}] = None", f.name, python_type(f.ty)),
+            false => format!("{}: {}", f.name, python_type(f.ty)),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let _ = write!(
+        out,
+        "\n\ndef encode_{}({params}) -> bytes:\n    \"\"\"0x{:02X} {} request.\"\"\"\n",
+        command.name.to_lowercase(),
+        command.cmd,
+        command.name
+    );
+    for field in fields {
+        if let Some((min, max)) = field.range {
+            let check = match field.optional {
+                true => format!("{} is not None and not ", field.name),
+                false => "not ".to_owned(),
+            };
+            let _ = write!(
+                out,
+                "    if {check}{min} <= {name} <= {max}:\n        \
+                 raise ValueError(\"{name} must be {min}..={max}\")\n",
+                min = bound(min),
+                max = bound(max),
+                name = field.name
+            );
+        }
+    }
+    let required: Vec<&Field> = fields.iter().filter(|f| !f.optional).collect();
+    let _ = writeln!(
+        out,
+        "    out = struct.pack(\"{}\", {})",
+        struct_format(fields.iter().filter(|f| !f.optional)),
+        required
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    if let Some(last) = fields.last().filter(|f| f.optional) {
+        let _ = write!(
+            out,
+            "    if {name} is not None:\n        out += struct.pack(\"<{}\", {name})\n",
+            last.ty.struct_code(),
+            name = last.name
+        );
+    }
+    out.push_str("    return out\n");
+}
+
+fn payload_limits(command: &Command) -> (usize, usize) {
+    match command.request() {
+        Some(request) => {
+            let len = |f: &Field| f.ty.len();
+            let max = request.fields.iter().map(len).sum();
+            let min = request.fields.iter().filter(|f| !f.optional).map(len).sum();
+            (min, max)
+        }
+        None => (command.min_payload, command.max_payload),
+    }
+}
+
+/// Hex for values that read better as bytes.
+fn bound(value: i64) -> String {
+    match value {
+        10..=0xFFFF => format!("0x{value:02X}"),
+        _ => value.to_string(),
+    }
+}
+
+fn struct_format<'a>(fields: impl IntoIterator<Item = &'a Field>) -> String {
+    let codes: String = fields.into_iter().map(|f| f.ty.struct_code()).collect();
+    format!("<{codes}")
+}
+
+fn names(fields: &[Field]) -> String {
+    match fields {
+        [] => "()".to_owned(),
+        [one] => format!("(\"{}\",)", one.name),
+        _ => format!(
+            "({})",
+            fields
+                .iter()
+                .map(|f| format!("\"{}\"", f.name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn python_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Bool => "bool",
+        Type::Bytes(_) => "bytes",
+        _ => "int",
+    }
+}
+
+fn python_bool(value: bool) -> &'static str {
+    if value { "True" } else { "False" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::parse;
+
+    #[test]
+    fn emits_codecs_with_range_checks() {
+        let schema = parse(
+            r#"
+            [[command]]
+            cmd = 0x04
+            name = "SET_ADDRESS"
+            kind = "setter"
+            doc = "Set it"
+            request = "SetAddress"
+            fields = [
+                { name = "node", type = "u8", min = 1, max = 0xEF },
+                { name = "groups", type = "u16", optional = true },
+            ]
+            "#,
+        )
+        .unwrap();
+        let py = module(&schema);
+        assert!(
+            py.contains(
+                "def encode_set_address(node: int, groups: Optional[int] = None) -> bytes:"
+            )
+        );
+        assert!(py.contains("    if not 1 <= node <= 0xEF:\n"));
+        assert!(py.contains("    out = struct.pack(\"<B\", node)\n"));
+        assert!(py.contains("        out += struct.pack(\"<H\", groups)\n"));
+        assert!(py.contains("    Cmd.SET_ADDRESS: CommandInfo(False, 1, 3),"));
+    }
+}
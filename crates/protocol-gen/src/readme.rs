@@ -0,0 +1,137 @@
+//! The command list and status table in `README.md`, between
+//! `<!-- protocol-gen:NAME -->` and `<!-- /protocol-gen:NAME -->`.
+
+use std::fmt::Write;
+
+use crate::schema::{Field, Schema, Type};
+
+pub fn update(readme: &str, schema: &Schema) -> Result<String, String> {
+    let readme = replace(readme, "commands", &commands(schema))?;
+    replace(&readme, "statuses", &statuses(schema))
+}
+
+fn replace(readme: &str, name: &str, body: &str) -> Result<String, String> {
+    let start = format!("<!-- protocol-gen:{name} -->\n");
+    let end = format!("<!-- /protocol-gen:{name} -->");
+    let missing = || format!("README.md has no {start:?} ... {end:?} section");
+    let (head, rest) = readme.split_once(&start).ok_or_else(missing)?;
+    let (_, tail) = rest.split_once(&end).ok_or_else(missing)?;
+    Ok(format!("{head}{start}{body}{end}{tail}"))
+}
+
+fn commands(schema: &Schema) -> String {
+    let mut out = String::new();
+    for command in schema.commands.iter().filter(|c| c.has_spec()) {
+        let _ = write!(
+            out,
+            "- `0x{:02X}` — {}: {}",
+            command.cmd, command.name, command.doc
+        );
+        if let Some(message) = &command.message {
+            let _ = write!(out, " (`{}`)", layout(&message.fields));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// `[NODE, [GROUPS u16 LE]]`: bytes in order, optional ones bracketed.
+fn layout(fields: &[Field]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|f| {
+            let name = f.name.to_uppercase();
+            let field = match f.ty {
+                Type::U8 | Type::Bool => name,
+                Type::Bytes(len) => format!("{name} ({len} bytes)"),
+                ty => format!("{name} {} LE", ty.rust()),
+            };
+            match f.optional {
+                true => format!("[{field}]"),
+                false => field,
+            }
+        })
+        .collect();
+    format!("[{}]", fields.join(", "))
+}
+
+fn statuses(schema: &Schema) -> String {
+    let rows: Vec<[String; 3]> = schema
+        .statuses
+        .iter()
+        .map(|s| {
+            [
+                format!("`0x{:02X}`", s.code),
+                format!("`{}`", s.name),
+                s.doc.clone(),
+            ]
+        })
+        .collect();
+    let header = ["Code", "Name", "Meaning"].map(str::to_owned);
+    let widths: Vec<usize> = (0..3)
+        .map(|i| {
+            rows.iter()
+                .chain([&header])
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |row: &[String; 3]| {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!(" {cell:width$} "))
+            .collect();
+        format!("|{}|\n", cells.join("|"))
+    };
+    let rule: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+
+    let mut out = line(&header);
+    let _ = writeln!(out, "|{}|", rule.join("|"));
+    for row in &rows {
+        out.push_str(&line(row));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::parse;
+
+    #[test]
+    fn replaces_marked_sections() {
+        let schema = parse(
+            r#"
+            [[status]]
+            code = 0x00
+            name = "OK"
+            doc = "Fine"
+
+            [[command]]
+            cmd = 0x04
+            name = "SET_ADDRESS"
+            kind = "setter"
+            doc = "Set it"
+            request = "SetAddress"
+            fields = [
+                { name = "node", type = "u8" },
+                { name = "groups", type = "u16", optional = true },
+            ]
+            "#,
+        )
+        .unwrap();
+        let readme = "# Title\n<!-- protocol-gen:commands -->\nold\n<!-- /protocol-gen:commands -->\n\
+                      <!-- protocol-gen:statuses -->\n<!-- /protocol-gen:statuses -->\nend\n";
+        assert_eq!(
+            update(readme, &schema).unwrap(),
+            "# Title\n<!-- protocol-gen:commands -->\n\
+             - `0x04` — SET_ADDRESS: Set it (`[NODE, [GROUPS u16 LE]]`)\n\
+             <!-- /protocol-gen:commands -->\n<!-- protocol-gen:statuses -->\n\
+             | Code   | Name | Meaning |\n|--------|------|---------|\n| `0x00` | `OK` | Fine    |\n\
+             <!-- /protocol-gen:statuses -->\nend\n"
+        );
+        assert!(update("no markers", &schema).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! `protocol::schema`, `protocol::client` and firmware handler stubs.
+
+use std::fmt::Write;
+
+use crate::schema::{Command, Direction, Field, Kind, Message, Schema};
+
+/// `protocol::schema`: CMD codes, CommandSpecs and payload types.
+pub fn schema(schema: &Schema) -> String {
+    let mut out = String::new();
+    out.push_str(
+        "//! Command table, payload types, status codes, event classes and feature\n\
+         //! bits from `protocol.toml`.\n\
+         //!\n\
+         //! @generated by `protocol-gen`: edit `crates/protocol/protocol.toml` and run\n\
+         //! `cargo protocol-gen` instead of changing this file.\n\n\
+         use crate::dispatch::{CommandKind, CommandSpec};\n\
+         use crate::payload::{Command, Payload};\n\n",
+    );
+
+    for command in &schema.commands {
+        let _ = writeln!(out, "/// {}", command.doc);
+        let _ = writeln!(
+            out,
+            "pub const CMD_{}: u8 = 0x{:02X};",
+            command.name, command.cmd
+        );
+    }
+
+    for command in schema.commands.iter().filter(|c| c.has_spec()) {
+        let kind = match command.kind {
+            Kind::Setter => "CommandKind::Setter",
+            Kind::Getter => "CommandKind::Getter",
+        };
+        let _ = writeln!(out, "\n/// 0x{:02X} {}.", command.cmd, command.name);
+        let _ = match command.request() {
+            Some(request) => writeln!(
+                out,
+                "pub const {}: CommandSpec = CommandSpec::of::<{}>(\"{}\", {kind});",
+                command.name, request.type_name, command.name
+            ),
+            None => writeln!(
+                out,
+                "pub const {name}: CommandSpec = CommandSpec {{ cmd: CMD_{name}, name: \"{name}\", \
+                 kind: {kind}, min_payload: {}, max_payload: {} }};",
+                command.min_payload,
+                command.max_payload,
+                name = command.name,
+            ),
+        };
+    }
+
+    for command in &schema.commands {
+        if let Some(message) = &command.message {
+            message_type(&mut out, command, message);
+        }
+    }
+
+    table(
+        &mut out,
+        "Status codes (`crate::Status`)",
+        "STATUS_CODES",
+        schema.statuses.iter().map(|s| (s.code, &s.name)),
+    );
+    table(
+        &mut out,
+        "Event classes (`crate::event::EventClass`); CMD is `EVENT_FIRST + class`",
+        "EVENT_CLASSES",
+        schema.events.iter().map(|e| (e.class, &e.name)),
+    );
+    table(
+        &mut out,
+        "Feature bits (`crate::capabilities::Features`), by bit value",
+        "FEATURES",
+        schema.features.iter().map(|f| (1 << f.bit, &f.name)),
+    );
+    out
+}
+
+fn message_type(out: &mut String, command: &Command, message: &Message) {
+    let what = match message.direction {
+        Direction::Request => "request",
+        Direction::Response => "data",
+    };
+    let _ = writeln!(out, "\n/// 0x{:02X} {} {what}.", command.cmd, command.name);
+    match message.direction {
+        Direction::Request => {
+            let _ = writeln!(
+                out,
+                "#[derive(Copy, Clone, Debug, Eq, PartialEq, Payload, Command)]\n\
+                 #[command(cmd = CMD_{})]",
+                command.name
+            );
+        }
+        Direction::Response => {
+            out.push_str("#[derive(Copy, Clone, Debug, Eq, PartialEq, Payload)]\n");
+        }
+    }
+    let _ = writeln!(out, "pub struct {} {{", message.type_name);
+    for field in &message.fields {
+        if let Some(doc) = &field.doc {
+            let _ = writeln!(out, "/// {doc}");
+        }
+        if let Some((min, max)) = field.range {
+            let _ = writeln!(
+                out,
+                "#[payload(range = {}..={})]",
+                literal(min),
+                literal(max)
+            );
+        }
+        let _ = writeln!(out, "pub {}: {},", field.name, field_type(field));
+    }
+    out.push_str("}\n");
+}
+
+fn field_type(field: &Field) -> String {
+    match field.optional {
+        true => format!("Option<{}>", field.ty.rust()),
+        false => field.ty.rust(),
+    }
+}
+
+/// Hex for values that read better as bytes.
+fn literal(value: i64) -> String {
+    match value {
+        0..=9 => value.to_string(),
+        10..=0xFFFF => format!("0x{value:02X}"),
+        _ => value.to_string(),
+    }
+}
+
+fn table<'a>(
+    out: &mut String,
+    doc: &str,
+    name: &str,
+    rows: impl ExactSizeIterator<Item = (u8, &'a String)>,
+) {
+    let _ = writeln!(out, "\n/// {doc}.");
+    let _ = writeln!(out, "pub const {name}: [(u8, &str); {}] = [", rows.len());
+    for (code, row) in rows {
+        let _ = writeln!(out, "(0x{code:02X}, \"{row}\"),");
+    }
+    out.push_str("];\n");
+}
+
+/// `protocol::client`: an `ArqClient` method per command.
+pub fn client(schema: &Schema) -> String {
+    let mut out = String::new();
+    out.push_str(
+        "//! Typed [`ArqClient`] methods for the commands in `protocol.toml`.\n\
+         //!\n\
+         //! Setters return `Ok(())` once the device answers OK; any other status is\n\
+         //! [`ClientError::Status`]. Getters return their decoded data. Commands\n\
+         //! with hand-written host support (`client = false`) are left out.\n\
+         //!\n\
+         //! @generated by `protocol-gen`: edit `crates/protocol/protocol.toml` and run\n\
+         //! `cargo protocol-gen` instead of changing this file.\n\n\
+         use crate::host::{ArqClient, ClientError, Link};\n\
+         use crate::schema::*;\n\n",
+    );
+
+    let commands: Vec<&Command> = schema.commands.iter().filter(|c| c.client).collect();
+    out.push_str("pub trait Commands {\n");
+    for command in &commands {
+        let _ = writeln!(out, "/// 0x{:02X}: {}.", command.cmd, command.doc);
+        let _ = writeln!(out, "{};", signature(command));
+    }
+    out.push_str("}\n\nimpl<L: Link> Commands for ArqClient<L> {\n");
+    for command in &commands {
+        let _ = writeln!(out, "{} {{", signature(command));
+        let request = match (command.request(), takes_payload(command)) {
+            (Some(_), _) => "self.command(addr, request)?".to_owned(),
+            (None, true) => format!("self.request(addr, CMD_{}, payload)?", command.name),
+            (None, false) => format!("self.request(addr, CMD_{}, &[])?", command.name),
+        };
+        let _ = match (command.kind, command.response()) {
+            (Kind::Setter, _) => writeln!(out, "{request}.check()?;\nOk(())"),
+            (Kind::Getter, Some(_)) => writeln!(out, "Ok({request}.decode()?)"),
+            (Kind::Getter, None) => writeln!(out, "Ok({request}.check()?.data().to_vec())"),
+        };
+        out.push_str("}\n\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Untyped commands whose payload cannot be empty take it as bytes.
+fn takes_payload(command: &Command) -> bool {
+    command.request().is_none() && command.min_payload > 0
+}
+
+fn signature(command: &Command) -> String {
+    let mut args = "&mut self, addr: u8".to_owned();
+    if let Some(request) = command.request() {
+        let _ = write!(args, ", request: &{}", request.type_name);
+    } else if takes_payload(command) {
+        args.push_str(", payload: &[u8]");
+    }
+    let ret = match (command.kind, command.response()) {
+        (Kind::Setter, _) => "()".to_owned(),
+        (Kind::Getter, Some(data)) => data.type_name.clone(),
+        (Kind::Getter, None) => "Vec<u8>".to_owned(),
+    };
+    format!(
+        "fn {}({args}) -> Result<{ret}, ClientError>",
+        command.name.to_lowercase()
+    )
+}
+
+/// `CommandHandler` skeletons for the firmware, for the named commands (all
+/// registry commands if `names` is empty).
+pub fn stubs(schema: &Schema, names: &[String]) -> Result<String, String> {
+    let mut out = String::from(
+        "use protocol::dispatch::{CommandHandler, CommandSpec, Data};\n\
+         use protocol::payload::{Command, Payload};\n\
+         use protocol::schema;\n\
+         use protocol::{Frame, Status};\n",
+    );
+    for name in names {
+        if !schema.commands.iter().any(|c| &c.name == name) {
+            return Err(format!("no command named {name}"));
+        }
+    }
+    let wanted = |c: &&Command| names.is_empty() || names.contains(&c.name);
+    for command in schema
+        .commands
+        .iter()
+        .filter(|c| c.has_spec())
+        .filter(wanted)
+    {
+        let ty = camel(&command.name);
+        let _ = writeln!(
+            out,
+            "\n/// 0x{:02X} {}: {}.\npub struct {ty};\n\n\
+             impl CommandHandler for {ty} {{\n\
+             const SPEC: CommandSpec = schema::{};\n\n\
+             async fn handle(&self, {frame}: &Frame<'_>, {data}: &mut Data) -> Result<(), Status> {{",
+            command.cmd,
+            command.name,
+            command.doc,
+            command.name,
+            // Unused parameters would warn
+            frame = if command.request().is_some() {
+                "frame"
+            } else {
+                "_frame"
+            },
+            data = if command.response().is_some() {
+                "data"
+            } else {
+                "_data"
+            },
+        );
+        if let Some(request) = command.request() {
+            let _ = writeln!(
+                out,
+                "let request = schema::{}::from_frame(frame)?;",
+                request.type_name
+            );
+        }
+        match command.response() {
+            Some(data) => {
+                let _ = writeln!(
+                    out,
+                    "let response: schema::{} = todo!();\n\
+                     response.encode(data).map_err(|_| Status::Internal)",
+                    data.type_name
+                );
+            }
+            None => out.push_str("todo!()\n"),
+        }
+        out.push_str("}\n}\n");
+    }
+    Ok(out)
+}
+
+/// `GET_ADDRESS` -> `GetAddress`.
+fn camel(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_string() + &chars.as_str().to_lowercase(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::parse;
+
+    const SRC: &str = r#"
+        [[command]]
+        cmd = 0x04
+        name = "SET_ADDRESS"
+        kind = "setter"
+        doc = "Set it"
+        request = "SetAddress"
+        fields = [{ name = "node", type = "u8", min = 1, max = 0xEF }]
+
+        [[command]]
+        cmd = 0x23
+        name = "GET_ADDRESS"
+        kind = "getter"
+        doc = "Get it"
+        response = "NodeAddress"
+        fields = [{ name = "node", type = "u8" }]
+    "#;
+
+    #[test]
+    fn emits_specs_types_and_client_methods() {
+        let schema = parse(SRC).unwrap();
+        let rust = super::schema(&schema);
+        assert!(rust.contains("pub const CMD_SET_ADDRESS: u8 = 0x04;"));
+        assert!(rust.contains(
+            "pub const SET_ADDRESS: CommandSpec = CommandSpec::of::<SetAddress>(\"SET_ADDRESS\", CommandKind::Setter);"
+        ));
+        assert!(rust.contains("#[payload(range = 1..=0xEF)]\npub node: u8,"));
+
+        let client = client(&schema);
+        assert!(client.contains(
+            "fn set_address(&mut self, addr: u8, request: &SetAddress) -> Result<(), ClientError>"
+        ));
+        assert!(client.contains("Ok(self.request(addr, CMD_GET_ADDRESS, &[])?.decode()?)"));
+
+        let stubs = stubs(&schema, &["GET_ADDRESS".to_owned()]).unwrap();
+        assert!(stubs.contains("pub struct GetAddress;"));
+        assert!(!stubs.contains("SetAddress"));
+        assert!(super::stubs(&schema, &["NOPE".to_owned()]).is_err());
+    }
+}
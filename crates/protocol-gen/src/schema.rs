@@ -0,0 +1,561 @@
+//! `protocol.toml`, parsed and checked.
+
+use std::collections::HashSet;
+
+use toml_edit::{Document, Item, TableLike};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Schema {
+    pub statuses: Vec<StatusCode>,
+    pub features: Vec<Feature>,
+    pub events: Vec<EventClass>,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StatusCode {
+    pub code: u8,
+    pub name: String,
+    pub doc: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Feature {
+    pub bit: u8,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EventClass {
+    pub class: u8,
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Setter,
+    Getter,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Command {
+    pub cmd: u8,
+    pub name: String,
+    pub kind: Kind,
+    pub doc: String,
+    /// Handled before the registry: no CommandSpec, no client method.
+    pub transport: bool,
+    /// Whether the generated host client gets a method for it.
+    pub client: bool,
+    /// Raw payload limits; ignored when there is a typed request.
+    pub min_payload: usize,
+    pub max_payload: usize,
+    pub message: Option<Message>,
+}
+
+/// A typed request or getter data.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+    pub direction: Direction,
+    pub type_name: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub ty: Type,
+    pub optional: bool,
+    pub range: Option<(i64, i64)>,
+    pub doc: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Type {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    Bool,
+    Bytes(usize),
+}
+
+impl Type {
+    pub fn rust(self) -> String {
+        match self {
+            Type::U8 => "u8".into(),
+            Type::I8 => "i8".into(),
+            Type::U16 => "u16".into(),
+            Type::I16 => "i16".into(),
+            Type::U32 => "u32".into(),
+            Type::I32 => "i32".into(),
+            Type::U64 => "u64".into(),
+            Type::I64 => "i64".into(),
+            Type::Bool => "bool".into(),
+            Type::Bytes(len) => format!("[u8; {len}]"),
+        }
+    }
+
+    /// Python `struct` format code (little-endian is set per message).
+    pub fn struct_code(self) -> String {
+        match self {
+            Type::U8 => "B".into(),
+            Type::I8 => "b".into(),
+            Type::U16 => "H".into(),
+            Type::I16 => "h".into(),
+            Type::U32 => "I".into(),
+            Type::I32 => "i".into(),
+            Type::U64 => "Q".into(),
+            Type::I64 => "q".into(),
+            Type::Bool => "?".into(),
+            Type::Bytes(len) => format!("{len}s"),
+        }
+    }
+
+    pub fn len(self) -> usize {
+        match self {
+            Type::U8 | Type::I8 | Type::Bool => 1,
+            Type::U16 | Type::I16 => 2,
+            Type::U32 | Type::I32 => 4,
+            Type::U64 | Type::I64 => 8,
+            Type::Bytes(len) => len,
+        }
+    }
+
+    /// Smallest and largest value (u64 is capped at `i64::MAX`).
+    fn bounds(self) -> (i64, i64) {
+        match self {
+            Type::U8 => (0, u8::MAX.into()),
+            Type::I8 => (i8::MIN.into(), i8::MAX.into()),
+            Type::U16 => (0, u16::MAX.into()),
+            Type::I16 => (i16::MIN.into(), i16::MAX.into()),
+            Type::U32 => (0, u32::MAX.into()),
+            Type::I32 => (i32::MIN.into(), i32::MAX.into()),
+            Type::U64 => (0, i64::MAX),
+            Type::I64 | Type::Bool | Type::Bytes(_) => (i64::MIN, i64::MAX),
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, Type::Bool | Type::Bytes(_))
+    }
+}
+
+impl Command {
+    /// Whether the firmware registry has a handler for it.
+    pub fn has_spec(&self) -> bool {
+        !self.transport
+    }
+
+    pub fn request(&self) -> Option<&Message> {
+        self.message
+            .as_ref()
+            .filter(|m| m.direction == Direction::Request)
+    }
+
+    pub fn response(&self) -> Option<&Message> {
+        self.message
+            .as_ref()
+            .filter(|m| m.direction == Direction::Response)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, String>;
+
+pub fn parse(src: &str) -> Result<Schema> {
+    let doc = Document::parse(src).map_err(|err| err.to_string())?;
+    let root = doc.as_item().as_table_like().ok_or("not a table")?;
+    check_keys(
+        root,
+        "the schema",
+        &["status", "feature", "event", "command"],
+    )?;
+
+    let schema = Schema {
+        statuses: tables(root, "status")?
+            .into_iter()
+            .map(|t| {
+                check_keys(t, "a status", &["code", "name", "doc"])?;
+                Ok(StatusCode {
+                    code: int(t, "code")?,
+                    name: string(t, "name")?,
+                    doc: string(t, "doc")?,
+                })
+            })
+            .collect::<Result<_>>()?,
+        features: tables(root, "feature")?
+            .into_iter()
+            .map(|t| {
+                check_keys(t, "a feature", &["bit", "name"])?;
+                let bit = int(t, "bit")?;
+                if bit >= 8 {
+                    return Err(format!("feature bit {bit} does not fit the u8 bitmask"));
+                }
+                Ok(Feature {
+                    bit,
+                    name: string(t, "name")?,
+                })
+            })
+            .collect::<Result<_>>()?,
+        events: tables(root, "event")?
+            .into_iter()
+            .map(|t| {
+                check_keys(t, "an event", &["class", "name", "fields"])?;
+                let class = int(t, "class")?;
+                if class >= 64 {
+                    return Err(format!("event class {class} is outside 0x80..=0xBF"));
+                }
+                Ok(EventClass {
+                    class,
+                    name: string(t, "name")?,
+                    fields: fields(t)?,
+                })
+            })
+            .collect::<Result<_>>()?,
+        commands: tables(root, "command")?
+            .into_iter()
+            .map(command)
+            .collect::<Result<_>>()?,
+    };
+    check(&schema)?;
+    Ok(schema)
+}
+
+fn command(t: &dyn TableLike) -> Result<Command> {
+    check_keys(
+        t,
+        "a command",
+        &[
+            "cmd",
+            "name",
+            "kind",
+            "doc",
+            "transport",
+            "client",
+            "min_payload",
+            "max_payload",
+            "request",
+            "response",
+            "fields",
+        ],
+    )?;
+    let name = string(t, "name")?;
+    let kind = match string(t, "kind")?.as_str() {
+        "setter" => Kind::Setter,
+        "getter" => Kind::Getter,
+        other => {
+            return Err(format!(
+                "{name}: kind must be setter or getter, not {other}"
+            ));
+        }
+    };
+    let message = match (
+        optional_string(t, "request")?,
+        optional_string(t, "response")?,
+    ) {
+        (Some(_), Some(_)) => return Err(format!("{name}: both request and response")),
+        (Some(type_name), None) => Some((Direction::Request, type_name)),
+        (None, Some(type_name)) => Some((Direction::Response, type_name)),
+        (None, None) => None,
+    };
+    let fields = fields(t)?;
+    let message = match message {
+        Some((direction, type_name)) => Some(Message {
+            direction,
+            type_name,
+            fields,
+        }),
+        None if fields.is_empty() => None,
+        None => return Err(format!("{name}: fields need a request or response type")),
+    };
+    if message
+        .as_ref()
+        .is_some_and(|m| m.direction == Direction::Response && m.fields.iter().any(|f| f.optional))
+    {
+        return Err(format!("{name}: getter data cannot have optional fields"));
+    }
+    if kind == Kind::Setter
+        && message
+            .as_ref()
+            .is_some_and(|m| m.direction == Direction::Response)
+    {
+        return Err(format!("{name}: setters have no response data"));
+    }
+
+    let transport = optional_bool(t, "transport")?.unwrap_or(false);
+    Ok(Command {
+        cmd: int(t, "cmd")?,
+        kind,
+        doc: string(t, "doc")?,
+        transport,
+        client: optional_bool(t, "client")?.unwrap_or(!transport),
+        min_payload: optional_int(t, "min_payload")?.unwrap_or(0),
+        max_payload: optional_int(t, "max_payload")?.unwrap_or(0),
+        message,
+        name,
+    })
+}
+
+fn fields(t: &dyn TableLike) -> Result<Vec<Field>> {
+    let Some(item) = t.get("fields") else {
+        return Ok(Vec::new());
+    };
+    let fields: Vec<Field> = list(item, "fields")?
+        .into_iter()
+        .map(|f| {
+            check_keys(
+                f,
+                "a field",
+                &["name", "type", "len", "optional", "min", "max", "doc"],
+            )?;
+            let name = string(f, "name")?;
+            let ty = match string(f, "type")?.as_str() {
+                "u8" => Type::U8,
+                "i8" => Type::I8,
+                "u16" => Type::U16,
+                "i16" => Type::I16,
+                "u32" => Type::U32,
+                "i32" => Type::I32,
+                "u64" => Type::U64,
+                "i64" => Type::I64,
+                "bool" => Type::Bool,
+                "bytes" => Type::Bytes(int(f, "len")?),
+                other => return Err(format!("{name}: unknown type {other}")),
+            };
+            let range = match (optional_int(f, "min")?, optional_int(f, "max")?) {
+                (None, None) => None,
+                (min, max) if ty.is_integer() => {
+                    let (low, high) = ty.bounds();
+                    Some((min.unwrap_or(low), max.unwrap_or(high)))
+                }
+                _ => return Err(format!("{name}: min/max need an integer type")),
+            };
+            Ok(Field {
+                name,
+                ty,
+                optional: optional_bool(f, "optional")?.unwrap_or(false),
+                range,
+                doc: optional_string(f, "doc")?,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    if let Some(pos) = fields.iter().position(|f| f.optional)
+        && pos + 1 != fields.len()
+    {
+        return Err(format!(
+            "{}: only the last field can be optional",
+            fields[pos].name
+        ));
+    }
+    Ok(fields)
+}
+
+/// Cross-entry checks: unique codes and names, payloads that fit a frame.
+fn check(schema: &Schema) -> Result<()> {
+    unique(
+        schema.statuses.iter().map(|s| (s.code, s.name.as_str())),
+        "status",
+    )?;
+    unique(
+        schema.features.iter().map(|f| (f.bit, f.name.as_str())),
+        "feature",
+    )?;
+    unique(
+        schema.events.iter().map(|e| (e.class, e.name.as_str())),
+        "event",
+    )?;
+    unique(
+        schema.commands.iter().map(|c| (c.cmd, c.name.as_str())),
+        "command",
+    )?;
+
+    let mut types = HashSet::new();
+    for command in &schema.commands {
+        if command.cmd >= 0x80 {
+            return Err(format!(
+                "{}: 0x80..=0xFF is reserved for events",
+                command.name
+            ));
+        }
+        if command.min_payload > command.max_payload || command.max_payload > MAX_PAYLOAD {
+            return Err(format!("{}: bad payload limits", command.name));
+        }
+        if let Some(message) = &command.message {
+            if !types.insert(message.type_name.as_str()) {
+                return Err(format!("type {} is defined twice", message.type_name));
+            }
+            let len: usize = message.fields.iter().map(|f| f.ty.len()).sum();
+            // Getter data is preceded by STATUS and BYTECOUNT
+            let max = match message.direction {
+                Direction::Request => MAX_PAYLOAD,
+                Direction::Response => MAX_PAYLOAD - 2,
+            };
+            if len > max {
+                return Err(format!("{}: {len}-byte payload does not fit", command.name));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `protocol::MAX_PAYLOAD`.
+const MAX_PAYLOAD: usize = 253;
+
+fn unique<'a>(entries: impl Iterator<Item = (u8, &'a str)>, what: &str) -> Result<()> {
+    let (mut codes, mut names) = (HashSet::new(), HashSet::new());
+    for (code, name) in entries {
+        if !codes.insert(code) {
+            return Err(format!("{what} 0x{code:02X} is listed twice"));
+        }
+        if !names.insert(name) {
+            return Err(format!("{what} {name} is listed twice"));
+        }
+    }
+    Ok(())
+}
+
+fn check_keys(t: &dyn TableLike, what: &str, known: &[&str]) -> Result<()> {
+    match t.iter().find(|(key, _)| !known.contains(key)) {
+        Some((key, _)) => Err(format!("unknown key {key} in {what}")),
+        None => Ok(()),
+    }
+}
+
+/// `[[key]]` entries (none if the key is missing).
+fn tables<'a>(root: &'a dyn TableLike, key: &str) -> Result<Vec<&'a dyn TableLike>> {
+    match root.get(key) {
+        Some(item) => list(item, key),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// An array of tables, either `[[key]]` sections or inline `[{ .. }, ..]`.
+fn list<'a>(item: &'a Item, key: &str) -> Result<Vec<&'a dyn TableLike>> {
+    if let Some(tables) = item.as_array_of_tables() {
+        return Ok(tables.iter().map(|t| t as &dyn TableLike).collect());
+    }
+    item.as_array()
+        .ok_or_else(|| format!("{key} must be an array of tables"))?
+        .iter()
+        .map(|value| {
+            value
+                .as_inline_table()
+                .map(|t| t as &dyn TableLike)
+                .ok_or_else(|| format!("{key} must be an array of tables"))
+        })
+        .collect()
+}
+
+fn string(t: &dyn TableLike, key: &str) -> Result<String> {
+    optional_string(t, key)?.ok_or_else(|| format!("missing {key}"))
+}
+
+fn optional_string(t: &dyn TableLike, key: &str) -> Result<Option<String>> {
+    t.get(key)
+        .map(|item| {
+            item.as_str()
+                .map(str::to_owned)
+                .ok_or_else(|| format!("{key} must be a string"))
+        })
+        .transpose()
+}
+
+fn int<T: TryFrom<i64>>(t: &dyn TableLike, key: &str) -> Result<T> {
+    optional_int(t, key)?.ok_or_else(|| format!("missing {key}"))
+}
+
+fn optional_int<T: TryFrom<i64>>(t: &dyn TableLike, key: &str) -> Result<Option<T>> {
+    t.get(key)
+        .map(|item| {
+            item.as_integer()
+                .and_then(|value| T::try_from(value).ok())
+                .ok_or_else(|| format!("{key} must be an integer in range"))
+        })
+        .transpose()
+}
+
+fn optional_bool(t: &dyn TableLike, key: &str) -> Result<Option<bool>> {
+    t.get(key)
+        .map(|item| {
+            item.as_bool()
+                .ok_or_else(|| format!("{key} must be true or false"))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands_with_typed_payloads() {
+        let schema = parse(
+            r#"
+            [[command]]
+            cmd = 0x04
+            name = "SET_ADDRESS"
+            kind = "setter"
+            doc = "Set it"
+            request = "SetAddress"
+            fields = [
+                { name = "node", type = "u8", min = 1, max = 0xEF },
+                { name = "groups", type = "u16", optional = true },
+            ]
+
+            [[command]]
+            cmd = 0x10
+            name = "FRAGMENT"
+            kind = "setter"
+            doc = "Part"
+            transport = true
+            "#,
+        )
+        .unwrap();
+
+        let set = &schema.commands[0];
+        let request = set.request().unwrap();
+        assert_eq!(request.type_name, "SetAddress");
+        assert_eq!(request.fields[0].range, Some((1, 0xEF)));
+        assert!(request.fields[1].optional);
+        assert!(set.client && set.has_spec());
+        let fragment = &schema.commands[1];
+        assert!(!fragment.client && !fragment.has_spec());
+    }
+
+    #[test]
+    fn rejects_inconsistent_schemas() {
+        let command = |extra: &str| {
+            format!("[[command]]\ncmd = 0x30\nname = \"X\"\nkind = \"getter\"\ndoc = \"\"\n{extra}")
+        };
+        let err = |src: &str| parse(src).unwrap_err();
+
+        assert!(err(&command("colour = 1")).contains("unknown key colour"));
+        assert!(err(&format!("{}\n{}", command(""), command(""))).contains("listed twice"));
+        assert!(err(&command("cmd = 0x90")).contains("duplicate key"));
+        assert!(
+            err(&command(
+                "request = \"X\"\nfields = [{ name = \"a\", type = \"u8\", optional = true }, { name = \"b\", type = \"u8\" }]"
+            ))
+            .contains("only the last field")
+        );
+        assert!(
+            err(&command(
+                "response = \"X\"\nfields = [{ name = \"a\", type = \"bytes\", len = 252 }]"
+            ))
+            .contains("does not fit")
+        );
+        assert!(err(&command("fields = [{ name = \"a\", type = \"f32\" }]")).contains("f32"));
+    }
+}
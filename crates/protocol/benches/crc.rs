@@ -0,0 +1,48 @@
+//! Host benchmark of the CRC-16/Modbus implementations: `cargo bench -p protocol`.
+//!
+//! No benchmark framework, so it also runs offline; timings come from
+//! `Instant` over enough rounds to hide the clock resolution. On x86 the
+//! compiler makes the bitwise loop branchless and the two run close; the
+//! numbers that matter are the ones measured on the target.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use protocol::{MAX_FRAME, crc16_modbus, crc16_modbus_bitwise};
+
+/// Bytes hashed per measurement, whatever the input size.
+const BYTES_PER_RUN: usize = 64 << 20;
+
+fn measure(f: fn(&[u8]) -> u16, data: &[u8]) -> Duration {
+    let rounds = BYTES_PER_RUN / data.len();
+    let start = Instant::now();
+    for _ in 0..rounds {
+        black_box(f(black_box(data)));
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let data: Vec<u8> = (0..4096u32).map(|i| (i * 31 + 7) as u8).collect();
+    assert_eq!(crc16_modbus(&data), crc16_modbus_bitwise(&data));
+
+    println!(
+        "{:>6}  {:>12}  {:>12}  speedup",
+        "bytes", "bitwise", "table"
+    );
+    for len in [8, MAX_FRAME, 4096] {
+        let input = &data[..len];
+        let bitwise = measure(crc16_modbus_bitwise, input);
+        let table = measure(crc16_modbus, input);
+        println!(
+            "{len:>6}  {:>8.1} MB/s  {:>8.1} MB/s  {:.1}x",
+            throughput(bitwise),
+            throughput(table),
+            bitwise.as_secs_f64() / table.as_secs_f64(),
+        );
+    }
+}
+
+fn throughput(elapsed: Duration) -> f64 {
+    BYTES_PER_RUN as f64 / elapsed.as_secs_f64() / 1e6
+}
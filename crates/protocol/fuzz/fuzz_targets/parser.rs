@@ -0,0 +1,40 @@
+//! Fuzz `Parser::push_bytes` / `Parser::next_frame` with arbitrary chunked input.
+//!
+//! The first byte selects the chunk size so libFuzzer also explores how
+//! frames straddle transport packets.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocol::{MAX_FRAME, MAX_PAYLOAD, Parser, build_frame_seq, crc16_modbus};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk, stream)) = data.split_first() else {
+        return;
+    };
+    let chunk = (chunk as usize % 64) + 1;
+
+    let mut parser = Parser::new();
+    for part in stream.chunks(chunk) {
+        parser.push_bytes(part);
+
+        loop {
+            match parser.next_frame() {
+                Ok(Some(frame)) => {
+                    // Anything accepted must re-encode to a CRC-valid frame.
+                    assert!(frame.payload.len() <= MAX_PAYLOAD);
+                    let bytes = build_frame_seq::<MAX_FRAME>(
+                        frame.seq,
+                        frame.addr,
+                        frame.cmd,
+                        frame.payload,
+                    )
+                    .unwrap();
+                    let (body, crc) = bytes.split_at(bytes.len() - 2);
+                    assert_eq!(crc16_modbus(body).to_le_bytes(), [crc[0], crc[1]]);
+                }
+                Ok(None) => break,
+                Err(_) => continue,
+            }
+        }
+    }
+});
@@ -0,0 +1,160 @@
+//! ADDR field semantics for multi-drop buses.
+//!
+//! - `0x01..=0xEF`: node addresses. A device answers frames sent to its own.
+//! - `0x00` ([`BROADCAST`]): every device executes the frame and none replies.
+//! - `0xF0..=0xFF`: 16 groups. Members execute the frame and none replies.
+//! - Frames for any other node are dropped without a reply (and without a NAK).
+//!
+//! Broadcast and group frames get no reply because several devices answering
+//! at once would collide on a shared bus.
+//!
+//! A device keeps its node address and group membership in a small record
+//! ([`AddressFilter::to_record`]) that survives resets.
+//!
+//! [`SetAddress`] and [`NodeAddress`] are the typed SET_ADDRESS request and
+//! GET_ADDRESS data, generated from `protocol.toml` (see [`crate::schema`]).
+
+use crate::crc16_modbus;
+
+/// Address every device executes without replying.
+pub const BROADCAST: u8 = 0x00;
+
+/// First group address; group `n` is `GROUP_BASE + n`.
+pub const GROUP_BASE: u8 = 0xF0;
+
+/// Number of group addresses.
+pub const GROUPS: u8 = 16;
+
+/// Node address of a device that was never configured.
+pub const DEFAULT_NODE: u8 = 0x01;
+
+/// Whether `addr` can be a device's own address.
+pub const fn is_node_address(addr: u8) -> bool {
+    addr != BROADCAST && addr < GROUP_BASE
+}
+
+/// What a device does with a frame, by its ADDR.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Delivery {
+    /// For another node: drop it.
+    Ignore,
+    /// For this node: execute and reply.
+    Reply,
+    /// Broadcast or one of this node's groups: execute, do not reply.
+    Silent,
+}
+
+pub use crate::schema::{CMD_GET_ADDRESS, CMD_SET_ADDRESS, NodeAddress, SetAddress};
+
+/// Length of the persisted record.
+pub const RECORD_LEN: usize = 9;
+
+const RECORD_MAGIC: [u8; 4] = *b"ADR1";
+
+/// A node address plus group membership (bit `n` = member of group `n`).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AddressFilter {
+    node: u8,
+    groups: u16,
+}
+
+impl AddressFilter {
+    /// Node [`DEFAULT_NODE`], no groups.
+    pub const DEFAULT: Self = Self {
+        node: DEFAULT_NODE,
+        groups: 0,
+    };
+
+    /// `None` if `node` is not a node address.
+    pub const fn new(node: u8, groups: u16) -> Option<Self> {
+        if is_node_address(node) {
+            Some(Self { node, groups })
+        } else {
+            None
+        }
+    }
+
+    pub const fn node(&self) -> u8 {
+        self.node
+    }
+
+    pub const fn groups(&self) -> u16 {
+        self.groups
+    }
+
+    pub const fn delivery(&self, addr: u8) -> Delivery {
+        if addr == self.node {
+            Delivery::Reply
+        } else if addr == BROADCAST || self.in_group(addr) {
+            Delivery::Silent
+        } else {
+            Delivery::Ignore
+        }
+    }
+
+    const fn in_group(&self, addr: u8) -> bool {
+        addr >= GROUP_BASE && self.groups & (1 << (addr - GROUP_BASE)) != 0
+    }
+
+    /// Record for non-volatile storage: `[MAGIC(4), NODE, GROUPS (u16 LE), CRC16 (LE)]`.
+    pub fn to_record(&self) -> [u8; RECORD_LEN] {
+        let mut record = [0u8; RECORD_LEN];
+        record[..4].copy_from_slice(&RECORD_MAGIC);
+        record[4] = self.node;
+        record[5..7].copy_from_slice(&self.groups.to_le_bytes());
+        let crc = crc16_modbus(&record[..7]);
+        record[7..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    /// Parse a stored record. Erased or corrupted storage gives `None`.
+    pub fn from_record(record: &[u8]) -> Option<Self> {
+        let record: &[u8; RECORD_LEN] = record.get(..RECORD_LEN)?.try_into().ok()?;
+        if record[..4] != RECORD_MAGIC
+            || crc16_modbus(&record[..7]) != u16::from_le_bytes([record[7], record[8]])
+        {
+            return None;
+        }
+        Self::new(record[4], u16::from_le_bytes([record[5], record[6]]))
+    }
+}
+
+impl Default for AddressFilter {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_addresses() {
+        // Node 0x12, member of groups 0 and 3.
+        let filter = AddressFilter::new(0x12, 0b1001).unwrap();
+        assert_eq!(filter.delivery(0x12), Delivery::Reply);
+        assert_eq!(filter.delivery(0x13), Delivery::Ignore);
+        assert_eq!(filter.delivery(BROADCAST), Delivery::Silent);
+        assert_eq!(filter.delivery(0xF0), Delivery::Silent);
+        assert_eq!(filter.delivery(0xF3), Delivery::Silent);
+        assert_eq!(filter.delivery(0xF1), Delivery::Ignore);
+        assert_eq!(filter.delivery(0xFF), Delivery::Ignore);
+
+        assert_eq!(AddressFilter::new(BROADCAST, 0), None);
+        assert_eq!(AddressFilter::new(0xF0, 0), None);
+    }
+
+    #[test]
+    fn record_round_trips_and_rejects_erased_or_corrupt_storage() {
+        let filter = AddressFilter::new(0x2A, 0x8001).unwrap();
+        let record = filter.to_record();
+        assert_eq!(AddressFilter::from_record(&record), Some(filter));
+
+        assert_eq!(AddressFilter::from_record(&[0xFF; RECORD_LEN]), None);
+        let mut corrupt = record;
+        corrupt[4] ^= 0x01;
+        assert_eq!(AddressFilter::from_record(&corrupt), None);
+        assert_eq!(AddressFilter::from_record(&record[..RECORD_LEN - 1]), None);
+    }
+}
@@ -0,0 +1,86 @@
+//! ChaCha20-Poly1305 AEAD (RFC 8439) for [`crate::session`], from the
+//! `chacha20poly1305` crate. Encrypts in place with a detached tag, so the
+//! session layer lays out its frames itself.
+
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, Tag};
+
+pub(crate) const KEY_LEN: usize = 32;
+pub(crate) const NONCE_LEN: usize = 12;
+pub(crate) const TAG_LEN: usize = 16;
+
+fn cipher(key: &[u8; KEY_LEN]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+/// Encrypt `buf` in place and return the tag over `aad` and the ciphertext,
+/// or `None` if `buf` is beyond what one nonce can encrypt (256 GiB).
+pub(crate) fn seal(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    buf: &mut [u8],
+) -> Option<[u8; TAG_LEN]> {
+    let tag = cipher(key)
+        .encrypt_in_place_detached(Nonce::from_slice(nonce), aad, buf)
+        .ok()?;
+    Some(tag.into())
+}
+
+/// Check `tag` and decrypt `buf` in place. On a mismatch `buf` is left
+/// encrypted and `false` is returned.
+pub(crate) fn open(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    buf: &mut [u8],
+    tag: &[u8],
+) -> bool {
+    tag.len() == TAG_LEN
+        && cipher(key)
+            .decrypt_in_place_detached(Nonce::from_slice(nonce), aad, buf, Tag::from_slice(tag))
+            .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> std::vec::Vec<u8> {
+        let s: std::string::String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn aead_matches_rfc8439_2_8_2() {
+        let key: [u8; 32] = core::array::from_fn(|i| 0x80 + i as u8);
+        let nonce = hex("070000004041424344454647").try_into().unwrap();
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+            only one tip for the future, sunscreen would be it.";
+
+        let mut buf = plaintext.to_vec();
+        let tag = seal(&key, &nonce, &aad, &mut buf).unwrap();
+        assert_eq!(
+            buf,
+            hex(
+                "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6
+                 3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36
+                 92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc
+                 3ff4def08e4b7a9de576d26586cec64b6116"
+            )
+        );
+        assert_eq!(tag[..], hex("1ae10b594f09e26a7e902ecbd0600691"));
+
+        assert!(open(&key, &nonce, &aad, &mut buf, &tag));
+        assert_eq!(buf, plaintext);
+
+        let tampered = seal(&key, &nonce, &aad, &mut buf).unwrap();
+        buf[0] ^= 0x01;
+        assert!(!open(&key, &nonce, &aad, &mut buf, &tampered));
+        assert!(!open(&key, &nonce, &aad, &mut buf, &tampered[..8]));
+    }
+}
@@ -0,0 +1,158 @@
+//! Optional reliability layer (ARQ) on top of the frame protocol.
+//!
+//! Hosts that need at-most-once execution send requests as sequenced frames
+//! (`STX_SEQ`, see the crate docs) and retransmit them on timeout. The device
+//! keeps one [`DuplicateFilter`] per link: a retransmission of a recent
+//! request (same SEQ, ADDR, CMD and payload) is answered from the cached
+//! response instead of running the command again, so a lost ACK can no longer
+//! make CHASE run twice. Plain frames bypass the filter entirely.
+//!
+//! With pipelining ([`crate::pipeline`]) the SEQ byte is also the request's
+//! transaction ID and the filter remembers several responses.
+//!
+//! Negotiation is implicit: the host sends a sequenced PING. Firmware with ARQ
+//! support answers with a sequenced ACK; older firmware never sees a frame
+//! (it does not recognise `STX_SEQ`) and the host falls back to plain frames.
+//! See `host::ArqClient` for the host side.
+
+use heapless::Vec;
+
+use crate::{Frame, MAX_FRAME, MAX_PAYLOAD};
+
+/// Identifies a sequenced request well enough to spot retransmissions.
+///
+/// Holds the whole payload rather than a checksum of it: a different request
+/// that happened to collide would otherwise get the earlier one's response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct RequestKey {
+    seq: u8,
+    addr: u8,
+    cmd: u8,
+    payload: Vec<u8, MAX_PAYLOAD>,
+}
+
+impl RequestKey {
+    pub(crate) fn of(frame: &Frame<'_>) -> Option<Self> {
+        Some(Self {
+            seq: frame.seq?,
+            addr: frame.addr,
+            cmd: frame.cmd,
+            payload: Vec::from_slice(frame.payload).ok()?,
+        })
+    }
+}
+
+/// Device-side duplicate detection for sequenced requests.
+///
+/// Remembers the responses to the last `N` requests, so retransmissions are
+/// still recognised when several requests are in flight (see
+/// [`crate::pipeline`]). `N = 1` suffices for stop-and-wait hosts.
+pub struct DuplicateFilter<const N: usize = 1> {
+    keys: [Option<RequestKey>; N],
+    responses: [Vec<u8, MAX_FRAME>; N],
+    next: usize,
+}
+
+impl<const N: usize> Default for DuplicateFilter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> DuplicateFilter<N> {
+    pub const fn new() -> Self {
+        Self {
+            keys: [const { None }; N],
+            responses: [const { Vec::new() }; N],
+            next: 0,
+        }
+    }
+
+    /// The cached response if `frame` retransmits a remembered request.
+    pub fn replay(&self, frame: &Frame<'_>) -> Option<&[u8]> {
+        let key = RequestKey::of(frame)?;
+        let slot = self.keys.iter().position(|k| k.as_ref() == Some(&key))?;
+        Some(&self.responses[slot][..])
+    }
+
+    /// Remember the response sent for `frame`, replacing the oldest entry.
+    /// Plain frames are ignored.
+    pub fn store(&mut self, frame: &Frame<'_>, response: &[u8]) {
+        let Some(key) = RequestKey::of(frame) else {
+            return;
+        };
+        let slot = match self.keys.iter().position(|k| k.as_ref() == Some(&key)) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.next;
+                self.next = (self.next + 1) % N;
+                slot
+            }
+        };
+        self.responses[slot].clear();
+        self.keys[slot] = self.responses[slot]
+            .extend_from_slice(response)
+            .ok()
+            .map(|()| key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_frame;
+
+    #[test]
+    fn replays_only_exact_retransmissions() {
+        let mut filter: DuplicateFilter = DuplicateFilter::new();
+        filter.store(&test_frame(Some(7), 0x02, &[1]), &[0xAA, 0xBB]);
+
+        assert_eq!(
+            filter.replay(&test_frame(Some(7), 0x02, &[1])),
+            Some(&[0xAA, 0xBB][..])
+        );
+        assert_eq!(filter.replay(&test_frame(Some(8), 0x02, &[1])), None);
+        assert_eq!(filter.replay(&test_frame(Some(7), 0x02, &[2])), None);
+    }
+
+    #[test]
+    fn payloads_with_the_same_crc_are_different_requests() {
+        // CRC-16/Modbus of both is 0x6161
+        let (a, b) = ([0x01, 0x02, 0x03], [0x04, 0xC2, 0x00]);
+        assert_eq!(crate::crc16_modbus(&a), crate::crc16_modbus(&b));
+
+        let mut filter: DuplicateFilter = DuplicateFilter::new();
+        filter.store(&test_frame(Some(7), 0x02, &a), &[0xAA]);
+        assert_eq!(filter.replay(&test_frame(Some(7), 0x02, &b)), None);
+    }
+
+    #[test]
+    fn deeper_filters_remember_several_requests() {
+        let mut filter = DuplicateFilter::<2>::new();
+        filter.store(&test_frame(Some(1), 0x02, &[]), &[0x01]);
+        filter.store(&test_frame(Some(2), 0x02, &[]), &[0x02]);
+        assert_eq!(
+            filter.replay(&test_frame(Some(1), 0x02, &[])),
+            Some(&[0x01][..])
+        );
+        assert_eq!(
+            filter.replay(&test_frame(Some(2), 0x02, &[])),
+            Some(&[0x02][..])
+        );
+
+        // A third request evicts the oldest.
+        filter.store(&test_frame(Some(3), 0x02, &[]), &[0x03]);
+        assert_eq!(filter.replay(&test_frame(Some(1), 0x02, &[])), None);
+        assert_eq!(
+            filter.replay(&test_frame(Some(3), 0x02, &[])),
+            Some(&[0x03][..])
+        );
+    }
+
+    #[test]
+    fn plain_frames_bypass_the_filter() {
+        let mut filter: DuplicateFilter = DuplicateFilter::new();
+        filter.store(&test_frame(None, 0x02, &[]), &[0xAA]);
+        assert_eq!(filter.replay(&test_frame(None, 0x02, &[])), None);
+    }
+}
@@ -0,0 +1,458 @@
+//! Authenticated frames: a truncated HMAC-SHA256 tag per request.
+//!
+//! A device with a key provisioned only runs requests signed with it. The
+//! signature is a trailer at the end of the payload, so the framing (and the
+//! COBS mode) is unchanged:
+//!   [ <PAYLOAD...>, COUNTER (u32 LE), TAG (8 bytes) ]
+//!
+//! - TAG is the first [`TAG_LEN`] bytes of HMAC-SHA256(key, ADDR, CMD,
+//!   PAYLOAD, COUNTER). SEQ is not covered; the counter already makes every
+//!   signed request unique.
+//! - COUNTER must be higher than that of any request the device accepted
+//!   before, so a captured frame cannot be replayed. The device persists a
+//!   bound on it, so replays stay refused across resets.
+//! - A request with a missing or wrong tag, or an old counter, is answered
+//!   `[UNAUTHORIZED, LAST_COUNTER (u32 LE)]` without running. A host whose
+//!   counter fell behind (say, after the device reset) continues above
+//!   LAST_COUNTER.
+//! - PING and GET_CAPABILITIES ([`is_open_cmd`]) also run unsigned, so hosts
+//!   can find the device and learn from `Features::AUTH` that it wants tags.
+//!   So do the [`crate::session`] commands, which authenticate themselves.
+//!
+//! Only requests are signed; responses and events are not authenticated.
+//! Retransmissions of a signed request are answered by the duplicate filter
+//! (see [`crate::arq`]) once their tag checks out ([`authenticate`]), but
+//! before their counter reaches the [`Verifier`], which would refuse it.
+//!
+//! [`Signer`] is the host side and [`Verifier`] the device side. Keys are
+//! stored as a [`key_record`], the counter bound as a [`counter_record`].
+
+use heapless::Vec;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::capabilities::CMD_GET_CAPABILITIES;
+use crate::session::is_session_cmd;
+use crate::{BuildError, Frame, Status, build_frame_seq, crc16_modbus};
+
+pub const KEY_LEN: usize = 32;
+
+/// Bytes of the HMAC kept in the frame (64 bits).
+pub const TAG_LEN: usize = 8;
+
+pub const COUNTER_LEN: usize = 4;
+
+/// Bytes a signature adds to the payload.
+pub const TRAILER_LEN: usize = COUNTER_LEN + TAG_LEN;
+
+pub type Key = [u8; KEY_LEN];
+
+/// CMD code of PING, which like GET_CAPABILITIES needs no signature.
+const PING: u8 = 0x01;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// HMAC-SHA256 keyed with `key`, fed the concatenation of `parts`. Check
+/// received tags with its `verify_truncated_left`, which compares in
+/// constant time.
+pub(crate) fn mac(key: &[u8], parts: &[&[u8]]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+/// HMAC-SHA256 (RFC 2104) of the concatenation of `parts`.
+pub fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    mac(key, parts).finalize().into_bytes().into()
+}
+
+/// HMAC over a request with `counter`, everything but SEQ.
+fn request_mac(key: &Key, addr: u8, cmd: u8, payload: &[u8], counter: u32) -> HmacSha256 {
+    mac(key, &[&[addr, cmd], payload, &counter.to_le_bytes()])
+}
+
+/// Tag of a request with `counter`, over everything but SEQ.
+pub fn tag(key: &Key, addr: u8, cmd: u8, payload: &[u8], counter: u32) -> [u8; TAG_LEN] {
+    let mac = request_mac(key, addr, cmd, payload, counter).finalize();
+    let mut tag = [0u8; TAG_LEN];
+    tag.copy_from_slice(&mac.into_bytes()[..TAG_LEN]);
+    tag
+}
+
+/// Commands a device with a key still runs unsigned: PING, GET_CAPABILITIES
+/// and the session commands.
+pub const fn is_open_cmd(cmd: u8) -> bool {
+    cmd == PING || cmd == CMD_GET_CAPABILITIES || is_session_cmd(cmd)
+}
+
+/// Why a request was refused.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AuthError {
+    /// Payload too short to carry a signature.
+    Missing,
+    /// The tag does not match the key.
+    BadTag,
+    /// The tag is right but the counter is not above the last one accepted.
+    Replayed,
+}
+
+/// Host side: signs requests with increasing counters.
+pub struct Signer {
+    key: Key,
+    counter: u32,
+}
+
+impl Signer {
+    /// The first request gets `counter + 1`.
+    pub const fn new(key: Key, counter: u32) -> Self {
+        Self { key, counter }
+    }
+
+    /// Counter of the last signed request.
+    pub fn counter(&self) -> u32 {
+        self.counter
+    }
+
+    /// Continue above `last`, the device's LAST_COUNTER from an UNAUTHORIZED
+    /// response. Returns whether the counter moved.
+    pub fn resync(&mut self, last: u32) -> bool {
+        let behind = last >= self.counter;
+        if behind {
+            self.counter = last;
+        }
+        behind
+    }
+
+    /// `payload` followed by a fresh signature.
+    pub fn sign<const N: usize>(
+        &mut self,
+        addr: u8,
+        cmd: u8,
+        payload: &[u8],
+    ) -> Result<Vec<u8, N>, BuildError> {
+        let counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(BuildError::CounterExhausted)?;
+        let mut out = Vec::new();
+        out.extend_from_slice(payload)
+            .and_then(|()| out.extend_from_slice(&counter.to_le_bytes()))
+            .and_then(|()| out.extend_from_slice(&tag(&self.key, addr, cmd, payload, counter)))
+            .map_err(|_| BuildError::PayloadTooLarge)?;
+        self.counter = counter;
+        Ok(out)
+    }
+}
+
+/// Device side: checks signatures and counters.
+pub struct Verifier {
+    key: Key,
+    last: u32,
+}
+
+impl Verifier {
+    /// `last` is the counter to stay above, e.g. the persisted bound after a reset.
+    pub const fn new(key: Key, last: u32) -> Self {
+        Self { key, last }
+    }
+
+    /// Counter of the last accepted request.
+    pub fn last(&self) -> u32 {
+        self.last
+    }
+
+    /// `frame` with the signature removed if it carries a valid one. Open
+    /// commands without a valid tag pass unchanged.
+    pub fn verify<'a>(&mut self, frame: &Frame<'a>) -> Result<Frame<'a>, AuthError> {
+        let signed = authenticate(&self.key, frame)?;
+        if let Some(counter) = signed.counter {
+            self.accept(counter)?;
+        }
+        Ok(signed.request)
+    }
+
+    /// Second half of [`verify`](Self::verify): take `counter`, from an
+    /// [`authenticate`]d request, if it is above the last one accepted.
+    pub fn accept(&mut self, counter: u32) -> Result<(), AuthError> {
+        if counter <= self.last {
+            return Err(AuthError::Replayed);
+        }
+        self.last = counter;
+        Ok(())
+    }
+}
+
+/// A request whose signature checked out, see [`authenticate`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Signed<'a> {
+    /// The request without its signature.
+    pub request: Frame<'a>,
+    /// Still to be [`accept`](Verifier::accept)ed; `None` for an open
+    /// command that came without a valid tag.
+    pub counter: Option<u32>,
+}
+
+/// First half of [`Verifier::verify`]: check the tag of `frame` against
+/// `key`, leaving the counter to [`Verifier::accept`]. Needs only the key,
+/// so a device can run it without holding the lock around its verifier.
+pub fn authenticate<'a>(key: &Key, frame: &Frame<'a>) -> Result<Signed<'a>, AuthError> {
+    match check_tag(key, frame) {
+        Err(AuthError::Missing | AuthError::BadTag) if is_open_cmd(frame.cmd) => Ok(Signed {
+            request: *frame,
+            counter: None,
+        }),
+        result => result,
+    }
+}
+
+fn check_tag<'a>(key: &Key, frame: &Frame<'a>) -> Result<Signed<'a>, AuthError> {
+    let split = frame
+        .payload
+        .len()
+        .checked_sub(TRAILER_LEN)
+        .ok_or(AuthError::Missing)?;
+    let (payload, trailer) = frame.payload.split_at(split);
+    let (counter, received) = trailer.split_at(COUNTER_LEN);
+    let counter = u32::from_le_bytes([counter[0], counter[1], counter[2], counter[3]]);
+
+    request_mac(key, frame.addr, frame.cmd, payload, counter)
+        .verify_truncated_left(received)
+        .map_err(|_| AuthError::BadTag)?;
+    Ok(Signed {
+        request: Frame { payload, ..*frame },
+        counter: Some(counter),
+    })
+}
+
+/// UNAUTHORIZED response to `frame`: `[UNAUTHORIZED, LAST_COUNTER (u32 LE)]`.
+pub fn unauthorized<const OUT_CAP: usize>(
+    frame: &Frame<'_>,
+    last: u32,
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    let [a, b, c, d] = last.to_le_bytes();
+    let payload = [Status::Unauthorized.as_u8(), a, b, c, d];
+    build_frame_seq(frame.seq, frame.addr, frame.cmd, &payload)
+}
+
+/// LAST_COUNTER of an UNAUTHORIZED response payload.
+pub fn last_counter(payload: &[u8]) -> Option<u32> {
+    match *payload {
+        [status, a, b, c, d] if status == Status::Unauthorized.as_u8() => {
+            Some(u32::from_le_bytes([a, b, c, d]))
+        }
+        _ => None,
+    }
+}
+
+pub const KEY_RECORD_LEN: usize = 4 + KEY_LEN + 2;
+
+const KEY_MAGIC: [u8; 4] = *b"KEY1";
+
+/// Stored key: `[b"KEY1", KEY, CRC16 (LE)]`.
+pub fn key_record(key: &Key) -> [u8; KEY_RECORD_LEN] {
+    let mut record = [0u8; KEY_RECORD_LEN];
+    record[..4].copy_from_slice(&KEY_MAGIC);
+    record[4..4 + KEY_LEN].copy_from_slice(key);
+    let crc = crc16_modbus(&record[..4 + KEY_LEN]);
+    record[4 + KEY_LEN..].copy_from_slice(&crc.to_le_bytes());
+    record
+}
+
+/// Parse a stored key. Erased or corrupted storage gives `None`.
+pub fn key_from_record(record: &[u8]) -> Option<Key> {
+    let (body, crc) = record.get(..KEY_RECORD_LEN)?.split_at(4 + KEY_LEN);
+    if body[..4] != KEY_MAGIC || crc16_modbus(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+        return None;
+    }
+    body[4..].try_into().ok()
+}
+
+pub const COUNTER_RECORD_LEN: usize = 4 + COUNTER_LEN + 2;
+
+const COUNTER_MAGIC: [u8; 4] = *b"CTR1";
+
+/// Stored counter bound: `[b"CTR1", COUNTER (u32 LE), CRC16 (LE)]`.
+pub fn counter_record(counter: u32) -> [u8; COUNTER_RECORD_LEN] {
+    let mut record = [0u8; COUNTER_RECORD_LEN];
+    record[..4].copy_from_slice(&COUNTER_MAGIC);
+    record[4..8].copy_from_slice(&counter.to_le_bytes());
+    let crc = crc16_modbus(&record[..8]);
+    record[8..].copy_from_slice(&crc.to_le_bytes());
+    record
+}
+
+/// Parse a stored counter bound. Erased or corrupted storage gives `None`.
+pub fn counter_from_record(record: &[u8]) -> Option<u32> {
+    let record = record.get(..COUNTER_RECORD_LEN)?;
+    if record[..4] != COUNTER_MAGIC
+        || crc16_modbus(&record[..8]) != u16::from_le_bytes([record[8], record[9]])
+    {
+        return None;
+    }
+    Some(u32::from_le_bytes([
+        record[4], record[5], record[6], record[7],
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MAX_FRAME, MAX_PAYLOAD, Parser, test_frame};
+
+    fn hex(s: &str) -> std::vec::Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn hmac_matches_rfc4231_vectors() {
+        // Test cases 1, 2 and 6 (key longer than a block)
+        let mac = hmac_sha256(&[0x0B; 20], &[b"Hi There"]);
+        assert_eq!(
+            mac[..],
+            hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+        );
+        let mac = hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        assert_eq!(
+            mac[..],
+            hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+        );
+        let mac = hmac_sha256(
+            &[0xAA; 131],
+            &[b"Test Using Larger Than Block-Size Key - Hash Key First"],
+        );
+        assert_eq!(
+            mac[..],
+            hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+        );
+    }
+
+    fn key() -> Key {
+        core::array::from_fn(|i| i as u8)
+    }
+
+    #[test]
+    fn frame_tag_matches_known_vector() {
+        // Also checked against Python's hmac module (tools/serial_client)
+        assert_eq!(
+            tag(&key(), 0x01, 0x02, &[0x2A], 7)[..],
+            hex("979bddf0507dbe16")
+        );
+
+        let mut signer = Signer::new(key(), 6);
+        let signed = signer.sign::<MAX_PAYLOAD>(0x01, 0x02, &[0x2A]).unwrap();
+        assert_eq!(signed[..], hex("2a07000000979bddf0507dbe16"));
+        assert_eq!(signer.counter(), 7);
+    }
+
+    #[test]
+    fn verifier_strips_valid_signatures_and_refuses_the_rest() {
+        let mut signer = Signer::new(key(), 0);
+        let mut verifier = Verifier::new(key(), 0);
+
+        let first = signer.sign::<MAX_PAYLOAD>(0x01, 0x02, &[0x2A]).unwrap();
+        assert_eq!(
+            verifier.verify(&test_frame(None, 0x02, &first)),
+            Ok(test_frame(None, 0x02, &[0x2A]))
+        );
+        assert_eq!(verifier.last(), 1);
+        // The same frame again is a replay
+        assert_eq!(
+            verifier.verify(&test_frame(None, 0x02, &first)),
+            Err(AuthError::Replayed)
+        );
+
+        let second = signer.sign::<MAX_PAYLOAD>(0x01, 0x02, &[0x2A]).unwrap();
+        let mut forged = second.clone();
+        forged[0] ^= 0x01;
+        assert_eq!(
+            verifier.verify(&test_frame(None, 0x02, &forged)),
+            Err(AuthError::BadTag)
+        );
+        assert_eq!(
+            verifier.verify(&test_frame(None, 0x02, &[])),
+            Err(AuthError::Missing)
+        );
+        let wrong_key = Signer::new([0xFF; KEY_LEN], 5).sign::<MAX_PAYLOAD>(0x01, 0x02, &[0x2A]);
+        assert_eq!(
+            verifier.verify(&test_frame(None, 0x02, &wrong_key.unwrap())),
+            Err(AuthError::BadTag)
+        );
+        assert_eq!(
+            verifier.verify(&test_frame(None, 0x02, &second)),
+            Ok(test_frame(None, 0x02, &[0x2A]))
+        );
+
+        // PING runs signed or not
+        assert_eq!(
+            verifier.verify(&test_frame(None, PING, &[])),
+            Ok(test_frame(None, PING, &[]))
+        );
+        let ping = signer.sign::<MAX_PAYLOAD>(0x01, PING, &[]).unwrap();
+        assert_eq!(
+            verifier.verify(&test_frame(None, PING, &ping)),
+            Ok(test_frame(None, PING, &[]))
+        );
+    }
+
+    #[test]
+    fn authenticate_leaves_the_counter_to_accept() {
+        let signed = Signer::new(key(), 4)
+            .sign::<MAX_PAYLOAD>(0x01, 0x02, &[0x2A])
+            .unwrap();
+        let checked = authenticate(&key(), &test_frame(None, 0x02, &signed)).unwrap();
+        assert_eq!(checked.request, test_frame(None, 0x02, &[0x2A]));
+        assert_eq!(checked.counter, Some(5));
+        // Checking twice is fine; accepting twice is a replay
+        assert_eq!(
+            authenticate(&key(), &test_frame(None, 0x02, &signed)),
+            Ok(checked)
+        );
+
+        let mut verifier = Verifier::new(key(), 0);
+        assert_eq!(verifier.accept(5), Ok(()));
+        assert_eq!(verifier.accept(5), Err(AuthError::Replayed));
+        assert_eq!(
+            authenticate(&key(), &test_frame(None, PING, &[])).map(|s| s.counter),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn host_resyncs_from_an_unauthorized_response() {
+        let mut verifier = Verifier::new(key(), 1024); // bound restored after a reset
+        let mut signer = Signer::new(key(), 3);
+        let request = signer.sign::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap();
+        assert_eq!(
+            verifier.verify(&test_frame(None, 0x02, &request)),
+            Err(AuthError::Replayed)
+        );
+
+        let resp =
+            unauthorized::<MAX_FRAME>(&test_frame(None, 0x02, &request), verifier.last()).unwrap();
+        let mut parser = Parser::new();
+        parser.push_bytes(&resp);
+        let resp = parser.next_frame().unwrap().unwrap();
+        assert!(signer.resync(last_counter(resp.payload).unwrap()));
+        assert!(!signer.resync(10));
+
+        let request = signer.sign::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap();
+        assert!(verifier.verify(&test_frame(None, 0x02, &request)).is_ok());
+        assert_eq!(verifier.last(), 1025);
+    }
+
+    #[test]
+    fn records_round_trip_and_reject_corruption() {
+        assert_eq!(key_from_record(&key_record(&key())), Some(key()));
+        assert_eq!(key_from_record(&[0xFF; KEY_RECORD_LEN]), None);
+        assert_eq!(counter_from_record(&counter_record(4096)), Some(4096));
+        let mut corrupt = counter_record(4096);
+        corrupt[5] ^= 0x01;
+        assert_eq!(counter_from_record(&corrupt), None);
+        assert_eq!(counter_from_record(&[0xFF; 3]), None);
+    }
+}
@@ -0,0 +1,249 @@
+//! GET_CAPABILITIES: what a firmware build supports.
+//!
+//! Data returned by the getter (multi-byte fields little-endian):
+//!   [ VER_MAJOR, VER_MINOR, FRAMINGS, FEATURES, MAX_PAYLOAD (u16),
+//!     STREAM_BUF_CAP (u16), MAX_MESSAGE (u32), N, (CMD, KIND) * N ]
+//!
+//! - VER_MAJOR changes when the frame format changes incompatibly; VER_MINOR
+//!   when commands, status codes or features are added.
+//! - FRAMINGS is a bit set of [`Framing`] modes the link speaks.
+//! - FEATURES is a bit set of optional layers, see [`Features`].
+//! - MAX_MESSAGE is the largest fragmented message the device reassembles
+//!   (0 without fragmentation).
+//! - KIND is 0 for setters and 1 for getters. Transport commands handled
+//!   outside the registry (fragments, CMD_SECURE) are implied by FEATURES
+//!   instead.
+
+use heapless::Vec;
+
+use crate::dispatch::{CommandKind, Dispatch, Registry};
+use crate::{Framing, MAX_DATA, MAX_PAYLOAD, STREAM_BUF_CAP};
+
+/// CMD code of the GET_CAPABILITIES getter.
+pub const CMD_GET_CAPABILITIES: u8 = crate::schema::CMD_GET_CAPABILITIES;
+
+/// Protocol version implemented by this crate: (major, minor).
+pub const PROTOCOL_VERSION: (u8, u8) = (1, 0);
+
+const HEADER_LEN: usize = 13;
+
+/// Most commands a response can list.
+pub const MAX_COMMANDS: usize = (MAX_DATA - HEADER_LEN) / 2;
+
+/// Optional protocol layers a build may enable.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Features(pub u8);
+
+impl Features {
+    pub const NONE: Features = Features(0);
+    /// Sequenced frames with duplicate replay ([`crate::arq`]).
+    pub const ARQ: Features = Features(1 << 0);
+    /// CMD_FRAGMENT / CMD_FRAGMENT_ABORT ([`crate::fragment`]).
+    pub const FRAGMENTATION: Features = Features(1 << 1);
+    /// Authenticated frames.
+    pub const AUTH: Features = Features(1 << 2);
+    /// Unsolicited event frames and SUBSCRIBE / UNSUBSCRIBE ([`crate::event`]).
+    pub const EVENTS: Features = Features(1 << 3);
+    /// Sequenced requests run concurrently, answered by SEQ ([`crate::pipeline`]).
+    pub const PIPELINING: Features = Features(1 << 4);
+    /// CMD_SESSION_OPEN / CMD_SECURE ([`crate::session`]).
+    pub const SESSION: Features = Features(1 << 5);
+
+    pub const fn union(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::ops::BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, rhs: Features) -> Features {
+        self.union(rhs)
+    }
+}
+
+impl Framing {
+    /// Bit for this framing in the FRAMINGS field.
+    pub const fn bit(self) -> u8 {
+        match self {
+            Framing::StxLen => 1 << 0,
+            Framing::Cobs => 1 << 1,
+        }
+    }
+}
+
+/// Decoded GET_CAPABILITIES data.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Capabilities {
+    pub version: (u8, u8),
+    pub framings: u8,
+    pub features: Features,
+    pub max_payload: u16,
+    pub stream_buf_cap: u16,
+    pub max_message: u32,
+    pub commands: Vec<(u8, CommandKind), MAX_COMMANDS>,
+}
+
+impl Capabilities {
+    /// Capabilities of a build that serves `registry` over `framing`.
+    /// Commands beyond [`MAX_COMMANDS`] are left out.
+    pub fn of<D: Dispatch>(
+        registry: &Registry<D>,
+        framing: Framing,
+        features: Features,
+        max_message: u32,
+    ) -> Self {
+        let mut commands = Vec::new();
+        registry.for_each_spec(|spec| {
+            let _ = commands.push((spec.cmd, spec.kind));
+        });
+        Self {
+            version: PROTOCOL_VERSION,
+            framings: framing.bit(),
+            features,
+            max_payload: MAX_PAYLOAD as u16,
+            stream_buf_cap: STREAM_BUF_CAP as u16,
+            max_message,
+            commands,
+        }
+    }
+
+    pub fn supports(&self, cmd: u8) -> bool {
+        self.commands.iter().any(|&(c, _)| c == cmd)
+    }
+
+    pub fn supports_framing(&self, framing: Framing) -> bool {
+        self.framings & framing.bit() != 0
+    }
+
+    /// Getter data for this response.
+    pub fn encode(&self) -> Vec<u8, MAX_DATA> {
+        let mut out = Vec::new();
+        // HEADER_LEN + 2 * MAX_COMMANDS <= MAX_DATA, so nothing here can fail.
+        let _ = out.extend_from_slice(&[
+            self.version.0,
+            self.version.1,
+            self.framings,
+            self.features.0,
+        ]);
+        let _ = out.extend_from_slice(&self.max_payload.to_le_bytes());
+        let _ = out.extend_from_slice(&self.stream_buf_cap.to_le_bytes());
+        let _ = out.extend_from_slice(&self.max_message.to_le_bytes());
+        let _ = out.push(self.commands.len() as u8);
+        for &(cmd, kind) in &self.commands {
+            let kind = match kind {
+                CommandKind::Setter => 0,
+                CommandKind::Getter => 1,
+            };
+            let _ = out.extend_from_slice(&[cmd, kind]);
+        }
+        out
+    }
+
+    /// Parse getter data. Newer firmware may append fields; they are ignored.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let header: &[u8; HEADER_LEN] = data.get(..HEADER_LEN)?.try_into().ok()?;
+        let count = header[12] as usize;
+        let list = data.get(HEADER_LEN..HEADER_LEN + 2 * count)?;
+
+        let mut commands = Vec::new();
+        for entry in list.chunks_exact(2) {
+            let kind = match entry[1] {
+                0 => CommandKind::Setter,
+                1 => CommandKind::Getter,
+                _ => return None,
+            };
+            commands.push((entry[0], kind)).ok()?;
+        }
+        Some(Self {
+            version: (header[0], header[1]),
+            framings: header[2],
+            features: Features(header[3]),
+            max_payload: u16::from_le_bytes([header[4], header[5]]),
+            stream_buf_cap: u16::from_le_bytes([header[6], header[7]]),
+            max_message: u32::from_le_bytes([header[8], header[9], header[10], header[11]]),
+            commands,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Frame;
+    use crate::dispatch::{CommandHandler, CommandSpec, Data};
+    use crate::{STX, Status};
+
+    struct Ping;
+
+    impl CommandHandler for Ping {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: 0x01,
+            name: "PING",
+            kind: CommandKind::Setter,
+            min_payload: 0,
+            max_payload: 0,
+        };
+
+        async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+            Ok(())
+        }
+    }
+
+    struct GetId;
+
+    impl CommandHandler for GetId {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: 0x20,
+            name: "GET_ID",
+            kind: CommandKind::Getter,
+            min_payload: 0,
+            max_payload: 0,
+        };
+
+        async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn encodes_documented_layout_and_round_trips() {
+        let registry = Registry::new().register(GetId).register(Ping);
+        let caps = Capabilities::of(
+            &registry,
+            Framing::StxLen,
+            Features::ARQ | Features::FRAGMENTATION,
+            2048,
+        );
+
+        let bytes = caps.encode();
+        assert_eq!(
+            &bytes[..],
+            &[
+                1, 0, 0x01, 0x03, 253, 0, 0x00, 0x02, 0x00, 0x08, 0, 0, 2, 0x01, 0, 0x20, 1
+            ]
+        );
+        assert_eq!(Capabilities::decode(&bytes), Some(caps.clone()));
+        assert!(caps.supports(0x20) && !caps.supports(STX));
+        assert!(caps.features.contains(Features::ARQ));
+        assert!(!caps.features.contains(Features::AUTH));
+    }
+
+    #[test]
+    fn decode_rejects_truncated_data_and_ignores_extensions() {
+        let registry = Registry::new().register(Ping);
+        let bytes = Capabilities::of(&registry, Framing::Cobs, Features::NONE, 0).encode();
+
+        assert_eq!(Capabilities::decode(&bytes[..bytes.len() - 1]), None);
+
+        let mut extended = std::vec::Vec::from(&bytes[..]);
+        extended.push(0xEE);
+        let caps = Capabilities::decode(&extended).unwrap();
+        assert!(caps.supports_framing(Framing::Cobs));
+        assert!(!caps.supports_framing(Framing::StxLen));
+    }
+}
@@ -0,0 +1,62 @@
+//! Typed [`ArqClient`] methods for the commands in `protocol.toml`.
+//!
+//! Setters return `Ok(())` once the device answers OK; any other status is
+//! [`ClientError::Status`]. Getters return their decoded data. Commands
+//! with hand-written host support (`client = false`) are left out.
+//!
+//! @generated by `protocol-gen`: edit `crates/protocol/protocol.toml` and run
+//! `cargo protocol-gen` instead of changing this file.
+
+use crate::host::{ArqClient, ClientError, Link};
+use crate::schema::*;
+
+pub trait Commands {
+    /// 0x01: Device health check.
+    fn ping(&mut self, addr: u8) -> Result<(), ClientError>;
+    /// 0x02: Start the LED chase pattern; ACKed at once, `CHASE_FINISHED` event when done.
+    fn chase(&mut self, addr: u8) -> Result<(), ClientError>;
+    /// 0x03: Reset the link-quality counters of the link it arrives on.
+    fn clear_link_stats(&mut self, addr: u8) -> Result<(), ClientError>;
+    /// 0x04: Store a new node address and optionally group membership.
+    fn set_address(&mut self, addr: u8, request: &SetAddress) -> Result<(), ClientError>;
+    /// 0x20: Query unique device identifier.
+    fn get_device_id(&mut self, addr: u8) -> Result<DeviceId, ClientError>;
+    /// 0x21: Read link-quality counters of the link it arrives on.
+    fn get_link_stats(&mut self, addr: u8) -> Result<LinkCounters, ClientError>;
+    /// 0x23: Read the node address and group membership.
+    fn get_address(&mut self, addr: u8) -> Result<NodeAddress, ClientError>;
+}
+
+impl<L: Link> Commands for ArqClient<L> {
+    fn ping(&mut self, addr: u8) -> Result<(), ClientError> {
+        self.request(addr, CMD_PING, &[])?.check()?;
+        Ok(())
+    }
+
+    fn chase(&mut self, addr: u8) -> Result<(), ClientError> {
+        self.request(addr, CMD_CHASE, &[])?.check()?;
+        Ok(())
+    }
+
+    fn clear_link_stats(&mut self, addr: u8) -> Result<(), ClientError> {
+        self.request(addr, CMD_CLEAR_LINK_STATS, &[])?.check()?;
+        Ok(())
+    }
+
+    fn set_address(&mut self, addr: u8, request: &SetAddress) -> Result<(), ClientError> {
+        self.command(addr, request)?.check()?;
+        Ok(())
+    }
+
+    fn get_device_id(&mut self, addr: u8) -> Result<DeviceId, ClientError> {
+        Ok(self.request(addr, CMD_GET_DEVICE_ID, &[])?.decode()?)
+    }
+
+    fn get_link_stats(&mut self, addr: u8) -> Result<LinkCounters, ClientError> {
+        Ok(self.request(addr, CMD_GET_LINK_STATS, &[])?.decode()?)
+    }
+
+    fn get_address(&mut self, addr: u8) -> Result<NodeAddress, ClientError> {
+        Ok(self.request(addr, CMD_GET_ADDRESS, &[])?.decode()?)
+    }
+}
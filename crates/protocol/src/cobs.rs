@@ -0,0 +1,191 @@
+//! COBS (Consistent Overhead Byte Stuffing) framing.
+//!
+//! An alternative to STX/LEN framing for links that see a lot of corruption.
+//! Each packet is
+//!   COBS([ ADDR, CMD, <PAYLOAD...>, CRCL, CRCH ]), 0x00
+//! where the body is the Modbus RTU layout: the same CRC-16/Modbus as STX
+//! frames, computed over ADDR through the end of PAYLOAD. COBS removes every
+//! 0x00 from the body, so the delimiter cannot appear inside a packet and the
+//! parser resyncs at the next 0x00 without CRC-checking false starts.
+//!
+//! COBS packets carry no SEQ, so [`crate::arq`] needs STX framing.
+//! Select the framing per transport with [`Framing`](crate::Framing).
+
+use heapless::Vec;
+
+use crate::{BuildError, MAX_FRAME, MAX_PAYLOAD, STX, STX_SEQ, crc16_modbus};
+
+/// Largest decoded body: ADDR + CMD + payload + CRC.
+pub const MAX_BODY: usize = MAX_PAYLOAD + 4;
+
+/// Largest encoded packet including the delimiter: one code byte per 254
+/// body bytes, plus the leading code byte and the 0x00.
+pub const MAX_PACKET: usize = MAX_BODY + MAX_BODY.div_ceil(254) + 1;
+
+// Encoded packets share buffers with STX frames.
+const _: () = assert!(MAX_PACKET <= MAX_FRAME);
+
+/// COBS-encode `src` onto the end of `out` (no delimiter).
+pub fn encode<const N: usize>(src: &[u8], out: &mut Vec<u8, N>) -> Result<(), BuildError> {
+    let full = |_| BuildError::OutputTooSmall;
+    let mut code_at = out.len();
+    out.push(0).map_err(full)?;
+    let mut code = 1u8;
+
+    for (i, &b) in src.iter().enumerate() {
+        if b != 0 {
+            out.push(b).map_err(full)?;
+            code += 1;
+        }
+        // A zero ends the block; so does a full block, unless it is the last one.
+        if b == 0 || (code == 0xFF && i + 1 < src.len()) {
+            out[code_at] = code;
+            code_at = out.len();
+            out.push(0).map_err(full)?;
+            code = 1;
+        }
+    }
+    out[code_at] = code;
+    Ok(())
+}
+
+/// Decode one COBS packet (without its delimiter) into `dst`.
+///
+/// Returns the decoded length, or `None` if `src` is not valid COBS or
+/// `dst` is too small.
+pub fn decode(src: &[u8], dst: &mut [u8]) -> Option<usize> {
+    let mut i = 0;
+    let mut out = 0;
+
+    while i < src.len() {
+        let code = src[i] as usize;
+        if code == 0 {
+            return None;
+        }
+        let block = src.get(i + 1..i + code)?;
+        dst.get_mut(out..out + block.len())?.copy_from_slice(block);
+        out += block.len();
+        i += code;
+
+        // Every block but the last (and full blocks) stood for a trailing zero.
+        if code != 0xFF && i < src.len() {
+            *dst.get_mut(out)? = 0;
+            out += 1;
+        }
+    }
+    Some(out)
+}
+
+/// Build a COBS packet: COBS([ADDR, CMD, payload..., CRCL, CRCH]), 0x00.
+pub fn build_frame<const OUT_CAP: usize>(
+    addr: u8,
+    cmd: u8,
+    payload: &[u8],
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(BuildError::PayloadTooLarge);
+    }
+
+    let mut body: Vec<u8, MAX_BODY> = Vec::new();
+    // Capacity is MAX_PAYLOAD + 4, checked above.
+    let _ = body.push(addr);
+    let _ = body.push(cmd);
+    let _ = body.extend_from_slice(payload);
+    let crc = crc16_modbus(&body);
+    let _ = body.extend_from_slice(&crc.to_le_bytes());
+
+    let mut out = Vec::new();
+    encode(&body, &mut out)?;
+    out.push(0).map_err(|_| BuildError::OutputTooSmall)?;
+    Ok(out)
+}
+
+/// Re-encode a frame from the `build_*` functions as a COBS packet.
+/// A SEQ byte, if any, is dropped.
+pub fn from_stx_frame<const OUT_CAP: usize>(frame: &[u8]) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    let header_len = match frame.first() {
+        Some(&STX) => 2,
+        Some(&STX_SEQ) => 3,
+        _ => return Err(BuildError::NotAFrame),
+    };
+    let len = *frame.get(1).ok_or(BuildError::NotAFrame)? as usize;
+    let body = frame
+        .get(header_len..header_len + len)
+        .filter(|body| body.len() >= 2)
+        .ok_or(BuildError::NotAFrame)?;
+    build_frame(body[0], body[1], &body[2..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_frame_seq;
+
+    fn encoded(src: &[u8]) -> std::vec::Vec<u8> {
+        let mut out: Vec<u8, 512> = Vec::new();
+        encode(src, &mut out).unwrap();
+        out.to_vec()
+    }
+
+    #[test]
+    fn matches_reference_vectors() {
+        let ramp = |from: u8, to: u8| (from..=to).collect::<std::vec::Vec<u8>>();
+        let cases: [(std::vec::Vec<u8>, std::vec::Vec<u8>); 7] = [
+            (vec![], vec![0x01]),
+            (vec![0x00], vec![0x01, 0x01]),
+            (vec![0x00, 0x00], vec![0x01, 0x01, 0x01]),
+            (
+                vec![0x11, 0x22, 0x00, 0x33],
+                vec![0x03, 0x11, 0x22, 0x02, 0x33],
+            ),
+            (
+                vec![0x11, 0x00, 0x00, 0x00],
+                vec![0x02, 0x11, 0x01, 0x01, 0x01],
+            ),
+            (ramp(0x01, 0xFE), [vec![0xFF], ramp(0x01, 0xFE)].concat()),
+            (
+                ramp(0x01, 0xFF),
+                [vec![0xFF], ramp(0x01, 0xFE), vec![0x02, 0xFF]].concat(),
+            ),
+        ];
+
+        for (raw, cobs) in cases {
+            assert_eq!(encoded(&raw), cobs);
+            let mut dst = [0u8; 300];
+            let n = decode(&cobs, &mut dst).unwrap();
+            assert_eq!(&dst[..n], &raw[..]);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_packets() {
+        let mut dst = [0u8; 16];
+        assert_eq!(decode(&[0x05, 0x11, 0x22], &mut dst), None); // block runs past the end
+        assert_eq!(decode(&[0x02, 0x11, 0x00], &mut dst), None); // embedded zero
+        assert_eq!(decode(&[0x03, 0x11, 0x22], &mut dst[..1]), None); // dst too small
+    }
+
+    #[test]
+    fn packets_have_no_zeros_before_the_delimiter() {
+        let payload = [0x00; MAX_PAYLOAD];
+        let packet = build_frame::<MAX_PACKET>(0x00, 0x00, &payload).unwrap();
+        assert_eq!(packet.iter().position(|&b| b == 0), Some(packet.len() - 1));
+
+        let payload = [0x5A; MAX_PAYLOAD];
+        let packet = build_frame::<MAX_PACKET>(0x01, 0x01, &payload).unwrap();
+        assert_eq!(packet.iter().position(|&b| b == 0), Some(packet.len() - 1));
+    }
+
+    #[test]
+    fn reframes_built_frames() {
+        let stx = build_frame_seq::<MAX_FRAME>(Some(9), 0x01, 0x20, &[0x00, 0xA5]).unwrap();
+        assert_eq!(
+            from_stx_frame::<MAX_PACKET>(&stx),
+            build_frame::<MAX_PACKET>(0x01, 0x20, &[0x00, 0xA5])
+        );
+        assert_eq!(
+            from_stx_frame::<MAX_PACKET>(&[0x00, 0x01]),
+            Err(BuildError::NotAFrame)
+        );
+    }
+}
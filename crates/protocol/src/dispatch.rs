@@ -0,0 +1,601 @@
+//! Command dispatch.
+//!
+//! Each command is a type implementing [`CommandHandler`]. Its [`CommandSpec`]
+//! declares the CMD code, whether it is a setter or a getter, and the payload
+//! sizes it accepts. Handlers are collected into a [`Registry`] (usually a
+//! `static` built with [`command_registry!`](crate::command_registry)), and
+//! [`Registry::dispatch`] turns a decoded [`Frame`] into a response frame:
+//!
+//! - unknown CMD codes get an ERROR frame (BAD_CMD) without reaching a handler
+//! - payloads outside `min_payload..=max_payload` get an ERROR frame (BAD_LEN)
+//! - setters that succeed get an ACK, getters get DATA
+//! - handler errors get an ERROR frame with the handler's status
+//!
+//! Responses echo the request's ADDR, CMD and (for sequenced frames) SEQ.
+//!
+//! The registry is a compile-time list, so dispatch is static, needs no
+//! allocation, and handlers can be `async`. A registry is itself a
+//! [`Dispatch`], so handlers that need runtime state (say, which link a
+//! request came in on) can be [`register`](Registry::register)ed on top of a
+//! reference to a `static` one, and a table that only some links offer can be
+//! [`merge`](Registry::merge)d in as an `Option`.
+
+use heapless::Vec;
+
+use crate::payload::Command;
+use crate::{BuildError, Frame, MAX_DATA, MAX_FRAME, Response, Status, build_response};
+
+/// Whether a command changes device state (answered with ACK) or reads it (answered with DATA).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CommandKind {
+    Setter,
+    Getter,
+}
+
+/// Static description of a command.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CommandSpec {
+    pub cmd: u8,
+    pub name: &'static str,
+    pub kind: CommandKind,
+    /// Smallest accepted request payload, in bytes.
+    pub min_payload: usize,
+    /// Largest accepted request payload, in bytes.
+    pub max_payload: usize,
+}
+
+impl CommandSpec {
+    /// Spec for a handler of the typed request `C`: CMD and payload limits
+    /// come from the type.
+    pub const fn of<C: Command>(name: &'static str, kind: CommandKind) -> Self {
+        Self {
+            cmd: C::CMD,
+            name,
+            kind,
+            min_payload: C::MIN_LEN,
+            max_payload: C::MAX_LEN,
+        }
+    }
+
+    pub fn accepts_len(&self, len: usize) -> bool {
+        (self.min_payload..=self.max_payload).contains(&len)
+    }
+}
+
+/// Getter response data, filled in by the handler.
+pub type Data = Vec<u8, MAX_DATA>;
+
+/// A command implementation.
+///
+/// `handle` is only called once the payload length has been validated
+/// against [`CommandHandler::SPEC`]. Setters leave `data` empty; getters push
+/// their response bytes into it. `Err(status)` is sent back as an ERROR frame.
+#[allow(async_fn_in_trait)] // single-threaded executor, no Send bound needed
+pub trait CommandHandler {
+    const SPEC: CommandSpec;
+
+    async fn handle(&self, frame: &Frame<'_>, data: &mut Data) -> Result<(), Status>;
+}
+
+/// Compile-time list of handlers. Implemented by [`Nil`] and [`Cons`]; use
+/// [`Registry`] rather than calling this directly.
+#[allow(async_fn_in_trait)]
+pub trait Dispatch {
+    fn spec(&self, cmd: u8) -> Option<CommandSpec>;
+
+    fn for_each_spec(&self, f: &mut dyn FnMut(&CommandSpec));
+
+    /// Run the handler for `frame.cmd`, or return `None` if there is none.
+    async fn call(&self, frame: &Frame<'_>, data: &mut Data) -> Option<Result<(), Status>>;
+}
+
+/// End of a handler list.
+pub struct Nil;
+
+/// A handler followed by the rest of the list.
+pub struct Cons<H, T> {
+    pub head: H,
+    pub tail: T,
+}
+
+impl Dispatch for Nil {
+    fn spec(&self, _cmd: u8) -> Option<CommandSpec> {
+        None
+    }
+
+    fn for_each_spec(&self, _f: &mut dyn FnMut(&CommandSpec)) {}
+
+    async fn call(&self, _frame: &Frame<'_>, _data: &mut Data) -> Option<Result<(), Status>> {
+        None
+    }
+}
+
+impl<H: CommandHandler, T: Dispatch> Dispatch for Cons<H, T> {
+    fn spec(&self, cmd: u8) -> Option<CommandSpec> {
+        if H::SPEC.cmd == cmd {
+            Some(H::SPEC)
+        } else {
+            self.tail.spec(cmd)
+        }
+    }
+
+    fn for_each_spec(&self, f: &mut dyn FnMut(&CommandSpec)) {
+        f(&H::SPEC);
+        self.tail.for_each_spec(f);
+    }
+
+    async fn call(&self, frame: &Frame<'_>, data: &mut Data) -> Option<Result<(), Status>> {
+        if H::SPEC.cmd == frame.cmd {
+            Some(self.head.handle(frame, data).await)
+        } else {
+            self.tail.call(frame, data).await
+        }
+    }
+}
+
+impl<D: Dispatch + ?Sized> Dispatch for &D {
+    fn spec(&self, cmd: u8) -> Option<CommandSpec> {
+        (**self).spec(cmd)
+    }
+
+    fn for_each_spec(&self, f: &mut dyn FnMut(&CommandSpec)) {
+        (**self).for_each_spec(f);
+    }
+
+    async fn call(&self, frame: &Frame<'_>, data: &mut Data) -> Option<Result<(), Status>> {
+        (**self).call(frame, data).await
+    }
+}
+
+/// A table that may be left out: `None` answers nothing and lists nothing.
+impl<D: Dispatch> Dispatch for Option<D> {
+    fn spec(&self, cmd: u8) -> Option<CommandSpec> {
+        self.as_ref()?.spec(cmd)
+    }
+
+    fn for_each_spec(&self, f: &mut dyn FnMut(&CommandSpec)) {
+        if let Some(entries) = self {
+            entries.for_each_spec(f);
+        }
+    }
+
+    async fn call(&self, frame: &Frame<'_>, data: &mut Data) -> Option<Result<(), Status>> {
+        self.as_ref()?.call(frame, data).await
+    }
+}
+
+/// Two tables, looked up in order; built by [`Registry::merge`].
+impl<A: Dispatch, B: Dispatch> Dispatch for (A, B) {
+    fn spec(&self, cmd: u8) -> Option<CommandSpec> {
+        self.0.spec(cmd).or_else(|| self.1.spec(cmd))
+    }
+
+    fn for_each_spec(&self, f: &mut dyn FnMut(&CommandSpec)) {
+        self.0.for_each_spec(f);
+        self.1.for_each_spec(f);
+    }
+
+    async fn call(&self, frame: &Frame<'_>, data: &mut Data) -> Option<Result<(), Status>> {
+        match self.0.call(frame, data).await {
+            Some(result) => Some(result),
+            None => self.1.call(frame, data).await,
+        }
+    }
+}
+
+impl<D: Dispatch> Dispatch for Registry<D> {
+    fn spec(&self, cmd: u8) -> Option<CommandSpec> {
+        self.entries.spec(cmd)
+    }
+
+    fn for_each_spec(&self, f: &mut dyn FnMut(&CommandSpec)) {
+        self.entries.for_each_spec(f);
+    }
+
+    async fn call(&self, frame: &Frame<'_>, data: &mut Data) -> Option<Result<(), Status>> {
+        self.entries.call(frame, data).await
+    }
+}
+
+/// Maps CMD codes to handlers.
+pub struct Registry<D> {
+    entries: D,
+}
+
+impl Registry<Nil> {
+    pub const fn new() -> Self {
+        Self { entries: Nil }
+    }
+}
+
+impl Default for Registry<Nil> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Dispatch> Registry<D> {
+    pub const fn from_entries(entries: D) -> Self {
+        Self { entries }
+    }
+
+    /// Add a handler.
+    ///
+    /// # Panics
+    ///
+    /// If a handler for the same CMD code is already registered: lookups
+    /// start at the newest entry, so the later one would silently win.
+    pub fn register<H: CommandHandler>(self, handler: H) -> Registry<Cons<H, D>> {
+        assert!(
+            self.entries.spec(H::SPEC.cmd).is_none(),
+            "CMD code registered twice"
+        );
+        Registry {
+            entries: Cons {
+                head: handler,
+                tail: self.entries,
+            },
+        }
+    }
+
+    /// Add every handler in `other`, e.g. `Some(&TABLE)` or `None` for a
+    /// table this registry should not offer.
+    ///
+    /// # Panics
+    ///
+    /// If `other` has a CMD code this registry already has.
+    pub fn merge<E: Dispatch>(self, other: E) -> Registry<(D, E)> {
+        other.for_each_spec(&mut |spec| {
+            assert!(
+                self.entries.spec(spec.cmd).is_none(),
+                "CMD code registered twice"
+            );
+        });
+        Registry {
+            entries: (self.entries, other),
+        }
+    }
+
+    pub fn spec(&self, cmd: u8) -> Option<CommandSpec> {
+        self.entries.spec(cmd)
+    }
+
+    /// Largest payload any registered command accepts. A reassembled message
+    /// longer than this could only be answered BAD_LEN.
+    pub fn max_payload(&self) -> usize {
+        let mut max = 0;
+        self.for_each_spec(|spec| max = max.max(spec.max_payload));
+        max
+    }
+
+    /// Visit every registered command.
+    pub fn for_each_spec(&self, mut f: impl FnMut(&CommandSpec)) {
+        self.entries.for_each_spec(&mut f);
+    }
+
+    /// Validate and run `frame`, returning the response frame to send back.
+    pub async fn dispatch(&self, frame: &Frame<'_>) -> Result<Vec<u8, MAX_FRAME>, BuildError> {
+        let reply =
+            |response: Response<'_>| build_response(frame.seq, frame.addr, frame.cmd, &response);
+
+        let Some(spec) = self.entries.spec(frame.cmd) else {
+            return reply(Response::Err(Status::BadCmd));
+        };
+        if !spec.accepts_len(frame.payload.len()) {
+            return reply(Response::Err(Status::BadLen));
+        }
+
+        let mut data = Data::new();
+        match self.entries.call(frame, &mut data).await {
+            Some(Ok(())) => match spec.kind {
+                CommandKind::Setter => reply(Response::Ack),
+                CommandKind::Getter => reply(Response::Data(&data)),
+            },
+            Some(Err(status)) => reply(Response::Err(status)),
+            None => reply(Response::Err(Status::BadCmd)),
+        }
+    }
+}
+
+/// Fails to compile if `cmds` holds a CMD code twice; used by
+/// [`command_registry!`](crate::command_registry).
+#[doc(hidden)]
+pub const fn assert_unique_cmds(cmds: &[u8]) {
+    let mut i = 0;
+    while i < cmds.len() {
+        let mut j = i + 1;
+        while j < cmds.len() {
+            assert!(cmds[i] != cmds[j], "CMD code registered twice");
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Declare a `static` [`Registry`] from a list of unit-struct handlers.
+/// Two handlers with the same CMD code are a compile error.
+///
+/// ```ignore
+/// protocol::command_registry! {
+///     pub static REGISTRY = [Ping, Chase, GetDeviceId];
+/// }
+/// ```
+#[macro_export]
+macro_rules! command_registry {
+    ($vis:vis static $name:ident = [$($handler:ident),* $(,)?];) => {
+        const _: () = $crate::dispatch::assert_unique_cmds(&[
+            $(<$handler as $crate::dispatch::CommandHandler>::SPEC.cmd),*
+        ]);
+        $vis static $name: $crate::dispatch::Registry<$crate::command_registry!(@type $($handler),*)> =
+            $crate::dispatch::Registry::from_entries($crate::command_registry!(@value $($handler),*));
+    };
+    (@type) => { $crate::dispatch::Nil };
+    (@type $head:ident $(, $tail:ident)*) => {
+        $crate::dispatch::Cons<$head, $crate::command_registry!(@type $($tail),*)>
+    };
+    (@value) => { $crate::dispatch::Nil };
+    (@value $head:ident $(, $tail:ident)*) => {
+        $crate::dispatch::Cons { head: $head, tail: $crate::command_registry!(@value $($tail),*) }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::{Parser, test_frame};
+
+    struct Ping;
+
+    impl CommandHandler for Ping {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: 0x01,
+            name: "PING",
+            kind: CommandKind::Setter,
+            min_payload: 0,
+            max_payload: 2,
+        };
+
+        async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+            Ok(())
+        }
+    }
+
+    struct Echo;
+
+    impl CommandHandler for Echo {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: 0x20,
+            name: "ECHO",
+            kind: CommandKind::Getter,
+            min_payload: 1,
+            max_payload: 4,
+        };
+
+        async fn handle(&self, frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+            if frame.payload[0] == 0xFF {
+                return Err(Status::BadArg);
+            }
+            data.extend_from_slice(frame.payload)
+                .map_err(|_| Status::Internal)
+        }
+    }
+
+    command_registry! {
+        static REGISTRY = [Ping, Echo];
+    }
+
+    /// Dispatch `frame` and decode the response frame back into (cmd, payload).
+    fn respond(registry: &Registry<impl Dispatch>, frame: Frame<'_>) -> (u8, std::vec::Vec<u8>) {
+        let resp = block_on(registry.dispatch(&frame)).unwrap();
+        let mut parser = Parser::new();
+        parser.push_bytes(&resp);
+        let decoded = parser.next_frame().unwrap().unwrap();
+        assert_eq!(decoded.addr, frame.addr);
+        (decoded.cmd, decoded.payload.to_vec())
+    }
+
+    #[test]
+    fn setter_is_acked() {
+        assert_eq!(
+            respond(&REGISTRY, test_frame(None, 0x01, &[])),
+            (0x01, vec![0x00])
+        );
+    }
+
+    #[test]
+    fn getter_returns_data() {
+        assert_eq!(
+            respond(&REGISTRY, test_frame(None, 0x20, &[7, 8])),
+            (0x20, vec![0x00, 2, 7, 8])
+        );
+    }
+
+    #[test]
+    fn sequenced_requests_get_sequenced_responses() {
+        let request = test_frame(Some(9), 0x20, &[1]);
+        let resp = block_on(REGISTRY.dispatch(&request)).unwrap();
+        let mut parser = Parser::new();
+        parser.push_bytes(&resp);
+        let decoded = parser.next_frame().unwrap().unwrap();
+        assert_eq!(decoded.seq, Some(9));
+        assert_eq!(decoded.payload, &[0x00, 1, 1]);
+    }
+
+    #[test]
+    fn unknown_cmd_is_bad_cmd() {
+        assert_eq!(
+            respond(&REGISTRY, test_frame(None, 0x7E, &[])),
+            (0x7E, vec![Status::BadCmd.as_u8()])
+        );
+    }
+
+    #[test]
+    fn payload_limits_are_checked_before_the_handler() {
+        assert_eq!(
+            respond(&REGISTRY, test_frame(None, 0x01, &[0; 3])),
+            (0x01, vec![Status::BadLen.as_u8()])
+        );
+        assert_eq!(
+            respond(&REGISTRY, test_frame(None, 0x20, &[])),
+            (0x20, vec![Status::BadLen.as_u8()])
+        );
+        assert_eq!(
+            respond(&REGISTRY, test_frame(None, 0x20, &[0; 5])),
+            (0x20, vec![Status::BadLen.as_u8()])
+        );
+    }
+
+    #[test]
+    fn handler_errors_become_error_frames() {
+        assert_eq!(
+            respond(&REGISTRY, test_frame(None, 0x20, &[0xFF])),
+            (0x20, vec![Status::BadArg.as_u8()])
+        );
+    }
+
+    #[test]
+    fn register_builds_the_same_table() {
+        struct Counter(Cell<u8>);
+
+        impl CommandHandler for Counter {
+            const SPEC: CommandSpec = CommandSpec {
+                cmd: 0x30,
+                name: "COUNT",
+                kind: CommandKind::Setter,
+                min_payload: 0,
+                max_payload: 0,
+            };
+
+            async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+                self.0.set(self.0.get() + 1);
+                Ok(())
+            }
+        }
+
+        let registry = Registry::new()
+            .register(Ping)
+            .register(Counter(Cell::new(0)));
+        assert_eq!(
+            respond(&registry, test_frame(None, 0x30, &[])),
+            (0x30, vec![0x00])
+        );
+        assert_eq!(
+            respond(&registry, test_frame(None, 0x01, &[])),
+            (0x01, vec![0x00])
+        );
+        assert_eq!(registry.entries.head.0.get(), 1);
+
+        let mut names = std::vec::Vec::new();
+        registry.for_each_spec(|spec| names.push(spec.name));
+        assert_eq!(names, ["COUNT", "PING"]);
+        assert_eq!(registry.max_payload(), 2);
+        assert_eq!(REGISTRY.max_payload(), 4);
+    }
+
+    #[test]
+    fn handlers_can_be_layered_over_a_static_registry() {
+        struct Scaled(u8);
+
+        impl CommandHandler for Scaled {
+            const SPEC: CommandSpec = CommandSpec {
+                cmd: 0x31,
+                name: "SCALED",
+                kind: CommandKind::Getter,
+                min_payload: 1,
+                max_payload: 1,
+            };
+
+            async fn handle(&self, frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+                data.push(frame.payload[0] * self.0)
+                    .map_err(|_| Status::Internal)
+            }
+        }
+
+        let layered = Registry::from_entries(&REGISTRY).register(Scaled(3));
+        assert_eq!(
+            respond(&layered, test_frame(None, 0x31, &[5])),
+            (0x31, vec![0x00, 1, 15])
+        );
+        assert_eq!(
+            respond(&layered, test_frame(None, 0x20, &[7])),
+            (0x20, vec![0x00, 1, 7])
+        );
+        let mut names = std::vec::Vec::new();
+        layered.for_each_spec(|spec| names.push(spec.name));
+        assert_eq!(names, ["SCALED", "PING", "ECHO"]);
+    }
+
+    #[test]
+    fn a_table_merged_as_none_answers_bad_cmd_and_is_not_listed() {
+        struct Subscribe;
+
+        impl CommandHandler for Subscribe {
+            const SPEC: CommandSpec = crate::schema::SUBSCRIBE;
+
+            async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+                Ok(())
+            }
+        }
+
+        command_registry! {
+            static EVENT_COMMANDS = [Subscribe];
+        }
+
+        // As the firmware builds it for a link that may or may not talk unasked
+        let link = |unsolicited: bool| {
+            Registry::from_entries(&REGISTRY).merge(unsolicited.then_some(&EVENT_COMMANDS))
+        };
+        let subscribe = || test_frame(None, crate::schema::CMD_SUBSCRIBE, &[0x01, 0x00]);
+        let listed = |registry: &Registry<_>| {
+            let mut names = std::vec::Vec::new();
+            registry.for_each_spec(|spec| names.push(spec.name));
+            names
+        };
+
+        let usb = link(true);
+        assert_eq!(respond(&usb, subscribe()), (0x05, vec![0x00]));
+        assert_eq!(listed(&usb), ["PING", "ECHO", "SUBSCRIBE"]);
+
+        let rs485 = link(false);
+        assert_eq!(
+            respond(&rs485, subscribe()),
+            (0x05, vec![Status::BadCmd.as_u8()])
+        );
+        assert_eq!(listed(&rs485), ["PING", "ECHO"]);
+        assert_eq!(
+            respond(&rs485, test_frame(None, 0x01, &[])),
+            (0x01, vec![0x00])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "CMD code registered twice")]
+    fn merging_a_cmd_code_twice_panics() {
+        command_registry! {
+            static AGAIN = [Echo];
+        }
+        let _ = Registry::from_entries(&REGISTRY).merge(Some(&AGAIN));
+    }
+
+    #[test]
+    #[should_panic(expected = "CMD code registered twice")]
+    fn layering_a_cmd_code_the_static_registry_has_panics() {
+        let _ = Registry::from_entries(&REGISTRY).register(Ping);
+    }
+
+    #[test]
+    #[should_panic(expected = "CMD code registered twice")]
+    fn registering_a_cmd_code_twice_panics() {
+        let _ = Registry::new().register(Ping).register(Ping);
+    }
+
+    #[test]
+    fn unique_cmd_codes_pass_the_registry_check() {
+        assert_unique_cmds(&[0x01, 0x20, 0x30]);
+        assert!(std::panic::catch_unwind(|| assert_unique_cmds(&[0x01, 0x20, 0x01])).is_err());
+    }
+}
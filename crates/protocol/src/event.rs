@@ -0,0 +1,242 @@
+//! Unsolicited event frames (device → host).
+//!
+//! Events are normal frames with ADDR = the sending node, no SEQ and a CMD in
+//! the reserved range [`EVENT_FIRST`]`..=`[`EVENT_LAST`]; CMD is
+//! `EVENT_FIRST + class`. The host never answers them. A device only sends
+//! the classes a host subscribed to with SUBSCRIBE / UNSUBSCRIBE (payload:
+//! class bitmask, u16 LE); after a reset nothing is subscribed.
+//!
+//! | CMD    | Class            | Payload                                |
+//! |--------|------------------|----------------------------------------|
+//! | `0x80` | `BUTTON`         | `[INDEX, PRESSED (0/1)]`               |
+//! | `0x81` | `THRESHOLD`      | `[CHANNEL, ABOVE (0/1), VALUE (i16 LE)]` |
+//! | `0x82` | `FAULT`          | `[CODE]`                               |
+//! | `0x83` | `CHASE_FINISHED` | `[]`                                   |
+
+use heapless::Vec;
+
+use crate::{BuildError, Response, build_response};
+
+/// First CMD code reserved for events.
+pub const EVENT_FIRST: u8 = 0x80;
+
+/// Last CMD code reserved for events.
+pub const EVENT_LAST: u8 = 0xBF;
+
+/// Setter: add the classes in the bitmask to the subscription.
+pub const CMD_SUBSCRIBE: u8 = crate::schema::CMD_SUBSCRIBE;
+
+/// Setter: remove the classes in the bitmask from the subscription.
+pub const CMD_UNSUBSCRIBE: u8 = crate::schema::CMD_UNSUBSCRIBE;
+
+/// Largest event payload.
+pub const MAX_EVENT_PAYLOAD: usize = 4;
+
+/// Whether `cmd` is in the event range.
+pub const fn is_event_cmd(cmd: u8) -> bool {
+    cmd >= EVENT_FIRST && cmd <= EVENT_LAST
+}
+
+/// Kinds of event a host can subscribe to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum EventClass {
+    Button = 0,
+    Threshold = 1,
+    Fault = 2,
+    ChaseFinished = 3,
+}
+
+impl EventClass {
+    pub const ALL: [EventClass; 4] = [
+        EventClass::Button,
+        EventClass::Threshold,
+        EventClass::Fault,
+        EventClass::ChaseFinished,
+    ];
+
+    pub const fn cmd(self) -> u8 {
+        EVENT_FIRST + self as u8
+    }
+
+    pub const fn from_cmd(cmd: u8) -> Option<Self> {
+        match cmd.wrapping_sub(EVENT_FIRST) {
+            0 => Some(EventClass::Button),
+            1 => Some(EventClass::Threshold),
+            2 => Some(EventClass::Fault),
+            3 => Some(EventClass::ChaseFinished),
+            _ => None,
+        }
+    }
+
+    /// Bit for this class in a subscription mask.
+    pub const fn bit(self) -> u16 {
+        1 << self as u8
+    }
+}
+
+/// A typed event.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// A button changed state.
+    Button { index: u8, pressed: bool },
+    /// A monitored value crossed its threshold (`above` is the new side).
+    Threshold {
+        channel: u8,
+        above: bool,
+        value: i16,
+    },
+    /// A fault was raised; codes are device-specific.
+    Fault { code: u8 },
+    /// A chase run finished.
+    ChaseFinished,
+}
+
+impl Event {
+    pub const fn class(&self) -> EventClass {
+        match self {
+            Event::Button { .. } => EventClass::Button,
+            Event::Threshold { .. } => EventClass::Threshold,
+            Event::Fault { .. } => EventClass::Fault,
+            Event::ChaseFinished => EventClass::ChaseFinished,
+        }
+    }
+
+    pub fn payload(&self) -> Vec<u8, MAX_EVENT_PAYLOAD> {
+        let mut out = Vec::new();
+        // Every payload fits MAX_EVENT_PAYLOAD.
+        let _ = match *self {
+            Event::Button { index, pressed } => out.extend_from_slice(&[index, pressed as u8]),
+            Event::Threshold {
+                channel,
+                above,
+                value,
+            } => {
+                let [lo, hi] = value.to_le_bytes();
+                out.extend_from_slice(&[channel, above as u8, lo, hi])
+            }
+            Event::Fault { code } => out.extend_from_slice(&[code]),
+            Event::ChaseFinished => Ok(()),
+        };
+        out
+    }
+
+    /// Parse an event frame's CMD and payload. Unknown classes give `None`.
+    pub fn decode(cmd: u8, payload: &[u8]) -> Option<Self> {
+        let flag = |b: u8| match b {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        };
+        Some(match (EventClass::from_cmd(cmd)?, payload) {
+            (EventClass::Button, &[index, pressed]) => Event::Button {
+                index,
+                pressed: flag(pressed)?,
+            },
+            (EventClass::Threshold, &[channel, above, lo, hi]) => Event::Threshold {
+                channel,
+                above: flag(above)?,
+                value: i16::from_le_bytes([lo, hi]),
+            },
+            (EventClass::Fault, &[code]) => Event::Fault { code },
+            (EventClass::ChaseFinished, &[]) => Event::ChaseFinished,
+            _ => return None,
+        })
+    }
+}
+
+/// Event frame from node `addr`.
+pub fn build_event<const OUT_CAP: usize>(
+    addr: u8,
+    event: &Event,
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    build_response(None, addr, event.class().cmd(), &Response::Event(*event))
+}
+
+/// The set of classes a host wants to receive.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Subscriptions(pub u16);
+
+impl Subscriptions {
+    pub const NONE: Subscriptions = Subscriptions(0);
+
+    /// Every class this crate knows.
+    pub const KNOWN: Subscriptions = Subscriptions(
+        EventClass::Button.bit()
+            | EventClass::Threshold.bit()
+            | EventClass::Fault.bit()
+            | EventClass::ChaseFinished.bit(),
+    );
+
+    pub fn of(classes: &[EventClass]) -> Self {
+        Subscriptions(classes.iter().fold(0, |mask, class| mask | class.bit()))
+    }
+
+    pub const fn contains(self, class: EventClass) -> bool {
+        self.0 & class.bit() != 0
+    }
+
+    pub const fn subscribe(self, other: Subscriptions) -> Self {
+        Subscriptions(self.0 | other.0)
+    }
+
+    pub const fn unsubscribe(self, other: Subscriptions) -> Self {
+        Subscriptions(self.0 & !other.0)
+    }
+
+    /// Payload for SUBSCRIBE / UNSUBSCRIBE.
+    pub const fn to_le_bytes(self) -> [u8; 2] {
+        self.0.to_le_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    #[test]
+    fn events_round_trip_through_frames() {
+        let events = [
+            Event::Button {
+                index: 0,
+                pressed: true,
+            },
+            Event::Threshold {
+                channel: 0,
+                above: false,
+                value: -125,
+            },
+            Event::Fault { code: 0x02 },
+            Event::ChaseFinished,
+        ];
+        let mut parser = Parser::new();
+        for event in &events {
+            let frame = build_event::<32>(0x07, event).unwrap();
+            parser.push_bytes(&frame);
+            let frame = parser.next_frame().unwrap().unwrap();
+            assert_eq!(frame.addr, 0x07);
+            assert!(is_event_cmd(frame.cmd));
+            assert_eq!(Event::decode(frame.cmd, frame.payload), Some(*event));
+        }
+        assert_eq!(EventClass::Threshold.cmd(), 0x81);
+    }
+
+    #[test]
+    fn decode_rejects_unknown_classes_and_bad_payloads() {
+        assert_eq!(Event::decode(0x84, &[]), None);
+        assert_eq!(Event::decode(0x01, &[]), None);
+        assert_eq!(Event::decode(0x80, &[0, 2]), None);
+        assert_eq!(Event::decode(0x83, &[0]), None);
+    }
+
+    #[test]
+    fn subscriptions_add_and_remove_classes() {
+        let subs = Subscriptions::NONE
+            .subscribe(Subscriptions::of(&[EventClass::Fault, EventClass::Button]))
+            .unsubscribe(Subscriptions::of(&[EventClass::Button]));
+        assert!(subs.contains(EventClass::Fault));
+        assert!(!subs.contains(EventClass::Button));
+        assert_eq!(Subscriptions::of(&EventClass::ALL), Subscriptions::KNOWN);
+    }
+}
@@ -0,0 +1,688 @@
+//! Fragmentation for messages larger than `MAX_PAYLOAD`.
+//!
+//! A large message is sent as a run of `CMD_FRAGMENT` frames whose payload is
+//!   [ INNER_CMD, INDEX (u16 LE), TOTAL_LEN (u32 LE), <DATA...>, [CRC32 (u32 LE)] ]
+//!
+//! - INDEX counts from 0 and fragments must arrive in order. Index 0 always
+//!   starts a new transfer, replacing any transfer in progress.
+//! - DATA fills the message front to back. Once TOTAL_LEN bytes have been
+//!   sent, the CRC-32 of the whole message follows: in the same fragment if
+//!   it fits, otherwise in one extra fragment with no data.
+//! - A `CMD_FRAGMENT_ABORT` frame (empty payload) drops the transfer in
+//!   progress. So does any error, and so does a gap between fragments longer
+//!   than the receiver's timeout.
+//!
+//! The device ACKs every fragment except the last one, which gives
+//! stop-and-wait flow control. The completed message is dispatched as if it
+//! had arrived in one frame with CMD = INNER_CMD, and that response answers
+//! the last fragment. A TOTAL_LEN that INNER_CMD's handler would not accept
+//! is refused on the first fragment, before anything is buffered.
+//!
+//! [`Fragmenter`] and [`FragmentReceiver`] stream: neither needs the whole
+//! message in memory, so they also work for flash images and log dumps.
+//! [`Reassembler`] adds a bounded buffer and does the device-side dispatch.
+
+use core::fmt;
+
+use heapless::Vec;
+
+use crate::dispatch::{Dispatch, Registry};
+use crate::{BuildError, Crc32, Frame, MAX_FRAME, MAX_PAYLOAD, Status, build_frame_seq};
+
+/// One fragment of a large message.
+pub const CMD_FRAGMENT: u8 = crate::schema::CMD_FRAGMENT;
+
+/// Drop the transfer in progress (empty payload).
+pub const CMD_FRAGMENT_ABORT: u8 = crate::schema::CMD_FRAGMENT_ABORT;
+
+/// INNER_CMD + INDEX + TOTAL_LEN.
+pub const FRAGMENT_HEADER: usize = 7;
+
+/// Largest DATA section of one fragment.
+pub const FRAGMENT_DATA: usize = MAX_PAYLOAD - FRAGMENT_HEADER;
+
+const CRC_LEN: usize = 4;
+
+/// Largest message that fits in `u16::MAX + 1` fragments.
+pub const MAX_MESSAGE: u32 = (u16::MAX as u32) * FRAGMENT_DATA as u32;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FragmentError {
+    /// Payload shorter than the fragment header.
+    BadHeader,
+    /// Message longer than the receiver's buffer (or than `MAX_MESSAGE`).
+    TooLarge,
+    /// INDEX, INNER_CMD or TOTAL_LEN does not continue the current transfer.
+    OutOfOrder { expected: u16, got: u16 },
+    /// The transfer in progress had been idle for longer than the timeout.
+    TimedOut,
+    /// DATA and CRC do not add up to TOTAL_LEN.
+    LengthMismatch,
+    /// The reassembled message failed its CRC-32.
+    CrcMismatch,
+}
+
+impl FragmentError {
+    /// Status sent back for a rejected fragment.
+    pub const fn status(&self) -> Status {
+        match self {
+            FragmentError::BadHeader | FragmentError::TooLarge | FragmentError::LengthMismatch => {
+                Status::BadLen
+            }
+            FragmentError::OutOfOrder { .. }
+            | FragmentError::TimedOut
+            | FragmentError::CrcMismatch => Status::BadArg,
+        }
+    }
+}
+
+impl fmt::Display for FragmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FragmentError::BadHeader => f.write_str("fragment shorter than its header"),
+            FragmentError::TooLarge => f.write_str("message too large"),
+            FragmentError::OutOfOrder { expected, got } => {
+                write!(f, "expected fragment {expected}, got {got}")
+            }
+            FragmentError::TimedOut => f.write_str("transfer timed out"),
+            FragmentError::LengthMismatch => f.write_str("fragment length does not match header"),
+            FragmentError::CrcMismatch => f.write_str("message CRC-32 mismatch"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FragmentError {}
+
+/// Decoded fragment header.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FragmentHeader {
+    pub cmd: u8,
+    pub index: u16,
+    pub total_len: u32,
+}
+
+impl FragmentHeader {
+    /// Split a fragment payload into its header and the bytes after it.
+    pub fn parse(payload: &[u8]) -> Result<(Self, &[u8]), FragmentError> {
+        let Some((head, rest)) = payload.split_first_chunk::<FRAGMENT_HEADER>() else {
+            return Err(FragmentError::BadHeader);
+        };
+        let header = Self {
+            cmd: head[0],
+            index: u16::from_le_bytes([head[1], head[2]]),
+            total_len: u32::from_le_bytes([head[3], head[4], head[5], head[6]]),
+        };
+        Ok((header, rest))
+    }
+}
+
+/// Splits a message into fragment payloads, one call per fragment.
+///
+/// ```ignore
+/// let mut tx = Fragmenter::new(cmd, total_len)?;
+/// while let Some(n) = tx.next_len() {
+///     let chunk = source.read(n); // exactly n bytes
+///     send(CMD_FRAGMENT, &tx.next_fragment(chunk)?);
+/// }
+/// ```
+pub struct Fragmenter {
+    cmd: u8,
+    total: u32,
+    sent: u32,
+    index: u16,
+    crc: Crc32,
+    done: bool,
+    /// Largest fragment payload, header included.
+    max_payload: usize,
+}
+
+impl Fragmenter {
+    pub fn new(cmd: u8, total_len: u32) -> Result<Self, FragmentError> {
+        if total_len > MAX_MESSAGE {
+            return Err(FragmentError::TooLarge);
+        }
+        Ok(Self {
+            cmd,
+            total: total_len,
+            sent: 0,
+            index: 0,
+            crc: Crc32::new(),
+            done: false,
+            max_payload: MAX_PAYLOAD,
+        })
+    }
+
+    /// Keep fragment payloads within `max_payload` bytes, leaving room for a
+    /// signature (see [`crate::auth`]). Fails if the fragments would not hold
+    /// at least one byte of data or would run out of INDEX values.
+    pub fn with_max_payload(mut self, max_payload: usize) -> Result<Self, FragmentError> {
+        let max_payload = max_payload.min(MAX_PAYLOAD);
+        let data = max_payload.saturating_sub(FRAGMENT_HEADER) as u32;
+        if data == 0 || self.total > u16::MAX as u32 * data {
+            return Err(FragmentError::TooLarge);
+        }
+        self.max_payload = max_payload;
+        Ok(self)
+    }
+
+    /// DATA bytes the next fragment must carry, or `None` once the CRC has been sent.
+    pub fn next_len(&self) -> Option<usize> {
+        let remaining = (self.total - self.sent) as usize;
+        (!self.done).then_some(remaining.min(self.max_payload - FRAGMENT_HEADER))
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Build the next fragment payload around `data` (exactly `next_len()` bytes).
+    pub fn next_fragment(&mut self, data: &[u8]) -> Result<Vec<u8, MAX_PAYLOAD>, FragmentError> {
+        if self.next_len() != Some(data.len()) {
+            return Err(FragmentError::LengthMismatch);
+        }
+
+        let mut out = Vec::new();
+        // Header + DATA is at most MAX_PAYLOAD, and the CRC is only added
+        // below when it fits, so none of these pushes can fail.
+        let _ = out.push(self.cmd);
+        let _ = out.extend_from_slice(&self.index.to_le_bytes());
+        let _ = out.extend_from_slice(&self.total.to_le_bytes());
+        let _ = out.extend_from_slice(data);
+
+        self.crc.update(data);
+        self.sent += data.len() as u32;
+        self.index = self.index.wrapping_add(1);
+
+        if self.sent == self.total && out.len() + CRC_LEN <= self.max_payload {
+            let _ = out.extend_from_slice(&self.crc.finish().to_le_bytes());
+            self.done = true;
+        }
+        Ok(out)
+    }
+}
+
+/// A validated piece of an incoming message.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Chunk<'a> {
+    pub cmd: u8,
+    /// Position of `data` in the message.
+    pub offset: u32,
+    pub data: &'a [u8],
+    /// The whole message has arrived and its CRC-32 matched.
+    pub complete: bool,
+}
+
+struct Transfer {
+    cmd: u8,
+    total: u32,
+    received: u32,
+    next_index: u16,
+    crc: Crc32,
+    last_us: u64,
+}
+
+/// Validates incoming fragments in order without buffering them.
+///
+/// Timestamps are in microseconds from any monotonic clock.
+pub struct FragmentReceiver {
+    timeout_us: u64,
+    transfer: Option<Transfer>,
+}
+
+impl FragmentReceiver {
+    pub const fn new(timeout_us: u64) -> Self {
+        Self {
+            timeout_us,
+            transfer: None,
+        }
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    pub fn abort(&mut self) {
+        self.transfer = None;
+    }
+
+    /// Drop the transfer in progress if it has been idle too long. Returns whether it was dropped.
+    pub fn expire(&mut self, now_us: u64) -> bool {
+        let idle = match &self.transfer {
+            Some(t) => now_us.saturating_sub(t.last_us) > self.timeout_us,
+            None => false,
+        };
+        if idle {
+            self.transfer = None;
+        }
+        idle
+    }
+
+    /// Check one fragment payload and return the message bytes it carries.
+    ///
+    /// Any error drops the transfer in progress; the sender has to start over.
+    pub fn accept<'p>(
+        &mut self,
+        payload: &'p [u8],
+        now_us: u64,
+    ) -> Result<Chunk<'p>, FragmentError> {
+        let result = self.accept_inner(payload, now_us);
+        if result.is_err() {
+            self.transfer = None;
+        }
+        result
+    }
+
+    fn accept_inner<'p>(
+        &mut self,
+        payload: &'p [u8],
+        now_us: u64,
+    ) -> Result<Chunk<'p>, FragmentError> {
+        let (header, rest) = FragmentHeader::parse(payload)?;
+        let expired = self.expire(now_us);
+
+        if header.index == 0 {
+            self.transfer = Some(Transfer {
+                cmd: header.cmd,
+                total: header.total_len,
+                received: 0,
+                next_index: 0,
+                crc: Crc32::new(),
+                last_us: now_us,
+            });
+        } else if expired {
+            return Err(FragmentError::TimedOut);
+        }
+
+        let Some(t) = self.transfer.as_mut() else {
+            return Err(FragmentError::OutOfOrder {
+                expected: 0,
+                got: header.index,
+            });
+        };
+        if header.index != t.next_index || header.cmd != t.cmd || header.total_len != t.total {
+            return Err(FragmentError::OutOfOrder {
+                expected: t.next_index,
+                got: header.index,
+            });
+        }
+
+        let remaining = (t.total - t.received) as usize;
+        let (data, crc) = rest.split_at(rest.len().min(remaining));
+        let complete = match crc.len() {
+            0 => false,
+            CRC_LEN if data.len() == remaining => true,
+            _ => return Err(FragmentError::LengthMismatch),
+        };
+        if data.is_empty() && !complete {
+            // Only the closing CRC fragment may be empty.
+            return Err(FragmentError::LengthMismatch);
+        }
+        if t.next_index == u16::MAX && !complete {
+            return Err(FragmentError::TooLarge);
+        }
+
+        let offset = t.received;
+        t.crc.update(data);
+        t.received += data.len() as u32;
+        t.next_index = t.next_index.wrapping_add(1);
+        t.last_us = now_us;
+
+        if complete {
+            let expected = u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]);
+            if t.crc.finish() != expected {
+                return Err(FragmentError::CrcMismatch);
+            }
+            self.transfer = None;
+        }
+
+        Ok(Chunk {
+            cmd: header.cmd,
+            offset,
+            data,
+            complete,
+        })
+    }
+}
+
+/// Device-side reassembly into a fixed buffer of `N` bytes.
+pub struct Reassembler<const N: usize> {
+    receiver: FragmentReceiver,
+    buf: Vec<u8, N>,
+}
+
+impl<const N: usize> Reassembler<N> {
+    pub const fn new(timeout_us: u64) -> Self {
+        Self {
+            receiver: FragmentReceiver::new(timeout_us),
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.receiver.in_progress()
+    }
+
+    pub fn abort(&mut self) {
+        self.receiver.abort();
+        self.buf.clear();
+    }
+
+    /// Drop an idle transfer so its buffer is not held forever.
+    pub fn expire(&mut self, now_us: u64) -> bool {
+        let expired = self.receiver.expire(now_us);
+        if expired {
+            self.buf.clear();
+        }
+        expired
+    }
+
+    /// Feed one fragment payload. Returns the message and its CMD once complete.
+    pub fn push(
+        &mut self,
+        payload: &[u8],
+        now_us: u64,
+    ) -> Result<Option<(u8, &[u8])>, FragmentError> {
+        let (header, _) = FragmentHeader::parse(payload)?;
+        if header.total_len as usize > N {
+            self.abort();
+            return Err(FragmentError::TooLarge);
+        }
+
+        let chunk = match self.receiver.accept(payload, now_us) {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                self.buf.clear();
+                return Err(err);
+            }
+        };
+        if chunk.offset == 0 {
+            self.buf.clear();
+        }
+        // TOTAL_LEN <= N was checked above, so the message always fits.
+        let _ = self.buf.extend_from_slice(chunk.data);
+
+        Ok(chunk.complete.then_some((chunk.cmd, &self.buf[..])))
+    }
+
+    /// Answer a `CMD_FRAGMENT` or `CMD_FRAGMENT_ABORT` frame, dispatching the
+    /// completed message through `registry`. A first fragment announcing a
+    /// message its command does not accept gets BAD_LEN.
+    pub async fn dispatch<D: Dispatch>(
+        &mut self,
+        registry: &Registry<D>,
+        frame: &Frame<'_>,
+        now_us: u64,
+    ) -> Result<Vec<u8, MAX_FRAME>, BuildError> {
+        let reply =
+            |status: Status| build_frame_seq(frame.seq, frame.addr, frame.cmd, &[status.as_u8()]);
+
+        match frame.cmd {
+            CMD_FRAGMENT_ABORT => {
+                self.abort();
+                reply(Status::Ok)
+            }
+            CMD_FRAGMENT if refused_up_front(registry, frame.payload) => {
+                self.abort();
+                reply(Status::BadLen)
+            }
+            CMD_FRAGMENT => match self.push(frame.payload, now_us) {
+                Ok(Some((cmd, message))) => {
+                    let message = Frame {
+                        seq: frame.seq,
+                        addr: frame.addr,
+                        cmd,
+                        payload: message,
+                    };
+                    registry.dispatch(&message).await
+                }
+                Ok(None) => reply(Status::Ok),
+                Err(err) => reply(err.status()),
+            },
+            _ => reply(Status::BadCmd),
+        }
+    }
+}
+
+/// A first fragment announcing a message its command would answer BAD_LEN.
+fn refused_up_front<D: Dispatch>(registry: &Registry<D>, payload: &[u8]) -> bool {
+    let Ok((header, _)) = FragmentHeader::parse(payload) else {
+        return false;
+    };
+    header.index == 0
+        && registry
+            .spec(header.cmd)
+            .is_some_and(|spec| !spec.accepts_len(header.total_len as usize))
+}
+
+/// Whether `cmd` belongs to the fragmentation layer rather than a handler.
+pub const fn is_fragment_cmd(cmd: u8) -> bool {
+    matches!(cmd, CMD_FRAGMENT | CMD_FRAGMENT_ABORT)
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::auth::TRAILER_LEN;
+    use crate::capabilities::{Capabilities, Features};
+    use crate::dispatch::{CommandHandler, CommandKind, CommandSpec, Data};
+    use crate::{Framing, Parser, command_registry, crc32, test_frame};
+
+    fn message(len: usize) -> std::vec::Vec<u8> {
+        (0..len).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    fn fragments(cmd: u8, msg: &[u8]) -> std::vec::Vec<Vec<u8, MAX_PAYLOAD>> {
+        fragments_of(cmd, msg, MAX_PAYLOAD)
+    }
+
+    fn fragments_of(
+        cmd: u8,
+        msg: &[u8],
+        max_payload: usize,
+    ) -> std::vec::Vec<Vec<u8, MAX_PAYLOAD>> {
+        let mut tx = Fragmenter::new(cmd, msg.len() as u32)
+            .unwrap()
+            .with_max_payload(max_payload)
+            .unwrap();
+        let mut sent = 0;
+        let mut out = std::vec::Vec::new();
+        while let Some(n) = tx.next_len() {
+            out.push(tx.next_fragment(&msg[sent..sent + n]).unwrap());
+            sent += n;
+        }
+        out
+    }
+
+    #[test]
+    fn round_trips_across_sizes() {
+        // Around the single-fragment limit and the point where the CRC spills over.
+        for len in [0, 1, 242, 243, 246, 247, 492, 1000] {
+            let msg = message(len);
+            let mut rx = Reassembler::<1024>::new(1_000);
+            let frags = fragments(0x30, &msg);
+
+            for (i, frag) in frags.iter().enumerate() {
+                let done = rx.push(frag, 0).unwrap();
+                if i + 1 == frags.len() {
+                    assert_eq!(done, Some((0x30, &msg[..])), "len {len}");
+                } else {
+                    assert_eq!(done, None, "len {len}");
+                }
+            }
+            assert!(!rx.in_progress());
+        }
+    }
+
+    #[test]
+    fn crc_rides_in_an_extra_fragment_when_it_does_not_fit() {
+        let frags = fragments(0x30, &message(FRAGMENT_DATA));
+        assert_eq!(frags.len(), 2);
+        assert_eq!(frags[1].len(), FRAGMENT_HEADER + CRC_LEN);
+        assert_eq!(
+            frags[1][FRAGMENT_HEADER..],
+            crc32(&message(FRAGMENT_DATA)).to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn streaming_receiver_reports_offsets() {
+        let msg = message(600);
+        let mut rx = FragmentReceiver::new(1_000);
+        let mut out = std::vec::Vec::new();
+
+        for frag in fragments(0x30, &msg) {
+            let chunk = rx.accept(&frag, 0).unwrap();
+            assert_eq!(chunk.offset as usize, out.len());
+            out.extend_from_slice(chunk.data);
+        }
+        assert_eq!(out, msg);
+    }
+
+    #[test]
+    fn rejects_gaps_and_corruption() {
+        let msg = message(600);
+        let frags = fragments(0x30, &msg);
+        let mut rx = Reassembler::<1024>::new(1_000);
+
+        rx.push(&frags[0], 0).unwrap();
+        assert_eq!(
+            rx.push(&frags[2], 0),
+            Err(FragmentError::OutOfOrder {
+                expected: 1,
+                got: 2
+            })
+        );
+        assert!(!rx.in_progress());
+
+        let mut bad = frags.last().unwrap().clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 0xFF;
+        for frag in &frags[..frags.len() - 1] {
+            rx.push(frag, 0).unwrap();
+        }
+        assert_eq!(rx.push(&bad, 0), Err(FragmentError::CrcMismatch));
+    }
+
+    #[test]
+    fn enforces_buffer_bound_and_timeout() {
+        let msg = message(600);
+        let frags = fragments(0x30, &msg);
+
+        let mut small = Reassembler::<512>::new(1_000);
+        assert_eq!(small.push(&frags[0], 0), Err(FragmentError::TooLarge));
+
+        let mut rx = Reassembler::<1024>::new(1_000);
+        rx.push(&frags[0], 0).unwrap();
+        assert_eq!(rx.push(&frags[1], 5_000), Err(FragmentError::TimedOut));
+
+        // Index 0 restarts even after a timeout or abort.
+        rx.push(&frags[0], 5_000).unwrap();
+        rx.abort();
+        assert!(!rx.in_progress());
+        assert_eq!(
+            rx.push(&frags[1], 5_000),
+            Err(FragmentError::OutOfOrder {
+                expected: 0,
+                got: 1
+            })
+        );
+    }
+
+    struct Length;
+
+    impl CommandHandler for Length {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: 0x30,
+            name: "LENGTH",
+            kind: CommandKind::Getter,
+            min_payload: 0,
+            max_payload: 1024,
+        };
+
+        async fn handle(&self, frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+            data.extend_from_slice(&(frame.payload.len() as u16).to_le_bytes())
+                .map_err(|_| Status::Internal)
+        }
+    }
+
+    #[test]
+    fn device_acks_fragments_and_dispatches_the_message() {
+        let registry = Registry::new().register(Length);
+        let mut rx = Reassembler::<1024>::new(1_000);
+        let mut parser = Parser::new();
+        let frags = fragments(0x30, &message(600));
+
+        for (i, frag) in frags.iter().enumerate() {
+            let frame = test_frame(None, CMD_FRAGMENT, frag);
+            let resp = block_on(rx.dispatch(&registry, &frame, 0)).unwrap();
+            parser.push_bytes(&resp);
+            let resp = parser.next_frame().unwrap().unwrap();
+
+            if i + 1 == frags.len() {
+                assert_eq!(resp.cmd, 0x30);
+                assert_eq!(resp.payload, &[0x00, 0x02, 0x58, 0x02]); // 600 LE
+            } else {
+                assert_eq!(resp.cmd, CMD_FRAGMENT);
+                assert_eq!(resp.payload, &[Status::Ok.as_u8()]);
+            }
+        }
+    }
+
+    #[test]
+    fn the_advertised_message_size_reaches_a_handler_through_the_registry() {
+        struct Ping;
+
+        impl CommandHandler for Ping {
+            const SPEC: CommandSpec = crate::schema::PING;
+
+            async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+                Ok(())
+            }
+        }
+
+        command_registry! {
+            static REGISTRY = [Ping];
+        }
+
+        // As the firmware serves a link: a static table seen through a layered
+        // registry, and a reassembly size no larger than its commands take
+        let registry = Registry::from_entries(&REGISTRY);
+        let mut rx = Reassembler::<MAX_PAYLOAD>::new(1_000);
+        let max_message = MAX_PAYLOAD.min(registry.max_payload());
+        let caps = Capabilities::of(
+            &registry,
+            Framing::StxLen,
+            Features::FRAGMENTATION,
+            max_message as u32,
+        );
+        assert_eq!(caps.max_message, MAX_PAYLOAD as u32);
+
+        let mut send = |frags: &[Vec<u8, MAX_PAYLOAD>]| {
+            let mut parser = Parser::new();
+            let mut last = None;
+            for frag in frags {
+                let frame = test_frame(None, CMD_FRAGMENT, frag);
+                parser.push_bytes(&block_on(rx.dispatch(&registry, &frame, 0)).unwrap());
+                let resp = parser.next_frame().unwrap().unwrap();
+                last = Some((resp.cmd, resp.payload.to_vec()));
+            }
+            last.unwrap()
+        };
+
+        // Signed fragments leave room for the trailer, so this takes two frames
+        let frags = fragments_of(0x01, &message(max_message), MAX_PAYLOAD - TRAILER_LEN);
+        assert!(frags.len() > 1);
+        assert_eq!(send(&frags), (0x01, vec![Status::Ok.as_u8()]));
+
+        // One byte more is refused on the first fragment
+        let frags = fragments_of(0x01, &message(max_message + 1), MAX_PAYLOAD - TRAILER_LEN);
+        assert_eq!(
+            send(&frags[..1]),
+            (CMD_FRAGMENT, vec![Status::BadLen.as_u8()])
+        );
+        assert!(!rx.in_progress());
+    }
+}
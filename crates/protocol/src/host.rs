@@ -0,0 +1,1752 @@
+//! Host-side client (requires the `std` feature).
+//!
+//! [`ArqClient`] sends requests over any byte [`Link`] and matches responses
+//! by ADDR, CMD and SEQ. When the device supports sequenced frames (see
+//! [`crate::arq`]) it retransmits on timeout with exponential backoff, relying
+//! on the device's duplicate filter to keep execution at-most-once. With
+//! plain frames it only retransmits after a NAK, since the device never ran a
+//! frame it could not parse.
+//!
+//! Messages larger than one frame go through [`crate::fragment`]:
+//! [`ArqClient::request_large`] / [`ArqClient::request_stream`] send them,
+//! [`ArqClient::receive_stream`] collects one sent by the device.
+//!
+//! [`ArqClient::discover`] asks the device what it supports (see
+//! [`crate::capabilities`]); afterwards unknown commands are refused locally
+//! and messages are only fragmented when they have to be.
+//!
+//! [`Pipeline`] keeps several sequenced requests in flight, one future per
+//! request, and matches the responses by SEQ in whatever order they arrive
+//! (see [`crate::pipeline`]).
+//!
+//! Event frames (see [`crate::event`]) that arrive while waiting for a
+//! response are queued; [`ArqClient::next_event`] returns them.
+//!
+//! With a key ([`ArqClient::with_key`]) every request is signed (see
+//! [`crate::auth`]). A device that reset and refuses the counter as old is
+//! answered by signing the request again above its LAST_COUNTER, once.
+//!
+//! [`ArqClient::open_session`] runs the [`crate::session`] handshake;
+//! [`ArqClient::secure_request`] then sends requests encrypted. Sessions are
+//! not available on [`Pipeline`].
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::auth::{Key, Signer, TRAILER_LEN, last_counter};
+use crate::capabilities::{CMD_GET_CAPABILITIES, Capabilities, Features};
+use crate::event::{CMD_SUBSCRIBE, CMD_UNSUBSCRIBE, Event, Subscriptions, is_event_cmd};
+use crate::fragment::{
+    CMD_FRAGMENT, CMD_FRAGMENT_ABORT, FRAGMENT_DATA, FragmentError, FragmentReceiver, Fragmenter,
+    is_fragment_cmd,
+};
+use crate::payload::{Command, Payload};
+use crate::session::{
+    CMD_SECURE, CMD_SESSION_OPEN, HostSession, Nonce, SESSION_NONCE_LEN, SessionError,
+};
+use crate::{BuildError, Frame, Framing, MAX_FRAME, MAX_PAYLOAD, Parser, Status, build_frame_seq};
+
+/// CMD code used to probe for sequenced-frame support.
+const PING: u8 = 0x01;
+
+/// Whether a device with `caps` wants signed requests.
+fn signs(caps: &Capabilities) -> bool {
+    caps.features.contains(Features::AUTH)
+}
+
+/// A fresh HOST_NONCE. It only has to be unique: `RandomState` keys are
+/// seeded from the OS and differ on every call.
+fn host_nonce() -> Nonce {
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut nonce = [0u8; SESSION_NONCE_LEN];
+    for chunk in nonce.chunks_exact_mut(8) {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0),
+        );
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    nonce
+}
+
+/// Byte transport to a device: serial port, socket, in-memory pipe, ...
+pub trait Link {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// Read whatever arrives within `timeout`. `Ok(0)` means the timeout expired.
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize>;
+}
+
+/// An owned response frame.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Response {
+    pub seq: Option<u8>,
+    pub addr: u8,
+    pub cmd: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Response {
+    fn from_frame(frame: &Frame<'_>) -> Self {
+        Self {
+            seq: frame.seq,
+            addr: frame.addr,
+            cmd: frame.cmd,
+            payload: frame.payload.to_vec(),
+        }
+    }
+
+    /// Status byte, if it is one this crate knows.
+    pub fn status(&self) -> Option<Status> {
+        Status::from_u8(*self.payload.first()?)
+    }
+
+    /// Getter data (the bytes after STATUS and BYTECOUNT).
+    pub fn data(&self) -> &[u8] {
+        match self.payload.get(1) {
+            Some(&count) => {
+                let end = (2 + count as usize).min(self.payload.len());
+                &self.payload[2..end]
+            }
+            None => &[],
+        }
+    }
+
+    /// OK, or the status the device answered with (BAD_FRAME if unknown).
+    fn result(&self) -> Result<(), Status> {
+        match self.status() {
+            Some(Status::Ok) => Ok(()),
+            Some(status) => Err(status),
+            None => Err(Status::BadFrame),
+        }
+    }
+
+    /// The response itself if the device answered OK, [`ClientError::Status`]
+    /// otherwise.
+    pub fn check(self) -> Result<Self, ClientError> {
+        self.result()?;
+        Ok(self)
+    }
+
+    /// Decode the getter data as `T`, after checking the status as [`check`](Self::check) does.
+    pub fn decode<T: Payload>(&self) -> Result<T, Status> {
+        self.result()?;
+        T::from_bytes(self.data())
+    }
+}
+
+/// An event frame received from device `addr`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DeviceEvent {
+    pub addr: u8,
+    pub event: Event,
+}
+
+/// Retransmission timing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// Wait for the first response attempt.
+    pub timeout: Duration,
+    /// Retransmissions after the first attempt.
+    pub retries: u32,
+    /// Each retry waits `backoff` times longer than the previous one...
+    pub backoff: u32,
+    /// ...but never longer than this.
+    pub max_timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(200),
+            retries: 3,
+            backoff: 2,
+            max_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Build(BuildError),
+    Fragment(FragmentError),
+    Session(SessionError),
+    /// No matching response after every attempt.
+    Timeout,
+    /// The device's capabilities do not list this CMD code.
+    Unsupported(u8),
+    /// Every SEQ value is taken by a request in flight.
+    Busy,
+    /// The device answered with a status other than OK.
+    Status(Status),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "link error: {err}"),
+            ClientError::Build(err) => write!(f, "cannot build request: {err}"),
+            ClientError::Fragment(err) => write!(f, "fragmented transfer failed: {err}"),
+            ClientError::Session(err) => write!(f, "session failed: {err}"),
+            ClientError::Timeout => f.write_str("no response from device"),
+            ClientError::Unsupported(cmd) => write!(f, "device does not support CMD 0x{cmd:02X}"),
+            ClientError::Busy => f.write_str("too many requests in flight"),
+            ClientError::Status(status) => write!(f, "device answered {}", status.name()),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::Io(err)
+    }
+}
+
+impl From<BuildError> for ClientError {
+    fn from(err: BuildError) -> Self {
+        ClientError::Build(err)
+    }
+}
+
+impl From<FragmentError> for ClientError {
+    fn from(err: FragmentError) -> Self {
+        ClientError::Fragment(err)
+    }
+}
+
+impl From<SessionError> for ClientError {
+    fn from(err: SessionError) -> Self {
+        ClientError::Session(err)
+    }
+}
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        ClientError::Status(status)
+    }
+}
+
+/// Request/response client with optional sequenced retransmission.
+pub struct ArqClient<L> {
+    link: L,
+    framing: Framing,
+    parser: Parser,
+    policy: RetryPolicy,
+    next_seq: u8,
+    sequenced: bool,
+    capabilities: Option<Capabilities>,
+    signer: Option<Signer>,
+    session: Option<HostSession>,
+    events: VecDeque<DeviceEvent>,
+}
+
+impl<L: Link> ArqClient<L> {
+    /// A client that sends plain frames until [`negotiate`](Self::negotiate) succeeds.
+    pub fn new(link: L, policy: RetryPolicy) -> Self {
+        // Start somewhere arbitrary so a restarted host does not reuse the
+        // device's last SEQ and get a stale replay.
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u8)
+            .unwrap_or(0);
+        Self {
+            link,
+            framing: Framing::StxLen,
+            parser: Parser::new(),
+            policy,
+            next_seq: seed,
+            sequenced: false,
+            capabilities: None,
+            signer: None,
+            session: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Sign requests with `key`. After [`discover`](Self::discover) they are
+    /// only signed if the device reports `Features::AUTH`.
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.signer = Some(Signer::new(key, 0));
+        self
+    }
+
+    /// Use `framing` instead of STX framing (must match the device's transport).
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self.parser = Parser::with_framing(framing);
+        self
+    }
+
+    /// Whether requests are sent as sequenced frames.
+    pub fn is_sequenced(&self) -> bool {
+        self.sequenced
+    }
+
+    /// Force sequenced frames on or off (skipping negotiation).
+    pub fn set_sequenced(&mut self, sequenced: bool) {
+        self.sequenced = sequenced;
+    }
+
+    pub fn link_mut(&mut self) -> &mut L {
+        &mut self.link
+    }
+
+    /// Switch to pipelined requests. Needs sequenced frames (SEQ is the
+    /// transaction ID), so a client that is not sequenced is given back.
+    #[allow(clippy::result_large_err)] // the error is the unchanged client
+    pub fn into_pipeline(self) -> Result<Pipeline<L>, Self> {
+        if !self.sequenced || self.framing != Framing::StxLen {
+            return Err(self);
+        }
+        Ok(Pipeline {
+            state: RefCell::new(PipelineState {
+                link: self.link,
+                parser: self.parser,
+                policy: self.policy,
+                next_seq: self.next_seq,
+                signer: self
+                    .signer
+                    .filter(|_| self.capabilities.as_ref().is_none_or(signs)),
+                pending: HashMap::new(),
+                events: self.events,
+            }),
+        })
+    }
+
+    /// What the device reported to [`discover`](Self::discover), if it answered.
+    pub fn capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.as_ref()
+    }
+
+    /// Ask `addr` for its capabilities and configure the client from them.
+    /// Firmware without GET_CAPABILITIES answers BAD_CMD; the client then
+    /// falls back to [`negotiate`](Self::negotiate) and returns `None`.
+    pub fn discover(&mut self, addr: u8) -> Result<Option<Capabilities>, ClientError> {
+        self.capabilities = None;
+        let resp = self.exchange(None, addr, CMD_GET_CAPABILITIES, &[], CMD_GET_CAPABILITIES)?;
+        let caps = match resp.status() {
+            Some(Status::Ok) => Capabilities::decode(resp.data()),
+            _ => None,
+        };
+        let Some(caps) = caps else {
+            self.negotiate(addr)?;
+            return Ok(None);
+        };
+
+        self.sequenced = self.framing == Framing::StxLen && caps.features.contains(Features::ARQ);
+        self.capabilities = Some(caps.clone());
+        Ok(Some(caps))
+    }
+
+    /// Probe `addr` with a sequenced PING and use sequenced frames if it answers in kind.
+    pub fn negotiate(&mut self, addr: u8) -> Result<bool, ClientError> {
+        if self.framing == Framing::Cobs {
+            // COBS packets have no SEQ byte.
+            self.sequenced = false;
+            return Ok(false);
+        }
+        let seq = self.take_seq();
+        self.sequenced = match self.exchange(Some(seq), addr, PING, &[], PING) {
+            Ok(resp) => resp.seq == Some(seq),
+            Err(ClientError::Timeout) => false,
+            Err(err) => return Err(err),
+        };
+        Ok(self.sequenced)
+    }
+
+    /// Send a request and wait for its response, retransmitting per the policy.
+    pub fn request(&mut self, addr: u8, cmd: u8, payload: &[u8]) -> Result<Response, ClientError> {
+        self.check_supported(cmd)?;
+        let seq = self.sequenced.then(|| self.take_seq());
+        self.exchange(seq, addr, cmd, payload, cmd)
+    }
+
+    /// Send a typed request (see [`crate::payload`]).
+    pub fn command<C: Command>(&mut self, addr: u8, request: &C) -> Result<Response, ClientError> {
+        let payload = request.to_bytes::<MAX_PAYLOAD>()?;
+        self.request(addr, C::CMD, &payload)
+    }
+
+    /// Ask `addr` to send events of `classes` (added to those already subscribed).
+    pub fn subscribe(&mut self, addr: u8, classes: Subscriptions) -> Result<Response, ClientError> {
+        self.request(addr, CMD_SUBSCRIBE, &classes.to_le_bytes())
+    }
+
+    /// Stop events of `classes` from `addr`.
+    pub fn unsubscribe(
+        &mut self,
+        addr: u8,
+        classes: Subscriptions,
+    ) -> Result<Response, ClientError> {
+        self.request(addr, CMD_UNSUBSCRIBE, &classes.to_le_bytes())
+    }
+
+    /// Open an encrypted session with `addr` using the pre-shared key `psk`.
+    /// Returns `false` if the device refuses (NOT_READY without a key,
+    /// BAD_CMD without sessions); a device that answers without holding
+    /// `psk` is an error.
+    pub fn open_session(&mut self, addr: u8, psk: &Key) -> Result<bool, ClientError> {
+        self.check_supported(CMD_SESSION_OPEN)?;
+        self.session = None;
+        let host_nonce = host_nonce();
+        let resp = self.request(addr, CMD_SESSION_OPEN, &host_nonce)?;
+        if resp.status() != Some(Status::Ok) {
+            return Ok(false);
+        }
+        self.session = Some(HostSession::complete(psk, &host_nonce, resp.data())?);
+        Ok(true)
+    }
+
+    /// Whether [`open_session`](Self::open_session) succeeded.
+    pub fn has_session(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Like [`request`](Self::request), but encrypted in the open session.
+    /// The response is decrypted; a request the device could not open is
+    /// answered with a plain UNAUTHORIZED or NOT_READY under `CMD_SECURE`.
+    pub fn secure_request(
+        &mut self,
+        addr: u8,
+        cmd: u8,
+        payload: &[u8],
+    ) -> Result<Response, ClientError> {
+        self.check_supported(cmd)?;
+        let session = self.session.as_mut().ok_or(SessionError::NoSession)?;
+        let (counter, sealed) = session.seal::<MAX_PAYLOAD>(addr, cmd, payload)?;
+        let seq = self.sequenced.then(|| self.take_seq());
+        let resp = self.exchange(seq, addr, CMD_SECURE, &sealed, CMD_SECURE)?;
+        if resp.payload.len() == 1 {
+            return Ok(resp); // refused, in the clear
+        }
+
+        let session = self.session.as_ref().ok_or(SessionError::NoSession)?;
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let (cmd, payload) = session.open(addr, counter, &resp.payload, &mut buf)?;
+        Ok(Response {
+            seq: resp.seq,
+            addr,
+            cmd,
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// Next event, queued or arriving within `timeout`. Responses that arrive
+    /// meanwhile belong to no request and are dropped.
+    pub fn next_event(&mut self, timeout: Duration) -> Result<Option<DeviceEvent>, ClientError> {
+        if self.events.is_empty() {
+            let deadline = Instant::now() + timeout;
+            self.receive_until(deadline, |resp| {
+                Event::decode(resp.cmd, &resp.payload).is_some()
+            })?;
+        }
+        Ok(self.events.pop_front())
+    }
+
+    /// Send a request that gets no response: to [`BROADCAST`](crate::address::BROADCAST)
+    /// or a group address (see [`crate::address`]). It is sent once, as a plain frame.
+    pub fn send(&mut self, addr: u8, cmd: u8, payload: &[u8]) -> Result<(), ClientError> {
+        self.check_supported(cmd)?;
+        let payload = self.sign(addr, cmd, payload)?;
+        let request = build_frame_seq::<MAX_FRAME>(None, addr, cmd, &payload)?;
+        self.link.send(&self.framing.reframe(&request)?)?;
+        Ok(())
+    }
+
+    /// Send `data` of any length to `cmd`, fragmenting it if needed.
+    ///
+    /// After [`discover`](Self::discover), data that fits one frame goes out
+    /// unfragmented, and messages the device cannot reassemble are refused
+    /// with [`FragmentError::TooLarge`] without sending anything.
+    pub fn request_large(
+        &mut self,
+        addr: u8,
+        cmd: u8,
+        data: &[u8],
+    ) -> Result<Response, ClientError> {
+        let len = u32::try_from(data.len()).map_err(|_| FragmentError::TooLarge)?;
+        let overhead = if self.signing() { TRAILER_LEN } else { 0 };
+        let (fits, can_fragment) = match &self.capabilities {
+            Some(caps) => (
+                data.len() + overhead <= usize::from(caps.max_payload).min(MAX_PAYLOAD),
+                caps.features.contains(Features::FRAGMENTATION) && len <= caps.max_message,
+            ),
+            None => (false, true),
+        };
+        if fits {
+            return self.request(addr, cmd, data);
+        }
+        self.check_supported(cmd)?;
+        if !can_fragment {
+            return Err(FragmentError::TooLarge.into());
+        }
+        self.request_stream(addr, cmd, len, data)
+    }
+
+    /// Send a `total_len`-byte message read from `source` as fragments and
+    /// return the response to the reassembled message. A device error on any
+    /// fragment is returned as that fragment's response.
+    pub fn request_stream(
+        &mut self,
+        addr: u8,
+        cmd: u8,
+        total_len: u32,
+        mut source: impl io::Read,
+    ) -> Result<Response, ClientError> {
+        let overhead = if self.signing() { TRAILER_LEN } else { 0 };
+        let mut tx = Fragmenter::new(cmd, total_len)?.with_max_payload(MAX_PAYLOAD - overhead)?;
+        let mut chunk = [0u8; FRAGMENT_DATA];
+
+        while let Some(n) = tx.next_len() {
+            let resp = match self.send_fragment(&mut tx, &mut source, &mut chunk[..n], addr, cmd) {
+                Ok(resp) => resp,
+                Err(err) => {
+                    // Best effort: free the device's buffer now rather than at its timeout.
+                    let _ = self.request(addr, CMD_FRAGMENT_ABORT, &[]);
+                    return Err(err);
+                }
+            };
+            if tx.is_done() || resp.status() != Some(Status::Ok) {
+                return Ok(resp);
+            }
+        }
+        unreachable!("Fragmenter always finishes with a fragment")
+    }
+
+    fn send_fragment(
+        &mut self,
+        tx: &mut Fragmenter,
+        source: &mut impl io::Read,
+        chunk: &mut [u8],
+        addr: u8,
+        cmd: u8,
+    ) -> Result<Response, ClientError> {
+        source.read_exact(chunk)?;
+        let payload = tx.next_fragment(chunk)?;
+        let seq = self.sequenced.then(|| self.take_seq());
+        // The last fragment is answered by the reassembled command.
+        let reply_cmd = if tx.is_done() { cmd } else { CMD_FRAGMENT };
+        self.exchange(seq, addr, CMD_FRAGMENT, &payload, reply_cmd)
+    }
+
+    /// Collect a fragmented message sent by device `addr`, writing it to
+    /// `sink` as it arrives. Returns the message's CMD.
+    pub fn receive_stream(
+        &mut self,
+        addr: u8,
+        mut sink: impl io::Write,
+    ) -> Result<u8, ClientError> {
+        let timeout = self.policy.max_timeout;
+        let mut rx = FragmentReceiver::new(timeout.as_micros() as u64);
+        let start = Instant::now();
+
+        loop {
+            let Some(frame) = self.wait_for(None, addr, CMD_FRAGMENT, CMD_FRAGMENT, timeout)?
+            else {
+                return Err(ClientError::Timeout);
+            };
+            let chunk = rx.accept(&frame.payload, start.elapsed().as_micros() as u64)?;
+            sink.write_all(chunk.data)?;
+            if chunk.complete {
+                return Ok(chunk.cmd);
+            }
+        }
+    }
+
+    /// Refuse `cmd` locally when the device's capabilities are known and
+    /// do not list it. Fragment and session commands are implied by FEATURES.
+    fn check_supported(&self, cmd: u8) -> Result<(), ClientError> {
+        match &self.capabilities {
+            Some(caps) if !caps.supports(cmd) && !is_fragment_cmd(cmd) && cmd != CMD_SECURE => {
+                Err(ClientError::Unsupported(cmd))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn take_seq(&mut self) -> u8 {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        seq
+    }
+
+    /// Whether requests get a signature: a key is set and the device, if
+    /// discovered, asks for one.
+    fn signing(&self) -> bool {
+        self.signer.is_some() && self.capabilities.as_ref().is_none_or(signs)
+    }
+
+    /// `payload` as sent: signed when [`signing`](Self::signing).
+    fn sign(&mut self, addr: u8, cmd: u8, payload: &[u8]) -> Result<Vec<u8>, ClientError> {
+        match &mut self.signer {
+            Some(signer) if self.capabilities.as_ref().is_none_or(signs) => {
+                Ok(signer.sign::<MAX_PAYLOAD>(addr, cmd, payload)?.to_vec())
+            }
+            _ => Ok(payload.to_vec()),
+        }
+    }
+
+    fn exchange(
+        &mut self,
+        seq: Option<u8>,
+        addr: u8,
+        cmd: u8,
+        payload: &[u8],
+        reply_cmd: u8,
+    ) -> Result<Response, ClientError> {
+        let resp = self.exchange_once(seq, addr, cmd, payload, reply_cmd)?;
+        // The device remembers a higher counter (it reset, or another host
+        // signed with the key): sign again above it, with a fresh SEQ so the
+        // duplicate filter does not replay the refusal.
+        if self.signing()
+            && let Some(last) = last_counter(&resp.payload)
+            && let Some(signer) = &mut self.signer
+            && signer.resync(last)
+        {
+            let seq = seq.map(|_| self.take_seq());
+            return self.exchange_once(seq, addr, cmd, payload, reply_cmd);
+        }
+        Ok(resp)
+    }
+
+    fn exchange_once(
+        &mut self,
+        seq: Option<u8>,
+        addr: u8,
+        cmd: u8,
+        payload: &[u8],
+        reply_cmd: u8,
+    ) -> Result<Response, ClientError> {
+        let payload = self.sign(addr, cmd, payload)?;
+        let request = build_frame_seq::<MAX_FRAME>(seq, addr, cmd, &payload)?;
+        let request = self.framing.reframe(&request)?;
+        let mut timeout = self.policy.timeout;
+
+        for _ in 0..=self.policy.retries {
+            self.link.send(&request)?;
+
+            match self.wait_for(seq, addr, cmd, reply_cmd, timeout)? {
+                Some(resp) if resp.status() == Some(Status::BadFrame) => {
+                    // NAK: the request was corrupted on the way and never ran.
+                }
+                Some(resp) => return Ok(resp),
+                // Plain frames have no duplicate filter, so a retransmission could run twice.
+                None if seq.is_none() => return Err(ClientError::Timeout),
+                None => {}
+            }
+
+            timeout = (timeout * self.policy.backoff).min(self.policy.max_timeout);
+        }
+        Err(ClientError::Timeout)
+    }
+
+    /// Wait for a response to (seq, addr, cmd), queueing events and dropping anything else.
+    /// The response may carry `reply_cmd` instead of `cmd`.
+    fn wait_for(
+        &mut self,
+        seq: Option<u8>,
+        addr: u8,
+        cmd: u8,
+        reply_cmd: u8,
+        timeout: Duration,
+    ) -> Result<Option<Response>, ClientError> {
+        self.receive_until(Instant::now() + timeout, |resp| {
+            let is_nak = resp.status() == Some(Status::BadFrame);
+            // NAKs are always plain: the device could not read the SEQ it came with.
+            let cmd_matches = resp.cmd == cmd || resp.cmd == reply_cmd;
+            resp.addr == addr && cmd_matches && (resp.seq == seq || is_nak)
+        })
+    }
+
+    /// Read until a frame satisfies `wanted` or `deadline` passes. Event
+    /// frames are queued on the way; anything else is dropped.
+    fn receive_until(
+        &mut self,
+        deadline: Instant,
+        mut wanted: impl FnMut(&Response) -> bool,
+    ) -> Result<Option<Response>, ClientError> {
+        let mut buf = [0u8; 256];
+
+        loop {
+            while let Some(resp) = self.next_response() {
+                if is_event_cmd(resp.cmd)
+                    && let Some(event) = Event::decode(resp.cmd, &resp.payload)
+                {
+                    let addr = resp.addr;
+                    self.events.push_back(DeviceEvent { addr, event });
+                }
+                if wanted(&resp) {
+                    return Ok(Some(resp));
+                }
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let n = self.link.recv(&mut buf, remaining)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.parser.push_bytes(&buf[..n]);
+        }
+    }
+
+    fn next_response(&mut self) -> Option<Response> {
+        loop {
+            match self.parser.next_frame() {
+                Ok(Some(frame)) => return Some(Response::from_frame(&frame)),
+                Ok(None) => return None,
+                Err(_) => continue,
+            }
+        }
+    }
+}
+
+/// Client with several sequenced requests in flight.
+///
+/// [`request`](Self::request) returns a future per request; poll several
+/// together (with any `join`) to overlap them. Each future sends its request
+/// on first poll, retransmits on timeout like [`ArqClient`], and resolves
+/// when a response with its SEQ arrives, in whatever order they come. A
+/// device that is at its in-flight limit answers BUSY; that response is
+/// returned as is. So is UNAUTHORIZED for a signed request whose counter the
+/// device considers old, though later requests are signed above it. The
+/// futures read the link without blocking and wake themselves while waiting,
+/// so they work on any executor.
+pub struct Pipeline<L> {
+    state: RefCell<PipelineState<L>>,
+}
+
+struct PipelineState<L> {
+    link: L,
+    parser: Parser,
+    policy: RetryPolicy,
+    next_seq: u8,
+    signer: Option<Signer>,
+    /// Requests awaiting a response, by SEQ.
+    pending: HashMap<u8, Slot>,
+    events: VecDeque<DeviceEvent>,
+}
+
+struct Slot {
+    addr: u8,
+    cmd: u8,
+    response: Option<Response>,
+}
+
+impl<L: Link> Pipeline<L> {
+    /// Prepare a request to `addr`. Fails if the request cannot be built or
+    /// all 256 SEQ values are in flight.
+    pub fn request(
+        &self,
+        addr: u8,
+        cmd: u8,
+        payload: &[u8],
+    ) -> Result<PipelinedRequest<'_, L>, ClientError> {
+        let mut state = self.state.borrow_mut();
+        if state.pending.len() > u8::MAX as usize {
+            return Err(ClientError::Busy);
+        }
+        let mut seq = state.next_seq;
+        while state.pending.contains_key(&seq) {
+            seq = seq.wrapping_add(1);
+        }
+        state.next_seq = seq.wrapping_add(1);
+
+        let frame = match &mut state.signer {
+            Some(signer) => {
+                let payload = signer.sign::<MAX_PAYLOAD>(addr, cmd, payload)?;
+                build_frame_seq::<MAX_FRAME>(Some(seq), addr, cmd, &payload)?
+            }
+            None => build_frame_seq::<MAX_FRAME>(Some(seq), addr, cmd, payload)?,
+        };
+        state.pending.insert(
+            seq,
+            Slot {
+                addr,
+                cmd,
+                response: None,
+            },
+        );
+        Ok(PipelinedRequest {
+            pipeline: self,
+            seq,
+            frame: frame.to_vec(),
+            timeout: state.policy.timeout,
+            deadline: None,
+            retries: 0,
+            done: false,
+        })
+    }
+
+    /// Events received so far, oldest first.
+    pub fn take_events(&self) -> Vec<DeviceEvent> {
+        self.state.borrow_mut().events.drain(..).collect()
+    }
+
+    /// Number of requests awaiting a response.
+    pub fn in_flight(&self) -> usize {
+        self.state.borrow().pending.len()
+    }
+}
+
+impl<L: Link> PipelineState<L> {
+    /// Read whatever has arrived and file responses under their SEQ.
+    fn pump(&mut self) -> Result<(), ClientError> {
+        let mut buf = [0u8; 256];
+        loop {
+            let n = self.link.recv(&mut buf, Duration::ZERO)?;
+            if n == 0 {
+                break;
+            }
+            self.parser.push_bytes(&buf[..n]);
+        }
+        loop {
+            let frame = match self.parser.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(()),
+                Err(_) => continue,
+            };
+            if is_event_cmd(frame.cmd) {
+                if let Some(event) = Event::decode(frame.cmd, frame.payload) {
+                    let addr = frame.addr;
+                    self.events.push_back(DeviceEvent { addr, event });
+                }
+                continue;
+            }
+            // NAKs carry no SEQ; the request they refer to times out and is resent.
+            let Some(slot) = frame.seq.and_then(|seq| self.pending.get_mut(&seq)) else {
+                continue;
+            };
+            if slot.addr == frame.addr && slot.cmd == frame.cmd {
+                slot.response = Some(Response::from_frame(&frame));
+                // Refused as old: later requests continue above the device's counter
+                if let Some(last) = last_counter(frame.payload)
+                    && let Some(signer) = &mut self.signer
+                {
+                    signer.resync(last);
+                }
+            }
+        }
+    }
+}
+
+/// One request of a [`Pipeline`], resolving to its response.
+pub struct PipelinedRequest<'p, L> {
+    pipeline: &'p Pipeline<L>,
+    seq: u8,
+    frame: Vec<u8>,
+    timeout: Duration,
+    deadline: Option<Instant>,
+    retries: u32,
+    done: bool,
+}
+
+impl<L> PipelinedRequest<'_, L> {
+    /// This request's SEQ (transaction ID).
+    pub fn seq(&self) -> u8 {
+        self.seq
+    }
+
+    /// Release the SEQ once resolved (or abandoned).
+    fn finish(&mut self) {
+        if !self.done {
+            self.done = true;
+            self.pipeline.state.borrow_mut().pending.remove(&self.seq);
+        }
+    }
+}
+
+impl<L: Link> PipelinedRequest<'_, L> {
+    fn step(&mut self) -> Result<Option<Response>, ClientError> {
+        let mut state = self.pipeline.state.borrow_mut();
+        let policy = state.policy;
+        let now = Instant::now();
+        match self.deadline {
+            None => {
+                state.link.send(&self.frame)?;
+                self.deadline = Some(now + self.timeout);
+            }
+            Some(deadline) if now >= deadline => {
+                if self.retries >= policy.retries {
+                    return Err(ClientError::Timeout);
+                }
+                self.retries += 1;
+                self.timeout = (self.timeout * policy.backoff).min(policy.max_timeout);
+                state.link.send(&self.frame)?;
+                self.deadline = Some(now + self.timeout);
+            }
+            Some(_) => {}
+        }
+
+        state.pump()?;
+        Ok(state
+            .pending
+            .get_mut(&self.seq)
+            .and_then(|slot| slot.response.take()))
+    }
+}
+
+impl<L: Link> Future for PipelinedRequest<'_, L> {
+    type Output = Result<Response, ClientError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.step() {
+            Ok(None) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Ok(Some(resp)) => {
+                this.finish();
+                Poll::Ready(Ok(resp))
+            }
+            Err(err) => {
+                this.finish();
+                Poll::Ready(Err(err))
+            }
+        }
+    }
+}
+
+impl<L> Drop for PipelinedRequest<'_, L> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+
+    use super::*;
+    use crate::address::{CMD_GET_ADDRESS, NodeAddress};
+    use crate::arq::DuplicateFilter;
+    use crate::auth::{KEY_LEN, Verifier, unauthorized};
+    use crate::dispatch::{CommandHandler, CommandKind, CommandSpec, Data, Registry};
+    use crate::fragment::{Reassembler, is_fragment_cmd};
+    use crate::pipeline::{Admission, InFlight, OwnedFrame, busy};
+    use crate::session::{DeviceSessions, SECURE_OVERHEAD, refused};
+    use crate::{Frame, STX_SEQ, build_frame};
+
+    /// Counts executions so tests can check at-most-once behaviour.
+    struct Counter<'a>(&'a Cell<u32>);
+
+    impl CommandHandler for Counter<'_> {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: 0x02,
+            name: "COUNT",
+            kind: CommandKind::Setter,
+            min_payload: 0,
+            max_payload: 0,
+        };
+
+        async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+            self.0.set(self.0.get() + 1);
+            Ok(())
+        }
+    }
+
+    /// Keeps the last (possibly reassembled) payload it was sent.
+    struct Store<'a>(&'a RefCell<Vec<u8>>);
+
+    impl CommandHandler for Store<'_> {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: 0x30,
+            name: "STORE",
+            kind: CommandKind::Setter,
+            min_payload: 0,
+            max_payload: 1024,
+        };
+
+        async fn handle(&self, frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+            *self.0.borrow_mut() = frame.payload.to_vec();
+            Ok(())
+        }
+    }
+
+    struct Ping;
+
+    impl CommandHandler for Ping {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: PING,
+            name: "PING",
+            kind: CommandKind::Setter,
+            min_payload: 0,
+            max_payload: 0,
+        };
+
+        async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+            Ok(())
+        }
+    }
+
+    /// Answers with the simulated device's capabilities, or BAD_CMD like
+    /// firmware that predates GET_CAPABILITIES.
+    struct GetCaps<'a>(&'a Option<Capabilities>);
+
+    impl CommandHandler for GetCaps<'_> {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: CMD_GET_CAPABILITIES,
+            name: "GET_CAPABILITIES",
+            kind: CommandKind::Getter,
+            min_payload: 0,
+            max_payload: 0,
+        };
+
+        async fn handle(&self, _frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+            let caps = self.0.as_ref().ok_or(Status::BadCmd)?;
+            data.extend_from_slice(&caps.encode())
+                .map_err(|_| Status::Internal)
+        }
+    }
+
+    /// Session handshake with a fixed DEVICE_NONCE; NOT_READY without a key.
+    struct OpenSession<'a> {
+        psk: Option<Key>,
+        session: &'a RefCell<DeviceSessions>,
+    }
+
+    impl CommandHandler for OpenSession<'_> {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: CMD_SESSION_OPEN,
+            name: "SESSION_OPEN",
+            kind: CommandKind::Getter,
+            min_payload: SESSION_NONCE_LEN,
+            max_payload: SESSION_NONCE_LEN,
+        };
+
+        async fn handle(&self, frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+            let psk = self.psk.as_ref().ok_or(Status::NotReady)?;
+            let host_nonce = frame.payload.try_into().map_err(|_| Status::BadLen)?;
+            let open =
+                self.session
+                    .borrow_mut()
+                    .accept(psk, host_nonce, &[0x5A; SESSION_NONCE_LEN]);
+            data.extend_from_slice(&open).map_err(|_| Status::Internal)
+        }
+    }
+
+    /// In-memory link to a simulated device. `drops` decides, message by
+    /// message (requests and responses alternating), whether it is lost.
+    struct LossyLink<'a> {
+        parser: Parser,
+        filter: DuplicateFilter,
+        reassembler: Reassembler<1024>,
+        executed: &'a Cell<u32>,
+        stored: RefCell<Vec<u8>>,
+        supports_seq: bool,
+        capabilities: Option<Capabilities>,
+        verifier: Option<Verifier>,
+        psk: Option<Key>,
+        session: RefCell<DeviceSessions>,
+        drops: VecDeque<bool>,
+        inbox: VecDeque<u8>,
+        timeouts: Vec<Duration>,
+    }
+
+    impl<'a> LossyLink<'a> {
+        fn new(executed: &'a Cell<u32>, drops: &[bool]) -> Self {
+            Self {
+                parser: Parser::new(),
+                filter: DuplicateFilter::new(),
+                reassembler: Reassembler::new(1_000_000),
+                executed,
+                stored: RefCell::new(Vec::new()),
+                supports_seq: true,
+                capabilities: None,
+                verifier: None,
+                psk: None,
+                session: RefCell::new(DeviceSessions::new()),
+                drops: drops.iter().copied().collect(),
+                inbox: VecDeque::new(),
+                timeouts: Vec::new(),
+            }
+        }
+
+        fn lost(&mut self) -> bool {
+            self.drops.pop_front().unwrap_or(false)
+        }
+    }
+
+    impl Link for LossyLink<'_> {
+        fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+            if self.lost() {
+                return Ok(());
+            }
+            if !self.supports_seq && bytes[0] == STX_SEQ {
+                return Ok(()); // old firmware: not a frame start
+            }
+
+            let registry = Registry::new()
+                .register(Ping)
+                .register(Counter(self.executed))
+                .register(Store(&self.stored))
+                .register(GetCaps(&self.capabilities))
+                .register(OpenSession {
+                    psk: self.psk,
+                    session: &self.session,
+                });
+            self.parser.push_bytes(bytes);
+            let mut responses = Vec::new();
+            while let Ok(Some(frame)) = self.parser.next_frame() {
+                let resp = match self.filter.replay(&frame) {
+                    Some(cached) => cached.to_vec(),
+                    None => {
+                        let request = match &mut self.verifier {
+                            Some(verifier) => verifier.verify(&frame).map_err(|_| verifier.last()),
+                            None => Ok(frame),
+                        };
+                        let resp = match request {
+                            Ok(request) if is_fragment_cmd(request.cmd) => {
+                                block_on(self.reassembler.dispatch(&registry, &request, 0))
+                            }
+                            Ok(request) if request.cmd == CMD_SECURE => {
+                                secure_dispatch(&registry, &self.session, &request)
+                            }
+                            Ok(request) => block_on(registry.dispatch(&request)),
+                            Err(last) => unauthorized(&frame, last),
+                        }
+                        .unwrap();
+                        self.filter.store(&frame, &resp);
+                        resp.to_vec()
+                    }
+                };
+                responses.push(resp);
+            }
+            for resp in responses {
+                if !self.lost() {
+                    self.inbox.extend(resp);
+                }
+            }
+            Ok(())
+        }
+
+        fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+            if self.inbox.is_empty() {
+                self.timeouts.push(timeout);
+            }
+            let n = buf.len().min(self.inbox.len());
+            for (slot, byte) in buf.iter_mut().zip(self.inbox.drain(..n)) {
+                *slot = byte;
+            }
+            Ok(n)
+        }
+    }
+
+    /// Device handling of a `CMD_SECURE` request, as the firmware does it.
+    fn secure_dispatch<D: crate::dispatch::Dispatch>(
+        registry: &Registry<D>,
+        sessions: &RefCell<DeviceSessions>,
+        frame: &Frame<'_>,
+    ) -> Result<heapless::Vec<u8, MAX_FRAME>, BuildError> {
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let opened = sessions.borrow_mut().open_request(frame, &mut buf);
+        match opened {
+            Ok(opened) => {
+                let resp = block_on(registry.dispatch(&opened.request))?;
+                opened.seal_response(&resp)
+            }
+            Err(err) => refused(frame, err),
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            timeout: Duration::from_millis(10),
+            retries: 3,
+            backoff: 2,
+            max_timeout: Duration::from_millis(30),
+        }
+    }
+
+    #[test]
+    fn negotiates_sequenced_frames() {
+        let executed = Cell::new(0);
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[]), policy());
+        assert!(client.negotiate(0x01).unwrap());
+        assert!(client.is_sequenced());
+    }
+
+    #[test]
+    fn falls_back_to_plain_frames_on_old_firmware() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        link.supports_seq = false;
+        let mut client = ArqClient::new(link, policy());
+
+        assert!(!client.negotiate(0x01).unwrap());
+        let resp = client.request(0x01, 0x02, &[]).unwrap();
+        assert_eq!(resp.seq, None);
+        assert_eq!(resp.status(), Some(Status::Ok));
+        assert_eq!(executed.get(), 1);
+    }
+
+    fn device_caps(features: Features, max_message: u32) -> Capabilities {
+        Capabilities {
+            version: crate::capabilities::PROTOCOL_VERSION,
+            framings: Framing::StxLen.bit(),
+            features,
+            max_payload: MAX_PAYLOAD as u16,
+            stream_buf_cap: crate::STREAM_BUF_CAP as u16,
+            max_message,
+            commands: heapless::Vec::from_slice(&[
+                (PING, CommandKind::Setter),
+                (0x02, CommandKind::Setter),
+                (0x30, CommandKind::Setter),
+                (CMD_GET_CAPABILITIES, CommandKind::Getter),
+                (CMD_SESSION_OPEN, CommandKind::Getter),
+            ])
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn discovery_configures_arq_and_refuses_unlisted_commands() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        link.capabilities = Some(device_caps(Features::ARQ, 0));
+        let mut client = ArqClient::new(link, policy());
+
+        let caps = client.discover(0x01).unwrap().unwrap();
+        assert_eq!(caps.version, crate::capabilities::PROTOCOL_VERSION);
+        assert!(client.is_sequenced());
+        assert!(matches!(
+            client.request(0x01, 0x40, &[]),
+            Err(ClientError::Unsupported(0x40))
+        ));
+        assert!(client.link_mut().timeouts.is_empty());
+
+        let resp = client.request(0x01, 0x02, &[]).unwrap();
+        assert!(resp.seq.is_some());
+        assert_eq!(executed.get(), 1);
+    }
+
+    #[test]
+    fn discovery_falls_back_to_negotiation_on_old_firmware() {
+        let executed = Cell::new(0);
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[]), policy());
+
+        assert_eq!(client.discover(0x01).unwrap(), None);
+        assert!(client.capabilities().is_none());
+        assert!(client.is_sequenced());
+        // Nothing is refused locally without a command list.
+        let resp = client.request(0x01, 0x40, &[]).unwrap();
+        assert_eq!(resp.status(), Some(Status::BadCmd));
+    }
+
+    #[test]
+    fn lost_response_is_replayed_not_rerun() {
+        let executed = Cell::new(0);
+        // request ok, response lost, retransmission ok, replayed response ok
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[false, true]), policy());
+        client.set_sequenced(true);
+
+        let resp = client.request(0x01, 0x02, &[]).unwrap();
+        assert_eq!(resp.status(), Some(Status::Ok));
+        assert_eq!(executed.get(), 1);
+    }
+
+    #[test]
+    fn lost_request_is_retransmitted() {
+        let executed = Cell::new(0);
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[true, true]), policy());
+        client.set_sequenced(true);
+
+        assert!(client.request(0x01, 0x02, &[]).is_ok());
+        assert_eq!(executed.get(), 1);
+
+        // The next request gets a new SEQ and runs again.
+        assert!(client.request(0x01, 0x02, &[]).is_ok());
+        assert_eq!(executed.get(), 2);
+    }
+
+    #[test]
+    fn gives_up_after_retries_with_backoff() {
+        let executed = Cell::new(0);
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[true; 8]), policy());
+        client.set_sequenced(true);
+
+        assert!(matches!(
+            client.request(0x01, 0x02, &[]),
+            Err(ClientError::Timeout)
+        ));
+        let timeouts: Vec<u128> = client
+            .link_mut()
+            .timeouts
+            .iter()
+            .map(|t| t.as_millis())
+            .collect();
+        // Remaining time is measured from a deadline, so allow for elapsed time.
+        let expected = [10, 20, 30, 30];
+        assert_eq!(timeouts.len(), expected.len());
+        for (got, max) in timeouts.iter().zip(expected) {
+            assert!(*got <= max && *got + 5 >= max, "{timeouts:?}");
+        }
+        assert_eq!(executed.get(), 0);
+    }
+
+    #[test]
+    fn plain_frames_are_not_retransmitted_on_timeout() {
+        let executed = Cell::new(0);
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[false, true]), policy());
+
+        assert!(matches!(
+            client.request(0x01, 0x02, &[]),
+            Err(ClientError::Timeout)
+        ));
+        assert_eq!(executed.get(), 1);
+        assert_eq!(client.link_mut().timeouts.len(), 1);
+    }
+
+    #[test]
+    fn broadcasts_are_sent_once_without_waiting() {
+        let executed = Cell::new(0);
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[]), policy());
+        client.set_sequenced(true);
+
+        client.send(crate::address::BROADCAST, 0x02, &[]).unwrap();
+        assert_eq!(executed.get(), 1);
+        assert!(client.link_mut().timeouts.is_empty());
+    }
+
+    #[test]
+    fn events_interleaved_with_responses_are_queued() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        let event = Event::Fault { code: 0x02 };
+        link.inbox
+            .extend(crate::event::build_event::<MAX_FRAME>(0x01, &event).unwrap());
+        let mut client = ArqClient::new(link, policy());
+
+        let resp = client.request(0x01, 0x02, &[]).unwrap();
+        assert_eq!(resp.status(), Some(Status::Ok));
+
+        let timeout = Duration::from_millis(1);
+        let queued = client.next_event(timeout).unwrap();
+        assert_eq!(queued, Some(DeviceEvent { addr: 0x01, event }));
+        assert_eq!(client.next_event(timeout).unwrap(), None);
+    }
+
+    #[test]
+    fn next_event_waits_for_an_unsolicited_frame() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        link.inbox
+            .extend(build_frame::<MAX_FRAME>(0x01, 0x7E, &[0x00]).unwrap());
+        link.inbox
+            .extend(crate::event::build_event::<MAX_FRAME>(0x01, &Event::ChaseFinished).unwrap());
+        let mut client = ArqClient::new(link, policy());
+
+        let event = client
+            .next_event(Duration::from_millis(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.event, Event::ChaseFinished);
+    }
+
+    /// Device that runs sequenced requests concurrently (up to `limit`) and
+    /// finishes them newest first: once `batch` are running, or after a few
+    /// idle polls.
+    struct ReorderingDevice {
+        parser: Parser,
+        in_flight: InFlight<4>,
+        running: Vec<OwnedFrame>,
+        batch: usize,
+        idle: u32,
+        finished: Vec<u8>,
+        inbox: VecDeque<u8>,
+    }
+
+    impl ReorderingDevice {
+        fn new(limit: usize, batch: usize) -> Self {
+            Self {
+                parser: Parser::new(),
+                in_flight: InFlight::new(limit),
+                running: Vec::new(),
+                batch,
+                idle: 0,
+                finished: Vec::new(),
+                inbox: VecDeque::new(),
+            }
+        }
+    }
+
+    impl Link for ReorderingDevice {
+        fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.parser.push_bytes(bytes);
+            while let Ok(Some(frame)) = self.parser.next_frame() {
+                match self.in_flight.admit(&frame) {
+                    Admission::Run => self.running.extend(OwnedFrame::from_frame(&frame)),
+                    Admission::InProgress => {}
+                    Admission::Busy => self.inbox.extend(busy::<MAX_FRAME>(&frame).unwrap()),
+                }
+            }
+            Ok(())
+        }
+
+        fn recv(&mut self, buf: &mut [u8], _timeout: Duration) -> io::Result<usize> {
+            self.idle += 1;
+            if self.running.len() >= self.batch || self.idle > 3 {
+                self.idle = 0;
+                while let Some(job) = self.running.pop() {
+                    let frame = job.as_frame();
+                    let resp = build_frame_seq::<MAX_FRAME>(frame.seq, frame.addr, frame.cmd, &[0]);
+                    self.inbox.extend(resp.unwrap());
+                    self.in_flight.finish(&frame);
+                    self.finished.push(frame.cmd);
+                }
+            }
+            let n = buf.len().min(self.inbox.len());
+            for (slot, byte) in buf.iter_mut().zip(self.inbox.drain(..n)) {
+                *slot = byte;
+            }
+            Ok(n)
+        }
+    }
+
+    fn pipeline(device: ReorderingDevice) -> Pipeline<ReorderingDevice> {
+        let mut client = ArqClient::new(device, policy());
+        client.set_sequenced(true);
+        client.into_pipeline().ok().unwrap()
+    }
+
+    #[test]
+    fn pipelined_responses_are_matched_out_of_order() {
+        let pipeline = pipeline(ReorderingDevice::new(4, 2));
+        let first = pipeline.request(0x01, 0x30, &[]).unwrap();
+        let second = pipeline.request(0x01, 0x31, &[]).unwrap();
+        assert_ne!(first.seq(), second.seq());
+
+        let (first, second) = block_on(join(first, second));
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!((first.cmd, first.status()), (0x30, Some(Status::Ok)));
+        assert_eq!((second.cmd, second.status()), (0x31, Some(Status::Ok)));
+        assert_eq!(pipeline.state.borrow().link.finished, [0x31, 0x30]);
+        assert_eq!(pipeline.in_flight(), 0);
+    }
+
+    #[test]
+    fn requests_beyond_the_in_flight_limit_get_busy() {
+        let pipeline = pipeline(ReorderingDevice::new(1, 2));
+        let first = pipeline.request(0x01, 0x30, &[]).unwrap();
+        let second = pipeline.request(0x01, 0x31, &[]).unwrap();
+
+        let (first, second) = block_on(join(first, second));
+        assert_eq!(first.unwrap().status(), Some(Status::Ok));
+        assert_eq!(second.unwrap().status(), Some(Status::Busy));
+    }
+
+    #[test]
+    fn plain_clients_cannot_pipeline() {
+        let executed = Cell::new(0);
+        let client = ArqClient::new(LossyLink::new(&executed, &[]), policy());
+        assert!(client.into_pipeline().is_err());
+    }
+
+    const KEY: Key = [0x5A; KEY_LEN];
+
+    #[test]
+    fn signed_requests_catch_up_with_a_reset_device() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        link.capabilities = Some(device_caps(Features::ARQ | Features::AUTH, 0));
+        // The bound the device restored from flash after a reset
+        link.verifier = Some(Verifier::new(KEY, 1024));
+        let mut client = ArqClient::new(link, policy()).with_key(KEY);
+        client.discover(0x01).unwrap();
+
+        let resp = client.request(0x01, 0x02, &[]).unwrap();
+        assert_eq!(resp.status(), Some(Status::Ok));
+        assert_eq!(executed.get(), 1);
+        let resp = client.request(0x01, 0x02, &[]).unwrap();
+        assert_eq!(resp.status(), Some(Status::Ok));
+        assert_eq!(executed.get(), 2);
+        // Discovery already caught up: 1025 for GET_CAPABILITIES, then the two requests
+        assert_eq!(client.link_mut().verifier.as_ref().unwrap().last(), 1027);
+    }
+
+    #[test]
+    fn wrong_keys_and_unsigned_requests_are_refused() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        link.verifier = Some(Verifier::new(KEY, 0));
+        let mut client = ArqClient::new(link, policy()).with_key([0xA5; KEY_LEN]);
+        client.set_sequenced(true);
+
+        let resp = client.request(0x01, 0x02, &[]).unwrap();
+        assert_eq!(resp.status(), Some(Status::Unauthorized));
+        // Unsigned: still refused, but PING is open
+        let mut client = ArqClient::new(client.link, policy());
+        let resp = client.request(0x01, 0x02, &[]).unwrap();
+        assert_eq!(resp.status(), Some(Status::Unauthorized));
+        assert_eq!(
+            client.request(0x01, PING, &[]).unwrap().status(),
+            Some(Status::Ok)
+        );
+        assert_eq!(executed.get(), 0);
+    }
+
+    #[test]
+    fn typed_commands_encode_their_payload() {
+        #[derive(crate::payload::Payload, crate::payload::Command)]
+        #[command(cmd = 0x30)]
+        struct Store {
+            level: u16,
+            name: heapless::String<4>,
+        }
+
+        let executed = Cell::new(0);
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[]), policy());
+        let request = Store {
+            level: 0x0102,
+            name: heapless::String::try_from("ab").unwrap(),
+        };
+        let resp = client.command(0x01, &request).unwrap();
+        assert_eq!(resp.status(), Some(Status::Ok));
+        assert_eq!(
+            *client.link_mut().stored.borrow(),
+            [0x02, 0x01, 2, b'a', b'b']
+        );
+
+        let resp = Response {
+            seq: None,
+            addr: 0x01,
+            cmd: CMD_GET_ADDRESS,
+            payload: vec![0x00, 3, 0x2A, 0x01, 0x80],
+        };
+        let address = NodeAddress {
+            node: 0x2A,
+            groups: 0x8001,
+        };
+        assert_eq!(resp.decode(), Ok(address));
+        assert_eq!(resp.decode::<u8>(), Err(Status::BadLen));
+    }
+
+    #[test]
+    fn generated_methods_check_the_status() {
+        use crate::client::Commands;
+
+        let executed = Cell::new(0);
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[]), policy());
+        client.ping(0x01).unwrap();
+        // The simulated device has no GET_ADDRESS handler
+        assert!(matches!(
+            client.get_address(0x01),
+            Err(ClientError::Status(Status::BadCmd))
+        ));
+
+        let refused = Response {
+            seq: None,
+            addr: 0x01,
+            cmd: CMD_GET_ADDRESS,
+            payload: vec![Status::Busy.as_u8()],
+        };
+        assert_eq!(refused.decode::<NodeAddress>(), Err(Status::Busy));
+        assert!(matches!(
+            refused.check(),
+            Err(ClientError::Status(Status::Busy))
+        ));
+    }
+
+    #[test]
+    fn signed_fragments_leave_room_for_the_signature() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        link.capabilities = Some(device_caps(
+            Features::ARQ | Features::FRAGMENTATION | Features::AUTH,
+            1024,
+        ));
+        link.verifier = Some(Verifier::new(KEY, 0));
+        let mut client = ArqClient::new(link, policy()).with_key(KEY);
+        client.discover(0x01).unwrap();
+
+        // Fits one frame unsigned, but not with the signature
+        let msg = message(MAX_PAYLOAD - 4);
+        let resp = client.request_large(0x01, 0x30, &msg).unwrap();
+        assert_eq!(resp.status(), Some(Status::Ok));
+        assert_eq!(*client.link_mut().stored.borrow(), msg);
+    }
+
+    #[test]
+    fn secure_requests_run_encrypted_and_decrypt_their_responses() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        link.capabilities = Some(device_caps(Features::ARQ | Features::SESSION, 0));
+        link.psk = Some(KEY);
+        let mut client = ArqClient::new(link, policy());
+        client.discover(0x01).unwrap();
+
+        assert!(matches!(
+            client.secure_request(0x01, 0x02, &[]),
+            Err(ClientError::Session(SessionError::NoSession))
+        ));
+        assert!(client.open_session(0x01, &KEY).unwrap());
+        let resp = client.secure_request(0x01, 0x02, &[]).unwrap();
+        assert_eq!((resp.cmd, resp.status()), (0x02, Some(Status::Ok)));
+        assert_eq!(executed.get(), 1);
+
+        // Payloads travel encrypted, so the device stores what was sent
+        let msg = message(MAX_PAYLOAD - SECURE_OVERHEAD);
+        let resp = client.secure_request(0x01, 0x30, &msg).unwrap();
+        assert_eq!(resp.status(), Some(Status::Ok));
+        assert_eq!(*client.link_mut().stored.borrow(), msg);
+        assert!(
+            client
+                .secure_request(0x01, 0x30, &message(MAX_PAYLOAD))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn sessions_need_the_devices_key() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        link.psk = Some(KEY);
+        let mut client = ArqClient::new(link, policy());
+        client.set_sequenced(true);
+
+        assert!(matches!(
+            client.open_session(0x01, &[0xA5; KEY_LEN]),
+            Err(ClientError::Session(SessionError::BadConfirm))
+        ));
+        assert!(!client.has_session());
+
+        // A device that lost the session (say, it reset) answers NOT_READY
+        assert!(client.open_session(0x01, &KEY).unwrap());
+        *client.link_mut().session.borrow_mut() = DeviceSessions::new();
+        let resp = client.secure_request(0x01, 0x02, &[]).unwrap();
+        assert_eq!(resp.status(), Some(Status::NotReady));
+
+        client.link_mut().psk = None;
+        assert!(!client.open_session(0x01, &KEY).unwrap());
+        assert_eq!(executed.get(), 0);
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 13) as u8).collect()
+    }
+
+    #[test]
+    fn large_requests_are_fragmented_and_reassembled() {
+        let executed = Cell::new(0);
+        // Lose the response to the second fragment; ARQ replays it.
+        let link = LossyLink::new(&executed, &[false, false, false, true]);
+        let mut client = ArqClient::new(link, policy());
+        client.set_sequenced(true);
+
+        let msg = message(600);
+        let resp = client.request_large(0x01, 0x30, &msg).unwrap();
+        assert_eq!(resp.cmd, 0x30);
+        assert_eq!(resp.status(), Some(Status::Ok));
+        assert_eq!(*client.link_mut().stored.borrow(), msg);
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected_on_the_first_fragment() {
+        let executed = Cell::new(0);
+        let mut client = ArqClient::new(LossyLink::new(&executed, &[]), policy());
+
+        let resp = client.request_large(0x01, 0x30, &message(2000)).unwrap();
+        assert_eq!(resp.cmd, CMD_FRAGMENT);
+        assert_eq!(resp.status(), Some(Status::BadLen));
+        assert!(client.link_mut().stored.borrow().is_empty());
+    }
+
+    #[test]
+    fn large_requests_follow_the_device_capabilities() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        link.capabilities = Some(device_caps(Features::NONE, 0));
+        let mut client = ArqClient::new(link, policy());
+        client.discover(0x01).unwrap();
+
+        // Fits one frame: sent as a plain request, no fragmentation needed.
+        let resp = client.request_large(0x01, 0x30, &message(100)).unwrap();
+        assert_eq!(resp.status(), Some(Status::Ok));
+        assert_eq!(*client.link_mut().stored.borrow(), message(100));
+        assert!(!client.link_mut().reassembler.in_progress());
+
+        // Needs fragments the device cannot reassemble: refused up front.
+        assert!(matches!(
+            client.request_large(0x01, 0x30, &message(600)),
+            Err(ClientError::Fragment(FragmentError::TooLarge))
+        ));
+        assert_eq!(*client.link_mut().stored.borrow(), message(100));
+    }
+
+    #[test]
+    fn receives_a_streamed_message_from_the_device() {
+        let executed = Cell::new(0);
+        let mut link = LossyLink::new(&executed, &[]);
+        let msg = message(700);
+
+        let mut tx = Fragmenter::new(0x31, msg.len() as u32).unwrap();
+        let mut sent = 0;
+        while let Some(n) = tx.next_len() {
+            let payload = tx.next_fragment(&msg[sent..sent + n]).unwrap();
+            link.inbox
+                .extend(build_frame::<MAX_FRAME>(0x01, CMD_FRAGMENT, &payload).unwrap());
+            sent += n;
+        }
+
+        let mut client = ArqClient::new(link, policy());
+        let mut out = Vec::new();
+        assert_eq!(client.receive_stream(0x01, &mut out).unwrap(), 0x31);
+        assert_eq!(out, msg);
+    }
+
+    /// Loopback device that answers every COBS request with an ACK.
+    struct CobsEcho {
+        parser: Parser,
+        inbox: VecDeque<u8>,
+    }
+
+    impl Link for CobsEcho {
+        fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.parser.push_bytes(bytes);
+            while let Ok(Some(frame)) = self.parser.next_frame() {
+                let ack = crate::build_ack::<MAX_FRAME>(frame.addr, frame.cmd).unwrap();
+                self.inbox.extend(Framing::Cobs.reframe(&ack).unwrap());
+            }
+            Ok(())
+        }
+
+        fn recv(&mut self, buf: &mut [u8], _timeout: Duration) -> io::Result<usize> {
+            let n = buf.len().min(self.inbox.len());
+            for (slot, byte) in buf.iter_mut().zip(self.inbox.drain(..n)) {
+                *slot = byte;
+            }
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn talks_cobs_when_configured() {
+        let link = CobsEcho {
+            parser: Parser::with_framing(Framing::Cobs),
+            inbox: VecDeque::new(),
+        };
+        let mut client = ArqClient::new(link, policy()).with_framing(Framing::Cobs);
+
+        assert!(!client.negotiate(0x01).unwrap());
+        let resp = client.request(0x01, 0x02, &[0x00, 0xA5]).unwrap();
+        assert_eq!((resp.addr, resp.cmd), (0x01, 0x02));
+        assert_eq!(resp.status(), Some(Status::Ok));
+    }
+}
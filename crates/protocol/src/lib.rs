@@ -0,0 +1,471 @@
+//! Frame protocol shared by the firmware and host tools.
+//!
+//! Framing (transport-agnostic, Modbus-inspired):
+//!   [ STX, LEN, ADDR, CMD, <PAYLOAD...>, CRCL, CRCH ]
+//!
+//! Where:
+//! - STX: 1 byte start marker (for resync)
+//! - LEN: 1 byte = number of bytes from ADDR through end of PAYLOAD
+//!   (so LEN >= 2 because it must include ADDR + CMD)
+//! - ADDR: 1 byte address (node, broadcast or group, see [`address`])
+//! - CMD:  1 byte command/function
+//! - PAYLOAD: 0..253 bytes (because LEN is u8 and includes ADDR+CMD)
+//! - CRC: CRC-16/Modbus over everything from STX through end of PAYLOAD
+//!   appended little-endian as CRCL then CRCH (Modbus convention)
+//!
+//! Sequenced frames (opt-in reliability layer, see [`arq`]) use a different
+//! start marker and carry one extra SEQ byte, which is not counted in LEN:
+//!   [ STX_SEQ, LEN, SEQ, ADDR, CMD, <PAYLOAD...>, CRCL, CRCH ]
+//!
+//! Notes:
+//! - Parser is stream-based (USB/UART chunks are arbitrary).
+//! - Parser resyncs by scanning for STX (or STX_SEQ).
+//! - Each decoded frame consumes exactly LEN + 4 bytes (LEN + 5 if sequenced).
+//! - No timing dependence by default. With an [`RxTiming`] the parser drops
+//!   partial frames after a silence, or keeps true Modbus RTU frame boundaries.
+//! - A transport can use COBS packets instead (see [`cobs`] and [`Framing`]);
+//!   the ADDR/CMD/PAYLOAD/CRC body and [`Frame`] stay the same.
+//! - Requests may end their payload with a signature (see [`auth`]), or be
+//!   encrypted inside a session (see [`session`]).
+//!
+//! Suggested semantics (optional, but handy):
+//! - Setters respond with payload: [STATUS] (see [`Status`] for the codes)
+//! - Getters respond with payload: [STATUS, BYTECOUNT, <DATA...>]
+//! - Events (CMD 0x80..=0xBF) are sent unrequested, see [`event`]
+//! - Request payloads and getter data can be typed, see [`payload`]
+//!
+//! CMD codes, command limits, payload types and the status, event and feature
+//! tables come from `protocol.toml` (see [`schema`]); on the host, [`client`]
+//! has a typed method per command.
+//!
+//! [`modbus`] is a separate, standard Modbus RTU slave for talking to PLCs
+//! and SCADA tools; it shares only the CRC with the framed protocol.
+//!
+//! The crate is `no_std` by default so the firmware can use it directly.
+//! [`transport::run_command_server`] runs the parse/dispatch loop over any
+//! byte transport (USB CDC, UART, TCP, a test pipe); [`rs485`] handles the
+//! driver enable of a half-duplex RS-485 transceiver.
+//!
+//! Enable the `std` feature on host builds for `std::error::Error` impls and
+//! the [`host`] client.
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+// Lets `#[derive(Payload)]` output (which names `::protocol`) compile in here too
+extern crate self as protocol;
+
+use heapless::Vec;
+
+pub mod address;
+mod aead;
+pub mod arq;
+pub mod auth;
+pub mod capabilities;
+#[cfg(feature = "std")]
+pub mod client;
+pub mod cobs;
+pub mod dispatch;
+pub mod event;
+pub mod fragment;
+#[cfg(feature = "std")]
+pub mod host;
+pub mod modbus;
+mod parser;
+pub mod payload;
+pub mod pipeline;
+pub mod rs485;
+pub mod schema;
+pub mod session;
+mod stats;
+mod status;
+pub mod transport;
+mod writer;
+
+pub use parser::{ParseError, Parser, RxTiming};
+pub use stats::LinkStats;
+pub use status::{BuildError, Status};
+pub use writer::{FrameWriter, Response};
+
+pub const STX: u8 = 0xA5;
+
+// Start marker for sequenced frames (STX, LEN, SEQ, ...)
+pub const STX_SEQ: u8 = 0xA6;
+
+// LEN is u8 and includes ADDR+CMD, so payload max is 255 - 2 = 253.
+pub const MAX_PAYLOAD: usize = 253;
+
+// Total frame bytes = STX(1) + LEN(1) + [SEQ(1)] + (LEN bytes) + CRC(2) = LEN + 4 (+1)
+// Max total = 255 + 5 = 260
+pub const MAX_FRAME: usize = 260;
+
+// Getter responses carry [STATUS, BYTECOUNT, data...], so data max is 253 - 2 = 251.
+pub const MAX_DATA: usize = MAX_PAYLOAD - 2;
+
+// Internal stream buffer capacity (can be bigger than MAX_FRAME to hold multiple frames/chunks)
+pub const STREAM_BUF_CAP: usize = 512;
+
+/// How frames are delimited on a transport. Chosen once, when the transport is set up.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Framing {
+    /// [STX, LEN, ...] frames.
+    #[default]
+    StxLen,
+    /// COBS packets ending in 0x00, see [`cobs`].
+    Cobs,
+}
+
+impl Framing {
+    /// Convert a frame from the `build_*` functions (which always use STX
+    /// framing) into this framing.
+    pub fn reframe(self, frame: &[u8]) -> Result<Vec<u8, MAX_FRAME>, BuildError> {
+        match self {
+            Framing::StxLen => Vec::from_slice(frame).map_err(|_| BuildError::OutputTooSmall),
+            Framing::Cobs => cobs::from_stx_frame(frame),
+        }
+    }
+}
+
+/// A decoded frame. The payload borrows the parser's buffer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Frame<'a> {
+    /// Sequence number, for frames sent with `STX_SEQ`.
+    pub seq: Option<u8>,
+    pub addr: u8,
+    pub cmd: u8,
+    pub payload: &'a [u8],
+}
+
+impl Frame<'_> {
+    /// Convenience if you use "status-first" responses:
+    pub fn status(&self) -> Option<u8> {
+        self.payload.first().copied()
+    }
+}
+
+// -----------------------------
+// Frame builders
+// -----------------------------
+
+/// Build a frame: [STX, LEN, ADDR, CMD, payload..., CRCL, CRCH]
+///
+/// Returns a heapless Vec that you can pass directly to your transport write().
+pub fn build_frame<const OUT_CAP: usize>(
+    addr: u8,
+    cmd: u8,
+    payload: &[u8],
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    build_frame_seq::<OUT_CAP>(None, addr, cmd, payload)
+}
+
+/// Build a plain frame (`seq == None`) or a sequenced one:
+/// [STX_SEQ, LEN, SEQ, ADDR, CMD, payload..., CRCL, CRCH]
+pub fn build_frame_seq<const OUT_CAP: usize>(
+    seq: Option<u8>,
+    addr: u8,
+    cmd: u8,
+    payload: &[u8],
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    collect(|buf| {
+        let mut writer = FrameWriter::new(buf, seq, addr, cmd, payload.len())?;
+        writer.write(payload)?;
+        writer.finish()
+    })
+}
+
+/// Response frame to a request with `seq`, `addr` and `cmd` (see [`Response`]).
+pub fn build_response<const OUT_CAP: usize>(
+    seq: Option<u8>,
+    addr: u8,
+    cmd: u8,
+    response: &Response<'_>,
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    collect(|buf| response.write(buf, seq, addr, cmd))
+}
+
+/// Run a [`FrameWriter`] over the whole capacity of a Vec, then trim it.
+pub(crate) fn collect<const OUT_CAP: usize>(
+    write: impl FnOnce(&mut [u8]) -> Result<&[u8], BuildError>,
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    let mut out = Vec::<u8, OUT_CAP>::new();
+    out.resize(OUT_CAP, 0)
+        .map_err(|_| BuildError::OutputTooSmall)?;
+    let len = write(&mut out)?.len();
+    out.truncate(len);
+    Ok(out)
+}
+
+/// ACK for setters (payload: [STATUS=OK])
+pub fn build_ack<const OUT_CAP: usize>(addr: u8, cmd: u8) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    build_response::<OUT_CAP>(None, addr, cmd, &Response::Ack)
+}
+
+/// ERROR for setters (payload: [STATUS=status])
+pub fn build_err<const OUT_CAP: usize>(
+    addr: u8,
+    cmd: u8,
+    status: Status,
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    build_response::<OUT_CAP>(None, addr, cmd, &Response::Err(status))
+}
+
+/// NAK for a frame that failed to parse (payload: [STATUS=BAD_FRAME, reason])
+///
+/// Only possible when the parser recovered ADDR/CMD, see [`ParseError::header`].
+pub fn build_nak<const OUT_CAP: usize>(
+    err: ParseError,
+) -> Result<Option<Vec<u8, OUT_CAP>>, BuildError> {
+    let Some((addr, cmd)) = err.header() else {
+        return Ok(None);
+    };
+    build_frame::<OUT_CAP>(addr, cmd, &[Status::BadFrame.as_u8(), err.code()]).map(Some)
+}
+
+/// Getter response (payload: [STATUS=OK, BYTECOUNT, data...])
+pub fn build_data<const OUT_CAP: usize>(
+    addr: u8,
+    cmd: u8,
+    data: &[u8],
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    build_response::<OUT_CAP>(None, addr, cmd, &Response::Data(data))
+}
+
+/// Getter response payload: [STATUS=OK, BYTECOUNT, data...]
+///
+/// A copy for callers that need the payload itself; [`Response::Data`] writes
+/// a frame without it.
+pub fn data_payload(data: &[u8]) -> Result<Vec<u8, MAX_PAYLOAD>, BuildError> {
+    // payload will be 2 + data.len(); this also keeps BYTECOUNT within a u8
+    if data.len() > MAX_DATA {
+        return Err(BuildError::PayloadTooLarge);
+    }
+
+    // Build payload into a small local buffer (stack) with heapless Vec
+    let mut payload = Vec::<u8, MAX_PAYLOAD>::new();
+    payload
+        .extend_from_slice(&[Status::Ok.as_u8(), data.len() as u8]) // STATUS, BYTECOUNT
+        .map_err(|_| BuildError::PayloadTooLarge)?;
+    payload
+        .extend_from_slice(data)
+        .map_err(|_| BuildError::PayloadTooLarge)?;
+    Ok(payload)
+}
+
+// -----------------------------
+// CRC-16/Modbus
+// -----------------------------
+
+/// CRC-16/Modbus: poly 0xA001 (reflected), init 0xFFFF
+///
+/// Table-driven, one lookup per byte; bit-identical to [`crc16_modbus_bitwise`].
+///
+/// The RP2350 DMA sniffer cannot take this over: it only implements CRC-32
+/// and CRC-16-CCITT (poly 0x1021), and no output transform turns either into
+/// the Modbus polynomial.
+pub fn crc16_modbus(data: &[u8]) -> u16 {
+    let mut crc = Crc16::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Incremental [`crc16_modbus`], for frames written a piece at a time.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Crc16(u16);
+
+impl Default for Crc16 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc16 {
+    pub const fn new() -> Self {
+        Self(0xFFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = (self.0 >> 8) ^ CRC16_TABLE[((self.0 ^ b as u16) & 0xFF) as usize];
+        }
+    }
+
+    pub const fn finish(&self) -> u16 {
+        self.0
+    }
+}
+
+/// Reference bit-by-bit CRC-16/Modbus, 8 iterations per byte and no table.
+/// Kept for equivalence tests and benchmarks.
+pub fn crc16_modbus_bitwise(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            let lsb = (crc & 0x0001) != 0;
+            crc >>= 1;
+            if lsb {
+                crc ^= 0xA001;
+            }
+        }
+    }
+    crc
+}
+
+/// CRC of each single byte value from a zero register, built at compile time (512 bytes).
+static CRC16_TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u16;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x0001 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// -----------------------------
+// CRC-32 (whole-message check for fragmented transfers)
+// -----------------------------
+
+/// Incremental CRC-32/ISO-HDLC (the zlib/PNG CRC): poly 0xEDB88320 (reflected),
+/// init and final XOR 0xFFFFFFFF.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 ^= b as u32;
+            for _ in 0..8 {
+                let lsb = (self.0 & 1) != 0;
+                self.0 >>= 1;
+                if lsb {
+                    self.0 ^= 0xEDB8_8320;
+                }
+            }
+        }
+    }
+
+    pub const fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+/// CRC-32 of `data` in one go.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Test fixture shared by the module tests: a request to node 0x01.
+#[cfg(test)]
+pub(crate) fn test_frame(seq: Option<u8>, cmd: u8, payload: &[u8]) -> Frame<'_> {
+    Frame {
+        seq,
+        addr: 0x01,
+        cmd,
+        payload,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_modbus_check_value() {
+        assert_eq!(crc16_modbus(b"123456789"), 0x4B37);
+        assert_eq!(crc16_modbus(&[]), 0xFFFF);
+        assert_eq!(crc16_modbus_bitwise(b"123456789"), 0x4B37);
+    }
+
+    #[test]
+    fn crc16_table_matches_bitwise_for_every_byte() {
+        for b in 0..=255u8 {
+            assert_eq!(crc16_modbus(&[b]), crc16_modbus_bitwise(&[b]));
+            assert_eq!(
+                crc16_modbus(&[0x01, b, 0xFF]),
+                crc16_modbus_bitwise(&[0x01, b, 0xFF])
+            );
+        }
+    }
+
+    #[test]
+    fn crc32_check_value_and_incremental_updates() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn build_ack_matches_documented_bytes() {
+        let frame = build_ack::<16>(0x01, 0x02).unwrap();
+        assert_eq!(&frame[..], &[0xA5, 0x03, 0x01, 0x02, 0x00, 0x38, 0xFD]);
+    }
+
+    #[test]
+    fn build_data_prefixes_status_and_bytecount() {
+        let frame = build_data::<16>(0x01, 0x20, &[0xAA, 0xBB]).unwrap();
+        assert_eq!(
+            &frame[..8],
+            &[STX, 0x06, 0x01, 0x20, 0x00, 0x02, 0xAA, 0xBB]
+        );
+    }
+
+    #[test]
+    fn build_frame_rejects_oversized_payload_and_output() {
+        assert_eq!(
+            build_frame::<MAX_FRAME>(0x01, 0x01, &[0; MAX_PAYLOAD + 1]),
+            Err(BuildError::PayloadTooLarge)
+        );
+        assert_eq!(
+            build_frame::<6>(0x01, 0x01, &[0x00]),
+            Err(BuildError::OutputTooSmall)
+        );
+        assert!(build_frame::<MAX_FRAME>(0x01, 0x01, &[0; MAX_PAYLOAD]).is_ok());
+        assert_eq!(
+            build_data::<MAX_FRAME>(0x01, 0x20, &[0; MAX_DATA + 1]),
+            Err(BuildError::PayloadTooLarge)
+        );
+    }
+
+    #[test]
+    fn build_nak_needs_a_recovered_header() {
+        let nak = build_nak::<16>(ParseError::CrcMismatch {
+            addr: 0x01,
+            cmd: 0x02,
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(&nak[..6], &[STX, 0x04, 0x01, 0x02, 0x08, 0x03]);
+        assert_eq!(build_nak::<16>(ParseError::LenTooSmall), Ok(None));
+    }
+
+    #[test]
+    fn build_err_carries_status_byte() {
+        let frame = build_err::<16>(0x01, 0x7E, Status::BadCmd).unwrap();
+        assert_eq!(frame[4], 0x02);
+    }
+}
@@ -0,0 +1,584 @@
+//! Modbus RTU slave.
+//!
+//! Unlike the framed protocol above, this is standard Modbus, so any master
+//! (PLC, SCADA package, `mbpoll`, ...) can read and write a [`RegisterMap`].
+//!
+//! RTU ADU: [ UNIT, FUNCTION, <DATA...>, CRCL, CRCH ], same CRC-16/Modbus as
+//! the framed protocol. ADUs are delimited by at least 3.5 character times of
+//! silence; [`RtuFramer`] finds them from byte arrival times.
+//!
+//! Supported function codes:
+//! - 0x01 Read Coils, 0x02 Read Discrete Inputs
+//! - 0x03 Read Holding Registers, 0x04 Read Input Registers
+//! - 0x05 Write Single Coil, 0x06 Write Single Register
+//! - 0x0F Write Multiple Coils, 0x10 Write Multiple Registers
+//!
+//! Anything else gets exception 01 (ILLEGAL FUNCTION). Requests to unit 0
+//! (broadcast) run writes without answering.
+
+use heapless::Vec;
+
+use crate::crc16_modbus;
+
+/// Largest RTU ADU: UNIT + 253-byte PDU + CRC.
+pub const MAX_ADU: usize = 256;
+
+/// Unit ID that addresses every slave; writes run but nobody answers.
+pub const BROADCAST: u8 = 0;
+
+pub const READ_COILS: u8 = 0x01;
+pub const READ_DISCRETE_INPUTS: u8 = 0x02;
+pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const READ_INPUT_REGISTERS: u8 = 0x04;
+pub const WRITE_SINGLE_COIL: u8 = 0x05;
+pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const WRITE_MULTIPLE_COILS: u8 = 0x0F;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// Modbus exception codes (sent as [FUNCTION | 0x80, CODE]).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Exception {
+    IllegalFunction = 0x01,
+    IllegalDataAddress = 0x02,
+    IllegalDataValue = 0x03,
+    ServerDeviceFailure = 0x04,
+}
+
+/// The four Modbus data tables. Reads return `None` for addresses that do
+/// not exist; writes may also refuse a value with `IllegalDataValue`.
+pub trait RegisterMap {
+    fn coil(&self, addr: u16) -> Option<bool>;
+    fn discrete_input(&self, addr: u16) -> Option<bool>;
+    fn input_register(&self, addr: u16) -> Option<u16>;
+    fn holding_register(&self, addr: u16) -> Option<u16>;
+    fn write_coil(&mut self, addr: u16, value: bool) -> Result<(), Exception>;
+    fn write_holding_register(&mut self, addr: u16, value: u16) -> Result<(), Exception>;
+}
+
+/// Array-backed register map. Every write is accepted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Registers<
+    const COILS: usize,
+    const DISCRETE: usize,
+    const INPUTS: usize,
+    const HOLDING: usize,
+> {
+    pub coils: [bool; COILS],
+    pub discrete_inputs: [bool; DISCRETE],
+    pub input_registers: [u16; INPUTS],
+    pub holding_registers: [u16; HOLDING],
+}
+
+impl<const C: usize, const D: usize, const I: usize, const H: usize> Default
+    for Registers<C, D, I, H>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const C: usize, const D: usize, const I: usize, const H: usize> Registers<C, D, I, H> {
+    pub const fn new() -> Self {
+        Self {
+            coils: [false; C],
+            discrete_inputs: [false; D],
+            input_registers: [0; I],
+            holding_registers: [0; H],
+        }
+    }
+}
+
+impl<const C: usize, const D: usize, const I: usize, const H: usize> RegisterMap
+    for Registers<C, D, I, H>
+{
+    fn coil(&self, addr: u16) -> Option<bool> {
+        self.coils.get(addr as usize).copied()
+    }
+
+    fn discrete_input(&self, addr: u16) -> Option<bool> {
+        self.discrete_inputs.get(addr as usize).copied()
+    }
+
+    fn input_register(&self, addr: u16) -> Option<u16> {
+        self.input_registers.get(addr as usize).copied()
+    }
+
+    fn holding_register(&self, addr: u16) -> Option<u16> {
+        self.holding_registers.get(addr as usize).copied()
+    }
+
+    fn write_coil(&mut self, addr: u16, value: bool) -> Result<(), Exception> {
+        let slot = self
+            .coils
+            .get_mut(addr as usize)
+            .ok_or(Exception::IllegalDataAddress)?;
+        *slot = value;
+        Ok(())
+    }
+
+    fn write_holding_register(&mut self, addr: u16, value: u16) -> Result<(), Exception> {
+        let slot = self
+            .holding_registers
+            .get_mut(addr as usize)
+            .ok_or(Exception::IllegalDataAddress)?;
+        *slot = value;
+        Ok(())
+    }
+}
+
+/// Handle one request ADU addressed to `unit` (or broadcast).
+///
+/// Returns the response ADU, or `None` when nothing must be sent: bad CRC,
+/// another unit's request, or a broadcast.
+pub fn handle_adu(map: &mut impl RegisterMap, unit: u8, adu: &[u8]) -> Option<Vec<u8, MAX_ADU>> {
+    if adu.len() < 4 {
+        return None;
+    }
+    let (body, crc) = adu.split_at(adu.len() - 2);
+    if crc16_modbus(body).to_le_bytes() != [crc[0], crc[1]] {
+        return None;
+    }
+    let (request_unit, pdu) = (body[0], &body[1..]);
+    if request_unit != unit && request_unit != BROADCAST {
+        return None;
+    }
+
+    let mut resp: Vec<u8, MAX_ADU> = Vec::new();
+    // UNIT + PDU (at most 253 bytes) + CRC fits MAX_ADU.
+    let _ = resp.push(unit);
+    if let Err(exception) = handle_pdu(map, pdu, &mut resp) {
+        resp.truncate(1);
+        let _ = resp.push(pdu[0] | 0x80);
+        let _ = resp.push(exception as u8);
+    }
+    if request_unit == BROADCAST {
+        return None;
+    }
+
+    let crc = crc16_modbus(&resp);
+    let _ = resp.extend_from_slice(&crc.to_le_bytes());
+    Some(resp)
+}
+
+/// Run one PDU, appending the response PDU to `resp`.
+fn handle_pdu(
+    map: &mut impl RegisterMap,
+    pdu: &[u8],
+    resp: &mut Vec<u8, MAX_ADU>,
+) -> Result<(), Exception> {
+    let function = pdu[0];
+    let data = &pdu[1..];
+    let word = |i: usize| -> Result<u16, Exception> {
+        data.get(i..i + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or(Exception::IllegalDataValue)
+    };
+    let push = |resp: &mut Vec<u8, MAX_ADU>, bytes: &[u8]| {
+        resp.extend_from_slice(bytes)
+            .map_err(|_| Exception::ServerDeviceFailure)
+    };
+
+    match function {
+        READ_COILS | READ_DISCRETE_INPUTS => {
+            let (start, count) = (word(0)?, word(2)?);
+            check_len(data, 4)?;
+            check_count(count, 2000)?;
+            let read = |addr| match function {
+                READ_COILS => map.coil(addr),
+                _ => map.discrete_input(addr),
+            };
+            let addrs = range(start, count)?;
+
+            push(resp, &[function, count.div_ceil(8) as u8])?;
+            let mut byte = 0u8;
+            for (i, addr) in addrs.enumerate() {
+                if read(addr).ok_or(Exception::IllegalDataAddress)? {
+                    byte |= 1 << (i % 8);
+                }
+                if i % 8 == 7 || i + 1 == count as usize {
+                    push(resp, &[byte])?;
+                    byte = 0;
+                }
+            }
+        }
+        READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+            let (start, count) = (word(0)?, word(2)?);
+            check_len(data, 4)?;
+            check_count(count, 125)?;
+            let read = |addr| match function {
+                READ_HOLDING_REGISTERS => map.holding_register(addr),
+                _ => map.input_register(addr),
+            };
+
+            push(resp, &[function, (count * 2) as u8])?;
+            for addr in range(start, count)? {
+                let value = read(addr).ok_or(Exception::IllegalDataAddress)?;
+                push(resp, &value.to_be_bytes())?;
+            }
+        }
+        WRITE_SINGLE_COIL => {
+            let (addr, value) = (word(0)?, word(2)?);
+            check_len(data, 4)?;
+            let value = match value {
+                0xFF00 => true,
+                0x0000 => false,
+                _ => return Err(Exception::IllegalDataValue),
+            };
+            map.coil(addr).ok_or(Exception::IllegalDataAddress)?;
+            map.write_coil(addr, value)?;
+            push(resp, pdu)?; // echo
+        }
+        WRITE_SINGLE_REGISTER => {
+            let (addr, value) = (word(0)?, word(2)?);
+            check_len(data, 4)?;
+            map.holding_register(addr)
+                .ok_or(Exception::IllegalDataAddress)?;
+            map.write_holding_register(addr, value)?;
+            push(resp, pdu)?; // echo
+        }
+        WRITE_MULTIPLE_COILS => {
+            let (start, count) = (word(0)?, word(2)?);
+            check_count(count, 0x07B0)?;
+            let bits = data.get(5..).ok_or(Exception::IllegalDataValue)?;
+            if data[4] as usize != count.div_ceil(8) as usize || bits.len() != data[4] as usize {
+                return Err(Exception::IllegalDataValue);
+            }
+            for addr in range(start, count)? {
+                map.coil(addr).ok_or(Exception::IllegalDataAddress)?;
+            }
+            for (i, addr) in range(start, count)?.enumerate() {
+                map.write_coil(addr, bits[i / 8] & (1 << (i % 8)) != 0)?;
+            }
+            push(resp, &pdu[..5])?;
+        }
+        WRITE_MULTIPLE_REGISTERS => {
+            let (start, count) = (word(0)?, word(2)?);
+            check_count(count, 123)?;
+            let values = data.get(5..).ok_or(Exception::IllegalDataValue)?;
+            if data[4] as usize != count as usize * 2 || values.len() != data[4] as usize {
+                return Err(Exception::IllegalDataValue);
+            }
+            for addr in range(start, count)? {
+                map.holding_register(addr)
+                    .ok_or(Exception::IllegalDataAddress)?;
+            }
+            for (addr, value) in range(start, count)?.zip(values.chunks_exact(2)) {
+                map.write_holding_register(addr, u16::from_be_bytes([value[0], value[1]]))?;
+            }
+            push(resp, &pdu[..5])?;
+        }
+        _ => return Err(Exception::IllegalFunction),
+    }
+    Ok(())
+}
+
+fn check_len(data: &[u8], len: usize) -> Result<(), Exception> {
+    if data.len() == len {
+        Ok(())
+    } else {
+        Err(Exception::IllegalDataValue)
+    }
+}
+
+fn check_count(count: u16, max: u16) -> Result<(), Exception> {
+    if (1..=max).contains(&count) {
+        Ok(())
+    } else {
+        Err(Exception::IllegalDataValue)
+    }
+}
+
+/// Addresses `start..start + count`, or IllegalDataAddress if that passes 0xFFFF.
+fn range(start: u16, count: u16) -> Result<core::ops::Range<u16>, Exception> {
+    let end = start
+        .checked_add(count - 1)
+        .ok_or(Exception::IllegalDataAddress)?;
+    Ok(start..end + 1)
+}
+
+/// RTU inter-character (t1.5) and inter-frame (t3.5) silences, in microseconds.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RtuTiming {
+    /// A longer gap inside a frame makes it invalid. `None` skips the check,
+    /// for receivers that only see bytes in FIFO-sized batches.
+    pub t1_5_us: Option<u64>,
+    /// A gap this long ends a frame.
+    pub t3_5_us: u64,
+}
+
+impl RtuTiming {
+    /// Timing for `baud` with 11-bit characters (start + 8 data + parity/stop + stop).
+    /// Above 19200 baud the spec fixes t1.5 = 750 µs and t3.5 = 1750 µs.
+    pub const fn for_baud(baud: u32) -> Self {
+        if baud > 19_200 {
+            return Self {
+                t1_5_us: Some(750),
+                t3_5_us: 1_750,
+            };
+        }
+        let baud = baud as u64;
+        Self {
+            t1_5_us: Some(16_500_000_u64.div_ceil(baud)),
+            t3_5_us: 38_500_000_u64.div_ceil(baud),
+        }
+    }
+}
+
+/// Splits a byte stream into RTU ADUs by silent intervals.
+///
+/// Timestamps are in microseconds from any monotonic clock. Call
+/// [`poll`](Self::poll) before pushing each batch of bytes and whenever the
+/// line has been quiet for t3.5.
+pub struct RtuFramer {
+    timing: RtuTiming,
+    buf: Vec<u8, MAX_ADU>,
+    last_us: u64,
+    // Frame broke the t1.5 rule or overflowed; drop it once it ends.
+    broken: bool,
+}
+
+impl RtuFramer {
+    pub const fn new(timing: RtuTiming) -> Self {
+        Self {
+            timing,
+            buf: Vec::new(),
+            last_us: 0,
+            broken: false,
+        }
+    }
+
+    pub fn timing(&self) -> RtuTiming {
+        self.timing
+    }
+
+    /// No frame in progress.
+    pub fn is_idle(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Drop the frame in progress (e.g. after a UART framing or parity error).
+    pub fn discard(&mut self) {
+        self.buf.clear();
+        self.broken = false;
+    }
+
+    /// Add one received byte.
+    pub fn push(&mut self, byte: u8, now_us: u64) {
+        if !self.buf.is_empty() {
+            let gap = now_us.saturating_sub(self.last_us);
+            if gap >= self.timing.t3_5_us {
+                // The previous frame ended without being polled.
+                self.discard();
+            } else if self.timing.t1_5_us.is_some_and(|t1_5| gap > t1_5) {
+                self.broken = true;
+            }
+        }
+        if self.buf.push(byte).is_err() {
+            self.broken = true;
+        }
+        self.last_us = now_us;
+    }
+
+    /// The finished ADU, once the line has been silent for t3.5.
+    pub fn poll(&mut self, now_us: u64) -> Option<Vec<u8, MAX_ADU>> {
+        if self.buf.is_empty() || now_us.saturating_sub(self.last_us) < self.timing.t3_5_us {
+            return None;
+        }
+        let frame = core::mem::take(&mut self.buf);
+        let broken = core::mem::take(&mut self.broken);
+        (!broken).then_some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Map = Registers<20, 4, 2, 4>;
+
+    fn adu(bytes: &[u8]) -> std::vec::Vec<u8> {
+        let mut out = bytes.to_vec();
+        out.extend_from_slice(&crc16_modbus(bytes).to_le_bytes());
+        out
+    }
+
+    fn request(map: &mut Map, pdu: &[u8]) -> Option<std::vec::Vec<u8>> {
+        let req = adu(&[&[0x11], pdu].concat());
+        let resp = handle_adu(map, 0x11, &req)?;
+        let (body, crc) = resp.split_at(resp.len() - 2);
+        assert_eq!(crc, crc16_modbus(body).to_le_bytes());
+        assert_eq!(body[0], 0x11);
+        Some(body[1..].to_vec())
+    }
+
+    #[test]
+    fn crc_matches_reference_request() {
+        // Read one holding register from unit 1: the classic "01 03 00 00 00 01 84 0A".
+        assert_eq!(
+            adu(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]),
+            [0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x84, 0x0A]
+        );
+    }
+
+    #[test]
+    fn reads_bits_lsb_first() {
+        let mut map = Map::new();
+        map.coils[0] = true;
+        map.coils[9] = true;
+        map.discrete_inputs[3] = true;
+
+        assert_eq!(
+            request(&mut map, &[0x01, 0x00, 0x00, 0x00, 0x0A]),
+            Some(vec![0x01, 0x02, 0x01, 0x02])
+        );
+        assert_eq!(
+            request(&mut map, &[0x02, 0x00, 0x01, 0x00, 0x03]),
+            Some(vec![0x02, 0x01, 0x04])
+        );
+    }
+
+    #[test]
+    fn reads_registers_big_endian() {
+        let mut map = Map::new();
+        map.holding_registers = [0x1234, 0xABCD, 0, 0];
+        map.input_registers = [7, 0xFFFF];
+
+        assert_eq!(
+            request(&mut map, &[0x03, 0x00, 0x00, 0x00, 0x02]),
+            Some(vec![0x03, 0x04, 0x12, 0x34, 0xAB, 0xCD])
+        );
+        assert_eq!(
+            request(&mut map, &[0x04, 0x00, 0x01, 0x00, 0x01]),
+            Some(vec![0x04, 0x02, 0xFF, 0xFF])
+        );
+    }
+
+    #[test]
+    fn writes_single_and_multiple_values() {
+        let mut map = Map::new();
+
+        let pdu = [0x05, 0x00, 0x03, 0xFF, 0x00];
+        assert_eq!(request(&mut map, &pdu), Some(pdu.to_vec()));
+        assert!(map.coils[3]);
+
+        let pdu = [0x06, 0x00, 0x02, 0xBE, 0xEF];
+        assert_eq!(request(&mut map, &pdu), Some(pdu.to_vec()));
+        assert_eq!(map.holding_registers[2], 0xBEEF);
+
+        // Coils 8..=17: 0b1100_1101, 0b01
+        let pdu = [0x0F, 0x00, 0x08, 0x00, 0x0A, 0x02, 0xCD, 0x01];
+        assert_eq!(request(&mut map, &pdu), Some(pdu[..5].to_vec()));
+        let bits: std::vec::Vec<bool> = map.coils[8..18].to_vec();
+        assert_eq!(
+            bits,
+            [
+                true, false, true, true, false, false, true, true, true, false
+            ]
+        );
+
+        let pdu = [0x10, 0x00, 0x00, 0x00, 0x02, 0x04, 0x00, 0x0A, 0x01, 0x02];
+        assert_eq!(request(&mut map, &pdu), Some(pdu[..5].to_vec()));
+        assert_eq!(map.holding_registers[..2], [0x000A, 0x0102]);
+    }
+
+    #[test]
+    fn answers_with_standard_exceptions() {
+        let mut map = Map::new();
+
+        assert_eq!(
+            request(&mut map, &[0x2B, 0x0E, 0x01, 0x00]),
+            Some(vec![0xAB, 0x01])
+        );
+        assert_eq!(
+            request(&mut map, &[0x03, 0x00, 0x03, 0x00, 0x02]),
+            Some(vec![0x83, 0x02])
+        );
+        assert_eq!(
+            request(&mut map, &[0x03, 0x00, 0x00, 0x00, 0x00]),
+            Some(vec![0x83, 0x03])
+        );
+        assert_eq!(
+            request(&mut map, &[0x05, 0x00, 0x00, 0x12, 0x34]),
+            Some(vec![0x85, 0x03])
+        );
+        assert_eq!(
+            request(
+                &mut map,
+                &[0x10, 0x00, 0x00, 0x00, 0x02, 0x03, 0x00, 0x0A, 0x01]
+            ),
+            Some(vec![0x90, 0x03])
+        );
+        assert_eq!(
+            request(&mut map, &[0x01, 0xFF, 0xFF, 0x00, 0x02]),
+            Some(vec![0x81, 0x02])
+        );
+        // A failed multi-write leaves the map untouched.
+        assert_eq!(
+            request(
+                &mut map,
+                &[0x10, 0x00, 0x03, 0x00, 0x02, 0x04, 0x00, 0x01, 0x00, 0x02]
+            ),
+            Some(vec![0x90, 0x02])
+        );
+        assert_eq!(map.holding_registers[3], 0);
+    }
+
+    #[test]
+    fn stays_silent_for_other_units_bad_crc_and_broadcasts() {
+        let mut map = Map::new();
+        let read = adu(&[0x12, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(handle_adu(&mut map, 0x11, &read), None);
+
+        let mut bad = adu(&[0x11, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        bad[2] ^= 1;
+        assert_eq!(handle_adu(&mut map, 0x11, &bad), None);
+
+        let write = adu(&[BROADCAST, 0x06, 0x00, 0x01, 0x00, 0x2A]);
+        assert_eq!(handle_adu(&mut map, 0x11, &write), None);
+        assert_eq!(map.holding_registers[1], 0x2A);
+    }
+
+    #[test]
+    fn timing_follows_the_serial_line_spec() {
+        assert_eq!(
+            RtuTiming::for_baud(9_600),
+            RtuTiming {
+                t1_5_us: Some(1_719),
+                t3_5_us: 4_011
+            }
+        );
+        assert_eq!(RtuTiming::for_baud(115_200).t3_5_us, 1_750);
+    }
+
+    #[test]
+    fn framer_splits_on_silence_and_drops_broken_frames() {
+        let timing = RtuTiming::for_baud(9_600);
+        let mut framer = RtuFramer::new(timing);
+        let char_us = 1_146;
+
+        for (i, &b) in [0x11, 0x03, 0x00].iter().enumerate() {
+            framer.push(b, i as u64 * char_us);
+        }
+        assert_eq!(framer.poll(3 * char_us), None); // not silent long enough yet
+        assert_eq!(
+            framer.poll(2 * char_us + timing.t3_5_us).as_deref(),
+            Some(&[0x11, 0x03, 0x00][..])
+        );
+        assert!(framer.is_idle());
+
+        // A gap over t1.5 inside a frame invalidates it.
+        framer.push(0x11, 100_000);
+        framer.push(0x03, 100_000 + 2 * char_us + 500);
+        assert_eq!(framer.poll(200_000), None);
+
+        // Without the t1.5 check the same bytes form a frame.
+        let mut framer = RtuFramer::new(RtuTiming {
+            t1_5_us: None,
+            ..timing
+        });
+        framer.push(0x11, 0);
+        framer.push(0x03, 2 * char_us + 500);
+        assert_eq!(framer.poll(100_000).as_deref(), Some(&[0x11, 0x03][..]));
+    }
+}
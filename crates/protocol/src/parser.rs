@@ -0,0 +1,683 @@
+//! Stream parser for [STX, LEN, ...] frames.
+//!
+//! Both plain (`STX`) and sequenced (`STX_SEQ`, see [`crate::arq`]) frames are
+//! accepted; the start marker decides whether a SEQ byte follows LEN.
+//!
+//! Bytes are held in a fixed-capacity ring buffer. The first `MAX_FRAME`
+//! slots are mirrored past the end of the ring, so any candidate frame
+//! (at most `MAX_FRAME` bytes) starting anywhere in the ring can be read
+//! as one contiguous slice. Resync and frame consumption just move the
+//! head index; nothing is ever shifted.
+//!
+//! With [`Framing::Cobs`] the same ring holds COBS packets instead. Each one
+//! is decoded into a separate scratch buffer, which the frame then borrows.
+//!
+//! By default the parser ignores timing, so a half-received frame waits for
+//! its remaining bytes forever. With an [`RxTiming`] and timestamps passed
+//! to [`Parser::push_bytes_at`], a silence on the line drops it instead.
+
+use core::fmt;
+
+use crate::cobs::{self, MAX_BODY, MAX_PACKET};
+use crate::modbus::RtuTiming;
+use crate::{Frame, Framing, LinkStats, MAX_FRAME, STREAM_BUF_CAP, STX, STX_SEQ, crc16_modbus};
+
+// The mirror trick only works if a whole frame fits in the ring.
+const _: () = assert!(MAX_FRAME <= STREAM_BUF_CAP);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    LenTooSmall,
+    LenTooBig,
+    /// The candidate was complete, so its (unverified) ADDR and CMD are known.
+    CrcMismatch {
+        addr: u8,
+        cmd: u8,
+    },
+    /// COBS packet that does not decode.
+    BadEncoding,
+}
+
+impl ParseError {
+    /// Reason byte carried in NAK frames.
+    pub const fn code(&self) -> u8 {
+        match self {
+            ParseError::LenTooSmall => 0x01,
+            ParseError::LenTooBig => 0x02,
+            ParseError::CrcMismatch { .. } => 0x03,
+            ParseError::BadEncoding => 0x04,
+        }
+    }
+
+    /// ADDR and CMD of the rejected candidate, if they could be recovered.
+    pub const fn header(&self) -> Option<(u8, u8)> {
+        match *self {
+            ParseError::CrcMismatch { addr, cmd } => Some((addr, cmd)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::LenTooSmall => f.write_str("LEN smaller than ADDR + CMD"),
+            ParseError::LenTooBig => f.write_str("LEN exceeds maximum frame size"),
+            ParseError::CrcMismatch { .. } => f.write_str("CRC mismatch"),
+            ParseError::BadEncoding => f.write_str("invalid COBS encoding"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// How [`Parser`] uses byte arrival times.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RxTiming {
+    /// Partial frames wait for their remaining bytes indefinitely.
+    None,
+    /// A gap of at least `gap_us` drops the unfinished frame before it.
+    Gap { gap_us: u64 },
+    /// Modbus RTU boundaries: a t3.5 silence ends every frame, and after
+    /// garbage or a bad frame the parser skips to the next silence instead
+    /// of scanning for STX (an STX inside a payload never starts a frame).
+    Rtu { t3_5_us: u64 },
+}
+
+impl RxTiming {
+    /// RTU boundaries for a UART at `baud` (see [`RtuTiming::for_baud`]).
+    pub const fn rtu(baud: u32) -> Self {
+        RxTiming::Rtu {
+            t3_5_us: RtuTiming::for_baud(baud).t3_5_us,
+        }
+    }
+
+    /// Silence that ends a frame, if any.
+    pub const fn gap_us(&self) -> Option<u64> {
+        match *self {
+            RxTiming::None => None,
+            RxTiming::Gap { gap_us } => Some(gap_us),
+            RxTiming::Rtu { t3_5_us } => Some(t3_5_us),
+        }
+    }
+}
+
+/// Stream parser for [STX, LEN, ...] frames (or COBS packets).
+pub struct Parser {
+    framing: Framing,
+    timing: RxTiming,
+    // Arrival time of the last pushed byte, for `timing`.
+    last_rx_us: u64,
+    // RTU only: drop everything until the next silence.
+    skip_to_gap: bool,
+    // Ring storage plus a mirror of the first MAX_FRAME slots.
+    buf: [u8; STREAM_BUF_CAP + MAX_FRAME],
+    // Index of the oldest unread byte (always < STREAM_BUF_CAP).
+    head: usize,
+    // Number of unread bytes.
+    len: usize,
+    // Decoded COBS body the current frame borrows from.
+    decoded: [u8; MAX_BODY],
+    stats: LinkStats,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self::with_framing(Framing::StxLen)
+    }
+
+    pub const fn with_framing(framing: Framing) -> Self {
+        Self {
+            framing,
+            timing: RxTiming::None,
+            last_rx_us: 0,
+            skip_to_gap: false,
+            buf: [0; STREAM_BUF_CAP + MAX_FRAME],
+            head: 0,
+            len: 0,
+            decoded: [0; MAX_BODY],
+            stats: LinkStats::new(),
+        }
+    }
+
+    /// Use byte arrival times (see [`push_bytes_at`](Self::push_bytes_at)).
+    pub const fn with_timing(mut self, timing: RxTiming) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    pub fn timing(&self) -> RxTiming {
+        self.timing
+    }
+
+    /// Link-quality counters since creation or the last `take_stats`.
+    pub fn stats(&self) -> &LinkStats {
+        &self.stats
+    }
+
+    /// Return the counters and reset them to zero.
+    pub fn take_stats(&mut self) -> LinkStats {
+        core::mem::take(&mut self.stats)
+    }
+
+    /// Number of buffered bytes not yet consumed.
+    pub fn buffered(&self) -> usize {
+        self.len
+    }
+
+    /// Push raw bytes into the stream buffer. Returns number accepted.
+    /// If buffer overflows, we clear it (simple, deterministic) and keep going.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> usize {
+        for &b in bytes {
+            if self.len == STREAM_BUF_CAP {
+                // Overflow policy: clear and keep latest byte.
+                self.stats.overflows = self.stats.overflows.wrapping_add(1);
+                self.discarded(STREAM_BUF_CAP);
+                self.head = 0;
+                self.len = 0;
+            }
+
+            let tail = (self.head + self.len) % STREAM_BUF_CAP;
+            self.buf[tail] = b;
+            if tail < MAX_FRAME {
+                self.buf[STREAM_BUF_CAP + tail] = b;
+            }
+            self.len += 1;
+        }
+        bytes.len()
+    }
+
+    /// [`push_bytes`](Self::push_bytes) with the arrival time of `bytes`, in
+    /// microseconds from any monotonic clock. Buffered bytes older than the
+    /// timing's gap are dropped first (see [`expire`](Self::expire)), so call
+    /// [`next_frame`](Self::next_frame) until it returns `Ok(None)` after
+    /// every push.
+    pub fn push_bytes_at(&mut self, bytes: &[u8], now_us: u64) -> usize {
+        self.expire(now_us);
+        self.last_rx_us = now_us;
+        self.push_bytes(bytes)
+    }
+
+    /// Drop an unfinished frame once the line has been silent for the
+    /// timing's gap. Returns whether bytes were dropped.
+    pub fn expire(&mut self, now_us: u64) -> bool {
+        let Some(gap_us) = self.timing.gap_us() else {
+            return false;
+        };
+        if now_us.saturating_sub(self.last_rx_us) < gap_us {
+            return false;
+        }
+        self.skip_to_gap = false;
+        if self.len == 0 {
+            return false;
+        }
+        self.stats.resyncs = self.stats.resyncs.wrapping_add(1);
+        self.discard(self.len);
+        true
+    }
+
+    /// Drop every buffered byte, e.g. an unfinished frame from a peer that
+    /// disconnected. The dropped bytes are counted as discarded.
+    pub fn clear(&mut self) {
+        self.skip_to_gap = false;
+        self.discard(self.len);
+    }
+
+    /// Attempt to parse the next valid frame.
+    ///
+    /// - Ok(Some(frame)) on success (consumes exactly LEN + 4 bytes, LEN + 5 for sequenced
+    ///   frames; the payload borrows the parser)
+    /// - Ok(None) if not enough data yet
+    /// - Err(e) only for "structural" issues of a candidate frame; the STX is dropped so the
+    ///   next call resumes scanning after it (a bad COBS packet is dropped whole)
+    pub fn next_frame(&mut self) -> Result<Option<Frame<'_>>, ParseError> {
+        match self.framing {
+            Framing::StxLen => self.next_stx_frame(),
+            Framing::Cobs => self.next_cobs_frame(),
+        }
+    }
+
+    fn next_stx_frame(&mut self) -> Result<Option<Frame<'_>>, ParseError> {
+        if self.skip_to_gap {
+            self.discard(self.len);
+            return Ok(None);
+        }
+
+        // Resync: drop anything before the next STX
+        let stx_pos = (0..self.len)
+            .position(|i| matches!(self.byte(i), STX | STX_SEQ))
+            .unwrap_or(self.len);
+        if stx_pos > 0 {
+            self.stats.resyncs = self.stats.resyncs.wrapping_add(1);
+            if self.rtu() {
+                // The frame started with garbage: it ends at the next silence
+                self.skip_to_gap = true;
+                self.discard(self.len);
+                return Ok(None);
+            }
+            self.discard(stx_pos);
+        }
+
+        // Need at least STX + LEN (+ SEQ)
+        let header_len = if self.byte(0) == STX_SEQ { 3 } else { 2 };
+        if self.len < header_len {
+            return Ok(None);
+        }
+
+        let len = self.byte(1) as usize;
+
+        // LEN must include ADDR+CMD
+        if len < 2 {
+            self.reject();
+            return Err(ParseError::LenTooSmall);
+        }
+
+        let total_len = header_len + len + 2; // STX + LEN (+ SEQ) + body + CRC
+        if total_len > MAX_FRAME {
+            // impossible while LEN is a u8, but keep for completeness
+            self.reject();
+            return Err(ParseError::LenTooBig);
+        }
+
+        // Wait for full candidate
+        if self.len < total_len {
+            return Ok(None);
+        }
+
+        // Contiguous thanks to the mirror (head < STREAM_BUF_CAP, total_len <= MAX_FRAME)
+        let start = self.head;
+        let candidate = &self.buf[start..start + total_len];
+
+        // Verify CRC over candidate[0 .. total_len-2]
+        let computed = crc16_modbus(&candidate[..total_len - 2]);
+        let got = u16::from_le_bytes([candidate[total_len - 2], candidate[total_len - 1]]); // CRCL, CRCH
+
+        if computed != got {
+            // CRC mismatch: drop STX and keep scanning.
+            let (addr, cmd) = (candidate[header_len], candidate[header_len + 1]);
+            self.stats.crc_errors = self.stats.crc_errors.wrapping_add(1);
+            self.reject();
+            return Err(ParseError::CrcMismatch { addr, cmd });
+        }
+
+        // Consume the whole frame; its bytes stay in place until the next push.
+        self.consume(total_len);
+        self.stats.frames_ok = self.stats.frames_ok.wrapping_add(1);
+
+        let candidate = &self.buf[start..start + total_len];
+        let body = &candidate[header_len..header_len + len]; // LEN counts ADDR through payload end
+        Ok(Some(Frame {
+            seq: (header_len == 3).then_some(candidate[2]),
+            addr: body[0],
+            cmd: body[1],
+            payload: &body[2..],
+        }))
+    }
+
+    fn next_cobs_frame(&mut self) -> Result<Option<Frame<'_>>, ParseError> {
+        // Empty packets (repeated delimiters) are allowed, e.g. to flush the line.
+        while self.len > 0 && self.byte(0) == 0 {
+            self.consume(1);
+        }
+
+        let Some(end) = (0..self.len.min(MAX_PACKET)).position(|i| self.byte(i) == 0) else {
+            if self.len >= MAX_PACKET {
+                // No delimiter where one must have been: drop up to the next one.
+                self.stats.resyncs = self.stats.resyncs.wrapping_add(1);
+                self.discard(MAX_PACKET);
+                return Err(ParseError::LenTooBig);
+            }
+            return Ok(None);
+        };
+
+        // Contiguous thanks to the mirror (end < MAX_PACKET <= MAX_FRAME)
+        let start = self.head;
+        let decoded = cobs::decode(&self.buf[start..start + end], &mut self.decoded);
+        let Some(n) = decoded else {
+            self.discard(end + 1);
+            return Err(ParseError::BadEncoding);
+        };
+        if n < 4 {
+            self.discard(end + 1);
+            return Err(ParseError::LenTooSmall);
+        }
+
+        let body = &self.decoded[..n];
+        let computed = crc16_modbus(&body[..n - 2]);
+        let got = u16::from_le_bytes([body[n - 2], body[n - 1]]);
+        if computed != got {
+            let (addr, cmd) = (body[0], body[1]);
+            self.stats.crc_errors = self.stats.crc_errors.wrapping_add(1);
+            self.discard(end + 1);
+            return Err(ParseError::CrcMismatch { addr, cmd });
+        }
+
+        self.consume(end + 1);
+        self.stats.frames_ok = self.stats.frames_ok.wrapping_add(1);
+
+        let body = &self.decoded[..n];
+        Ok(Some(Frame {
+            seq: None,
+            addr: body[0],
+            cmd: body[1],
+            payload: &body[2..n - 2],
+        }))
+    }
+
+    fn rtu(&self) -> bool {
+        matches!(self.timing, RxTiming::Rtu { .. })
+    }
+
+    /// Drop a bad candidate: its STX, so scanning resumes after it, or with
+    /// RTU boundaries everything up to the next silence.
+    fn reject(&mut self) {
+        if self.rtu() {
+            self.skip_to_gap = true;
+            self.discard(self.len);
+        } else {
+            self.discard(1);
+        }
+    }
+
+    /// Byte `i` positions after the head.
+    fn byte(&self, i: usize) -> u8 {
+        self.buf[(self.head + i) % STREAM_BUF_CAP]
+    }
+
+    /// Consume `count` bytes that did not belong to a frame.
+    fn discard(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.discarded(count);
+        self.consume(count);
+    }
+
+    fn discarded(&mut self, count: usize) {
+        self.stats.bytes_discarded = self.stats.bytes_discarded.wrapping_add(count as u32);
+    }
+
+    fn consume(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.head = (self.head + count) % STREAM_BUF_CAP;
+        self.len -= count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_ack, build_frame, build_frame_seq};
+
+    #[test]
+    fn parses_built_frame() {
+        let bytes = build_frame::<32>(0x07, 0x10, &[1, 2, 3]).unwrap();
+        let mut parser = Parser::new();
+        assert_eq!(parser.push_bytes(&bytes), bytes.len());
+
+        let frame = parser.next_frame().unwrap().unwrap();
+        assert_eq!(frame.addr, 0x07);
+        assert_eq!(frame.cmd, 0x10);
+        assert_eq!(frame.payload, &[1, 2, 3]);
+        assert_eq!(frame.status(), Some(1));
+    }
+
+    #[test]
+    fn waits_for_split_frame() {
+        let bytes = build_ack::<16>(0x01, 0x01).unwrap();
+        let mut parser = Parser::new();
+
+        parser.push_bytes(&bytes[..3]);
+        assert_eq!(parser.next_frame(), Ok(None));
+
+        parser.push_bytes(&bytes[3..]);
+        assert_eq!(parser.next_frame().unwrap().unwrap().cmd, 0x01);
+    }
+
+    #[test]
+    fn skips_leading_garbage() {
+        let bytes = build_ack::<16>(0x01, 0x01).unwrap();
+        let mut parser = Parser::new();
+        parser.push_bytes(&[0x00, 0x11, 0x22]);
+        parser.push_bytes(&bytes);
+
+        assert_eq!(parser.next_frame().unwrap().unwrap().addr, 0x01);
+    }
+
+    #[test]
+    fn reports_crc_mismatch() {
+        let mut bytes = build_ack::<16>(0x01, 0x01).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+
+        let mut parser = Parser::new();
+        parser.push_bytes(&bytes);
+        assert_eq!(
+            parser.next_frame(),
+            Err(ParseError::CrcMismatch {
+                addr: 0x01,
+                cmd: 0x01
+            })
+        );
+    }
+
+    #[test]
+    fn reports_len_too_small() {
+        let mut parser = Parser::new();
+        parser.push_bytes(&[STX, 0x01, 0x00, 0x00]);
+        assert_eq!(parser.next_frame(), Err(ParseError::LenTooSmall));
+    }
+
+    #[test]
+    fn overflow_clears_buffer() {
+        let mut parser = Parser::new();
+        let junk = [0u8; STREAM_BUF_CAP + 1];
+        assert_eq!(parser.push_bytes(&junk), junk.len());
+
+        let bytes = build_ack::<16>(0x01, 0x01).unwrap();
+        parser.push_bytes(&bytes);
+        assert_eq!(parser.next_frame().unwrap().unwrap().cmd, 0x01);
+    }
+
+    #[test]
+    fn consumes_whole_frame_when_payload_contains_stx() {
+        // Payload and CRC bytes equal to STX used to be rescanned as phantom frames.
+        let first = build_frame::<32>(0x01, 0x10, &[STX, 0x02, 0x01, 0x01, STX]).unwrap();
+        let second = build_ack::<16>(0x02, 0x11).unwrap();
+
+        let mut parser = Parser::new();
+        parser.push_bytes(&first);
+        parser.push_bytes(&second);
+
+        assert_eq!(
+            parser.next_frame().unwrap().unwrap().payload,
+            &[STX, 0x02, 0x01, 0x01, STX]
+        );
+        assert_eq!(parser.next_frame().unwrap().unwrap().cmd, 0x11);
+        assert_eq!(parser.next_frame(), Ok(None));
+        assert_eq!(parser.buffered(), 0);
+    }
+
+    #[test]
+    fn frames_straddling_the_ring_end_decode() {
+        let bytes = build_frame::<MAX_FRAME>(0x01, 0x30, &[0x5A; 100]).unwrap();
+        let mut parser = Parser::new();
+
+        // Walk the head all the way around the ring a few times.
+        for _ in 0..(3 * STREAM_BUF_CAP / bytes.len() + 1) {
+            parser.push_bytes(&bytes);
+            let frame = parser.next_frame().unwrap().unwrap();
+            assert_eq!(frame.cmd, 0x30);
+            assert_eq!(frame.payload, &[0x5A; 100][..]);
+        }
+        assert_eq!(parser.buffered(), 0);
+    }
+
+    #[test]
+    fn crc_mismatch_resumes_after_stx() {
+        let mut bad = build_ack::<16>(0x01, 0x01).unwrap();
+        bad[4] ^= 0x01;
+        let good = build_ack::<16>(0x01, 0x02).unwrap();
+
+        let mut parser = Parser::new();
+        parser.push_bytes(&bad);
+        parser.push_bytes(&good);
+
+        assert_eq!(
+            parser.next_frame(),
+            Err(ParseError::CrcMismatch {
+                addr: 0x01,
+                cmd: 0x01
+            })
+        );
+        assert_eq!(parser.next_frame().unwrap().unwrap().cmd, 0x02);
+    }
+
+    #[test]
+    fn counts_link_quality() {
+        let good = build_ack::<16>(0x01, 0x01).unwrap();
+        let mut bad = good.clone();
+        bad[4] ^= 0x01;
+
+        let mut parser = Parser::new();
+        parser.push_bytes(&[0x00, 0x11]);
+        parser.push_bytes(&bad);
+        parser.push_bytes(&good);
+        while !matches!(parser.next_frame(), Ok(None)) {}
+
+        let stats = parser.take_stats();
+        assert_eq!(stats.frames_ok, 1);
+        assert_eq!(stats.crc_errors, 1);
+        assert!(stats.resyncs >= 2); // leading junk + remainder of the bad frame
+        assert_eq!(stats.bytes_discarded as usize, 2 + bad.len());
+        assert_eq!(stats.overflows, 0);
+        assert_eq!(parser.stats(), &LinkStats::default());
+
+        parser.push_bytes(&[0u8; STREAM_BUF_CAP + 1]);
+        assert_eq!(parser.stats().overflows, 1);
+    }
+
+    #[test]
+    fn parses_sequenced_frames_alongside_plain_ones() {
+        let seq = build_frame_seq::<32>(Some(0x42), 0x01, 0x10, &[STX_SEQ, STX]).unwrap();
+        let plain = build_ack::<16>(0x01, 0x11).unwrap();
+
+        let mut parser = Parser::new();
+        parser.push_bytes(&seq);
+        parser.push_bytes(&plain);
+
+        let frame = parser.next_frame().unwrap().unwrap();
+        assert_eq!(frame.seq, Some(0x42));
+        assert_eq!((frame.addr, frame.cmd), (0x01, 0x10));
+        assert_eq!(frame.payload, &[STX_SEQ, STX]);
+
+        let frame = parser.next_frame().unwrap().unwrap();
+        assert_eq!(frame.seq, None);
+        assert_eq!(frame.cmd, 0x11);
+        assert_eq!(parser.buffered(), 0);
+    }
+
+    #[test]
+    fn stale_partial_frames_are_dropped_after_a_gap() {
+        let bytes = build_ack::<16>(0x01, 0x01).unwrap();
+        let mut parser = Parser::new().with_timing(RxTiming::Gap { gap_us: 1_000 });
+
+        // Host disconnected mid-frame, then sent a whole frame much later
+        parser.push_bytes_at(&bytes[..3], 0);
+        assert_eq!(parser.next_frame(), Ok(None));
+        parser.push_bytes_at(&bytes, 5_000);
+        assert_eq!(parser.next_frame().unwrap().unwrap().cmd, 0x01);
+        assert_eq!(parser.stats().bytes_discarded, 3);
+
+        // Shorter pauses inside a frame are fine
+        parser.push_bytes_at(&bytes[..3], 6_000);
+        parser.push_bytes_at(&bytes[3..], 6_900);
+        assert_eq!(parser.next_frame().unwrap().unwrap().cmd, 0x01);
+
+        parser.push_bytes_at(&bytes[..3], 7_000);
+        assert!(!parser.expire(7_999));
+        assert!(parser.expire(8_000));
+        assert_eq!(parser.buffered(), 0);
+
+        // Without timing the partial frame would have corrupted the next one
+        let mut untimed = Parser::new();
+        untimed.push_bytes_at(&bytes[..3], 0);
+        untimed.push_bytes_at(&bytes, 5_000);
+        assert!(untimed.next_frame().is_err());
+    }
+
+    #[test]
+    fn rtu_timing_skips_to_the_next_silence_after_a_bad_frame() {
+        let timing = RxTiming::rtu(9_600);
+        assert_eq!(timing, RxTiming::Rtu { t3_5_us: 4_011 });
+        // A corrupted frame whose tail looks like a frame of its own
+        let inner = build_frame::<16>(0x01, 0x10, &[]).unwrap();
+        let mut trap = build_frame::<32>(0x02, 0x20, &[0x00]).unwrap();
+        trap[2] = 0x7F; // ADDR altered: CRC fails
+        trap.extend_from_slice(&inner).unwrap();
+
+        let mut parser = Parser::new().with_timing(timing);
+        parser.push_bytes_at(&trap, 0);
+        assert!(matches!(
+            parser.next_frame(),
+            Err(ParseError::CrcMismatch { .. })
+        ));
+        // Scanning on would find `inner`; RTU drops the rest of the frame
+        assert_eq!(parser.next_frame(), Ok(None));
+        parser.push_bytes_at(&inner, 1_000);
+        assert_eq!(parser.next_frame(), Ok(None));
+
+        // After a t3.5 silence frames parse again
+        parser.push_bytes_at(&inner, 6_000);
+        assert_eq!(parser.next_frame().unwrap().unwrap().cmd, 0x10);
+
+        // Garbage at the start of a frame spoils it too
+        parser.push_bytes_at(&[0x00], 20_000);
+        parser.push_bytes_at(&inner, 20_100);
+        assert_eq!(parser.next_frame(), Ok(None));
+        assert_eq!(parser.stats().frames_ok, 1);
+    }
+
+    #[test]
+    fn parses_cobs_packets_and_resyncs_at_the_delimiter() {
+        let good = cobs::build_frame::<MAX_PACKET>(0x01, 0x20, &[0x00, STX, 0x00]).unwrap();
+        let mut bad = good.clone();
+        bad[3] ^= 0x40;
+
+        let mut parser = Parser::with_framing(Framing::Cobs);
+        parser.push_bytes(&[0x00, 0x00]);
+        parser.push_bytes(&bad);
+        parser.push_bytes(&good);
+
+        assert!(matches!(
+            parser.next_frame(),
+            Err(ParseError::CrcMismatch { .. } | ParseError::BadEncoding)
+        ));
+        let frame = parser.next_frame().unwrap().unwrap();
+        assert_eq!(frame.seq, None);
+        assert_eq!((frame.addr, frame.cmd), (0x01, 0x20));
+        assert_eq!(frame.payload, &[0x00, STX, 0x00]);
+        assert_eq!(parser.next_frame(), Ok(None));
+        assert_eq!(parser.stats().bytes_discarded as usize, bad.len());
+    }
+
+    #[test]
+    fn cobs_run_without_delimiter_is_dropped() {
+        let mut parser = Parser::with_framing(Framing::Cobs);
+        parser.push_bytes(&[0x11; MAX_PACKET]);
+        assert_eq!(parser.next_frame(), Err(ParseError::LenTooBig));
+        assert_eq!(parser.buffered(), 0);
+
+        parser.push_bytes(&[0x02, 0x01, 0x00]); // decodes to a 1-byte body
+        assert_eq!(parser.next_frame(), Err(ParseError::LenTooSmall));
+    }
+}
@@ -0,0 +1,335 @@
+//! Typed command payloads.
+//!
+//! A type implementing [`Payload`] is both the device's decoder and the
+//! host's encoder for a request (or getter data), so the two cannot drift
+//! apart. Implement it with `#[derive(Payload)]`, and add
+//! `#[derive(Command)]` with `#[command(cmd = ...)]` for request types:
+//!
+//! ```ignore
+//! #[derive(Payload, Command)]
+//! #[command(cmd = 0x04)]
+//! pub struct SetAddress {
+//!     #[payload(range = 0x01..=0xEF)]
+//!     pub node: u8,
+//!     pub groups: Option<u16>,
+//! }
+//! ```
+//!
+//! Encoding:
+//! - integers and floats: little-endian, `bool`: one byte, 0 or 1
+//! - `[u8; N]`: N bytes as they are
+//! - `heapless::Vec<T, N>` and `heapless::String<N>`: a count byte (items or
+//!   UTF-8 bytes), then the items
+//! - `Option<T>`: nothing or `T`; only meaningful as the last field
+//! - structs: their fields in order; enums: a tag byte, then the variant's fields
+//!
+//! Decoding answers BAD_LEN for a payload that is too short or too long and
+//! BAD_ARG for a value it does not accept: an unknown enum tag, a `bool`
+//! other than 0/1, invalid UTF-8, or a field outside its `#[payload(range)]`.
+//! Encoding does not check ranges; the device does.
+//!
+//! [`Payload::MIN_LEN`] and [`Payload::MAX_LEN`] bound the encoded size, so
+//! [`CommandSpec::of`](crate::dispatch::CommandSpec::of) can take a handler's
+//! payload limits from its request type.
+
+use heapless::{String, Vec};
+
+use crate::{BuildError, Frame, FrameWriter, Status};
+
+pub use protocol_derive::{Command, Payload};
+
+/// Where [`Payload::encode`] writes.
+pub trait Sink {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), BuildError>;
+}
+
+impl<const N: usize> Sink for Vec<u8, N> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), BuildError> {
+        self.extend_from_slice(bytes)
+            .map_err(|_| BuildError::OutputTooSmall)
+    }
+}
+
+impl Sink for FrameWriter<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), BuildError> {
+        self.write(bytes)
+    }
+}
+
+/// The unread part of a payload.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The next `n` bytes (BAD_LEN if there are fewer).
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], Status> {
+        if n > self.bytes.len() {
+            return Err(Status::BadLen);
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    pub fn take_array<const N: usize>(&mut self) -> Result<[u8; N], Status> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    /// BAD_LEN if anything is left over.
+    pub fn finish(self) -> Result<(), Status> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(Status::BadLen)
+        }
+    }
+}
+
+/// A value with a fixed wire encoding, see the module docs.
+pub trait Payload: Sized {
+    /// Fewest bytes any value encodes to.
+    const MIN_LEN: usize;
+    /// Most bytes any value encodes to.
+    const MAX_LEN: usize;
+
+    /// Bytes [`encode`](Self::encode) writes for this value.
+    fn encoded_len(&self) -> usize;
+
+    fn encode<S: Sink + ?Sized>(&self, out: &mut S) -> Result<(), BuildError>;
+
+    fn decode(input: &mut Reader<'_>) -> Result<Self, Status>;
+
+    /// Decode a whole payload; leftover bytes are BAD_LEN.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Status> {
+        let mut input = Reader::new(bytes);
+        let value = Self::decode(&mut input)?;
+        input.finish()?;
+        Ok(value)
+    }
+
+    fn to_bytes<const N: usize>(&self) -> Result<Vec<u8, N>, BuildError> {
+        let mut out = Vec::new();
+        self.encode(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// A request type with its CMD code.
+pub trait Command: Payload {
+    const CMD: u8;
+
+    /// Decode the request carried by `frame` (the device side).
+    fn from_frame(frame: &Frame<'_>) -> Result<Self, Status> {
+        if frame.cmd != Self::CMD {
+            return Err(Status::BadCmd);
+        }
+        Self::from_bytes(frame.payload)
+    }
+
+    /// Build the request frame for node `addr` (the host side).
+    fn build<const OUT_CAP: usize>(
+        &self,
+        seq: Option<u8>,
+        addr: u8,
+    ) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+        crate::collect(|buf| {
+            let mut writer = FrameWriter::new(buf, seq, addr, Self::CMD, self.encoded_len())?;
+            self.encode(&mut writer)?;
+            writer.finish()
+        })
+    }
+}
+
+/// `min` for derived enum lengths (`Ord::min` is not const).
+#[doc(hidden)]
+pub const fn min_len(a: usize, b: usize) -> usize {
+    if a < b { a } else { b }
+}
+
+/// `max` for derived enum lengths.
+#[doc(hidden)]
+pub const fn max_len(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+macro_rules! le_payload {
+    ($($ty:ty),*) => {$(
+        impl Payload for $ty {
+            const MIN_LEN: usize = size_of::<$ty>();
+            const MAX_LEN: usize = size_of::<$ty>();
+
+            fn encoded_len(&self) -> usize {
+                size_of::<$ty>()
+            }
+
+            fn encode<S: Sink + ?Sized>(&self, out: &mut S) -> Result<(), BuildError> {
+                out.put(&self.to_le_bytes())
+            }
+
+            fn decode(input: &mut Reader<'_>) -> Result<Self, Status> {
+                Ok(<$ty>::from_le_bytes(input.take_array()?))
+            }
+        }
+    )*};
+}
+
+le_payload!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+impl Payload for bool {
+    const MIN_LEN: usize = 1;
+    const MAX_LEN: usize = 1;
+
+    fn encoded_len(&self) -> usize {
+        1
+    }
+
+    fn encode<S: Sink + ?Sized>(&self, out: &mut S) -> Result<(), BuildError> {
+        out.put(&[u8::from(*self)])
+    }
+
+    fn decode(input: &mut Reader<'_>) -> Result<Self, Status> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Status::BadArg),
+        }
+    }
+}
+
+impl<const N: usize> Payload for [u8; N] {
+    const MIN_LEN: usize = N;
+    const MAX_LEN: usize = N;
+
+    fn encoded_len(&self) -> usize {
+        N
+    }
+
+    fn encode<S: Sink + ?Sized>(&self, out: &mut S) -> Result<(), BuildError> {
+        out.put(self)
+    }
+
+    fn decode(input: &mut Reader<'_>) -> Result<Self, Status> {
+        input.take_array()
+    }
+}
+
+/// Count byte for a Vec or String (they can hold more than 255).
+fn count(len: usize) -> Result<u8, BuildError> {
+    u8::try_from(len).map_err(|_| BuildError::PayloadTooLarge)
+}
+
+impl<T: Payload, const N: usize> Payload for Vec<T, N> {
+    const MIN_LEN: usize = 1;
+    const MAX_LEN: usize = 1 + N * T::MAX_LEN;
+
+    fn encoded_len(&self) -> usize {
+        1 + self.iter().map(T::encoded_len).sum::<usize>()
+    }
+
+    fn encode<S: Sink + ?Sized>(&self, out: &mut S) -> Result<(), BuildError> {
+        out.put(&[count(self.len())?])?;
+        self.iter().try_for_each(|item| item.encode(out))
+    }
+
+    fn decode(input: &mut Reader<'_>) -> Result<Self, Status> {
+        let count = u8::decode(input)?;
+        let mut out = Vec::new();
+        for _ in 0..count {
+            out.push(T::decode(input)?).map_err(|_| Status::BadLen)?;
+        }
+        Ok(out)
+    }
+}
+
+impl<const N: usize> Payload for String<N> {
+    const MIN_LEN: usize = 1;
+    const MAX_LEN: usize = 1 + N;
+
+    fn encoded_len(&self) -> usize {
+        1 + self.len()
+    }
+
+    fn encode<S: Sink + ?Sized>(&self, out: &mut S) -> Result<(), BuildError> {
+        out.put(&[count(self.len())?])?;
+        out.put(self.as_bytes())
+    }
+
+    fn decode(input: &mut Reader<'_>) -> Result<Self, Status> {
+        let len = u8::decode(input)?;
+        let text = core::str::from_utf8(input.take(len.into())?).map_err(|_| Status::BadArg)?;
+        let mut out = String::new();
+        out.push_str(text).map_err(|_| Status::BadLen)?;
+        Ok(out)
+    }
+}
+
+impl<T: Payload> Payload for Option<T> {
+    const MIN_LEN: usize = 0;
+    const MAX_LEN: usize = T::MAX_LEN;
+
+    fn encoded_len(&self) -> usize {
+        self.as_ref().map_or(0, T::encoded_len)
+    }
+
+    fn encode<S: Sink + ?Sized>(&self, out: &mut S) -> Result<(), BuildError> {
+        match self {
+            Some(value) => value.encode(out),
+            None => Ok(()),
+        }
+    }
+
+    fn decode(input: &mut Reader<'_>) -> Result<Self, Status> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        T::decode(input).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primitives_are_little_endian() {
+        assert_eq!(&0x1234u16.to_bytes::<8>().unwrap()[..], &[0x34, 0x12]);
+        assert_eq!(
+            &(-2i32).to_bytes::<8>().unwrap()[..],
+            &[0xFE, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(u32::from_bytes(&[1, 0, 0, 0]), Ok(1));
+        assert_eq!(u16::from_bytes(&[1]), Err(Status::BadLen));
+        assert_eq!(u8::from_bytes(&[1, 2]), Err(Status::BadLen));
+        assert_eq!(bool::from_bytes(&[2]), Err(Status::BadArg));
+        assert_eq!(<[u8; 3]>::from_bytes(&[7, 8, 9]), Ok([7, 8, 9]));
+    }
+
+    #[test]
+    fn counted_strings_and_vecs() {
+        let name: String<8> = String::try_from("pico").unwrap();
+        let bytes = name.to_bytes::<16>().unwrap();
+        assert_eq!(&bytes[..], b"\x04pico");
+        assert_eq!(String::<8>::from_bytes(&bytes), Ok(name));
+        // Longer than the capacity, or not UTF-8
+        assert_eq!(String::<2>::from_bytes(&bytes), Err(Status::BadLen));
+        assert_eq!(String::<8>::from_bytes(&[1, 0xFF]), Err(Status::BadArg));
+
+        let values: Vec<u16, 4> = Vec::from_slice(&[1, 0x0203]).unwrap();
+        let bytes = values.to_bytes::<16>().unwrap();
+        assert_eq!(&bytes[..], &[2, 1, 0, 3, 2]);
+        assert_eq!(Vec::<u16, 4>::from_bytes(&bytes), Ok(values));
+        assert_eq!(Vec::<u16, 4>::MAX_LEN, 9);
+        assert_eq!(Option::<u16>::from_bytes(&[]), Ok(None));
+    }
+}
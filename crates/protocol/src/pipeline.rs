@@ -0,0 +1,174 @@
+//! Pipelining: several sequenced requests in flight at once.
+//!
+//! The SEQ byte of a sequenced frame doubles as the request's transaction ID.
+//! The device echoes it in the response, so responses may come back in any
+//! order and the host matches them by SEQ. Plain frames carry no ID and are
+//! still executed one at a time, in arrival order.
+//!
+//! The device copies each sequenced request into an [`OwnedFrame`], runs it on
+//! a worker of its own and tracks it in an [`InFlight`] table until the
+//! response has been sent:
+//! - a retransmission of a request that is still running is dropped, because
+//!   its response is on the way;
+//! - a new request beyond the in-flight limit is answered BUSY at once
+//!   ([`busy`]) without running, and the host may send it again later.
+//!
+//! Once a request has finished, retransmissions are answered by the
+//! [`DuplicateFilter`](crate::arq::DuplicateFilter), which should remember at
+//! least as many responses as the limit allows in flight.
+
+use heapless::Vec;
+
+use crate::arq::RequestKey;
+use crate::{BuildError, Frame, MAX_PAYLOAD, Status, build_frame_seq};
+
+/// A request copied out of the parser's buffer so it can outlive it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OwnedFrame {
+    pub seq: Option<u8>,
+    pub addr: u8,
+    pub cmd: u8,
+    pub payload: Vec<u8, MAX_PAYLOAD>,
+}
+
+impl OwnedFrame {
+    /// `None` if the payload does not fit one frame (a reassembled message).
+    pub fn from_frame(frame: &Frame<'_>) -> Option<Self> {
+        Some(Self {
+            seq: frame.seq,
+            addr: frame.addr,
+            cmd: frame.cmd,
+            payload: Vec::from_slice(frame.payload).ok()?,
+        })
+    }
+
+    pub fn as_frame(&self) -> Frame<'_> {
+        Frame {
+            seq: self.seq,
+            addr: self.addr,
+            cmd: self.cmd,
+            payload: &self.payload,
+        }
+    }
+}
+
+/// What to do with a request, see [`InFlight::admit`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Admission {
+    /// Run it, and call [`InFlight::finish`] once its response is sent.
+    Run,
+    /// Retransmission of a request that is still running: drop it.
+    InProgress,
+    /// Too many requests in flight: answer BUSY without running it.
+    Busy,
+}
+
+/// The sequenced requests a device is running, up to `N` at once.
+pub struct InFlight<const N: usize> {
+    running: Vec<RequestKey, N>,
+    limit: usize,
+}
+
+impl<const N: usize> InFlight<N> {
+    /// Allow `limit` requests in flight (at most `N`).
+    pub const fn new(limit: usize) -> Self {
+        Self {
+            running: Vec::new(),
+            limit: if limit < N { limit } else { N },
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    /// Decide what to do with `frame`. Plain frames are always [`Admission::Run`]
+    /// and never tracked.
+    pub fn admit(&mut self, frame: &Frame<'_>) -> Admission {
+        let Some(key) = RequestKey::of(frame) else {
+            return Admission::Run;
+        };
+        if self.running.contains(&key) {
+            Admission::InProgress
+        } else if self.running.len() >= self.limit || self.running.push(key).is_err() {
+            Admission::Busy
+        } else {
+            Admission::Run
+        }
+    }
+
+    /// `frame` has been answered.
+    pub fn finish(&mut self, frame: &Frame<'_>) {
+        if let Some(key) = RequestKey::of(frame)
+            && let Some(i) = self.running.iter().position(|k| *k == key)
+        {
+            self.running.swap_remove(i);
+        }
+    }
+}
+
+/// BUSY response to `frame`, echoing its SEQ.
+pub fn busy<const OUT_CAP: usize>(frame: &Frame<'_>) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    build_frame_seq(frame.seq, frame.addr, frame.cmd, &[Status::Busy.as_u8()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MAX_FRAME, Parser, test_frame};
+
+    #[test]
+    fn admits_up_to_the_limit_then_answers_busy() {
+        let mut in_flight = InFlight::<4>::new(2);
+        assert_eq!(
+            in_flight.admit(&test_frame(Some(1), 0x02, &[])),
+            Admission::Run
+        );
+        assert_eq!(
+            in_flight.admit(&test_frame(Some(2), 0x02, &[])),
+            Admission::Run
+        );
+        assert_eq!(
+            in_flight.admit(&test_frame(Some(1), 0x02, &[])),
+            Admission::InProgress
+        );
+        assert_eq!(
+            in_flight.admit(&test_frame(Some(3), 0x02, &[])),
+            Admission::Busy
+        );
+        // Plain frames are not pipelined, so the limit does not apply.
+        assert_eq!(
+            in_flight.admit(&test_frame(None, 0x02, &[])),
+            Admission::Run
+        );
+        assert_eq!(in_flight.len(), 2);
+
+        in_flight.finish(&test_frame(Some(1), 0x02, &[]));
+        assert_eq!(
+            in_flight.admit(&test_frame(Some(3), 0x02, &[])),
+            Admission::Run
+        );
+        assert_eq!(InFlight::<4>::new(9).limit(), 4);
+    }
+
+    #[test]
+    fn busy_response_echoes_the_transaction_id() {
+        let resp = busy::<MAX_FRAME>(&test_frame(Some(42), 0x02, &[])).unwrap();
+        let mut parser = Parser::new();
+        parser.push_bytes(&resp);
+        let resp = parser.next_frame().unwrap().unwrap();
+        assert_eq!(resp.seq, Some(42));
+        assert_eq!(resp.status(), Some(Status::Busy.as_u8()));
+
+        let owned = OwnedFrame::from_frame(&resp).unwrap();
+        assert_eq!(owned.as_frame(), resp);
+    }
+}
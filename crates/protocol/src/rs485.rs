@@ -0,0 +1,325 @@
+//! Driver-enable control for a half-duplex RS-485 transceiver.
+//!
+//! The transceiver only drives the bus while its DE input is high; /RE is
+//! normally tied to DE, so the node does not hear its own echo. [`Rs485`]
+//! raises DE a setup time before the first start bit and drops it a hold time
+//! after the last stop bit has left the UART's shift register.
+//!
+//! Leaving the shifter is later than being written: a buffered UART's flush
+//! only means the bytes reached the hardware FIFO, which may still hold a few
+//! dozen characters. Dropping DE then would cut the end off every reply.
+//! Characters go out back to back at best, so the last stop bit cannot leave
+//! before `len` character times after the first byte was queued; [`Rs485`]
+//! sleeps until then and only polls the UART's busy flag for the remainder.
+//!
+//! Pin, clock and UART are traits so the timing can be tested on the host.
+
+/// Parity bit after the data bits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Stop bits after the data (and parity) bits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Character format of the UART, always with 8 data bits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct UartFormat {
+    pub baud: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl UartFormat {
+    /// 8N1 at `baud`.
+    pub const fn new(baud: u32) -> Self {
+        Self {
+            baud,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+
+    /// Bits on the wire per character: start, 8 data, parity and stop bits.
+    pub const fn bits_per_char(&self) -> u64 {
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Even | Parity::Odd => 1,
+        };
+        let stop = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        1 + 8 + parity + stop
+    }
+
+    /// One bit time in microseconds, rounded up.
+    pub const fn bit_us(&self) -> u64 {
+        1_000_000_u64.div_ceil(self.baud as u64)
+    }
+
+    /// Time to shift out `chars` characters back to back, rounded up.
+    pub const fn chars_us(&self, chars: usize) -> u64 {
+        (chars as u64 * self.bits_per_char() * 1_000_000).div_ceil(self.baud as u64)
+    }
+}
+
+/// The transceiver's DE input (and /RE, when tied to it).
+pub trait DriverEnable {
+    /// `true` drives the bus, `false` releases it and listens.
+    fn set_driving(&mut self, driving: bool);
+}
+
+/// Monotonic microsecond clock.
+#[allow(async_fn_in_trait)] // single-threaded executor, no Send bound needed
+pub trait Clock {
+    fn now_us(&self) -> u64;
+
+    /// Return once `now_us()` has reached `deadline_us`.
+    async fn wait_until(&mut self, deadline_us: u64);
+}
+
+/// Transmit half of a buffered UART.
+#[allow(async_fn_in_trait)]
+pub trait UartTx {
+    type Error;
+
+    /// Queue all of `bytes` for transmission.
+    async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Wait until every queued byte has reached the hardware FIFO.
+    async fn flush(&mut self) -> Result<(), Self::Error>;
+
+    /// A character is still in the FIFO or the shift register.
+    fn busy(&self) -> bool;
+}
+
+/// Toggles DE around each transmission on one UART.
+pub struct Rs485<P, C> {
+    de: P,
+    clock: C,
+    format: UartFormat,
+    setup_us: u64,
+    hold_us: u64,
+}
+
+impl<P: DriverEnable, C: Clock> Rs485<P, C> {
+    /// Release the bus and wait one bit time on either side of a
+    /// transmission (see [`with_delays`](Self::with_delays)).
+    pub fn new(mut de: P, clock: C, format: UartFormat) -> Self {
+        de.set_driving(false);
+        Self {
+            de,
+            clock,
+            format,
+            setup_us: format.bit_us(),
+            hold_us: format.bit_us(),
+        }
+    }
+
+    /// Drive the bus `setup_us` before the first start bit, so the
+    /// transceiver is on in time, and `hold_us` after the last stop bit, so
+    /// receivers see it end before the bus floats.
+    pub fn with_delays(mut self, setup_us: u64, hold_us: u64) -> Self {
+        self.setup_us = setup_us;
+        self.hold_us = hold_us;
+        self
+    }
+
+    pub fn format(&self) -> UartFormat {
+        self.format
+    }
+
+    /// Send `bytes` with the driver enabled. The bus is released again even
+    /// if the UART fails.
+    pub async fn send<T: UartTx>(&mut self, tx: &mut T, bytes: &[u8]) -> Result<(), T::Error> {
+        self.de.set_driving(true);
+        let setup_done = self.clock.now_us() + self.setup_us;
+        self.clock.wait_until(setup_done).await;
+
+        let start = self.clock.now_us();
+        let result = match tx.write_all(bytes).await {
+            Ok(()) => tx.flush().await,
+            Err(err) => Err(err),
+        };
+
+        if result.is_ok() {
+            // Nothing can finish sooner; an interrupt serviced late only adds gaps
+            let sent = start + self.format.chars_us(bytes.len());
+            self.clock.wait_until(sent).await;
+        }
+        while tx.busy() {
+            let next = self.clock.now_us() + self.format.bit_us();
+            self.clock.wait_until(next).await;
+        }
+
+        let hold_done = self.clock.now_us() + self.hold_us;
+        self.clock.wait_until(hold_done).await;
+        self.de.set_driving(false);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    /// Shared simulated time plus a log of DE edges, in microseconds.
+    #[derive(Default)]
+    struct Bench {
+        now: Cell<u64>,
+        edges: RefCell<Vec<(u64, bool)>>,
+    }
+
+    struct MockPin<'a>(&'a Bench);
+
+    impl DriverEnable for MockPin<'_> {
+        fn set_driving(&mut self, driving: bool) {
+            self.0.edges.borrow_mut().push((self.0.now.get(), driving));
+        }
+    }
+
+    /// Jumps straight to each deadline.
+    struct MockClock<'a>(&'a Bench);
+
+    impl Clock for MockClock<'_> {
+        fn now_us(&self) -> u64 {
+            self.0.now.get()
+        }
+
+        async fn wait_until(&mut self, deadline_us: u64) {
+            self.0.now.set(self.0.now.get().max(deadline_us));
+        }
+    }
+
+    /// Shifts bytes out at `format`, starting `latency_us` after each write
+    /// (an interrupt serviced late). `flush` returns at once, as if all the
+    /// bytes fit in the FIFO.
+    struct MockUart<'a> {
+        bench: &'a Bench,
+        format: UartFormat,
+        latency_us: u64,
+        fail: bool,
+        // First bit time of each byte
+        sent: Vec<u64>,
+        // When the last stop bit leaves the shifter
+        idle_at: u64,
+    }
+
+    impl<'a> MockUart<'a> {
+        fn new(bench: &'a Bench, format: UartFormat) -> Self {
+            Self {
+                bench,
+                format,
+                latency_us: 0,
+                fail: false,
+                sent: Vec::new(),
+                idle_at: 0,
+            }
+        }
+    }
+
+    impl UartTx for MockUart<'_> {
+        type Error = ();
+
+        async fn write_all(&mut self, bytes: &[u8]) -> Result<(), ()> {
+            if self.fail {
+                return Err(());
+            }
+            let start = self.bench.now.get() + self.latency_us;
+            for i in 0..bytes.len() {
+                self.sent.push(start + self.format.chars_us(i));
+            }
+            self.idle_at = start + self.format.chars_us(bytes.len());
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn busy(&self) -> bool {
+            self.bench.now.get() < self.idle_at
+        }
+    }
+
+    const MODBUS: UartFormat = UartFormat {
+        baud: 19_200,
+        parity: Parity::Even,
+        stop_bits: StopBits::One,
+    };
+
+    #[test]
+    fn character_time_counts_parity_and_stop_bits() {
+        assert_eq!(UartFormat::new(115_200).bits_per_char(), 10);
+        assert_eq!(MODBUS.bits_per_char(), 11);
+        let two_stop = UartFormat {
+            stop_bits: StopBits::Two,
+            ..MODBUS
+        };
+        assert_eq!(two_stop.bits_per_char(), 12);
+
+        // 11 bits at 19200 baud is 572.9 µs; rounded up so DE never drops early
+        assert_eq!(MODBUS.chars_us(1), 573);
+        assert_eq!(MODBUS.chars_us(10), 5_730);
+        assert_eq!(UartFormat::new(115_200).chars_us(10), 869);
+        assert_eq!(MODBUS.bit_us(), 53);
+    }
+
+    #[test]
+    fn driver_is_enabled_for_the_whole_transmission() {
+        let bench = Bench::default();
+        bench.now.set(1_000);
+        let mut rs485 = Rs485::new(MockPin(&bench), MockClock(&bench), MODBUS).with_delays(20, 30);
+        let mut uart = MockUart::new(&bench, MODBUS);
+
+        block_on(rs485.send(&mut uart, &[0x01; 8])).unwrap();
+
+        assert_eq!(uart.sent.first(), Some(&1_020));
+        assert_eq!(uart.idle_at, 1_020 + 4_584);
+        assert_eq!(
+            *bench.edges.borrow(),
+            [(1_000, false), (1_000, true), (1_020 + 4_584 + 30, false)]
+        );
+    }
+
+    #[test]
+    fn waits_for_the_shifter_when_the_uart_starts_late() {
+        let bench = Bench::default();
+        let mut rs485 = Rs485::new(MockPin(&bench), MockClock(&bench), MODBUS);
+        let mut uart = MockUart::new(&bench, MODBUS);
+        uart.latency_us = 500;
+
+        block_on(rs485.send(&mut uart, &[0x01; 4])).unwrap();
+
+        // Released within one bit time plus the hold after the stop bit
+        let (released, driving) = *bench.edges.borrow().last().unwrap();
+        assert!(!driving);
+        assert!(released >= uart.idle_at + MODBUS.bit_us());
+        assert!(released <= uart.idle_at + 2 * MODBUS.bit_us());
+    }
+
+    #[test]
+    fn releases_the_bus_when_the_uart_fails() {
+        let bench = Bench::default();
+        let mut rs485 = Rs485::new(MockPin(&bench), MockClock(&bench), MODBUS);
+        let mut uart = MockUart::new(&bench, MODBUS);
+        uart.fail = true;
+
+        assert_eq!(block_on(rs485.send(&mut uart, &[0x01; 4])), Err(()));
+        assert_eq!(bench.edges.borrow().last().map(|&(_, on)| on), Some(false));
+        assert!(uart.sent.is_empty());
+    }
+}
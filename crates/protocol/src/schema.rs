@@ -0,0 +1,195 @@
+//! Command table, payload types, status codes, event classes and feature
+//! bits from `protocol.toml`.
+//!
+//! @generated by `protocol-gen`: edit `crates/protocol/protocol.toml` and run
+//! `cargo protocol-gen` instead of changing this file.
+
+use crate::dispatch::{CommandKind, CommandSpec};
+use crate::payload::{Command, Payload};
+
+/// Device health check
+pub const CMD_PING: u8 = 0x01;
+/// Start the LED chase pattern; ACKed at once, `CHASE_FINISHED` event when done
+pub const CMD_CHASE: u8 = 0x02;
+/// Reset the link-quality counters of the link it arrives on
+pub const CMD_CLEAR_LINK_STATS: u8 = 0x03;
+/// Store a new node address and optionally group membership
+pub const CMD_SET_ADDRESS: u8 = 0x04;
+/// Enable event classes (`[CLASSES u16 LE]` bitmask, see below)
+pub const CMD_SUBSCRIBE: u8 = 0x05;
+/// Disable event classes (same payload)
+pub const CMD_UNSUBSCRIBE: u8 = 0x06;
+/// One fragment of a large request
+pub const CMD_FRAGMENT: u8 = 0x10;
+/// Drop a partly received fragmented request
+pub const CMD_FRAGMENT_ABORT: u8 = 0x11;
+/// Start an encrypted session (16-byte host nonce)
+pub const CMD_SESSION_OPEN: u8 = 0x12;
+/// Encrypted request inside a session
+pub const CMD_SECURE: u8 = 0x13;
+/// Query unique device identifier
+pub const CMD_GET_DEVICE_ID: u8 = 0x20;
+/// Read link-quality counters of the link it arrives on
+pub const CMD_GET_LINK_STATS: u8 = 0x21;
+/// Protocol version, framings, optional features, buffer sizes and the command table
+pub const CMD_GET_CAPABILITIES: u8 = 0x22;
+/// Read the node address and group membership
+pub const CMD_GET_ADDRESS: u8 = 0x23;
+
+/// 0x01 PING.
+pub const PING: CommandSpec = CommandSpec {
+    cmd: CMD_PING,
+    name: "PING",
+    kind: CommandKind::Setter,
+    min_payload: 0,
+    max_payload: 253,
+};
+
+/// 0x02 CHASE.
+pub const CHASE: CommandSpec = CommandSpec {
+    cmd: CMD_CHASE,
+    name: "CHASE",
+    kind: CommandKind::Setter,
+    min_payload: 0,
+    max_payload: 2,
+};
+
+/// 0x03 CLEAR_LINK_STATS.
+pub const CLEAR_LINK_STATS: CommandSpec = CommandSpec {
+    cmd: CMD_CLEAR_LINK_STATS,
+    name: "CLEAR_LINK_STATS",
+    kind: CommandKind::Setter,
+    min_payload: 0,
+    max_payload: 0,
+};
+
+/// 0x04 SET_ADDRESS.
+pub const SET_ADDRESS: CommandSpec =
+    CommandSpec::of::<SetAddress>("SET_ADDRESS", CommandKind::Setter);
+
+/// 0x05 SUBSCRIBE.
+pub const SUBSCRIBE: CommandSpec = CommandSpec {
+    cmd: CMD_SUBSCRIBE,
+    name: "SUBSCRIBE",
+    kind: CommandKind::Setter,
+    min_payload: 2,
+    max_payload: 2,
+};
+
+/// 0x06 UNSUBSCRIBE.
+pub const UNSUBSCRIBE: CommandSpec = CommandSpec {
+    cmd: CMD_UNSUBSCRIBE,
+    name: "UNSUBSCRIBE",
+    kind: CommandKind::Setter,
+    min_payload: 2,
+    max_payload: 2,
+};
+
+/// 0x12 SESSION_OPEN.
+pub const SESSION_OPEN: CommandSpec = CommandSpec {
+    cmd: CMD_SESSION_OPEN,
+    name: "SESSION_OPEN",
+    kind: CommandKind::Getter,
+    min_payload: 16,
+    max_payload: 16,
+};
+
+/// 0x20 GET_DEVICE_ID.
+pub const GET_DEVICE_ID: CommandSpec = CommandSpec {
+    cmd: CMD_GET_DEVICE_ID,
+    name: "GET_DEVICE_ID",
+    kind: CommandKind::Getter,
+    min_payload: 0,
+    max_payload: 0,
+};
+
+/// 0x21 GET_LINK_STATS.
+pub const GET_LINK_STATS: CommandSpec = CommandSpec {
+    cmd: CMD_GET_LINK_STATS,
+    name: "GET_LINK_STATS",
+    kind: CommandKind::Getter,
+    min_payload: 0,
+    max_payload: 0,
+};
+
+/// 0x22 GET_CAPABILITIES.
+pub const GET_CAPABILITIES: CommandSpec = CommandSpec {
+    cmd: CMD_GET_CAPABILITIES,
+    name: "GET_CAPABILITIES",
+    kind: CommandKind::Getter,
+    min_payload: 0,
+    max_payload: 0,
+};
+
+/// 0x23 GET_ADDRESS.
+pub const GET_ADDRESS: CommandSpec = CommandSpec {
+    cmd: CMD_GET_ADDRESS,
+    name: "GET_ADDRESS",
+    kind: CommandKind::Getter,
+    min_payload: 0,
+    max_payload: 0,
+};
+
+/// 0x04 SET_ADDRESS request.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Payload, Command)]
+#[command(cmd = CMD_SET_ADDRESS)]
+pub struct SetAddress {
+    #[payload(range = 1..=0xEF)]
+    pub node: u8,
+    /// Group bitmask; left out keeps the membership
+    pub groups: Option<u16>,
+}
+
+/// 0x20 GET_DEVICE_ID data.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Payload)]
+pub struct DeviceId {
+    pub id: [u8; 8],
+}
+
+/// 0x21 GET_LINK_STATS data.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Payload)]
+pub struct LinkCounters {
+    pub frames_ok: u32,
+    pub crc_errors: u32,
+    pub resyncs: u32,
+    pub overflows: u32,
+    pub bytes_discarded: u32,
+}
+
+/// 0x23 GET_ADDRESS data.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Payload)]
+pub struct NodeAddress {
+    pub node: u8,
+    pub groups: u16,
+}
+
+/// Status codes (`crate::Status`).
+pub const STATUS_CODES: [(u8, &str); 9] = [
+    (0x00, "OK"),
+    (0x01, "BAD_LEN"),
+    (0x02, "BAD_CMD"),
+    (0x03, "BAD_ARG"),
+    (0x04, "BUSY"),
+    (0x05, "NOT_READY"),
+    (0x06, "INTERNAL"),
+    (0x07, "UNAUTHORIZED"),
+    (0x08, "BAD_FRAME"),
+];
+
+/// Event classes (`crate::event::EventClass`); CMD is `EVENT_FIRST + class`.
+pub const EVENT_CLASSES: [(u8, &str); 4] = [
+    (0x00, "BUTTON"),
+    (0x01, "THRESHOLD"),
+    (0x02, "FAULT"),
+    (0x03, "CHASE_FINISHED"),
+];
+
+/// Feature bits (`crate::capabilities::Features`), by bit value.
+pub const FEATURES: [(u8, &str); 6] = [
+    (0x01, "ARQ"),
+    (0x02, "FRAGMENTATION"),
+    (0x04, "AUTH"),
+    (0x08, "EVENTS"),
+    (0x10, "PIPELINING"),
+    (0x20, "SESSION"),
+];
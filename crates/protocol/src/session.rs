@@ -0,0 +1,762 @@
+//! Encrypted sessions: ChaCha20-Poly1305 (RFC 8439) over a PSK handshake.
+//!
+//! [`crate::auth`] signs requests but leaves them readable. A session also
+//! encrypts them, and authenticates and encrypts the responses. The
+//! pre-shared key (PSK) is the same key `auth` uses.
+//!
+//! Handshake, one `CMD_SESSION_OPEN` getter:
+//!   request  [ HOST_NONCE (16) ]
+//!   response [ STATUS, BYTECOUNT, DEVICE_NONCE (16), CONFIRM (16) ]
+//!
+//! - The device draws DEVICE_NONCE from its TRNG. HOST_NONCE only has to be
+//!   unique, so hosts without a good RNG are fine.
+//! - Each direction gets its own key: HMAC-SHA256(PSK, "h2d" or "d2h",
+//!   HOST_NONCE, DEVICE_NONCE). CONFIRM is the first 16 bytes of
+//!   HMAC-SHA256(PSK, "confirm", HOST_NONCE, DEVICE_NONCE) and proves to the
+//!   host that the device holds the PSK. The device learns the same about the
+//!   host from the first request that decrypts.
+//! - Handshakes are not signed, so until that first request the new session
+//!   is only pending and the previous one stays in use (see
+//!   [`DeviceSessions`]). A device keeps one session of each.
+//!
+//! Within a session requests are sent as `CMD_SECURE` frames:
+//!   [ COUNTER (u32 LE), ENCRYPTED( INNER_CMD, <INNER_PAYLOAD...> ), TAG (16) ]
+//!
+//! - The nonce is COUNTER (LE) padded with zeros; ADDR and CMD_SECURE are
+//!   the associated data.
+//! - Requests count up from 1. The device accepts each COUNTER once and
+//!   allows [`REPLAY_WINDOW`] of reordering, so pipelined requests can be
+//!   opened by workers in any order.
+//! - The response is sealed the same way under the device's key with the
+//!   request's COUNTER, and echoes SEQ. INNER_CMD and the payload are those
+//!   of the plain response.
+//! - A request that does not decrypt is answered with a plain
+//!   `[UNAUTHORIZED]`, one sent with no session open with `[NOT_READY]`.
+//!
+//! Events, fragments and plain requests are not covered; send
+//! [`MAX_SECURE_PAYLOAD`] bytes or less per request.
+
+use core::fmt;
+
+use heapless::Vec;
+use hmac::Mac;
+
+use crate::aead::{self, NONCE_LEN};
+use crate::auth::{self, COUNTER_LEN, Key, hmac_sha256};
+use crate::{BuildError, Frame, MAX_PAYLOAD, STX, STX_SEQ, Status, build_frame_seq};
+
+/// Handshake: `[HOST_NONCE]` in, `[DEVICE_NONCE, CONFIRM]` out.
+pub const CMD_SESSION_OPEN: u8 = crate::schema::CMD_SESSION_OPEN;
+
+/// A request or response sealed with the session keys.
+pub const CMD_SECURE: u8 = crate::schema::CMD_SECURE;
+
+pub const SESSION_NONCE_LEN: usize = 16;
+
+pub const CONFIRM_LEN: usize = 16;
+
+/// Handshake response data: DEVICE_NONCE followed by CONFIRM.
+pub const OPEN_DATA_LEN: usize = SESSION_NONCE_LEN + CONFIRM_LEN;
+
+pub const SECURE_TAG_LEN: usize = aead::TAG_LEN;
+
+/// Bytes a `CMD_SECURE` frame adds: COUNTER, INNER_CMD and TAG.
+pub const SECURE_OVERHEAD: usize = COUNTER_LEN + 1 + SECURE_TAG_LEN;
+
+/// Largest INNER_PAYLOAD of one `CMD_SECURE` frame.
+pub const MAX_SECURE_PAYLOAD: usize = MAX_PAYLOAD - SECURE_OVERHEAD;
+
+/// How far behind the highest counter seen a request may arrive.
+pub const REPLAY_WINDOW: u32 = 64;
+
+pub type Nonce = [u8; SESSION_NONCE_LEN];
+
+/// Why a handshake or sealed frame was refused.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SessionError {
+    /// No session is open.
+    NoSession,
+    /// Payload too short or too long for a sealed frame or handshake.
+    Malformed,
+    /// The tag does not match: wrong key, or the frame was altered.
+    BadTag,
+    /// The counter was already used or is too old.
+    Replayed,
+    /// The device's CONFIRM does not match the PSK.
+    BadConfirm,
+}
+
+impl SessionError {
+    /// Status sent back for a refused `CMD_SECURE` frame.
+    pub const fn status(&self) -> Status {
+        match self {
+            SessionError::NoSession => Status::NotReady,
+            SessionError::Malformed => Status::BadLen,
+            SessionError::BadTag | SessionError::Replayed | SessionError::BadConfirm => {
+                Status::Unauthorized
+            }
+        }
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::NoSession => f.write_str("no session open"),
+            SessionError::Malformed => f.write_str("malformed sealed frame"),
+            SessionError::BadTag => f.write_str("sealed frame failed authentication"),
+            SessionError::Replayed => f.write_str("sealed frame counter reused"),
+            SessionError::BadConfirm => f.write_str("device does not hold the key"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SessionError {}
+
+/// Derived keys for host-to-device and device-to-host frames.
+#[derive(Clone, Eq, PartialEq)]
+struct Keys {
+    h2d: Key,
+    d2h: Key,
+}
+
+impl Keys {
+    fn derive(psk: &Key, host_nonce: &Nonce, device_nonce: &Nonce) -> Self {
+        Self {
+            h2d: hmac_sha256(psk, &[b"h2d", host_nonce, device_nonce]),
+            d2h: hmac_sha256(psk, &[b"d2h", host_nonce, device_nonce]),
+        }
+    }
+}
+
+fn confirm(psk: &Key, host_nonce: &Nonce, device_nonce: &Nonce) -> [u8; CONFIRM_LEN] {
+    let mac = hmac_sha256(psk, &[b"confirm", host_nonce, device_nonce]);
+    let mut confirm = [0u8; CONFIRM_LEN];
+    confirm.copy_from_slice(&mac[..CONFIRM_LEN]);
+    confirm
+}
+
+fn nonce(counter: u32) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..COUNTER_LEN].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+/// `CMD_SECURE` payload carrying `cmd` and `payload`.
+fn seal<const N: usize>(
+    key: &Key,
+    addr: u8,
+    counter: u32,
+    cmd: u8,
+    payload: &[u8],
+) -> Result<Vec<u8, N>, BuildError> {
+    if payload.len() > MAX_SECURE_PAYLOAD {
+        return Err(BuildError::PayloadTooLarge);
+    }
+    let mut out = Vec::new();
+    out.extend_from_slice(&counter.to_le_bytes())
+        .and_then(|()| out.push(cmd).map_err(|_| ()))
+        .and_then(|()| out.extend_from_slice(payload))
+        .map_err(|_| BuildError::OutputTooSmall)?;
+    let tag = aead::seal(
+        key,
+        &nonce(counter),
+        &[addr, CMD_SECURE],
+        &mut out[COUNTER_LEN..],
+    )
+    .ok_or(BuildError::PayloadTooLarge)?;
+    out.extend_from_slice(&tag)
+        .map_err(|_| BuildError::OutputTooSmall)?;
+    Ok(out)
+}
+
+/// COUNTER of a `CMD_SECURE` payload, before it is authenticated.
+fn counter_of(sealed: &[u8]) -> Result<u32, SessionError> {
+    if sealed.len() < SECURE_OVERHEAD || sealed.len() > MAX_PAYLOAD {
+        return Err(SessionError::Malformed);
+    }
+    Ok(u32::from_le_bytes([
+        sealed[0], sealed[1], sealed[2], sealed[3],
+    ]))
+}
+
+/// Decrypt a `CMD_SECURE` payload into the start of `buf`. `false` if the
+/// tag does not match `key`.
+fn decrypt(key: &Key, addr: u8, sealed: &[u8], buf: &mut [u8]) -> Result<bool, SessionError> {
+    let counter = counter_of(sealed)?;
+    let (body, tag) = sealed[COUNTER_LEN..].split_at(sealed.len() - COUNTER_LEN - SECURE_TAG_LEN);
+    let buf = buf.get_mut(..body.len()).ok_or(SessionError::Malformed)?;
+    buf.copy_from_slice(body);
+    Ok(aead::open(
+        key,
+        &nonce(counter),
+        &[addr, CMD_SECURE],
+        buf,
+        tag,
+    ))
+}
+
+/// (INNER_CMD, INNER_PAYLOAD) of `sealed`, once [`decrypt`]ed into `buf`.
+fn inner<'b>(sealed: &[u8], buf: &'b [u8]) -> Result<(u8, &'b [u8]), SessionError> {
+    let (cmd, payload) = buf[..sealed.len() - SECURE_OVERHEAD + 1]
+        .split_first()
+        .ok_or(SessionError::Malformed)?;
+    Ok((*cmd, payload))
+}
+
+/// Decrypt a `CMD_SECURE` payload into `buf`: (INNER_CMD, INNER_PAYLOAD).
+fn open<'b>(
+    key: &Key,
+    addr: u8,
+    sealed: &[u8],
+    buf: &'b mut [u8],
+) -> Result<(u8, &'b [u8]), SessionError> {
+    if !decrypt(key, addr, sealed, buf)? {
+        return Err(SessionError::BadTag);
+    }
+    inner(sealed, buf)
+}
+
+/// Host side of a session.
+pub struct HostSession {
+    keys: Keys,
+    counter: u32,
+}
+
+impl HostSession {
+    /// Check the device's handshake `data` (`[DEVICE_NONCE, CONFIRM]`) and
+    /// derive the session keys.
+    pub fn complete(psk: &Key, host_nonce: &Nonce, data: &[u8]) -> Result<Self, SessionError> {
+        if data.len() != OPEN_DATA_LEN {
+            return Err(SessionError::Malformed);
+        }
+        let (device_nonce, received) = data.split_at(SESSION_NONCE_LEN);
+        let device_nonce: Nonce = device_nonce
+            .try_into()
+            .map_err(|_| SessionError::Malformed)?;
+
+        auth::mac(psk, &[b"confirm", host_nonce, &device_nonce])
+            .verify_truncated_left(received)
+            .map_err(|_| SessionError::BadConfirm)?;
+        Ok(Self {
+            keys: Keys::derive(psk, host_nonce, &device_nonce),
+            counter: 0,
+        })
+    }
+
+    /// Seal a request: its COUNTER and the `CMD_SECURE` payload.
+    pub fn seal<const N: usize>(
+        &mut self,
+        addr: u8,
+        cmd: u8,
+        payload: &[u8],
+    ) -> Result<(u32, Vec<u8, N>), BuildError> {
+        let counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(BuildError::CounterExhausted)?;
+        let sealed = seal(&self.keys.h2d, addr, counter, cmd, payload)?;
+        self.counter = counter;
+        Ok((counter, sealed))
+    }
+
+    /// Open the response to the request sealed with `counter`, into `buf`:
+    /// (INNER_CMD, INNER_PAYLOAD).
+    pub fn open<'b>(
+        &self,
+        addr: u8,
+        counter: u32,
+        sealed: &[u8],
+        buf: &'b mut [u8],
+    ) -> Result<(u8, &'b [u8]), SessionError> {
+        if counter_of(sealed)? != counter {
+            return Err(SessionError::Replayed);
+        }
+        open(&self.keys.d2h, addr, sealed, buf)
+    }
+}
+
+/// Device side of a session.
+pub struct DeviceSession {
+    keys: Keys,
+    /// Highest request counter accepted.
+    last: u32,
+    /// Bit `n` set: counter `last - n` was accepted.
+    seen: u64,
+}
+
+impl DeviceSession {
+    /// Answer a handshake: the session and the response data
+    /// (`[DEVICE_NONCE, CONFIRM]`). `device_nonce` must come from a TRNG.
+    pub fn accept(
+        psk: &Key,
+        host_nonce: &Nonce,
+        device_nonce: &Nonce,
+    ) -> (Self, [u8; OPEN_DATA_LEN]) {
+        let mut data = [0u8; OPEN_DATA_LEN];
+        data[..SESSION_NONCE_LEN].copy_from_slice(device_nonce);
+        data[SESSION_NONCE_LEN..].copy_from_slice(&confirm(psk, host_nonce, device_nonce));
+        let session = Self {
+            keys: Keys::derive(psk, host_nonce, device_nonce),
+            last: 0,
+            // Counter 0 is never valid
+            seen: 1,
+        };
+        (session, data)
+    }
+
+    /// Open a `CMD_SECURE` request into `buf` and take its counter.
+    pub fn open_request<'b>(
+        &mut self,
+        frame: &Frame<'_>,
+        buf: &'b mut [u8],
+    ) -> Result<Opened<'b>, SessionError> {
+        let keys = SessionKeys {
+            active: Some(self.keys.clone()),
+            pending: None,
+        };
+        let opened = keys.open_request(frame, buf)?;
+        self.claim(opened.counter)?;
+        Ok(opened)
+    }
+
+    /// Take `counter` unless it was used before or is too old.
+    fn claim(&mut self, counter: u32) -> Result<(), SessionError> {
+        if !self.is_fresh(counter) {
+            return Err(SessionError::Replayed);
+        }
+        self.mark(counter);
+        Ok(())
+    }
+
+    fn is_fresh(&self, counter: u32) -> bool {
+        match self.last.checked_sub(counter) {
+            Some(age) => age < REPLAY_WINDOW && self.seen & (1 << age) == 0,
+            None => true,
+        }
+    }
+
+    fn mark(&mut self, counter: u32) {
+        if counter > self.last {
+            let shift = counter - self.last;
+            self.seen = self.seen.checked_shl(shift).unwrap_or(0) | 1;
+            self.last = counter;
+        } else {
+            self.seen |= 1 << (self.last - counter);
+        }
+    }
+}
+
+/// The sessions of a device: the one requests are opened under, and the one
+/// the last handshake started.
+///
+/// SESSION_OPEN is not signed, so anyone can start a handshake. The new
+/// session therefore stays pending, and the active one keeps working, until
+/// a request opens under the new keys: only a host holding the PSK can seal
+/// one. It then replaces the active session.
+pub struct DeviceSessions {
+    active: Option<DeviceSession>,
+    pending: Option<DeviceSession>,
+}
+
+impl Default for DeviceSessions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceSessions {
+    pub const fn new() -> Self {
+        Self {
+            active: None,
+            pending: None,
+        }
+    }
+
+    /// Answer a handshake (see [`DeviceSession::accept`]): the response data.
+    /// Replaces the pending session, not the active one.
+    pub fn accept(
+        &mut self,
+        psk: &Key,
+        host_nonce: &Nonce,
+        device_nonce: &Nonce,
+    ) -> [u8; OPEN_DATA_LEN] {
+        let (session, data) = DeviceSession::accept(psk, host_nonce, device_nonce);
+        self.pending = Some(session);
+        data
+    }
+
+    /// Copies of the keys, so requests can be opened without holding the
+    /// lock around the sessions.
+    pub fn keys(&self) -> SessionKeys {
+        SessionKeys {
+            active: self.active.as_ref().map(|session| session.keys.clone()),
+            pending: self.pending.as_ref().map(|session| session.keys.clone()),
+        }
+    }
+
+    /// Take the counter of a request [`SessionKeys::open_request`] opened.
+    /// A request opened under the pending session confirms it, and it
+    /// becomes the active one. Fails with [`SessionError::NoSession`] if the
+    /// session was replaced since the keys were copied.
+    pub fn claim(&mut self, opened: &Opened<'_>) -> Result<(), SessionError> {
+        if let Some(active) = &mut self.active
+            && active.keys == opened.keys
+        {
+            return active.claim(opened.counter);
+        }
+        match &mut self.pending {
+            Some(pending) if pending.keys == opened.keys => {
+                pending.claim(opened.counter)?;
+                self.active = self.pending.take();
+                Ok(())
+            }
+            _ => Err(SessionError::NoSession),
+        }
+    }
+
+    /// [`keys`](Self::keys), [`SessionKeys::open_request`] and
+    /// [`claim`](Self::claim) in one.
+    pub fn open_request<'b>(
+        &mut self,
+        frame: &Frame<'_>,
+        buf: &'b mut [u8],
+    ) -> Result<Opened<'b>, SessionError> {
+        let opened = self.keys().open_request(frame, buf)?;
+        self.claim(&opened)?;
+        Ok(opened)
+    }
+}
+
+/// Keys of a device's active and pending session, see [`DeviceSessions::keys`].
+#[derive(Clone)]
+pub struct SessionKeys {
+    active: Option<Keys>,
+    pending: Option<Keys>,
+}
+
+impl SessionKeys {
+    /// Open a `CMD_SECURE` request into `buf`, under the active session or
+    /// else the pending one. Its counter is not checked until it is
+    /// [`claim`](DeviceSessions::claim)ed.
+    pub fn open_request<'b>(
+        &self,
+        frame: &Frame<'_>,
+        buf: &'b mut [u8],
+    ) -> Result<Opened<'b>, SessionError> {
+        if self.active.is_none() && self.pending.is_none() {
+            return Err(SessionError::NoSession);
+        }
+        let counter = counter_of(frame.payload)?;
+        let mut opened_with = None;
+        for keys in [&self.active, &self.pending].into_iter().flatten() {
+            if decrypt(&keys.h2d, frame.addr, frame.payload, buf)? {
+                opened_with = Some(keys.clone());
+                break;
+            }
+        }
+        let keys = opened_with.ok_or(SessionError::BadTag)?;
+        let (cmd, payload) = inner(frame.payload, buf)?;
+        Ok(Opened {
+            counter,
+            request: Frame {
+                seq: frame.seq,
+                addr: frame.addr,
+                cmd,
+                payload,
+            },
+            keys,
+        })
+    }
+}
+
+/// A `CMD_SECURE` request that decrypted.
+pub struct Opened<'b> {
+    pub counter: u32,
+    /// The inner request, with the SEQ and ADDR of the sealed one.
+    pub request: Frame<'b>,
+    /// The session it was opened under, which also seals the response.
+    keys: Keys,
+}
+
+impl Opened<'_> {
+    /// Seal `response`, a frame built for the inner request, as the
+    /// `CMD_SECURE` response.
+    pub fn seal_response<const OUT_CAP: usize>(
+        &self,
+        response: &[u8],
+    ) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+        let (seq, addr, cmd, payload) = split_frame(response).ok_or(BuildError::NotAFrame)?;
+        let sealed: Vec<u8, MAX_PAYLOAD> = seal(&self.keys.d2h, addr, self.counter, cmd, payload)?;
+        build_frame_seq(seq, addr, CMD_SECURE, &sealed)
+    }
+}
+
+/// Plain response to a `CMD_SECURE` frame that was not opened: `[STATUS]`.
+pub fn refused<const OUT_CAP: usize>(
+    frame: &Frame<'_>,
+    err: SessionError,
+) -> Result<Vec<u8, OUT_CAP>, BuildError> {
+    build_frame_seq(frame.seq, frame.addr, frame.cmd, &[err.status().as_u8()])
+}
+
+/// Whether `cmd` belongs to the session layer rather than a handler.
+pub const fn is_session_cmd(cmd: u8) -> bool {
+    matches!(cmd, CMD_SESSION_OPEN | CMD_SECURE)
+}
+
+/// SEQ, ADDR, CMD and payload of a built STX frame.
+fn split_frame(frame: &[u8]) -> Option<(Option<u8>, u8, u8, &[u8])> {
+    let (seq, header_len) = match frame.first() {
+        Some(&STX) => (None, 2),
+        Some(&STX_SEQ) => (Some(*frame.get(2)?), 3),
+        _ => return None,
+    };
+    let len = *frame.get(1)? as usize;
+    let body = frame
+        .get(header_len..header_len + len)
+        .filter(|body| body.len() >= 2)?;
+    Some((seq, body[0], body[1], &body[2..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::KEY_LEN;
+    use crate::{MAX_FRAME, Parser, build_ack, build_data, test_frame};
+
+    const PSK: Key = [0x42; KEY_LEN];
+    const HOST_NONCE: Nonce = [0x11; SESSION_NONCE_LEN];
+    const DEVICE_NONCE: Nonce = [0x22; SESSION_NONCE_LEN];
+
+    fn pair() -> (HostSession, DeviceSession) {
+        let (device, data) = DeviceSession::accept(&PSK, &HOST_NONCE, &DEVICE_NONCE);
+        let host = HostSession::complete(&PSK, &HOST_NONCE, &data).unwrap();
+        (host, device)
+    }
+
+    fn secure_frame(seq: Option<u8>, payload: &[u8]) -> Frame<'_> {
+        test_frame(seq, CMD_SECURE, payload)
+    }
+
+    #[test]
+    fn requests_and_responses_round_trip() {
+        let (mut host, mut device) = pair();
+
+        let (counter, sealed) = host.seal::<MAX_PAYLOAD>(0x01, 0x20, &[1, 2, 3]).unwrap();
+        assert_eq!(counter, 1);
+        assert_eq!(sealed.len(), 3 + SECURE_OVERHEAD);
+        // Encrypted: the inner command and payload are not visible
+        assert_ne!(&sealed[COUNTER_LEN..COUNTER_LEN + 4], &[0x20, 1, 2, 3]);
+
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let opened = device
+            .open_request(&secure_frame(Some(7), &sealed), &mut buf)
+            .unwrap();
+        assert_eq!(opened.counter, counter);
+        assert_eq!((opened.request.seq, opened.request.cmd), (Some(7), 0x20));
+        assert_eq!(opened.request.payload, &[1, 2, 3]);
+
+        let response = build_data::<MAX_FRAME>(0x01, 0x20, &[9, 8]).unwrap();
+        let sealed_response = opened.seal_response::<MAX_FRAME>(&response).unwrap();
+        let mut parser = Parser::new();
+        parser.push_bytes(&sealed_response);
+        let frame = parser.next_frame().unwrap().unwrap();
+        assert_eq!((frame.seq, frame.cmd), (None, CMD_SECURE));
+
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let (cmd, payload) = host.open(0x01, counter, frame.payload, &mut buf).unwrap();
+        assert_eq!((cmd, payload), (0x20, &[0x00, 2, 9, 8][..]));
+        // A response to another request does not pass
+        assert_eq!(
+            host.open(0x01, counter + 1, frame.payload, &mut buf),
+            Err(SessionError::Replayed)
+        );
+    }
+
+    #[test]
+    fn wrong_psk_fails_the_handshake_and_the_requests() {
+        let (mut device, data) = DeviceSession::accept(&PSK, &HOST_NONCE, &DEVICE_NONCE);
+        let other = [0x43; KEY_LEN];
+        assert_eq!(
+            HostSession::complete(&other, &HOST_NONCE, &data).err(),
+            Some(SessionError::BadConfirm)
+        );
+        assert_eq!(
+            HostSession::complete(&PSK, &HOST_NONCE, &data[1..]).err(),
+            Some(SessionError::Malformed)
+        );
+
+        // A host that skips the confirm check still cannot get requests through
+        let mut data = data;
+        data[SESSION_NONCE_LEN..].copy_from_slice(&confirm(&other, &HOST_NONCE, &DEVICE_NONCE));
+        let mut host = HostSession::complete(&other, &HOST_NONCE, &data).unwrap();
+        let (_, sealed) = host.seal::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap();
+        let mut buf = [0u8; MAX_PAYLOAD];
+        assert_eq!(
+            device
+                .open_request(&secure_frame(None, &sealed), &mut buf)
+                .err(),
+            Some(SessionError::BadTag)
+        );
+    }
+
+    #[test]
+    fn tampered_or_readdressed_frames_are_refused() {
+        let (mut host, mut device) = pair();
+        let (_, sealed) = host.seal::<MAX_PAYLOAD>(0x01, 0x02, &[5]).unwrap();
+        let mut buf = [0u8; MAX_PAYLOAD];
+
+        let mut tampered = sealed.clone();
+        tampered[COUNTER_LEN] ^= 0x01;
+        assert_eq!(
+            device
+                .open_request(&secure_frame(None, &tampered), &mut buf)
+                .err(),
+            Some(SessionError::BadTag)
+        );
+        let readdressed = Frame {
+            addr: 0x02,
+            ..secure_frame(None, &sealed)
+        };
+        assert_eq!(
+            device.open_request(&readdressed, &mut buf).err(),
+            Some(SessionError::BadTag)
+        );
+        assert_eq!(
+            device
+                .open_request(&secure_frame(None, &sealed[..10]), &mut buf)
+                .err(),
+            Some(SessionError::Malformed)
+        );
+        // None of that used up the counter
+        assert!(
+            device
+                .open_request(&secure_frame(None, &sealed), &mut buf)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn counters_are_accepted_once_within_the_window() {
+        let (mut host, mut device) = pair();
+        let sealed: std::vec::Vec<_> = (0..REPLAY_WINDOW + 2)
+            .map(|_| host.seal::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap().1)
+            .collect();
+        let mut open = |i: usize| {
+            let mut buf = [0u8; MAX_PAYLOAD];
+            device
+                .open_request(&secure_frame(None, &sealed[i]), &mut buf)
+                .map(|opened| opened.counter)
+        };
+
+        // Pipelined requests opened out of order
+        assert_eq!(open(2), Ok(3));
+        assert_eq!(open(0), Ok(1));
+        assert_eq!(open(0), Err(SessionError::Replayed));
+
+        // 66 leaves counter 2 behind the window, unused or not
+        assert_eq!(open(65), Ok(66));
+        assert_eq!(open(1), Err(SessionError::Replayed));
+        assert_eq!(open(3), Ok(4));
+        assert_eq!(open(3), Err(SessionError::Replayed));
+    }
+
+    #[test]
+    fn a_handshake_only_replaces_the_session_once_a_request_confirms_it() {
+        let mut sessions = DeviceSessions::new();
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let data = sessions.accept(&PSK, &HOST_NONCE, &DEVICE_NONCE);
+        let mut first = HostSession::complete(&PSK, &HOST_NONCE, &data).unwrap();
+        let (_, sealed) = first.seal::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap();
+        assert!(
+            sessions
+                .open_request(&secure_frame(None, &sealed), &mut buf)
+                .is_ok()
+        );
+
+        // Anyone can send SESSION_OPEN; the session in use keeps working
+        sessions.accept(&PSK, &[0x33; SESSION_NONCE_LEN], &[0x44; SESSION_NONCE_LEN]);
+        let (_, sealed) = first.seal::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap();
+        assert!(
+            sessions
+                .open_request(&secure_frame(None, &sealed), &mut buf)
+                .is_ok()
+        );
+
+        // A host holding the PSK confirms its handshake with its first request
+        let other_nonce = [0x55; SESSION_NONCE_LEN];
+        let data = sessions.accept(&PSK, &other_nonce, &DEVICE_NONCE);
+        let mut second = HostSession::complete(&PSK, &other_nonce, &data).unwrap();
+        let (_, sealed) = second.seal::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap();
+        assert!(
+            sessions
+                .open_request(&secure_frame(None, &sealed), &mut buf)
+                .is_ok()
+        );
+        let (_, sealed) = first.seal::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap();
+        assert_eq!(
+            sessions
+                .open_request(&secure_frame(None, &sealed), &mut buf)
+                .err(),
+            Some(SessionError::BadTag)
+        );
+    }
+
+    #[test]
+    fn requests_opened_from_copied_keys_are_claimed_once() {
+        let mut sessions = DeviceSessions::new();
+        let mut buf = [0u8; MAX_PAYLOAD];
+        assert_eq!(
+            sessions
+                .keys()
+                .open_request(&secure_frame(None, &[0; SECURE_OVERHEAD]), &mut buf)
+                .err(),
+            Some(SessionError::NoSession)
+        );
+
+        let data = sessions.accept(&PSK, &HOST_NONCE, &DEVICE_NONCE);
+        let mut host = HostSession::complete(&PSK, &HOST_NONCE, &data).unwrap();
+        let (_, sealed) = host.seal::<MAX_PAYLOAD>(0x01, 0x02, &[7]).unwrap();
+        let keys = sessions.keys();
+        let opened = keys
+            .open_request(&secure_frame(None, &sealed), &mut buf)
+            .unwrap();
+        assert_eq!(opened.request.payload, &[7]);
+        assert_eq!(sessions.claim(&opened), Ok(()));
+        assert_eq!(sessions.claim(&opened), Err(SessionError::Replayed));
+
+        // Another handshake replaced the pending session before the claim
+        let (_, sealed) = host.seal::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap();
+        let data = sessions.accept(&PSK, &[0x66; SESSION_NONCE_LEN], &DEVICE_NONCE);
+        let mut next = HostSession::complete(&PSK, &[0x66; SESSION_NONCE_LEN], &data).unwrap();
+        let (_, pending) = next.seal::<MAX_PAYLOAD>(0x01, 0x02, &[]).unwrap();
+        let keys = sessions.keys();
+        sessions.accept(&PSK, &[0x77; SESSION_NONCE_LEN], &DEVICE_NONCE);
+        let mut buf2 = [0u8; MAX_PAYLOAD];
+        let opened = keys
+            .open_request(&secure_frame(None, &pending), &mut buf2)
+            .unwrap();
+        assert_eq!(sessions.claim(&opened), Err(SessionError::NoSession));
+        // The active session was not touched
+        assert!(
+            sessions
+                .open_request(&secure_frame(None, &sealed), &mut buf)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn refusals_are_plain_status_frames() {
+        let frame = secure_frame(Some(3), &[]);
+        let expected = build_frame_seq::<MAX_FRAME>(Some(3), 0x01, CMD_SECURE, &[0x05]).unwrap();
+        assert_eq!(
+            refused::<MAX_FRAME>(&frame, SessionError::NoSession).unwrap(),
+            expected
+        );
+        let ack = build_ack::<MAX_FRAME>(0x01, 0x02).unwrap();
+        assert_eq!(split_frame(&ack), Some((None, 0x01, 0x02, &[0x00][..])));
+        assert_eq!(split_frame(&[0x00]), None);
+    }
+}
@@ -0,0 +1,103 @@
+//! Link-quality counters kept by the [`Parser`](crate::Parser).
+
+/// Per-link counters. All counters wrap on overflow.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LinkStats {
+    /// Frames that passed the CRC check.
+    pub frames_ok: u32,
+    /// Complete candidates rejected by the CRC check.
+    pub crc_errors: u32,
+    /// Times the parser skipped bytes to find the next STX.
+    pub resyncs: u32,
+    /// Times `push_bytes` overflowed the stream buffer and cleared it.
+    pub overflows: u32,
+    /// Bytes dropped without becoming part of a frame.
+    pub bytes_discarded: u32,
+}
+
+impl LinkStats {
+    /// Size of the GET_LINK_STATS response data.
+    pub const WIRE_LEN: usize = 20;
+
+    pub const fn new() -> Self {
+        Self {
+            frames_ok: 0,
+            crc_errors: 0,
+            resyncs: 0,
+            overflows: 0,
+            bytes_discarded: 0,
+        }
+    }
+
+    /// Wire format: the counters above, in order, each as u32 little-endian.
+    pub fn to_le_bytes(&self) -> [u8; Self::WIRE_LEN] {
+        let mut out = [0u8; Self::WIRE_LEN];
+        let counters = [
+            self.frames_ok,
+            self.crc_errors,
+            self.resyncs,
+            self.overflows,
+            self.bytes_discarded,
+        ];
+        for (chunk, counter) in out.chunks_exact_mut(4).zip(counters) {
+            chunk.copy_from_slice(&counter.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn from_le_bytes(bytes: &[u8; Self::WIRE_LEN]) -> Self {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Self {
+            frames_ok: word(0),
+            crc_errors: word(4),
+            resyncs: word(8),
+            overflows: word(12),
+            bytes_discarded: word(16),
+        }
+    }
+
+    /// Add `other` into `self`.
+    pub fn merge(&mut self, other: &LinkStats) {
+        self.frames_ok = self.frames_ok.wrapping_add(other.frames_ok);
+        self.crc_errors = self.crc_errors.wrapping_add(other.crc_errors);
+        self.resyncs = self.resyncs.wrapping_add(other.resyncs);
+        self.overflows = self.overflows.wrapping_add(other.overflows);
+        self.bytes_discarded = self.bytes_discarded.wrapping_add(other.bytes_discarded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wire_format_round_trips() {
+        let stats = LinkStats {
+            frames_ok: 1,
+            crc_errors: 2,
+            resyncs: 3,
+            overflows: 4,
+            bytes_discarded: 0x0102_0304,
+        };
+        let bytes = stats.to_le_bytes();
+        assert_eq!(&bytes[..4], &[1, 0, 0, 0]);
+        assert_eq!(&bytes[16..], &[4, 3, 2, 1]);
+        assert_eq!(LinkStats::from_le_bytes(&bytes), stats);
+    }
+
+    #[test]
+    fn merge_adds_and_wraps() {
+        let mut total = LinkStats {
+            frames_ok: u32::MAX,
+            ..Default::default()
+        };
+        total.merge(&LinkStats {
+            frames_ok: 2,
+            crc_errors: 1,
+            ..Default::default()
+        });
+        assert_eq!(total.frames_ok, 1);
+        assert_eq!(total.crc_errors, 1);
+    }
+}
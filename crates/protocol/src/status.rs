@@ -0,0 +1,152 @@
+//! Status codes and builder errors.
+//!
+//! `Status` is the first payload byte of every response. The wire values are
+//! part of the protocol: never renumber an existing variant, only append.
+
+use core::fmt;
+
+/// Response status byte.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Status {
+    /// Command accepted / data follows.
+    Ok = 0x00,
+    /// Payload length outside the command's limits.
+    BadLen = 0x01,
+    /// Unknown CMD code.
+    BadCmd = 0x02,
+    /// Payload length fine, but a value is out of range.
+    BadArg = 0x03,
+    /// Device is busy with a previous request; retry later.
+    Busy = 0x04,
+    /// Subsystem not initialised or not configured.
+    NotReady = 0x05,
+    /// Unexpected firmware failure.
+    Internal = 0x06,
+    /// Command needs authentication.
+    Unauthorized = 0x07,
+    /// NAK: the request frame failed to parse. Followed by the `ParseError` reason byte.
+    BadFrame = 0x08,
+}
+
+impl Status {
+    /// Every status, in wire order.
+    pub const ALL: [Status; 9] = [
+        Status::Ok,
+        Status::BadLen,
+        Status::BadCmd,
+        Status::BadArg,
+        Status::Busy,
+        Status::NotReady,
+        Status::Internal,
+        Status::Unauthorized,
+        Status::BadFrame,
+    ];
+
+    pub const fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub const fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            0x00 => Some(Status::Ok),
+            0x01 => Some(Status::BadLen),
+            0x02 => Some(Status::BadCmd),
+            0x03 => Some(Status::BadArg),
+            0x04 => Some(Status::Busy),
+            0x05 => Some(Status::NotReady),
+            0x06 => Some(Status::Internal),
+            0x07 => Some(Status::Unauthorized),
+            0x08 => Some(Status::BadFrame),
+            _ => None,
+        }
+    }
+
+    /// Protocol name, as used in docs and the host tools (e.g. `BAD_CMD`).
+    pub const fn name(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::BadLen => "BAD_LEN",
+            Status::BadCmd => "BAD_CMD",
+            Status::BadArg => "BAD_ARG",
+            Status::Busy => "BUSY",
+            Status::NotReady => "NOT_READY",
+            Status::Internal => "INTERNAL",
+            Status::Unauthorized => "UNAUTHORIZED",
+            Status::BadFrame => "BAD_FRAME",
+        }
+    }
+}
+
+impl From<Status> for u8 {
+    fn from(status: Status) -> u8 {
+        status.as_u8()
+    }
+}
+
+impl TryFrom<u8> for Status {
+    type Error = u8;
+
+    fn try_from(code: u8) -> Result<Self, u8> {
+        Status::from_u8(code).ok_or(code)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Why a frame could not be built.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BuildError {
+    /// Payload (or getter data) longer than the frame format allows.
+    PayloadTooLarge,
+    /// The output buffer cannot hold the whole frame.
+    OutputTooSmall,
+    /// `Framing::reframe` was given bytes that are not a built frame.
+    NotAFrame,
+    /// A [`crate::FrameWriter`] was finished before its declared payload was written.
+    PayloadIncomplete,
+    /// The signing counter reached `u32::MAX` (see `auth`); the key needs replacing.
+    CounterExhausted,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::PayloadTooLarge => f.write_str("payload too large"),
+            BuildError::OutputTooSmall => f.write_str("output buffer too small"),
+            BuildError::NotAFrame => f.write_str("input is not a frame"),
+            BuildError::PayloadIncomplete => f.write_str("payload shorter than declared"),
+            BuildError::CounterExhausted => f.write_str("signing counter exhausted"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wire_values_are_stable() {
+        let codes: std::vec::Vec<u8> = Status::ALL.iter().map(|s| s.as_u8()).collect();
+        assert_eq!(
+            codes,
+            [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]
+        );
+    }
+
+    #[test]
+    fn round_trips_through_u8() {
+        for status in Status::ALL {
+            assert_eq!(Status::try_from(u8::from(status)), Ok(status));
+        }
+        assert_eq!(Status::try_from(0xEE), Err(0xEE));
+        assert_eq!(Status::BadCmd.to_string(), "BAD_CMD");
+    }
+}
@@ -0,0 +1,352 @@
+//! Byte transports and the command loop that runs over them.
+//!
+//! A [`FrameTransport`] only moves bytes: USB CDC, a UART, a TCP socket or an
+//! in-memory pipe in tests. [`run_command_server`] owns the [`Parser`], reads
+//! chunks from the transport, hands every request to a [`Service`] and writes
+//! the reply back, so the same loop runs over any of them.
+//!
+//! A read or write error means the peer went away: the server drops the
+//! unfinished frame it was collecting and waits for the next connection.
+
+use heapless::Vec;
+
+use crate::dispatch::{Dispatch, Registry};
+use crate::{Frame, Framing, LinkStats, MAX_FRAME, ParseError, Parser, build_nak};
+
+/// Bytes read from the transport per call: one full-speed USB packet.
+pub const CHUNK: usize = 64;
+
+/// A byte stream to a single peer.
+#[allow(async_fn_in_trait)] // single-threaded executor, no Send bound needed
+pub trait FrameTransport {
+    type Error;
+
+    /// Wait for bytes from the peer and copy up to `buf.len()` of them into
+    /// `buf`, returning how many.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Send all of `bytes`, without other writers' bytes in between.
+    async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Wait until a peer is attached (USB port opened, TCP client accepted).
+    /// Links that are always up return at once.
+    async fn wait_connected(&mut self);
+}
+
+/// What the server does with the frames it reads.
+#[allow(async_fn_in_trait)]
+pub trait Service {
+    /// Reply to a request, already in the transport's framing; `None` sends
+    /// nothing (the request was for another node, or its reply comes later).
+    async fn serve(&mut self, frame: &Frame<'_>) -> Option<Vec<u8, MAX_FRAME>>;
+
+    /// Reply to a frame that failed to parse. The default sends nothing.
+    fn parse_error(&mut self, _err: ParseError) -> Option<Vec<u8, MAX_FRAME>> {
+        None
+    }
+
+    /// Link counters taken from the parser since the last call.
+    fn record(&mut self, _stats: LinkStats) {}
+}
+
+/// Dispatch every frame to a registry, NAK frames whose header survived, and
+/// reply in `framing`.
+pub struct RegistryService<'r, D> {
+    pub registry: &'r Registry<D>,
+    pub framing: Framing,
+}
+
+impl<D: Dispatch> Service for RegistryService<'_, D> {
+    async fn serve(&mut self, frame: &Frame<'_>) -> Option<Vec<u8, MAX_FRAME>> {
+        let resp = self.registry.dispatch(frame).await.ok()?;
+        self.framing.reframe(&resp).ok()
+    }
+
+    fn parse_error(&mut self, err: ParseError) -> Option<Vec<u8, MAX_FRAME>> {
+        let nak = build_nak::<MAX_FRAME>(err).ok().flatten()?;
+        self.framing.reframe(&nak).ok()
+    }
+}
+
+/// Serve requests from `transport` forever. `now_us` is a monotonic clock
+/// for the parser's [`RxTiming`](crate::RxTiming).
+pub async fn run_command_server<T: FrameTransport, S: Service>(
+    transport: &mut T,
+    mut parser: Parser,
+    service: &mut S,
+    now_us: impl Fn() -> u64,
+) -> ! {
+    let mut chunk = [0u8; CHUNK];
+    loop {
+        transport.wait_connected().await;
+        // Only returns once the peer has gone
+        let _ = serve_connection(transport, &mut parser, service, &now_us, &mut chunk).await;
+        parser.clear();
+        service.record(parser.take_stats());
+    }
+}
+
+async fn serve_connection<T: FrameTransport, S: Service>(
+    transport: &mut T,
+    parser: &mut Parser,
+    service: &mut S,
+    now_us: &impl Fn() -> u64,
+    chunk: &mut [u8],
+) -> Result<(), T::Error> {
+    loop {
+        let n = transport.read(chunk).await?;
+        parser.push_bytes_at(&chunk[..n], now_us());
+
+        // Drain every complete frame in the buffer before reading again
+        loop {
+            // Publish counters from the previous step while no frame is borrowed
+            service.record(parser.take_stats());
+
+            let reply = match parser.next_frame() {
+                Ok(Some(frame)) => service.serve(&frame).await,
+                Ok(None) => break, // need more bytes
+                Err(err) => service.parse_error(err),
+            };
+            if let Some(reply) = reply {
+                transport.write_all(&reply).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+    use std::future::poll_fn;
+    use std::task::{Poll, Waker};
+
+    use embassy_futures::block_on;
+    use embassy_futures::select::{Either, select};
+
+    use super::*;
+    use crate::dispatch::{CommandHandler, CommandKind, CommandSpec, Data};
+    use crate::{Status, build_frame, cobs};
+
+    struct Ping;
+
+    impl CommandHandler for Ping {
+        const SPEC: CommandSpec = CommandSpec {
+            cmd: 0x01,
+            name: "PING",
+            kind: CommandKind::Setter,
+            min_payload: 0,
+            max_payload: 0,
+        };
+
+        async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+            Ok(())
+        }
+    }
+
+    /// One direction of an in-memory byte stream.
+    #[derive(Default)]
+    struct Pipe {
+        bytes: RefCell<VecDeque<u8>>,
+        waker: Cell<Option<Waker>>,
+    }
+
+    impl Pipe {
+        fn push(&self, bytes: &[u8]) {
+            self.bytes.borrow_mut().extend(bytes);
+            self.wake();
+        }
+
+        fn wake(&self) {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+
+        /// Wait for at least one byte, then take everything buffered.
+        async fn take(&self) -> std::vec::Vec<u8> {
+            poll_fn(|cx| {
+                let mut bytes = self.bytes.borrow_mut();
+                if bytes.is_empty() {
+                    self.waker.set(Some(cx.waker().clone()));
+                    return Poll::Pending;
+                }
+                Poll::Ready(bytes.drain(..).collect())
+            })
+            .await
+        }
+    }
+
+    /// Both directions plus a connection flag the test can drop.
+    #[derive(Default)]
+    struct Duplex {
+        to_device: Pipe,
+        to_host: Pipe,
+        connected: Cell<bool>,
+    }
+
+    impl Duplex {
+        fn connect(&self) {
+            self.connected.set(true);
+            self.to_device.wake();
+        }
+
+        fn disconnect(&self) {
+            self.connected.set(false);
+            self.to_device.wake();
+        }
+    }
+
+    /// The device's end of a [`Duplex`].
+    struct DeviceEnd<'a>(&'a Duplex);
+
+    #[derive(Debug)]
+    struct Disconnected;
+
+    impl FrameTransport for DeviceEnd<'_> {
+        type Error = Disconnected;
+
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Disconnected> {
+            let duplex = self.0;
+            poll_fn(|cx| {
+                if !duplex.connected.get() {
+                    return Poll::Ready(Err(Disconnected));
+                }
+                let mut bytes = duplex.to_device.bytes.borrow_mut();
+                if bytes.is_empty() {
+                    duplex.to_device.waker.set(Some(cx.waker().clone()));
+                    return Poll::Pending;
+                }
+                let n = buf.len().min(bytes.len());
+                for (slot, byte) in buf.iter_mut().zip(bytes.drain(..n)) {
+                    *slot = byte;
+                }
+                Poll::Ready(Ok(n))
+            })
+            .await
+        }
+
+        async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Disconnected> {
+            if !self.0.connected.get() {
+                return Err(Disconnected);
+            }
+            self.0.to_host.push(bytes);
+            Ok(())
+        }
+
+        async fn wait_connected(&mut self) {
+            let duplex = self.0;
+            poll_fn(|cx| match duplex.connected.get() {
+                true => Poll::Ready(()),
+                false => {
+                    duplex.to_device.waker.set(Some(cx.waker().clone()));
+                    Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+
+    /// Run the server over `duplex` until `host` finishes.
+    fn with_server<F: Future<Output = ()>>(
+        duplex: &Duplex,
+        framing: Framing,
+        stats: &Cell<LinkStats>,
+        host: F,
+    ) {
+        struct Recording<'a, S>(S, &'a Cell<LinkStats>);
+
+        impl<S: Service> Service for Recording<'_, S> {
+            async fn serve(&mut self, frame: &Frame<'_>) -> Option<Vec<u8, MAX_FRAME>> {
+                self.0.serve(frame).await
+            }
+
+            fn parse_error(&mut self, err: ParseError) -> Option<Vec<u8, MAX_FRAME>> {
+                self.0.parse_error(err)
+            }
+
+            fn record(&mut self, delta: LinkStats) {
+                let mut total = self.1.get();
+                total.merge(&delta);
+                self.1.set(total);
+            }
+        }
+
+        let registry = Registry::new().register(Ping);
+        let mut service = Recording(
+            RegistryService {
+                registry: &registry,
+                framing,
+            },
+            stats,
+        );
+        let mut transport = DeviceEnd(duplex);
+        let server = run_command_server(
+            &mut transport,
+            Parser::with_framing(framing),
+            &mut service,
+            || 0,
+        );
+        match block_on(select(server, host)) {
+            Either::First(never) => never,
+            Either::Second(()) => {}
+        }
+    }
+
+    #[test]
+    fn serves_requests_over_a_pipe() {
+        let duplex = Duplex::default();
+        duplex.connect();
+        let stats = Cell::new(LinkStats::new());
+        with_server(&duplex, Framing::StxLen, &stats, async {
+            let ping = build_frame::<16>(0x01, 0x01, &[]).unwrap();
+            let mut corrupt = ping.clone();
+            corrupt[4] ^= 0xFF;
+            // Two requests in one chunk, the second split across reads
+            duplex
+                .to_device
+                .push(&[&ping[..], &corrupt[..], &ping[..3]].concat());
+            let mut replies = duplex.to_host.take().await;
+            duplex.to_device.push(&ping[3..]);
+            replies.extend(duplex.to_host.take().await);
+
+            let ack = crate::build_ack::<16>(0x01, 0x01).unwrap();
+            let nak = [
+                Status::BadFrame.as_u8(),
+                ParseError::CrcMismatch {
+                    addr: 0x01,
+                    cmd: 0x01,
+                }
+                .code(),
+            ];
+            let nak = build_frame::<16>(0x01, 0x01, &nak).unwrap();
+            assert_eq!(replies, [&ack[..], &nak[..], &ack[..]].concat());
+        });
+        assert_eq!(stats.get().frames_ok, 2);
+        assert_eq!(stats.get().crc_errors, 1);
+    }
+
+    #[test]
+    fn drops_a_partial_frame_when_the_peer_disconnects() {
+        let duplex = Duplex::default();
+        duplex.connect();
+        let stats = Cell::new(LinkStats::new());
+        with_server(&duplex, Framing::Cobs, &stats, async {
+            let ping = build_frame::<16>(0x01, 0x01, &[]).unwrap();
+            let ping = cobs::from_stx_frame::<16>(&ping).unwrap();
+            duplex.to_device.push(&ping[..2]);
+            // Let the server take the half frame, then hang up
+            embassy_futures::yield_now().await;
+            duplex.disconnect();
+            embassy_futures::yield_now().await;
+
+            duplex.connect();
+            duplex.to_device.push(&ping);
+            let ack = crate::build_ack::<16>(0x01, 0x01).unwrap();
+            let ack = cobs::from_stx_frame::<16>(&ack).unwrap();
+            assert_eq!(duplex.to_host.take().await, &ack[..]);
+        });
+        assert_eq!(stats.get().frames_ok, 1);
+        assert_eq!(stats.get().bytes_discarded, 2);
+    }
+}
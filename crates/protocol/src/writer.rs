@@ -0,0 +1,239 @@
+//! Frames serialized straight into a caller's buffer.
+//!
+//! [`FrameWriter`] writes the header, then the payload in as many pieces as
+//! the caller likes, and the CRC, updating the CRC as bytes go in. LEN comes
+//! before the payload on the wire and is covered by the CRC, so the payload
+//! length is declared up front. Nothing is staged in a temporary buffer and
+//! there is no const generic to pick: the output is any `&mut [u8]`, e.g. a
+//! stack array of [`MAX_FRAME`](crate::MAX_FRAME) bytes or a transport's TX
+//! packet buffer.
+//!
+//! [`Response`] covers the usual response payloads (see the crate docs) and
+//! writes them with a `FrameWriter`; the `build_*` functions are built on it.
+
+use crate::event::Event;
+use crate::{BuildError, Crc16, MAX_DATA, MAX_PAYLOAD, STX, STX_SEQ, Status};
+
+/// Writes one STX frame into a borrowed buffer.
+pub struct FrameWriter<'b> {
+    buf: &'b mut [u8],
+    /// Bytes written so far.
+    pos: usize,
+    /// Where the CRC goes: the end of the declared payload.
+    end: usize,
+    crc: Crc16,
+}
+
+impl<'b> FrameWriter<'b> {
+    /// Write the header of a frame with a `payload_len`-byte payload into
+    /// `buf` (sequenced if `seq` is set). The payload follows with
+    /// [`write`](Self::write).
+    pub fn new(
+        buf: &'b mut [u8],
+        seq: Option<u8>,
+        addr: u8,
+        cmd: u8,
+        payload_len: usize,
+    ) -> Result<Self, BuildError> {
+        if payload_len > MAX_PAYLOAD {
+            return Err(BuildError::PayloadTooLarge);
+        }
+        // LEN counts ADDR + CMD + payload
+        let len = 2 + payload_len as u8;
+        let header_len = 2 + usize::from(seq.is_some());
+        let end = header_len + 2 + payload_len;
+        if end + 2 > buf.len() {
+            return Err(BuildError::OutputTooSmall);
+        }
+
+        let mut writer = Self {
+            buf,
+            pos: 0,
+            end,
+            crc: Crc16::new(),
+        };
+        match seq {
+            Some(seq) => writer.put(&[STX_SEQ, len, seq]),
+            None => writer.put(&[STX, len]),
+        }
+        writer.put(&[addr, cmd]);
+        Ok(writer)
+    }
+
+    /// Payload bytes still expected.
+    pub fn remaining(&self) -> usize {
+        self.end - self.pos
+    }
+
+    /// Append the next part of the payload.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), BuildError> {
+        if bytes.len() > self.remaining() {
+            return Err(BuildError::PayloadTooLarge);
+        }
+        self.put(bytes);
+        Ok(())
+    }
+
+    /// Append the CRC and return the whole frame. Fails if less payload was
+    /// written than declared.
+    pub fn finish(self) -> Result<&'b [u8], BuildError> {
+        if self.remaining() != 0 {
+            return Err(BuildError::PayloadIncomplete);
+        }
+        let end = self.end;
+        let crc = self.crc.finish();
+        // CRCL, CRCH (Modbus convention); room was checked in `new`
+        self.buf[end..end + 2].copy_from_slice(&crc.to_le_bytes());
+        Ok(&self.buf[..end + 2])
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        let dst = &mut self.buf[self.pos..self.pos + bytes.len()];
+        dst.copy_from_slice(bytes);
+        self.crc.update(dst);
+        self.pos += bytes.len();
+    }
+}
+
+/// A response payload, before it is framed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Response<'a> {
+    /// Setter success: `[OK]`.
+    Ack,
+    /// `[STATUS]`, for any status (usually not OK).
+    Err(Status),
+    /// Getter success: `[OK, BYTECOUNT, data...]`.
+    Data(&'a [u8]),
+    /// An event frame; its class decides CMD (see [`crate::event`]).
+    Event(Event),
+}
+
+impl Response<'_> {
+    /// CMD of the response frame: the request's `cmd`, except for events.
+    pub const fn cmd(&self, cmd: u8) -> u8 {
+        match self {
+            Response::Event(event) => event.class().cmd(),
+            _ => cmd,
+        }
+    }
+
+    /// Payload length on the wire.
+    pub fn payload_len(&self) -> usize {
+        match self {
+            Response::Ack | Response::Err(_) => 1,
+            Response::Data(data) => 2 + data.len(),
+            Response::Event(event) => event.payload().len(),
+        }
+    }
+
+    /// Write the response frame to a request with `seq`, `addr` and `cmd`
+    /// into `buf`, and return it.
+    pub fn write<'b>(
+        &self,
+        buf: &'b mut [u8],
+        seq: Option<u8>,
+        addr: u8,
+        cmd: u8,
+    ) -> Result<&'b [u8], BuildError> {
+        if let Response::Data(data) = self
+            && data.len() > MAX_DATA
+        {
+            return Err(BuildError::PayloadTooLarge);
+        }
+        let mut writer = FrameWriter::new(buf, seq, addr, self.cmd(cmd), self.payload_len())?;
+        match *self {
+            Response::Ack => writer.write(&[Status::Ok.as_u8()])?,
+            Response::Err(status) => writer.write(&[status.as_u8()])?,
+            Response::Data(data) => {
+                // STATUS, BYTECOUNT (MAX_DATA keeps it within a u8)
+                writer.write(&[Status::Ok.as_u8(), data.len() as u8])?;
+                writer.write(data)?;
+            }
+            Response::Event(event) => writer.write(&event.payload())?,
+        }
+        writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MAX_FRAME, Parser, build_ack, build_data, build_frame_seq};
+
+    #[test]
+    fn writes_the_same_bytes_as_the_builders() {
+        let mut buf = [0u8; MAX_FRAME];
+        let frame = Response::Ack.write(&mut buf, None, 0x01, 0x02).unwrap();
+        assert_eq!(frame, &[0xA5, 0x03, 0x01, 0x02, 0x00, 0x38, 0xFD]);
+
+        let frame = Response::Data(&[0xAA, 0xBB])
+            .write(&mut buf, None, 0x01, 0x20)
+            .unwrap();
+        assert_eq!(
+            frame,
+            &build_data::<MAX_FRAME>(0x01, 0x20, &[0xAA, 0xBB]).unwrap()[..]
+        );
+
+        let frame = Response::Err(Status::Busy)
+            .write(&mut buf, Some(9), 0x01, 0x02)
+            .unwrap();
+        assert_eq!(
+            frame,
+            &build_frame_seq::<MAX_FRAME>(Some(9), 0x01, 0x02, &[0x04]).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn payload_can_be_written_in_pieces() {
+        let mut buf = [0u8; 32];
+        let mut writer = FrameWriter::new(&mut buf, Some(3), 0x01, 0x30, 5).unwrap();
+        writer.write(&[1, 2]).unwrap();
+        assert_eq!(writer.remaining(), 3);
+        writer.write(&[3]).unwrap();
+        writer.write(&[4, 5]).unwrap();
+        assert_eq!(writer.write(&[6]), Err(BuildError::PayloadTooLarge));
+        let frame = writer.finish().unwrap();
+
+        let mut parser = Parser::new();
+        parser.push_bytes(frame);
+        let frame = parser.next_frame().unwrap().unwrap();
+        assert_eq!((frame.seq, frame.cmd), (Some(3), 0x30));
+        assert_eq!(frame.payload, &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn refuses_short_payloads_and_small_buffers() {
+        let mut buf = [0u8; 32];
+        let mut writer = FrameWriter::new(&mut buf, None, 0x01, 0x30, 2).unwrap();
+        writer.write(&[1]).unwrap();
+        assert_eq!(writer.finish().err(), Some(BuildError::PayloadIncomplete));
+
+        let mut small = [0u8; 6];
+        assert!(matches!(
+            Response::Ack.write(&mut small, None, 0x01, 0x02),
+            Err(BuildError::OutputTooSmall)
+        ));
+        let mut buf = [0u8; 2 * MAX_FRAME];
+        assert!(matches!(
+            FrameWriter::new(&mut buf, None, 0x01, 0x30, MAX_PAYLOAD + 1),
+            Err(BuildError::PayloadTooLarge)
+        ));
+        assert!(matches!(
+            Response::Data(&[0; MAX_DATA + 1]).write(&mut buf, None, 0x01, 0x20),
+            Err(BuildError::PayloadTooLarge)
+        ));
+    }
+
+    #[test]
+    fn events_use_their_class_cmd() {
+        let event = Event::Fault { code: 3 };
+        let mut buf = [0u8; MAX_FRAME];
+        let frame = Response::Event(event)
+            .write(&mut buf, None, 0x01, 0x00)
+            .unwrap();
+        assert_eq!(frame[3], 0x82);
+        assert_eq!(&frame[4..5], &[3]);
+        assert_eq!(Response::Ack.cmd(0x02), 0x02);
+        assert_eq!(build_ack::<16>(0x01, 0x02).unwrap()[3], 0x02);
+    }
+}
@@ -0,0 +1,20 @@
+//! Equivalence of the table-driven CRC-16/Modbus with the bit-by-bit reference.
+
+use proptest::prelude::*;
+use protocol::{crc16_modbus, crc16_modbus_bitwise};
+
+proptest! {
+    #[test]
+    fn table_matches_bitwise(data in prop::collection::vec(any::<u8>(), 0..=4096)) {
+        prop_assert_eq!(crc16_modbus(&data), crc16_modbus_bitwise(&data));
+    }
+
+    #[test]
+    fn appended_crc_checks_to_zero(data in prop::collection::vec(any::<u8>(), 0..=512)) {
+        // Modbus property: running the CRC over data + CRCL, CRCH leaves 0.
+        let mut framed = data.clone();
+        framed.extend_from_slice(&crc16_modbus(&data).to_le_bytes());
+        prop_assert_eq!(crc16_modbus(&framed), 0);
+        prop_assert_eq!(crc16_modbus_bitwise(&framed), 0);
+    }
+}
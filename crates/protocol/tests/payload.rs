@@ -0,0 +1,115 @@
+//! `#[derive(Payload)]` and `#[derive(Command)]` from outside the crate.
+
+use heapless::{String, Vec};
+use protocol::address::{NodeAddress, SetAddress};
+use protocol::dispatch::{CommandKind, CommandSpec};
+use protocol::payload::{Command, Payload};
+use protocol::{MAX_FRAME, Parser, Status};
+
+#[derive(Clone, Debug, PartialEq, Payload, Command)]
+#[command(cmd = 0x30)]
+struct Configure {
+    mode: Mode,
+    #[payload(range = 1..=100)]
+    brightness: u8,
+    label: String<8>,
+    steps: Vec<i16, 4>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Payload)]
+#[repr(u8)]
+enum Mode {
+    Off,
+    Blink { period_ms: u16 },
+    Fade(u8, u8) = 7,
+}
+
+#[derive(Debug, PartialEq, Payload)]
+struct Wrapper<T>(T, bool);
+
+fn configure() -> Configure {
+    Configure {
+        mode: Mode::Blink { period_ms: 500 },
+        brightness: 40,
+        label: String::try_from("led").unwrap(),
+        steps: Vec::from_slice(&[-1, 2]).unwrap(),
+    }
+}
+
+#[test]
+fn encodes_fields_in_order_little_endian() {
+    let bytes = configure().to_bytes::<64>().unwrap();
+    assert_eq!(
+        &bytes[..],
+        &[
+            1, 0xF4, 0x01, 40, 3, b'l', b'e', b'd', 2, 0xFF, 0xFF, 0x02, 0x00
+        ]
+    );
+    assert_eq!(configure().encoded_len(), bytes.len());
+    assert_eq!(Configure::from_bytes(&bytes), Ok(configure()));
+}
+
+#[test]
+fn enum_tags_follow_discriminants() {
+    assert_eq!(&Mode::Off.to_bytes::<4>().unwrap()[..], &[0]);
+    assert_eq!(&Mode::Fade(1, 2).to_bytes::<4>().unwrap()[..], &[7, 1, 2]);
+    assert_eq!(Mode::from_bytes(&[7, 1, 2]), Ok(Mode::Fade(1, 2)));
+    assert_eq!(Mode::from_bytes(&[2]), Err(Status::BadArg));
+    assert_eq!((Mode::MIN_LEN, Mode::MAX_LEN), (1, 3));
+    assert_eq!(Wrapper::<u16>::from_bytes(&[1, 0, 1]), Ok(Wrapper(1, true)));
+}
+
+#[test]
+fn decoding_checks_lengths_and_ranges() {
+    let bytes = configure().to_bytes::<64>().unwrap();
+    assert_eq!(
+        Configure::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(Status::BadLen)
+    );
+    let mut long = bytes.clone();
+    long.push(0).unwrap();
+    assert_eq!(Configure::from_bytes(&long), Err(Status::BadLen));
+
+    let mut dim = bytes;
+    dim[3] = 0;
+    assert_eq!(Configure::from_bytes(&dim), Err(Status::BadArg));
+
+    assert_eq!(Configure::MIN_LEN, 1 + 1 + 1 + 1);
+    assert_eq!(Configure::MAX_LEN, 3 + 1 + 9 + 9);
+}
+
+#[test]
+fn commands_build_and_decode_frames() {
+    let request = SetAddress {
+        node: 0x2A,
+        groups: Some(0x8001),
+    };
+    let bytes = request.build::<MAX_FRAME>(Some(5), 0x01).unwrap();
+
+    let mut parser = Parser::new();
+    parser.push_bytes(&bytes);
+    let frame = parser.next_frame().unwrap().unwrap();
+    assert_eq!((frame.seq, frame.cmd), (Some(5), 0x04));
+    assert_eq!(frame.payload, &[0x2A, 0x01, 0x80]);
+    assert_eq!(SetAddress::from_frame(&frame), Ok(request));
+    assert_eq!(
+        SetAddress::from_bytes(&[0x2A]),
+        Ok(SetAddress {
+            node: 0x2A,
+            groups: None
+        })
+    );
+    assert_eq!(SetAddress::from_bytes(&[0xF0]), Err(Status::BadArg));
+
+    let spec = CommandSpec::of::<SetAddress>("SET_ADDRESS", CommandKind::Setter);
+    assert_eq!((spec.cmd, spec.min_payload, spec.max_payload), (0x04, 1, 3));
+
+    let data = NodeAddress {
+        node: 0x2A,
+        groups: 0x8001,
+    };
+    assert_eq!(
+        NodeAddress::from_bytes(&data.to_bytes::<3>().unwrap()),
+        Ok(data)
+    );
+}
@@ -0,0 +1,164 @@
+//! Property tests for the frame builders and stream parser.
+
+use proptest::prelude::*;
+use protocol::cobs::{self, MAX_PACKET};
+use protocol::{Framing, MAX_FRAME, MAX_PAYLOAD, Parser, STX, STX_SEQ, build_frame};
+
+/// Owned copy of a decoded frame: (addr, cmd, payload).
+type Owned = (u8, u8, Vec<u8>);
+
+/// Feed `bytes` to the parser in the given chunk sizes and collect every frame found.
+fn parse_all_chunked(bytes: &[u8], chunks: &[usize]) -> Vec<Owned> {
+    parse_all_chunked_with(Framing::StxLen, bytes, chunks)
+}
+
+fn parse_all_chunked_with(framing: Framing, bytes: &[u8], chunks: &[usize]) -> Vec<Owned> {
+    let mut parser = Parser::with_framing(framing);
+    let mut rest = bytes;
+    let mut sizes = chunks.iter().cycle();
+    let mut frames = Vec::new();
+
+    while !rest.is_empty() {
+        let n = (*sizes.next().unwrap()).min(rest.len());
+        parser.push_bytes(&rest[..n]);
+        rest = &rest[n..];
+
+        loop {
+            match parser.next_frame() {
+                Ok(Some(frame)) => frames.push((frame.addr, frame.cmd, frame.payload.to_vec())),
+                Ok(None) => break,
+                Err(_) => continue,
+            }
+        }
+    }
+    frames
+}
+
+fn parse_chunked(bytes: &[u8], chunks: &[usize]) -> Option<Owned> {
+    parse_all_chunked(bytes, chunks).into_iter().next()
+}
+
+proptest! {
+    #[test]
+    fn build_then_parse_round_trips(
+        addr in any::<u8>(),
+        cmd in any::<u8>(),
+        payload in prop::collection::vec(any::<u8>(), 0..=MAX_PAYLOAD),
+    ) {
+        let bytes = build_frame::<MAX_FRAME>(addr, cmd, &payload).unwrap();
+        prop_assert_eq!(bytes.len(), payload.len() + 6);
+
+        let frames = parse_all_chunked(&bytes, &[bytes.len()]);
+        prop_assert_eq!(frames, vec![(addr, cmd, payload)]);
+    }
+
+    #[test]
+    fn arbitrary_chunking_round_trips(
+        addr in any::<u8>(),
+        cmd in any::<u8>(),
+        payload in prop::collection::vec(any::<u8>(), 0..=MAX_PAYLOAD),
+        chunks in prop::collection::vec(1usize..=64, 1..16),
+    ) {
+        let bytes = build_frame::<MAX_FRAME>(addr, cmd, &payload).unwrap();
+
+        let frames = parse_all_chunked(&bytes, &chunks);
+        prop_assert_eq!(frames, vec![(addr, cmd, payload)]);
+    }
+
+    #[test]
+    fn back_to_back_frames_round_trip_without_phantoms(
+        frames in prop::collection::vec(
+            (any::<u8>(), any::<u8>(), prop::collection::vec(any::<u8>(), 0..64)),
+            1..12,
+        ),
+        chunks in prop::collection::vec(1usize..=64, 1..16),
+    ) {
+        let mut stream = Vec::new();
+        for (addr, cmd, payload) in &frames {
+            stream.extend_from_slice(&build_frame::<MAX_FRAME>(*addr, *cmd, payload).unwrap());
+        }
+
+        prop_assert_eq!(parse_all_chunked(&stream, &chunks), frames);
+    }
+
+    #[test]
+    fn leading_garbage_is_skipped(
+        garbage in prop::collection::vec(any::<u8>().prop_filter("not a start marker", |b| *b != STX && *b != STX_SEQ), 0..128),
+        addr in any::<u8>(),
+        cmd in any::<u8>(),
+        payload in prop::collection::vec(any::<u8>(), 0..32),
+        chunks in prop::collection::vec(1usize..=64, 1..16),
+    ) {
+        let frame_bytes = build_frame::<MAX_FRAME>(addr, cmd, &payload).unwrap();
+        let mut stream = garbage.clone();
+        stream.extend_from_slice(&frame_bytes);
+
+        let frame = parse_chunked(&stream, &chunks).unwrap();
+        prop_assert_eq!(frame, (addr, cmd, payload));
+    }
+
+    #[test]
+    fn corrupted_frame_is_never_accepted(
+        addr in any::<u8>(),
+        cmd in any::<u8>(),
+        payload in prop::collection::vec(any::<u8>(), 0..32),
+        flip_at in any::<prop::sample::Index>(),
+        flip_mask in 1u8..=255,
+    ) {
+        let mut bytes = build_frame::<MAX_FRAME>(addr, cmd, &payload).unwrap();
+        let i = flip_at.index(bytes.len());
+        bytes[i] ^= flip_mask;
+
+        let mut parser = Parser::new();
+        parser.push_bytes(&bytes);
+        loop {
+            match parser.next_frame() {
+                Ok(Some(frame)) => {
+                    let unchanged = frame.addr == addr && frame.cmd == cmd && frame.payload == &payload[..];
+                    prop_assert!(!unchanged, "corrupted frame decoded as the original");
+                }
+                Ok(None) => break,
+                Err(_) => continue,
+            }
+        }
+    }
+
+    #[test]
+    fn cobs_framing_round_trips_like_stx_framing(
+        frames in prop::collection::vec(
+            (any::<u8>(), any::<u8>(), prop::collection::vec(any::<u8>(), 0..=MAX_PAYLOAD)),
+            1..6,
+        ),
+        chunks in prop::collection::vec(1usize..=64, 1..16),
+    ) {
+        let mut stx = Vec::new();
+        let mut packets = Vec::new();
+        for (addr, cmd, payload) in &frames {
+            let frame = build_frame::<MAX_FRAME>(*addr, *cmd, payload).unwrap();
+            let packet = cobs::build_frame::<MAX_PACKET>(*addr, *cmd, payload).unwrap();
+            prop_assert_eq!(&Framing::Cobs.reframe(&frame).unwrap(), &packet);
+            prop_assert_eq!(packet.iter().position(|&b| b == 0), Some(packet.len() - 1));
+            stx.extend_from_slice(&frame);
+            packets.extend_from_slice(&packet);
+        }
+
+        prop_assert_eq!(parse_all_chunked_with(Framing::Cobs, &packets, &chunks), frames.clone());
+        prop_assert_eq!(parse_all_chunked(&stx, &chunks), frames);
+    }
+
+    #[test]
+    fn cobs_resyncs_after_garbage(
+        garbage in prop::collection::vec(any::<u8>(), 0..300),
+        addr in any::<u8>(),
+        cmd in any::<u8>(),
+        payload in prop::collection::vec(any::<u8>(), 0..32),
+        chunks in prop::collection::vec(1usize..=64, 1..16),
+    ) {
+        let mut stream = garbage;
+        stream.push(0x00);
+        stream.extend_from_slice(&cobs::build_frame::<MAX_PACKET>(addr, cmd, &payload).unwrap());
+
+        let frames = parse_all_chunked_with(Framing::Cobs, &stream, &chunks);
+        prop_assert_eq!(frames.last(), Some(&(addr, cmd, payload)));
+    }
+}
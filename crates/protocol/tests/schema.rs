@@ -0,0 +1,38 @@
+//! The generated tables agree with the hand-written enums they describe.
+
+use protocol::Status;
+use protocol::capabilities::Features;
+use protocol::event::{EVENT_FIRST, EventClass};
+use protocol::schema::{EVENT_CLASSES, FEATURES, STATUS_CODES};
+
+#[test]
+fn status_codes_match() {
+    assert_eq!(STATUS_CODES.len(), Status::ALL.len());
+    for (status, (code, name)) in Status::ALL.into_iter().zip(STATUS_CODES) {
+        assert_eq!((status.as_u8(), status.name()), (code, name));
+    }
+}
+
+#[test]
+fn event_classes_match() {
+    assert_eq!(EVENT_CLASSES.len(), EventClass::ALL.len());
+    for (class, (code, name)) in EventClass::ALL.into_iter().zip(EVENT_CLASSES) {
+        assert_eq!(class.cmd(), EVENT_FIRST + code, "{name}");
+    }
+}
+
+#[test]
+fn feature_bits_match() {
+    let features = [
+        Features::ARQ,
+        Features::FRAGMENTATION,
+        Features::AUTH,
+        Features::EVENTS,
+        Features::PIPELINING,
+        Features::SESSION,
+    ];
+    assert_eq!(FEATURES.len(), features.len());
+    for (feature, (bit, name)) in features.into_iter().zip(FEATURES) {
+        assert_eq!(feature.0, bit, "{name}");
+    }
+}
@@ -11,8 +11,9 @@ MEMORY {
       * The RP2350 has either external or internal flash.
       *
       * 2 MiB is a safe default here, although a Pico 2 has 4 MiB.
+      * The last four 4K sectors hold settings (src/storage.rs).
       */
-      FLASH : ORIGIN = 0x10000000, LENGTH = 2048K
+      FLASH : ORIGIN = 0x10000000, LENGTH = 2048K - 16K
       /*
       * RAM consists of 8 banks, SRAM0-SRAM7, with a striped mapping.
       * This is usually good for performance, as it distributes load on
@@ -0,0 +1,71 @@
+//! Publishes the on-chip temperature sensor into the register map and the
+//! USB log port's telemetry, and raises a threshold event when the die gets
+//! hot or cools down again.
+use embassy_rp::Peri;
+use embassy_rp::adc::{Adc, Async, Channel, Config, InterruptHandler};
+use embassy_rp::bind_interrupts;
+use embassy_rp::peripherals::{ADC, ADC_TEMP_SENSOR};
+use embassy_time::Timer;
+use protocol::event::Event;
+
+use crate::serial_usb::UsbLogPort;
+use crate::{events, registers};
+
+bind_interrupts!(struct Irqs {
+    ADC_IRQ_FIFO => InterruptHandler;
+});
+
+const SAMPLE_PERIOD_MS: u64 = 500;
+
+/// Die temperature that raises `Event::Threshold` (channel 0), 0.1 °C.
+const TEMP_ALARM_DECI_C: i16 = 500;
+/// It must fall this far below the alarm level to clear, 0.1 °C.
+const TEMP_HYSTERESIS_DECI_C: i16 = 20;
+
+pub struct Sensors {
+    adc: Adc<'static, Async>,
+    temp: Channel<'static>,
+}
+
+pub fn init(adc: Peri<'static, ADC>, temp: Peri<'static, ADC_TEMP_SENSOR>) -> Sensors {
+    Sensors {
+        adc: Adc::new(adc, Irqs, Config::default()),
+        temp: Channel::new_temp_sensor(temp),
+    }
+}
+
+#[embassy_executor::task]
+pub async fn adc_task(mut sensors: Sensors, log: UsbLogPort) -> ! {
+    let mut hot = false;
+    loop {
+        if let Ok(raw) = sensors.adc.read(&mut sensors.temp).await {
+            let deci = deci_celsius(raw);
+            registers::set_input(registers::INPUT_TEMP_RAW, raw);
+            registers::set_input(registers::INPUT_TEMP_DECI_C, deci as u16);
+            log.telemetry("temp", &[raw as i32, deci as i32]);
+
+            let now_hot = if hot {
+                deci > TEMP_ALARM_DECI_C - TEMP_HYSTERESIS_DECI_C
+            } else {
+                deci >= TEMP_ALARM_DECI_C
+            };
+            if now_hot != hot {
+                hot = now_hot;
+                let state = if hot { "alarm" } else { "normal" };
+                log.line(format_args!("temperature {state}: {deci} (0.1 C)"));
+                events::publish(Event::Threshold {
+                    channel: 0,
+                    above: hot,
+                    value: deci,
+                });
+            }
+        }
+        Timer::after_millis(SAMPLE_PERIOD_MS).await;
+    }
+}
+
+/// RP2350 datasheet 12.4.6: T = 27 - (V - 0.706) / 0.001721, with V = raw * 3.3 / 4096.
+fn deci_celsius(raw: u16) -> i16 {
+    let microvolts = raw as i32 * 3_300_000 / 4096;
+    (270 - (microvolts - 706_000) * 10 / 1721) as i16
+}
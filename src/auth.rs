@@ -0,0 +1,178 @@
+//! Request authentication (see `protocol::auth`).
+//!
+//! The key is read from OTP when rows `KEY_OTP_ROW..KEY_OTP_ROW + 16` are
+//! programmed, otherwise from a key record in `storage::KEY_SECTOR`. Without
+//! either, requests run unsigned as before and `Features::AUTH` is not
+//! reported.
+//!
+//! The counter is persisted as a bound `COUNTER_RESERVE` ahead of the last
+//! accepted one, so flash is written once every `COUNTER_RESERVE` requests.
+//! After a reset the device starts at the bound and hosts catch up from the
+//! UNAUTHORIZED response. The bound alternates between two sectors, so power
+//! loss during a write leaves the previous one readable.
+use core::cell::RefCell;
+
+use embassy_rp::otp;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex as AsyncMutex;
+use protocol::Frame;
+use protocol::auth::{
+    COUNTER_RECORD_LEN, KEY_LEN, KEY_RECORD_LEN, Key, Signed, Verifier, counter_from_record,
+    counter_record, key_from_record,
+};
+use protocol::event::Event;
+
+use crate::{events, storage};
+
+/// First of the 16 ECC rows holding the key, little-endian (OTP page 56).
+pub const KEY_OTP_ROW: usize = 56 * otp::NUM_ROWS_PER_PAGE;
+
+/// Accepted counters between two writes of the persisted bound.
+const COUNTER_RESERVE: u32 = 1024;
+
+struct State {
+    /// Also the pre-shared key of `session`.
+    key: Key,
+    verifier: Verifier,
+    /// Persisted bound; every request that runs has a counter below it.
+    reserved: u32,
+    /// Index in `storage::COUNTER_SECTORS` holding `reserved`.
+    slot: usize,
+}
+
+/// `None` while no key is provisioned.
+static STATE: Mutex<CriticalSectionRawMutex, RefCell<Option<State>>> =
+    Mutex::new(RefCell::new(None));
+
+// Held while a new bound is written, so concurrent requests write it once
+static RESERVING: AsyncMutex<CriticalSectionRawMutex, ()> = AsyncMutex::new(());
+
+/// Load the key and counter bound. Call after `storage::init`, before the
+/// command loop starts.
+pub fn init() {
+    let Some(key) = otp_key().or_else(flash_key) else {
+        return;
+    };
+    let (slot, reserved) = (0..storage::COUNTER_SECTORS.len())
+        .filter_map(|slot| stored_bound(slot).map(|bound| (slot, bound)))
+        .max_by_key(|&(_, bound)| bound)
+        .unwrap_or((1, 0));
+    let state = State {
+        key,
+        verifier: Verifier::new(key, reserved),
+        reserved,
+        slot,
+    };
+    STATE.lock(|cell| cell.replace(Some(state)));
+}
+
+/// Whether a key is provisioned, i.e. requests must be signed.
+pub fn enabled() -> bool {
+    STATE.lock(|state| state.borrow().is_some())
+}
+
+/// The provisioned key, if any.
+pub fn key() -> Option<Key> {
+    STATE.lock(|state| state.borrow().as_ref().map(|state| state.key))
+}
+
+/// `frame` with its signature removed, or the device's last counter if it is
+/// refused (for `protocol::auth::unauthorized`). Without a key every frame
+/// passes unchanged.
+///
+/// Only the counter check runs in a critical section: the HMAC runs on a
+/// copy of the key, and a new bound is written to flash under `RESERVING`.
+pub async fn verify<'a>(frame: &Frame<'a>) -> Result<Frame<'a>, u32> {
+    accept(authenticate(frame)?).await
+}
+
+/// The tag check of `verify`, which leaves the counter alone, so a
+/// retransmission still passes.
+pub fn authenticate<'a>(frame: &Frame<'a>) -> Result<Signed<'a>, u32> {
+    match key() {
+        Some(key) => protocol::auth::authenticate(&key, frame).map_err(|_| last()),
+        None => Ok(Signed {
+            request: *frame,
+            counter: None,
+        }),
+    }
+}
+
+/// The rest of `verify`: take the counter of an `authenticate`d request.
+pub async fn accept(signed: Signed<'_>) -> Result<Frame<'_>, u32> {
+    let Some(counter) = signed.counter else {
+        return Ok(signed.request);
+    };
+    let reserved = with_state(|state| {
+        state
+            .verifier
+            .accept(counter)
+            .map_err(|_| state.verifier.last())?;
+        Ok(state.reserved)
+    })?;
+    if counter >= reserved {
+        reserve(counter).await?;
+    }
+    Ok(signed.request)
+}
+
+/// Persist a new bound above `counter` before its request runs, in the other
+/// sector. Requests that crossed the old bound meanwhile share the write.
+async fn reserve(counter: u32) -> Result<(), u32> {
+    let _writing = RESERVING.lock().await;
+    let Some((bound, slot)) = with_state(|state| {
+        Ok((counter >= state.reserved).then(|| {
+            let bound = state.verifier.last().saturating_add(COUNTER_RESERVE);
+            (bound, 1 - state.slot)
+        }))
+    })?
+    else {
+        return Ok(()); // written while this request waited
+    };
+    if storage::replace(storage::COUNTER_SECTORS[slot], &counter_record(bound)).is_err() {
+        // Without a persisted bound a reset would reopen old counters: refuse
+        events::publish(Event::Fault {
+            code: events::FAULT_AUTH_STORE,
+        });
+        return Err(last());
+    }
+    with_state(|state| {
+        state.reserved = bound;
+        state.slot = slot;
+        Ok(())
+    })
+}
+
+/// Run `f` on the state in a critical section. Without a key, which `accept`
+/// never gets to, it fails with counter 0.
+fn with_state<R>(f: impl FnOnce(&mut State) -> Result<R, u32>) -> Result<R, u32> {
+    STATE.lock(|state| f(state.borrow_mut().as_mut().ok_or(0u32)?))
+}
+
+/// Counter of the last accepted request.
+fn last() -> u32 {
+    STATE.lock(|state| state.borrow().as_ref().map_or(0, |s| s.verifier.last()))
+}
+
+fn otp_key() -> Option<Key> {
+    let mut key = [0u8; KEY_LEN];
+    for (row, pair) in (KEY_OTP_ROW..).zip(key.chunks_exact_mut(2)) {
+        let word = otp::read_ecc_word(row).ok()?;
+        pair.copy_from_slice(&word.to_le_bytes());
+    }
+    // Unprogrammed rows read as zero
+    (key != [0; KEY_LEN]).then_some(key)
+}
+
+fn flash_key() -> Option<Key> {
+    let mut record = [0u8; KEY_RECORD_LEN];
+    storage::read(storage::KEY_SECTOR, &mut record).ok()?;
+    key_from_record(&record)
+}
+
+fn stored_bound(slot: usize) -> Option<u32> {
+    let mut record = [0u8; COUNTER_RECORD_LEN];
+    storage::read(storage::COUNTER_SECTORS[slot], &mut record).ok()?;
+    counter_from_record(&record)
+}
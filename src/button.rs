@@ -0,0 +1,34 @@
+//! User button on GPIO 15 (to GND, internal pull-up), reported as events.
+use embassy_rp::Peri;
+use embassy_rp::gpio::{AnyPin, Input, Pull};
+use embassy_time::Timer;
+use protocol::event::Event;
+
+use crate::events;
+
+/// Contacts settle within this long after an edge.
+const DEBOUNCE_MS: u64 = 20;
+
+pub struct Button {
+    input: Input<'static>,
+}
+
+pub fn init(pin: Peri<'static, AnyPin>) -> Button {
+    Button {
+        input: Input::new(pin, Pull::Up),
+    }
+}
+
+/// Publishes `Event::Button` (index 0) on each debounced press and release.
+#[embassy_executor::task]
+pub async fn button_task(mut button: Button) -> ! {
+    let mut pressed = button.input.is_low();
+    loop {
+        button.input.wait_for_any_edge().await;
+        Timer::after_millis(DEBOUNCE_MS).await;
+        if button.input.is_low() != pressed {
+            pressed = !pressed;
+            events::publish(Event::Button { index: 0, pressed });
+        }
+    }
+}
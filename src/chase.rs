@@ -1,24 +1,73 @@
 use embassy_rp::Peri;
 use embassy_rp::gpio::{AnyPin, Level, Output};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
 use embassy_time::Timer;
+use protocol::dispatch::{CommandHandler, CommandSpec, Data};
+use protocol::event::Event;
+use protocol::schema;
+use protocol::{Frame, Status};
+
+use crate::{events, registers};
+
+// Set by the CHASE command, consumed by `chase_task`
+static CHASE_REQUEST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
 pub struct Chase {
     pins: [Output<'static>; 5],
-    delay_ms: u64,
 }
 
-pub fn init(pins: [Peri<'static, AnyPin>; 5], delay_ms: u64) -> Chase {
+pub fn init(pins: [Peri<'static, AnyPin>; 5]) -> Chase {
     let pins = pins.map(|p| Output::new(p, Level::Low));
-    Chase { pins, delay_ms }
+    Chase { pins }
 }
 
 impl Chase {
+    /// One pass over the LEDs. The step delay is re-read from the register
+    /// map each step, so a Modbus write takes effect mid-run.
     pub async fn run(&mut self) {
-        for pin in self.pins.iter_mut() {
+        registers::set_chase_running(true);
+        for (i, pin) in self.pins.iter_mut().enumerate() {
             pin.set_high();
-            Timer::after_millis(self.delay_ms).await;
+            registers::set_led(i, true);
+            Timer::after_millis(registers::chase_delay_ms()).await;
             pin.set_low();
-            Timer::after_millis(self.delay_ms).await;
+            registers::set_led(i, false);
+            Timer::after_millis(registers::chase_delay_ms()).await;
         }
+        registers::set_chase_running(false);
+        events::publish(Event::ChaseFinished);
+    }
+}
+
+/// Start a chase run (from the CHASE command or the Modbus CHASE coil).
+pub fn request() {
+    CHASE_REQUEST.signal(());
+}
+
+/// Runs the chase pattern each time a CHASE command arrives, so the command
+/// loop can ACK immediately instead of waiting for the pattern to finish.
+#[embassy_executor::task]
+pub async fn chase_task(mut chase: Chase) -> ! {
+    loop {
+        CHASE_REQUEST.wait().await;
+        chase.run().await;
+    }
+}
+
+/// 0x02 CHASE (setter). Payload is ignored (the host tool sends 0 or 2 bytes).
+///
+/// The ACK means the run has been queued, not that it has finished: hosts
+/// that need to know subscribe to `CHASE_FINISHED`, published at the end of
+/// every run. A CHASE during a run queues one more run after it; further
+/// ones before that starts are folded into it, so they share one event.
+pub struct ChaseCommand;
+
+impl CommandHandler for ChaseCommand {
+    const SPEC: CommandSpec = schema::CHASE;
+
+    async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+        request();
+        Ok(())
     }
 }
@@ -0,0 +1,109 @@
+//! Command table: every CMD code the firmware answers.
+//!
+//! Subsystems implement `protocol::dispatch::CommandHandler` next to the code
+//! they control and are listed in `REGISTRY` below. Commands that answer for
+//! the link a request came in on are layered on top by `registry`; `server`
+//! only forwards decoded frames to `registry(link).dispatch`.
+use protocol::capabilities::{Capabilities, Features};
+use protocol::dispatch::{CommandHandler, CommandSpec, Data, Dispatch, Registry};
+use protocol::schema;
+use protocol::{Frame, Framing, Status};
+
+use crate::chase::ChaseCommand;
+use crate::events::{Subscribe, Unsubscribe};
+use crate::link::{ClearLinkStats, GetLinkStats, Link};
+use crate::node::{GetAddress, SetAddress};
+use crate::session::SessionOpen;
+
+protocol::command_registry! {
+    pub static REGISTRY = [
+        Ping,
+        ChaseCommand,
+        SetAddress,
+        SessionOpen,
+        GetDeviceId,
+        GetAddress,
+    ];
+}
+
+// Events are only written to a link the device may talk on unasked
+protocol::command_registry! {
+    static EVENT_COMMANDS = [Subscribe, Unsubscribe];
+}
+
+/// Every command a request on `link` can reach: `REGISTRY`, SUBSCRIBE and
+/// UNSUBSCRIBE where events can be sent, and the commands that report or
+/// reset that link. Elsewhere the event commands answer BAD_CMD and are not
+/// listed by GET_CAPABILITIES.
+pub fn registry(link: &'static Link) -> Registry<impl Dispatch> {
+    Registry::from_entries(&REGISTRY)
+        .merge(link.unsolicited().then_some(&EVENT_COMMANDS))
+        .register(ClearLinkStats(link))
+        .register(GetLinkStats(link))
+        .register(GetCapabilities(link))
+}
+
+/// 0x01 PING: health check, answered with ACK.
+pub struct Ping;
+
+impl CommandHandler for Ping {
+    const SPEC: CommandSpec = schema::PING;
+
+    async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+        Ok(())
+    }
+}
+
+/// 0x20 GET_DEVICE_ID (getter)
+pub struct GetDeviceId;
+
+impl CommandHandler for GetDeviceId {
+    const SPEC: CommandSpec = schema::GET_DEVICE_ID;
+
+    async fn handle(&self, _frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+        let id: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8]; // replace with real ID bytes
+        data.extend_from_slice(&id).map_err(|_| Status::Internal)
+    }
+}
+
+/// 0x22 GET_CAPABILITIES (getter): protocol version, framing, optional
+/// layers, buffer sizes and the command table, see `protocol::capabilities`.
+/// Pipelining and events are reported for the link the request came in on.
+pub struct GetCapabilities(pub &'static Link);
+
+impl CommandHandler for GetCapabilities {
+    const SPEC: CommandSpec = schema::GET_CAPABILITIES;
+
+    async fn handle(&self, _frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+        // COBS packets carry no SEQ byte, so ARQ and pipelining need STX framing.
+        // Pipelined responses only go to a link the device may talk on unasked.
+        let sequenced = match crate::link::FRAMING {
+            Framing::StxLen if self.0.unsolicited() => Features::ARQ | Features::PIPELINING,
+            Framing::StxLen => Features::ARQ,
+            Framing::Cobs => Features::NONE,
+        };
+        // So do events, and SUBSCRIBE is only listed there
+        let events = if self.0.unsolicited() {
+            Features::EVENTS
+        } else {
+            Features::NONE
+        };
+        // Only a device with a key asks for signed requests and opens sessions
+        let auth = if crate::auth::enabled() {
+            Features::AUTH | Features::SESSION
+        } else {
+            Features::NONE
+        };
+        // Longer messages would only reassemble into a BAD_LEN
+        let commands = registry(self.0);
+        let max_message = crate::FRAGMENT_BUF.min(commands.max_payload());
+        let caps = Capabilities::of(
+            &commands,
+            crate::link::FRAMING,
+            sequenced | auth | events | Features::FRAGMENTATION,
+            max_message as u32,
+        );
+        data.extend_from_slice(&caps.encode())
+            .map_err(|_| Status::Internal)
+    }
+}
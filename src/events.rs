@@ -0,0 +1,91 @@
+//! Unsolicited events to the USB host (`protocol::event`).
+//!
+//! Any task calls `publish`; `event_task` frames the events and writes them to
+//! the port between command responses. Classes the host has not subscribed to
+//! are dropped at `publish`, and so are events that find the queue full, so a
+//! publisher never waits on the host.
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use protocol::MAX_FRAME;
+use protocol::dispatch::{CommandHandler, CommandSpec, Data};
+use protocol::event::{Event, Subscriptions};
+use protocol::schema;
+use protocol::{Frame, Response, Status};
+
+use crate::serial_usb::UsbSerialPort;
+use crate::{link, node};
+
+/// Fault codes sent with `Event::Fault`.
+pub const FAULT_RX_OVERFLOW: u8 = 0x01;
+pub const FAULT_ADDRESS_STORE: u8 = 0x02;
+pub const FAULT_AUTH_STORE: u8 = 0x03;
+
+static SUBSCRIPTIONS: Mutex<CriticalSectionRawMutex, Cell<Subscriptions>> =
+    Mutex::new(Cell::new(Subscriptions::NONE));
+
+static EVENTS: Channel<CriticalSectionRawMutex, Event, 8> = Channel::new();
+
+/// Queue `event` for the host if it subscribed to its class.
+pub fn publish(event: Event) {
+    if SUBSCRIPTIONS
+        .lock(|subs| subs.get())
+        .contains(event.class())
+    {
+        let _ = EVENTS.try_send(event);
+    }
+}
+
+#[embassy_executor::task]
+pub async fn event_task(port: UsbSerialPort) -> ! {
+    let mut buf = [0u8; MAX_FRAME];
+    loop {
+        let event = EVENTS.receive().await;
+        let frame = Response::Event(event)
+            .write(&mut buf, None, node::filter().node(), 0)
+            .and_then(|frame| link::FRAMING.reframe(frame));
+        if let Ok(frame) = frame {
+            port.write(&frame).await;
+        }
+    }
+}
+
+/// Parse a class bitmask payload, rejecting classes this firmware never sends.
+fn classes(frame: &Frame<'_>) -> Result<Subscriptions, Status> {
+    let &[lo, hi] = frame.payload else {
+        return Err(Status::BadLen);
+    };
+    let classes = Subscriptions(u16::from_le_bytes([lo, hi]));
+    if classes.unsubscribe(Subscriptions::KNOWN) != Subscriptions::NONE {
+        return Err(Status::BadArg);
+    }
+    Ok(classes)
+}
+
+/// 0x05 SUBSCRIBE: payload `[CLASSES (u16 LE)]`, added to the subscription.
+pub struct Subscribe;
+
+impl CommandHandler for Subscribe {
+    const SPEC: CommandSpec = schema::SUBSCRIBE;
+
+    async fn handle(&self, frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+        let classes = classes(frame)?;
+        SUBSCRIPTIONS.lock(|subs| subs.set(subs.get().subscribe(classes)));
+        Ok(())
+    }
+}
+
+/// 0x06 UNSUBSCRIBE: payload `[CLASSES (u16 LE)]`, removed from the subscription.
+pub struct Unsubscribe;
+
+impl CommandHandler for Unsubscribe {
+    const SPEC: CommandSpec = schema::UNSUBSCRIBE;
+
+    async fn handle(&self, frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+        let classes = classes(frame)?;
+        SUBSCRIPTIONS.lock(|subs| subs.set(subs.get().unsubscribe(classes)));
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Link-quality counters and parse-error NAKs for the command links.
+//!
+//! Each command link has a `Link` of its own state: `USB` and `RS485`. SEQ
+//! numbers are per link, so two hosts never see each other's cached replies.
+//!
+//! The parser counts events itself; the command loop moves them into its
+//! link with `Link::record` whenever no frame is borrowed. The
+//! GET_LINK_STATS/CLEAR_LINK_STATS handlers hold the link the request came in
+//! on (see `commands::registry`), so each host reads its own counters.
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use heapless::Vec;
+use protocol::address::Delivery;
+use protocol::arq::DuplicateFilter;
+use protocol::dispatch::{CommandHandler, CommandSpec, Data};
+use protocol::event::Event;
+use protocol::schema;
+use protocol::{Frame, Framing, LinkStats, MAX_FRAME, ParseError, RxTiming, Status};
+
+/// Framing used on the USB command link. The host must be configured to match.
+pub const FRAMING: Framing = Framing::StxLen;
+
+/// A frame still incomplete after this much silence is dropped, so a host
+/// that disconnected mid-frame does not corrupt the first frame after it
+/// reconnects. The RS-485 link uses `RxTiming::rtu(baud)` instead.
+pub const RX_TIMING: RxTiming = RxTiming::Gap { gap_us: 100_000 };
+
+/// Reply to corrupted frames with a NAK when ADDR/CMD can be recovered,
+/// instead of letting the host time out.
+pub const NAK_PARSE_ERRORS: bool = true;
+
+/// Responses kept for retransmissions; covers every request in flight and then some.
+const REPLAY_DEPTH: usize = 2 * crate::pipeline::WORKERS;
+
+/// The USB command link, shared by its server and the pipeline workers.
+pub static USB: Link = Link::new(true);
+
+/// The RS-485 command link.
+pub static RS485: Link = Link::new(false);
+
+/// State one command link keeps apart from the others.
+pub struct Link {
+    unsolicited: bool,
+    stats: Mutex<CriticalSectionRawMutex, Cell<LinkStats>>,
+    replay: Mutex<CriticalSectionRawMutex, RefCell<DuplicateFilter<REPLAY_DEPTH>>>,
+}
+
+impl Link {
+    const fn new(unsolicited: bool) -> Self {
+        Self {
+            unsolicited,
+            stats: Mutex::new(Cell::new(LinkStats::new())),
+            replay: Mutex::new(RefCell::new(DuplicateFilter::new())),
+        }
+    }
+
+    /// The device may send on this link unasked, so sequenced requests run on
+    /// the pipeline workers and events are written to it. A half-duplex bus
+    /// only hears the device in answer to a request.
+    pub fn unsolicited(&self) -> bool {
+        self.unsolicited
+    }
+
+    /// Accumulate counters taken from the parser.
+    pub fn record(&self, delta: LinkStats) {
+        if delta.overflows > 0 {
+            crate::events::publish(Event::Fault {
+                code: crate::events::FAULT_RX_OVERFLOW,
+            });
+        }
+        self.stats.lock(|stats| {
+            let mut total = stats.get();
+            total.merge(&delta);
+            stats.set(total);
+        });
+    }
+
+    /// The response already sent for `frame`, if it retransmits a finished request.
+    pub fn replay(&self, frame: &Frame<'_>) -> Option<Vec<u8, MAX_FRAME>> {
+        self.replay
+            .lock(|replay| Vec::from_slice(replay.borrow().replay(frame)?).ok())
+    }
+
+    /// Remember `response` for retransmissions of `frame`.
+    pub fn store(&self, frame: &Frame<'_>, response: &[u8]) {
+        self.replay
+            .lock(|replay| replay.borrow_mut().store(frame, response));
+    }
+}
+
+/// NAK frame for `err`, if enabled and the header was recovered with this
+/// node's address (broadcast, group and other nodes' frames are never NAKed).
+pub fn nak(err: ParseError) -> Option<Vec<u8, MAX_FRAME>> {
+    if !NAK_PARSE_ERRORS {
+        return None;
+    }
+    let (addr, _) = err.header()?;
+    if crate::node::filter().delivery(addr) != Delivery::Reply {
+        return None;
+    }
+    let nak = protocol::build_nak::<MAX_FRAME>(err).ok().flatten()?;
+    FRAMING.reframe(&nak).ok()
+}
+
+/// 0x21 GET_LINK_STATS (getter): five u32 LE counters of the link the request
+/// came in on, see `LinkStats::to_le_bytes`.
+pub struct GetLinkStats(pub &'static Link);
+
+impl CommandHandler for GetLinkStats {
+    const SPEC: CommandSpec = schema::GET_LINK_STATS;
+
+    async fn handle(&self, _frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+        let stats = self.0.stats.lock(|stats| stats.get());
+        data.extend_from_slice(&stats.to_le_bytes())
+            .map_err(|_| Status::Internal)
+    }
+}
+
+/// 0x03 CLEAR_LINK_STATS (setter): resets the counters of the link the
+/// request came in on.
+pub struct ClearLinkStats(pub &'static Link);
+
+impl CommandHandler for ClearLinkStats {
+    const SPEC: CommandSpec = schema::CLEAR_LINK_STATS;
+
+    async fn handle(&self, _frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+        self.0.stats.lock(|stats| stats.set(LinkStats::new()));
+        Ok(())
+    }
+}
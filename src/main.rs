@@ -6,11 +6,32 @@ use embassy_executor::Spawner;
 use embassy_rp as hal;
 use embassy_rp::Peri;
 use embassy_rp::gpio::AnyPin;
+mod adc;
+mod auth;
+mod button;
 mod chase;
-mod protocol;
+mod commands;
+mod events;
+mod link;
+mod modbus;
+mod node;
+mod pipeline;
+mod registers;
+mod rs485;
 mod serial_usb;
+mod server;
+mod session;
+mod storage;
 mod sys;
 
+/// Largest fragmented request the device will reassemble, in bytes. No
+/// command takes more than one frame's payload, so neither does this; raise it
+/// along with a command that accepts larger messages.
+const FRAGMENT_BUF: usize = protocol::MAX_PAYLOAD;
+
+/// Drop a fragmented request whose next fragment is this late.
+const FRAGMENT_TIMEOUT_US: u64 = 1_000_000;
+
 /// Entry point.
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
@@ -20,11 +41,20 @@ async fn main(spawner: Spawner) {
     // Get peripherals
     let peripherals: embassy_rp::Peripherals = hal::init(Default::default());
 
-    // Start USB communication
-    let port: serial_usb::UsbSerialPort = serial_usb::init(&spawner, peripherals.USB);
+    // Load this node's address and key before any frame can arrive
+    storage::init(peripherals.FLASH);
+    node::init();
+    auth::init();
+    session::init(peripherals.TRNG);
 
-    // Create parser to read comands
-    let mut parser = protocol::Parser::new();
+    // Start USB communication
+    let (port, log) = serial_usb::init(
+        &spawner,
+        peripherals.USB,
+        serial_usb::UsbIdentity::default(),
+    );
+    // Runs when a 1200-baud touch reboots the chip into BOOTSEL
+    serial_usb::on_shutdown(|| defmt::info!("1200-baud touch: rebooting to BOOTSEL"));
 
     // Prepare chase pins
     let pins: [Peri<'static, AnyPin>; 5] = [
@@ -34,45 +64,32 @@ async fn main(spawner: Spawner) {
         peripherals.PIN_3.into(),
         peripherals.PIN_4.into(),
     ];
-    let mut chase: chase::Chase = chase::init(pins, 100);
+    let chase: chase::Chase = chase::init(pins);
+    spawner.must_spawn(chase::chase_task(chase));
+
+    // Modbus RTU slave serving the shared register map
+    let uart = modbus::init(peripherals.UART1, peripherals.PIN_8, peripherals.PIN_9);
+    spawner.must_spawn(modbus::modbus_task(uart));
+    // The command link for field units, alongside USB
+    let bus = rs485::init(
+        peripherals.UART0,
+        peripherals.PIN_12,
+        peripherals.PIN_13,
+        peripherals.PIN_14,
+    );
+    spawner.must_spawn(rs485::rs485_task(bus));
+    let sensors: adc::Sensors = adc::init(peripherals.ADC, peripherals.ADC_TEMP_SENSOR);
+    spawner.must_spawn(adc::adc_task(sensors, log));
+
+    // Unsolicited events to the host, written between command responses
+    let button: button::Button = button::init(peripherals.PIN_15.into());
+    spawner.must_spawn(button::button_task(button));
+    spawner.must_spawn(events::event_task(port));
 
-    // Action
-    loop {
-        let data: heapless::Vec<u8, 64> = port.read().await;
-        parser.push_bytes(&data);
+    // Workers for pipelined (sequenced) requests
+    pipeline::spawn(&spawner, port);
 
-        match parser.next_frame() {
-            Ok(Some(frame)) => {
-                match frame.cmd {
-                    0x01 => {
-                        // PING
-                        let resp = protocol::build_ack::<64>(frame.addr, frame.cmd).unwrap();
-                        port.write(&resp).await;
-                    }
-                    0x02 => {
-                        // CHASE (setter)
-                        let resp = protocol::build_ack::<64>(frame.addr, frame.cmd).unwrap();
-                        port.write(&resp).await;
-                        chase.run().await;
-                    }
-                    0x20 => {
-                        // GET_DEVICE_ID (getter)
-                        let id: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8]; // replace with real ID bytes
-                        let resp = protocol::build_data::<64>(frame.addr, frame.cmd, &id).unwrap();
-                        port.write(&resp).await;
-                    }
-                    _ => {
-                        let resp = protocol::build_err::<64>(
-                            frame.addr, frame.cmd, 0x02, /* BAD_CMD */
-                        )
-                        .unwrap();
-                        port.write(&resp).await;
-                    }
-                }
-            }
-            Ok(None) => break,  // need more bytes
-            Err(_) => continue, // resync + keep scanning
-        }
-    }
+    // Serve the command link
+    server::run(port, link::RX_TIMING, &link::USB).await
 }
 // End of file
@@ -0,0 +1,87 @@
+//! Modbus RTU slave on UART1 (GPIO 8 = TX, GPIO 9 = RX).
+//!
+//! Frames are split by the 3.5-character silence rule: the task waits for
+//! bytes, and once a frame has started it treats a t3.5 read timeout as the
+//! end of the frame. The UART hands bytes over in FIFO-sized batches, so the
+//! t1.5 inter-character check is left off (batch timestamps would trip it).
+use embassy_rp::Peri;
+use embassy_rp::bind_interrupts;
+use embassy_rp::peripherals::{PIN_8, PIN_9, UART1};
+use embassy_rp::uart::{BufferedInterruptHandler, BufferedUart, Config};
+use embassy_time::{Duration, Instant, with_timeout};
+use embedded_io_async::{Read, Write};
+use protocol::modbus::{RtuFramer, RtuTiming};
+use static_cell::StaticCell;
+
+use crate::node;
+use crate::registers::{self, DeviceRegisters};
+
+bind_interrupts!(struct Irqs {
+    UART1_IRQ => BufferedInterruptHandler<UART1>;
+});
+
+pub const BAUD: u32 = 19_200;
+
+const TIMING: RtuTiming = RtuTiming {
+    t1_5_us: None,
+    ..RtuTiming::for_baud(BAUD)
+};
+
+pub fn init(
+    uart: Peri<'static, UART1>,
+    tx: Peri<'static, PIN_8>,
+    rx: Peri<'static, PIN_9>,
+) -> BufferedUart {
+    static TX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    static RX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+
+    // 8E1 is the Modbus RTU default framing.
+    let mut config = Config::default();
+    config.baudrate = BAUD;
+    config.parity = embassy_rp::uart::Parity::ParityEven;
+
+    BufferedUart::new(
+        uart,
+        tx,
+        rx,
+        Irqs,
+        TX_BUF.init([0; 256]),
+        RX_BUF.init([0; 256]),
+        config,
+    )
+}
+
+#[embassy_executor::task]
+pub async fn modbus_task(uart: BufferedUart) -> ! {
+    let (mut tx, mut rx) = uart.split();
+    let mut framer = RtuFramer::new(TIMING);
+    let mut buf = [0u8; 32];
+
+    loop {
+        let read = if framer.is_idle() {
+            Ok(rx.read(&mut buf).await)
+        } else {
+            with_timeout(Duration::from_micros(TIMING.t3_5_us), rx.read(&mut buf)).await
+        };
+        let now = Instant::now().as_micros();
+
+        // Either the timeout or the first byte after a long gap can end a frame
+        if let Some(adu) = framer.poll(now) {
+            // Same address as the framed protocol; 1..=239 are valid unit IDs
+            let unit = node::filter().node();
+            let resp = registers::with(|map| {
+                protocol::modbus::handle_adu(&mut DeviceRegisters(map), unit, &adu)
+            });
+            if let Some(resp) = resp {
+                let _ = tx.write_all(&resp).await;
+            }
+        }
+
+        match read {
+            Ok(Ok(n)) => buf[..n].iter().for_each(|&b| framer.push(b, now)),
+            // Parity, framing, overrun or break: the frame is unusable
+            Ok(Err(_)) => framer.discard(),
+            Err(_) => {} // t3.5 silence, handled by poll above
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! This device's node address and group membership.
+//!
+//! Kept as a `protocol::address` record in `storage::NODE_SECTOR`. Erased or
+//! corrupt storage falls back to `AddressFilter::DEFAULT` (node 0x01, no
+//! groups). `server` checks every frame's ADDR against `filter()`; SET_ADDRESS
+//! stores a new one, effective from the next frame.
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use protocol::address::{AddressFilter, NodeAddress, RECORD_LEN, SetAddress as Request};
+use protocol::dispatch::{CommandHandler, CommandSpec, Data};
+use protocol::event::Event;
+use protocol::payload::{Command, Payload};
+use protocol::schema;
+use protocol::{Frame, Status};
+
+use crate::storage;
+
+static FILTER: Mutex<CriticalSectionRawMutex, Cell<AddressFilter>> =
+    Mutex::new(Cell::new(AddressFilter::DEFAULT));
+
+/// Load the stored address. Call after `storage::init`, before the command loop starts.
+pub fn init() {
+    let mut record = [0u8; RECORD_LEN];
+    if storage::read(storage::NODE_SECTOR, &mut record).is_ok()
+        && let Some(stored) = AddressFilter::from_record(&record)
+    {
+        FILTER.lock(|filter| filter.set(stored));
+    }
+}
+
+/// Current address and groups.
+pub fn filter() -> AddressFilter {
+    FILTER.lock(|filter| filter.get())
+}
+
+/// Persist `new` and start using it. Blocks for the sector erase (tens of ms).
+fn store(new: AddressFilter) -> Result<(), Status> {
+    storage::replace(storage::NODE_SECTOR, &new.to_record()).map_err(|_| {
+        crate::events::publish(Event::Fault {
+            code: crate::events::FAULT_ADDRESS_STORE,
+        });
+        Status::Internal
+    })?;
+    FILTER.lock(|filter| filter.set(new));
+    Ok(())
+}
+
+/// 0x04 SET_ADDRESS: payload `[NODE]` or `[NODE, GROUPS (u16 LE)]`
+/// (`protocol::address::SetAddress`). Without GROUPS the membership is kept.
+pub struct SetAddress;
+
+impl CommandHandler for SetAddress {
+    const SPEC: CommandSpec = schema::SET_ADDRESS;
+
+    async fn handle(&self, frame: &Frame<'_>, _data: &mut Data) -> Result<(), Status> {
+        let request = Request::from_frame(frame)?;
+        let groups = request.groups.unwrap_or_else(|| filter().groups());
+        store(AddressFilter::new(request.node, groups).ok_or(Status::BadArg)?)
+    }
+}
+
+/// 0x23 GET_ADDRESS (getter): `[NODE, GROUPS (u16 LE)]` (`NodeAddress`).
+pub struct GetAddress;
+
+impl CommandHandler for GetAddress {
+    const SPEC: CommandSpec = schema::GET_ADDRESS;
+
+    async fn handle(&self, _frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+        let filter = filter();
+        let address = NodeAddress {
+            node: filter.node(),
+            groups: filter.groups(),
+        };
+        address.encode(data).map_err(|_| Status::Internal)
+    }
+}
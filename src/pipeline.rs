@@ -0,0 +1,98 @@
+//! Runs sequenced requests on worker tasks, so a slow handler does not hold
+//! up the requests queued behind it (see `protocol::pipeline`).
+//!
+//! `server` hands every sequenced request to `submit`; each worker dispatches
+//! one request at a time and writes its response itself, so responses leave
+//! in completion order, matched by SEQ on the host. Plain frames still run
+//! inline in `server`, in order. Workers only serve USB, so their responses
+//! go to the `link::USB` replay cache.
+use core::cell::RefCell;
+
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use heapless::Vec;
+use protocol::auth::unauthorized;
+use protocol::pipeline::{Admission, InFlight, OwnedFrame, busy};
+use protocol::session::CMD_SECURE;
+use protocol::{Frame, MAX_FRAME, Response, Status, build_response};
+
+use crate::serial_usb::UsbSerialPort;
+use crate::{auth, commands, link, session};
+
+/// Worker tasks, i.e. requests that can run at once.
+pub const WORKERS: usize = 4;
+
+/// Requests allowed in flight before new ones are answered BUSY (at most `WORKERS`).
+pub const MAX_IN_FLIGHT: usize = WORKERS;
+
+// Capacity WORKERS >= MAX_IN_FLIGHT, so `submit` never waits for room
+static JOBS: Channel<CriticalSectionRawMutex, OwnedFrame, WORKERS> = Channel::new();
+
+static IN_FLIGHT: Mutex<CriticalSectionRawMutex, RefCell<InFlight<WORKERS>>> =
+    Mutex::new(RefCell::new(InFlight::new(MAX_IN_FLIGHT)));
+
+pub fn spawn(spawner: &Spawner, port: UsbSerialPort) {
+    for _ in 0..WORKERS {
+        spawner.must_spawn(worker_task(port));
+    }
+}
+
+/// Queue a sequenced request for a worker. Returns a response to send now
+/// when it will not run: BUSY at the in-flight limit, BAD_LEN for a payload
+/// too large to queue.
+pub async fn submit(frame: &Frame<'_>) -> Option<Vec<u8, MAX_FRAME>> {
+    match IN_FLIGHT.lock(|in_flight| in_flight.borrow_mut().admit(frame)) {
+        Admission::Run => match OwnedFrame::from_frame(frame) {
+            Some(job) => {
+                JOBS.send(job).await;
+                None
+            }
+            None => {
+                IN_FLIGHT.lock(|in_flight| in_flight.borrow_mut().finish(frame));
+                // Answered, or the host would retransmit it until it timed out
+                build_response::<MAX_FRAME>(
+                    frame.seq,
+                    frame.addr,
+                    frame.cmd,
+                    &Response::Err(Status::BadLen),
+                )
+                .and_then(|resp| link::FRAMING.reframe(&resp))
+                .ok()
+            }
+        },
+        // Still running: its response is on the way
+        Admission::InProgress => None,
+        Admission::Busy => busy::<MAX_FRAME>(frame)
+            .and_then(|resp| link::FRAMING.reframe(&resp))
+            .ok(),
+    }
+}
+
+#[embassy_executor::task(pool_size = WORKERS)]
+async fn worker_task(port: UsbSerialPort) -> ! {
+    loop {
+        let job = JOBS.receive().await;
+        let frame = job.as_frame();
+        // The server only checked the tag: the counter is taken here, so the
+        // retransmission check in `submit` sees the request as sent
+        let resp = match auth::verify(&frame).await {
+            Ok(request) if request.cmd == CMD_SECURE => {
+                session::dispatch(&link::USB, &request).await
+            }
+            Ok(request) => commands::registry(&link::USB).dispatch(&request).await,
+            Err(last) => unauthorized(&frame, last),
+        }
+        .and_then(|resp| link::FRAMING.reframe(&resp));
+
+        // Cache before leaving the in-flight table, so a retransmission always finds one
+        if let Ok(resp) = &resp {
+            link::USB.store(&frame, resp);
+        }
+        IN_FLIGHT.lock(|in_flight| in_flight.borrow_mut().finish(&frame));
+        if let Ok(resp) = resp {
+            port.write(&resp).await;
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Shared Modbus register map.
+//!
+//! Subsystems publish their state into `REGISTERS` and read their settings
+//! from it; the Modbus RTU slave (`modbus.rs`) serves it to the bus.
+//!
+//! | Table            | Addr | Meaning                                              |
+//! |------------------|------|------------------------------------------------------|
+//! | Coil             | 0    | CHASE: write 1 to start a run, reads 1 while running |
+//! | Discrete input   | 0-4  | Chase LED outputs (GPIO 0-4)                         |
+//! | Input register   | 0    | Temperature sensor, raw 12-bit ADC                   |
+//! | Input register   | 1    | Die temperature, 0.1 °C (signed)                     |
+//! | Holding register | 0    | Chase step delay, ms (1-10000)                       |
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use protocol::modbus::{Exception, RegisterMap, Registers};
+
+pub const COIL_CHASE: u16 = 0;
+pub const INPUT_TEMP_RAW: u16 = 0;
+pub const INPUT_TEMP_DECI_C: u16 = 1;
+pub const HOLDING_CHASE_DELAY_MS: u16 = 0;
+
+const CHASE_DELAY_DEFAULT_MS: u16 = 100;
+const CHASE_DELAY_RANGE_MS: core::ops::RangeInclusive<u16> = 1..=10_000;
+
+/// Coils, discrete inputs, input registers, holding registers.
+pub type Map = Registers<1, 5, 2, 1>;
+
+static REGISTERS: Mutex<CriticalSectionRawMutex, RefCell<Map>> = Mutex::new(RefCell::new(Map {
+    coils: [false],
+    discrete_inputs: [false; 5],
+    input_registers: [0; 2],
+    holding_registers: [CHASE_DELAY_DEFAULT_MS],
+}));
+
+/// Run `f` with the register map locked.
+pub fn with<R>(f: impl FnOnce(&mut Map) -> R) -> R {
+    REGISTERS.lock(|map| f(&mut map.borrow_mut()))
+}
+
+pub fn chase_delay_ms() -> u64 {
+    with(|map| map.holding_registers[HOLDING_CHASE_DELAY_MS as usize] as u64)
+}
+
+pub fn set_chase_running(running: bool) {
+    with(|map| map.coils[COIL_CHASE as usize] = running);
+}
+
+pub fn set_led(index: usize, on: bool) {
+    with(|map| {
+        if let Some(led) = map.discrete_inputs.get_mut(index) {
+            *led = on;
+        }
+    });
+}
+
+pub fn set_input(addr: u16, value: u16) {
+    with(|map| map.input_registers[addr as usize] = value);
+}
+
+/// The register map as the Modbus master sees it: writes are range-checked
+/// and have side effects on the subsystems that own them.
+pub struct DeviceRegisters<'a>(pub &'a mut Map);
+
+impl RegisterMap for DeviceRegisters<'_> {
+    fn coil(&self, addr: u16) -> Option<bool> {
+        self.0.coil(addr)
+    }
+
+    fn discrete_input(&self, addr: u16) -> Option<bool> {
+        self.0.discrete_input(addr)
+    }
+
+    fn input_register(&self, addr: u16) -> Option<u16> {
+        self.0.input_register(addr)
+    }
+
+    fn holding_register(&self, addr: u16) -> Option<u16> {
+        self.0.holding_register(addr)
+    }
+
+    fn write_coil(&mut self, addr: u16, value: bool) -> Result<(), Exception> {
+        match addr {
+            // Writing 0 does not stop a run; the coil clears itself when it ends.
+            COIL_CHASE if value => {
+                crate::chase::request();
+                self.0.write_coil(addr, true)
+            }
+            COIL_CHASE => Ok(()),
+            _ => Err(Exception::IllegalDataAddress),
+        }
+    }
+
+    fn write_holding_register(&mut self, addr: u16, value: u16) -> Result<(), Exception> {
+        match addr {
+            HOLDING_CHASE_DELAY_MS if !CHASE_DELAY_RANGE_MS.contains(&value) => {
+                Err(Exception::IllegalDataValue)
+            }
+            _ => self.0.write_holding_register(addr, value),
+        }
+    }
+}
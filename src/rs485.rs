@@ -0,0 +1,143 @@
+//! Command link over RS-485 on UART0 (GPIO 12 = TX, GPIO 13 = RX,
+//! GPIO 14 = DE/RE).
+//!
+//! The UART is interrupt-driven with ring buffers on both sides, and
+//! `protocol::rs485::Rs485` raises DE around each reply. The bus is half
+//! duplex with the host as master, so the device only talks when asked:
+//! events and pipelined responses stay on USB.
+use embassy_rp::Peri;
+use embassy_rp::bind_interrupts;
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::peripherals::{PIN_12, PIN_13, PIN_14, UART0};
+use embassy_rp::uart::{
+    self, BufferedInterruptHandler, BufferedUart, BufferedUartRx, BufferedUartTx, Config,
+};
+use embassy_time::{Instant, Timer};
+use embedded_io_async::{Read, Write};
+use protocol::RxTiming;
+use protocol::rs485::{Clock, DriverEnable, Parity, Rs485, StopBits, UartFormat, UartTx};
+use protocol::transport::FrameTransport;
+use static_cell::StaticCell;
+
+use crate::{link, server};
+
+bind_interrupts!(struct Irqs {
+    UART0_IRQ => BufferedInterruptHandler<UART0>;
+});
+
+/// Line settings; every node on the bus must use the same.
+pub const FORMAT: UartFormat = UartFormat {
+    baud: 115_200,
+    parity: Parity::None,
+    stop_bits: StopBits::One,
+};
+
+/// Frames end at a t3.5 silence, so garbage on a shared bus resyncs cleanly.
+const RX_TIMING: RxTiming = RxTiming::rtu(FORMAT.baud);
+
+pub struct Rs485Port {
+    rx: BufferedUartRx,
+    tx: Tx,
+    driver: Rs485<DePin, EmbassyClock>,
+}
+
+pub fn init(
+    uart: Peri<'static, UART0>,
+    tx: Peri<'static, PIN_12>,
+    rx: Peri<'static, PIN_13>,
+    de: Peri<'static, PIN_14>,
+) -> Rs485Port {
+    static TX_BUF: StaticCell<[u8; 512]> = StaticCell::new();
+    static RX_BUF: StaticCell<[u8; 512]> = StaticCell::new();
+
+    let mut config = Config::default();
+    config.baudrate = FORMAT.baud;
+    config.parity = match FORMAT.parity {
+        Parity::None => uart::Parity::ParityNone,
+        Parity::Even => uart::Parity::ParityEven,
+        Parity::Odd => uart::Parity::ParityOdd,
+    };
+    config.stop_bits = match FORMAT.stop_bits {
+        StopBits::One => uart::StopBits::STOP1,
+        StopBits::Two => uart::StopBits::STOP2,
+    };
+
+    let (tx, rx) = BufferedUart::new(
+        uart,
+        tx,
+        rx,
+        Irqs,
+        TX_BUF.init([0; 512]),
+        RX_BUF.init([0; 512]),
+        config,
+    )
+    .split();
+    // Listen until the first reply
+    let de = DePin(Output::new(de, Level::Low));
+    Rs485Port {
+        rx,
+        tx: Tx(tx),
+        driver: Rs485::new(de, EmbassyClock, FORMAT),
+    }
+}
+
+#[embassy_executor::task]
+pub async fn rs485_task(port: Rs485Port) -> ! {
+    server::run(port, RX_TIMING, &link::RS485).await
+}
+
+impl FrameTransport for Rs485Port {
+    type Error = uart::Error;
+
+    /// Bytes received so far. Parity, framing, overrun and break errors are
+    /// returned, so the server drops the frame they hit.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, uart::Error> {
+        self.rx.read(buf).await
+    }
+
+    async fn write_all(&mut self, bytes: &[u8]) -> Result<(), uart::Error> {
+        self.driver.send(&mut self.tx, bytes).await
+    }
+
+    /// The bus is always there.
+    async fn wait_connected(&mut self) {}
+}
+
+struct Tx(BufferedUartTx);
+
+impl UartTx for Tx {
+    type Error = uart::Error;
+
+    async fn write_all(&mut self, bytes: &[u8]) -> Result<(), uart::Error> {
+        self.0.write_all(bytes).await
+    }
+
+    /// Returns once the ring buffer is empty; the FIFO may still be sending.
+    async fn flush(&mut self) -> Result<(), uart::Error> {
+        self.0.flush().await
+    }
+
+    fn busy(&self) -> bool {
+        self.0.busy()
+    }
+}
+
+struct DePin(Output<'static>);
+
+impl DriverEnable for DePin {
+    fn set_driving(&mut self, driving: bool) {
+        self.0.set_level(Level::from(driving));
+    }
+}
+
+struct EmbassyClock;
+
+impl Clock for EmbassyClock {
+    fn now_us(&self) -> u64 {
+        Instant::now().as_micros()
+    }
+
+    async fn wait_until(&mut self, deadline_us: u64) {
+        Timer::at(Instant::from_micros(deadline_us)).await;
+    }
+}
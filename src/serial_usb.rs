@@ -1,16 +1,43 @@
 //! Transport Layer via USB Serial
 //!
+//! A composite device with two CDC ACM ports, each with its own channels and
+//! task, so a burst on one never holds up the other:
+//! - port 0 (USB interfaces 0-1), `UsbSerialPort`: the command link's
+//!   `FrameTransport`. Events and pipeline workers share it through `write`.
+//! - port 1 (USB interfaces 2-3), `UsbLogPort`: a text stream of log lines
+//!   and telemetry samples for a terminal, readable in the field without a
+//!   debug probe.
+//!
+//! The device enumerates with a `UsbIdentity`: VID, PID and strings default to
+//! the `USB_*` build variables in `.cargo/config.toml`, and the serial number
+//! is the chip ID, so boards on one hub can be told apart.
+//!
+//! 1200-baud touch: when the host sets 1200 baud and drops DTR (opens the port
+//! at 1200 baud and closes it), the device flushes what is queued for the
+//! host, runs the shutdown hook and restarts in BOOTSEL mode, ready for
+//! `picotool` to flash it.
+use core::fmt::{self, Write as _};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
 use embassy_executor::Spawner;
-use embassy_futures::select::{Either, select};
+use embassy_futures::select::{Either, Either3, select, select3};
 use embassy_rp::Peri;
 use embassy_rp::bind_interrupts;
+use embassy_rp::otp;
 use embassy_rp::peripherals::USB;
+use embassy_rp::rom_data;
 use embassy_rp::usb::{Driver, InterruptHandler};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::once_lock::OnceLock;
+use embassy_sync::pipe::Pipe;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
 use embassy_usb::UsbDevice;
-use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
-use heapless::Vec;
+use embassy_usb::class::cdc_acm::{CdcAcmClass, Receiver, Sender, State};
+use heapless::{String, Vec};
+use protocol::transport::FrameTransport;
 use static_cell::StaticCell;
 
 // Interrupt handler
@@ -22,40 +49,195 @@ bind_interrupts!(struct Irqs {
 type MyUsbDriver = Driver<'static, USB>;
 type MyUsbDevice = UsbDevice<'static, MyUsbDriver>;
 
-// Channels
-static TX_TO_USB: Channel<CriticalSectionRawMutex, Vec<u8, 64>, 8> = Channel::new();
+// Channels; packets to the host carry the `OPENED` count their write began under
+static TX_TO_USB: Channel<CriticalSectionRawMutex, (u32, Vec<u8, 64>), 8> = Channel::new();
 static RX_FROM_USB: Channel<CriticalSectionRawMutex, Vec<u8, 64>, 8> = Channel::new();
 
+// Log port output, queued a whole line at a time
+static LOG_TO_USB: Pipe<CriticalSectionRawMutex, LOG_BUF> = Pipe::new();
+
+/// Bytes of log output held for a host that is slow to read.
+const LOG_BUF: usize = 1024;
+
+/// Longest log or telemetry line; longer ones are cut short.
+const LOG_LINE: usize = 128;
+
+// Held while one write queues its packets, so frames from different tasks never interleave
+static WRITE_LOCK: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+// Follows DTR, which a terminal raises when it opens the port and drops when it
+// closes it; signalled on each rising edge
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+static CONNECTION: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Times the port has opened. Packets from an earlier opening were meant for the
+// previous host and are dropped whole, even if a write was still queueing them.
+static OPENED: AtomicU32 = AtomicU32::new(0);
+
+// Run just before a 1200-baud touch restarts the chip
+static SHUTDOWN_HOOK: OnceLock<fn()> = OnceLock::new();
+
+/// Line rate that, with DTR dropped, asks for BOOTSEL.
+const TOUCH_BAUD: u32 = 1200;
+
+/// How long each queued packet may take to leave before a reboot gives up on it.
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// What the device reports when it enumerates.
+#[derive(Copy, Clone)]
+pub struct UsbIdentity {
+    pub vid: u16,
+    pub pid: u16,
+    pub manufacturer: &'static str,
+    pub product: &'static str,
+    /// `None` uses the chip ID (see `chip_serial`).
+    pub serial_number: Option<&'static str>,
+}
+
+impl UsbIdentity {
+    /// From the `USB_VID`, `USB_PID`, `USB_MANUFACTURER` and `USB_PRODUCT`
+    /// build variables (VID and PID in hex), with the chip ID as serial number.
+    pub const fn from_build() -> Self {
+        Self {
+            vid: BUILD_VID,
+            pid: BUILD_PID,
+            manufacturer: env!("USB_MANUFACTURER"),
+            product: env!("USB_PRODUCT"),
+            serial_number: None,
+        }
+    }
+}
+
+impl Default for UsbIdentity {
+    fn default() -> Self {
+        Self::from_build()
+    }
+}
+
+// Evaluated at compile time, so a bad value fails the build
+const BUILD_VID: u16 = hex_u16(env!("USB_VID"));
+const BUILD_PID: u16 = hex_u16(env!("USB_PID"));
+
+const fn hex_u16(hex: &str) -> u16 {
+    match u16::from_str_radix(hex, 16) {
+        Ok(value) => value,
+        Err(_) => panic!("USB_VID and USB_PID must be 4 hex digits"),
+    }
+}
+
+/// The host closed the port.
+#[derive(Copy, Clone, Debug)]
+pub struct Disconnected;
+
 // API Struct
+#[derive(Copy, Clone)]
 pub struct UsbSerialPort;
 
 impl UsbSerialPort {
     /// Queue bytes to send to the host. Data is chunked into max 64-byte packets.
+    /// Safe to call from several tasks: each call's bytes stay contiguous.
     pub async fn write(&self, data: &[u8]) {
+        let _guard = WRITE_LOCK.lock().await;
+        let opened = OPENED.load(Ordering::Acquire);
         for chunk in data.chunks(64) {
             let mut v = Vec::<u8, 64>::new();
             let _ = v.extend_from_slice(chunk);
-            TX_TO_USB.send(v).await;
+            TX_TO_USB.send((opened, v)).await;
+        }
+    }
+}
+
+impl FrameTransport for UsbSerialPort {
+    type Error = Disconnected;
+
+    /// Next packet from the host (up to 64 bytes; `buf` should hold one).
+    /// The empty packet `cdc_task` queues on a disconnect is the error.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Disconnected> {
+        let packet = RX_FROM_USB.receive().await;
+        if packet.is_empty() {
+            return Err(Disconnected);
+        }
+        let n = packet.len().min(buf.len());
+        buf[..n].copy_from_slice(&packet[..n]);
+        Ok(n)
+    }
+
+    async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Disconnected> {
+        self.write(bytes).await;
+        Ok(())
+    }
+
+    async fn wait_connected(&mut self) {
+        while !CONNECTED.load(Ordering::Acquire) {
+            CONNECTION.wait().await;
+        }
+    }
+}
+
+/// The log and telemetry port (the second CDC port).
+///
+/// Lines never wait: when the host is not reading and the buffer is full they
+/// are dropped, so logging cannot slow down the code doing it.
+#[derive(Copy, Clone)]
+pub struct UsbLogPort;
+
+impl UsbLogPort {
+    /// Queue one line of text, e.g. `log.line(format_args!("node {addr}"))`.
+    pub fn line(&self, args: fmt::Arguments<'_>) {
+        let mut line = String::<LOG_LINE>::new();
+        let _ = line.write_fmt(args); // a full line is cut short
+        push_line(line);
+    }
+
+    /// Queue a telemetry sample as `@name v1 v2 ...`; the `@` lets a script
+    /// pick samples out of the log lines.
+    pub fn telemetry(&self, name: &str, values: &[i32]) {
+        let mut line = String::<LOG_LINE>::new();
+        let _ = write!(line, "@{name}");
+        for value in values {
+            let _ = write!(line, " {value}");
         }
+        push_line(line);
     }
+}
 
-    /// Receive next packet of bytes from the host (up to 64 bytes).
-    pub async fn read(&self) -> Vec<u8, 64> {
-        RX_FROM_USB.receive().await
+/// Queue `line` and a CRLF if both fit, so the host never sees half a line.
+fn push_line(line: String<LOG_LINE>) {
+    if LOG_TO_USB.free_capacity() < line.len() + 2 {
+        return; // dropped
+    }
+    for mut bytes in [line.as_bytes(), b"\r\n"] {
+        // At the end of the ring a write only takes the part before it wraps
+        while let Ok(n) = LOG_TO_USB.try_write(bytes)
+            && n < bytes.len()
+        {
+            bytes = &bytes[n..];
+        }
     }
 }
 
+/// Call `hook` right before a 1200-baud touch reboots into BOOTSEL, e.g. to
+/// put outputs in a safe state. It runs on the USB task, so keep it short.
+/// Only the first hook registered is kept.
+pub fn on_shutdown(hook: fn()) {
+    let _ = SHUTDOWN_HOOK.init(hook);
+}
+
 // USB Device Initialisation
-pub fn init(spawner: &Spawner, usb_peripheral: Peri<'static, USB>) -> UsbSerialPort {
+pub fn init(
+    spawner: &Spawner,
+    usb_peripheral: Peri<'static, USB>,
+    identity: UsbIdentity,
+) -> (UsbSerialPort, UsbLogPort) {
     // Create the driver, from the HAL.
     let driver = Driver::new(usb_peripheral, Irqs);
 
     // Create embassy-usb Config
     let config = {
-        let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
-        config.manufacturer = Some("Embassy");
-        config.product = Some("USB-serial example");
-        config.serial_number = Some("12345678");
+        let mut config = embassy_usb::Config::new(identity.vid, identity.pid);
+        config.manufacturer = Some(identity.manufacturer);
+        config.product = Some(identity.product);
+        config.serial_number = Some(identity.serial_number.unwrap_or_else(chip_serial));
         config.max_power = 100;
         config.max_packet_size_0 = 64;
         config
@@ -76,20 +258,38 @@ pub fn init(spawner: &Spawner, usb_peripheral: Peri<'static, USB>) -> UsbSerialP
         CONTROL_BUF.init([0; 64]),
     );
 
-    // CDC class storage
+    // CDC class storage, in interface order: command link, then logs
     static STATE: StaticCell<State> = StaticCell::new();
     let state = STATE.init(State::new());
     let class = CdcAcmClass::new(&mut builder, state, 64);
+    static LOG_STATE: StaticCell<State> = StaticCell::new();
+    let log_state = LOG_STATE.init(State::new());
+    let log_class = CdcAcmClass::new(&mut builder, log_state, 64);
 
     // Build the builder.
     let usb = builder.build();
 
-    // Spawn tasks: USB runner + CDC handler
+    // Spawn tasks: USB runner + one handler per CDC port
     spawner.must_spawn(usb_task(usb));
     spawner.must_spawn(cdc_task(class));
+    spawner.must_spawn(log_task(log_class));
 
     // Return API to user
-    UsbSerialPort
+    (UsbSerialPort, UsbLogPort)
+}
+
+/// The 64-bit chip ID from OTP as 16 upper-case hex digits.
+fn chip_serial() -> &'static str {
+    static SERIAL: StaticCell<[u8; 16]> = StaticCell::new();
+    // Reading OTP only fails on an ECC error; all zeros still enumerates
+    let id = otp::get_chipid().unwrap_or(0);
+    let digits = SERIAL.init([0; 16]);
+    for (i, digit) in digits.iter_mut().enumerate() {
+        let nibble = (id >> (60 - 4 * i)) & 0xF;
+        *digit = b"0123456789ABCDEF"[nibble as usize];
+    }
+    let digits: &'static [u8] = digits;
+    core::str::from_utf8(digits).unwrap_or("0000000000000000")
 }
 
 #[embassy_executor::task]
@@ -98,17 +298,26 @@ async fn usb_task(mut usb: MyUsbDevice) -> ! {
 }
 
 #[embassy_executor::task]
-async fn cdc_task(mut class: CdcAcmClass<'static, MyUsbDriver>) -> ! {
+async fn cdc_task(class: CdcAcmClass<'static, MyUsbDriver>) -> ! {
+    let (mut sender, mut receiver, control) = class.split_with_control();
     let mut buf = [0u8; 64];
 
     loop {
-        // Wait until host opens the port
-        class.wait_connection().await;
+        // Wait until the host configures the interface; the port opens with DTR
+        receiver.wait_connection().await;
+        set_connected(receiver.dtr());
 
-        // While connected, service both RX and TX without blocking one on the other.
+        // While configured, service RX, TX and line changes without blocking one on another.
         loop {
-            match select(class.read_packet(&mut buf), TX_TO_USB.receive()).await {
-                Either::First(read_res) => match read_res {
+            let event = select3(
+                receiver.read_packet(&mut buf),
+                TX_TO_USB.receive(),
+                control.control_changed(),
+            )
+            .await;
+            match event {
+                Either3::First(read_res) => match read_res {
+                    Ok(0) => {} // zero-length packet: nothing to forward
                     Ok(n) => {
                         let mut v = Vec::<u8, 64>::new();
                         let _ = v.extend_from_slice(&buf[..n]);
@@ -116,13 +325,96 @@ async fn cdc_task(mut class: CdcAcmClass<'static, MyUsbDriver>) -> ! {
                     }
                     Err(_) => break, // disconnected
                 },
-                Either::Second(out) => {
+                Either3::Second((opened, out)) => {
+                    // Nobody reads a closed port, so writing would stall line changes too;
+                    // packets queued before it last opened were for the previous host
+                    if !CONNECTED.load(Ordering::Acquire)
+                        || opened != OPENED.load(Ordering::Acquire)
+                    {
+                        continue;
+                    }
                     // Best-effort send; if disconnected write_packet will error and we break.
-                    if class.write_packet(&out).await.is_err() {
+                    if sender.write_packet(&out).await.is_err() {
+                        break;
+                    }
+                }
+                Either3::Third(()) => {
+                    if is_bootsel_touch(&receiver) {
+                        reboot_to_bootsel(&mut sender).await;
+                    }
+                    // A terminal closing the port ends the connection like a disconnect
+                    let was_open = CONNECTED.load(Ordering::Acquire);
+                    let open = receiver.dtr();
+                    set_connected(open);
+                    if was_open && !open {
+                        RX_FROM_USB.send(Vec::new()).await;
+                    }
+                }
+            }
+        }
+
+        // Tell the reader, so a half-received frame is not completed by the next host
+        if CONNECTED.load(Ordering::Acquire) {
+            CONNECTED.store(false, Ordering::Release);
+            RX_FROM_USB.send(Vec::new()).await;
+        }
+    }
+}
+
+/// Record whether the port is open. Replies still queued for the previous
+/// host are dropped when it opens, so the new one only sees its own.
+fn set_connected(open: bool) {
+    if open && !CONNECTED.swap(true, Ordering::AcqRel) {
+        OPENED.fetch_add(1, Ordering::AcqRel);
+        CONNECTION.signal(());
+    } else if !open {
+        CONNECTED.store(false, Ordering::Release);
+    }
+}
+
+#[embassy_executor::task]
+async fn log_task(mut class: CdcAcmClass<'static, MyUsbDriver>) -> ! {
+    let mut out = [0u8; 64];
+    let mut discard = [0u8; 64];
+
+    loop {
+        class.wait_connection().await;
+
+        loop {
+            // Typing into the log terminal does nothing; read it so the host is not stalled
+            match select(LOG_TO_USB.read(&mut out), class.read_packet(&mut discard)).await {
+                Either::First(n) => {
+                    if class.write_packet(&out[..n]).await.is_err() {
                         break;
                     }
                 }
+                Either::Second(Ok(_)) => {}
+                Either::Second(Err(_)) => break, // disconnected
             }
         }
     }
 }
+
+/// The host set the touch baud rate and then dropped DTR.
+fn is_bootsel_touch(receiver: &Receiver<'static, MyUsbDriver>) -> bool {
+    receiver.line_coding().data_rate() == TOUCH_BAUD && !receiver.dtr()
+}
+
+/// Send what is still queued, run the shutdown hook and restart in BOOTSEL.
+async fn reboot_to_bootsel(sender: &mut Sender<'static, MyUsbDriver>) -> ! {
+    // The host may have stopped reading, so each packet gets a short deadline
+    while let Ok((_, out)) = TX_TO_USB.try_receive() {
+        match with_timeout(FLUSH_TIMEOUT, sender.write_packet(&out)).await {
+            Ok(Ok(())) => {}
+            _ => break,
+        }
+    }
+    if let Some(hook) = SHUTDOWN_HOOK.try_get() {
+        hook();
+    }
+    // Keep both the mass storage drive and PICOBOOT, which picotool uses
+    rom_data::reset_to_usb_boot(0, 0);
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -0,0 +1,95 @@
+//! What the device does with each frame on the command link.
+//!
+//! `protocol::transport::run_command_server` reads and parses; `Device`
+//! drops other nodes' frames, checks signatures, answers retransmissions
+//! from the replay cache, hands sequenced requests from USB to the pipeline
+//! workers and runs the rest inline, reassembling fragments and opening
+//! sessions on the way. USB and RS-485 each run their own server, with the
+//! replay cache, counters and link commands of their own `link::Link`.
+use embassy_time::Instant;
+use heapless::Vec;
+use protocol::address::Delivery;
+use protocol::auth::unauthorized;
+use protocol::fragment::{Reassembler, is_fragment_cmd};
+use protocol::session::CMD_SECURE;
+use protocol::transport::{FrameTransport, Service, run_command_server};
+use protocol::{Frame, LinkStats, MAX_FRAME, ParseError, Parser, RxTiming};
+
+use crate::link::Link;
+use crate::{auth, commands, link, node, pipeline, session};
+
+/// Serve the command link `link` on `transport` forever. When the link is
+/// `unsolicited`, sequenced requests go to the pipeline workers, which reply
+/// over USB; other links run them inline like any other request.
+pub async fn run<T: FrameTransport>(mut transport: T, timing: RxTiming, link: &'static Link) -> ! {
+    let parser = Parser::with_framing(link::FRAMING).with_timing(timing);
+    let mut device = Device {
+        // Collects requests larger than one frame
+        fragments: Reassembler::new(crate::FRAGMENT_TIMEOUT_US),
+        link,
+    };
+    run_command_server(&mut transport, parser, &mut device, || {
+        Instant::now().as_micros()
+    })
+    .await
+}
+
+struct Device {
+    fragments: Reassembler<{ crate::FRAGMENT_BUF }>,
+    link: &'static Link,
+}
+
+impl Service for Device {
+    async fn serve(&mut self, frame: &Frame<'_>) -> Option<Vec<u8, MAX_FRAME>> {
+        let delivery = node::filter().delivery(frame.addr);
+        if delivery == Delivery::Ignore {
+            return None; // another node's frame
+        }
+        // Checked before the replay cache, so only a request with a valid tag
+        // gets the response to an earlier signed one
+        let signed = auth::authenticate(frame);
+        if delivery == Delivery::Reply && signed.is_ok() {
+            // Answer retransmitted sequenced requests without running them twice
+            if let Some(cached) = self.link.replay(frame) {
+                return Some(cached);
+            }
+            // Sequenced requests run concurrently; their workers reply
+            if self.link.unsolicited() && frame.seq.is_some() && !is_fragment_cmd(frame.cmd) {
+                return pipeline::submit(frame).await;
+            }
+        }
+        // Signed requests lose their signature here; refused ones never run
+        let verified = match signed {
+            Ok(signed) => auth::accept(signed).await,
+            Err(last) => Err(last),
+        };
+        let resp = match verified {
+            Ok(request) if is_fragment_cmd(request.cmd) => {
+                let now = Instant::now().as_micros();
+                self.fragments
+                    .dispatch(&commands::registry(self.link), &request, now)
+                    .await
+            }
+            Ok(request) if request.cmd == CMD_SECURE => {
+                session::dispatch(self.link, &request).await
+            }
+            Ok(request) => commands::registry(self.link).dispatch(&request).await,
+            Err(last) => unauthorized(frame, last),
+        };
+        if delivery == Delivery::Silent {
+            return None; // broadcast or group: executed, never answered
+        }
+        let resp = resp.and_then(|resp| link::FRAMING.reframe(&resp)).ok()?;
+        self.link.store(frame, &resp);
+        Some(resp)
+    }
+
+    /// NAK if the header survived (see `link::NAK_PARSE_ERRORS`).
+    fn parse_error(&mut self, err: ParseError) -> Option<Vec<u8, MAX_FRAME>> {
+        link::nak(err)
+    }
+
+    fn record(&mut self, stats: LinkStats) {
+        self.link.record(stats);
+    }
+}
@@ -0,0 +1,87 @@
+//! Encrypted sessions (see `protocol::session`), keyed with the `auth` key.
+//!
+//! SESSION_OPEN draws DEVICE_NONCE from the TRNG and starts a pending
+//! session, which replaces the current one once a request opens under it.
+//! `CMD_SECURE` frames bypass the registry: `dispatch` opens them, runs the
+//! inner request through `commands::registry` for the link it came in on and
+//! seals the response. Without a key SESSION_OPEN answers NOT_READY and
+//! `Features::SESSION` is not reported.
+use core::cell::RefCell;
+
+use embassy_rp::Peri;
+use embassy_rp::bind_interrupts;
+use embassy_rp::peripherals::TRNG;
+use embassy_rp::trng::{self, Trng};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex as AsyncMutex;
+use embassy_sync::once_lock::OnceLock;
+use heapless::Vec;
+use protocol::dispatch::{CommandHandler, CommandSpec, Data};
+use protocol::schema;
+use protocol::session::{DeviceSessions, Nonce, SESSION_NONCE_LEN, refused};
+use protocol::{BuildError, Frame, MAX_FRAME, MAX_PAYLOAD, Status};
+
+use crate::link::Link;
+use crate::{auth, commands};
+
+bind_interrupts!(struct Irqs {
+    TRNG_IRQ => trng::InterruptHandler<TRNG>;
+});
+
+static RNG: OnceLock<AsyncMutex<CriticalSectionRawMutex, Trng<'static, TRNG>>> = OnceLock::new();
+
+static SESSIONS: Mutex<CriticalSectionRawMutex, RefCell<DeviceSessions>> =
+    Mutex::new(RefCell::new(DeviceSessions::new()));
+
+pub fn init(trng: Peri<'static, TRNG>) {
+    let trng = Trng::new(trng, Irqs, trng::Config::default());
+    let _ = RNG.init(AsyncMutex::new(trng));
+}
+
+/// 0x12 SESSION_OPEN (getter): `[HOST_NONCE]` in, `[DEVICE_NONCE, CONFIRM]` out.
+pub struct SessionOpen;
+
+impl CommandHandler for SessionOpen {
+    const SPEC: CommandSpec = schema::SESSION_OPEN;
+
+    async fn handle(&self, frame: &Frame<'_>, data: &mut Data) -> Result<(), Status> {
+        let psk = auth::key().ok_or(Status::NotReady)?;
+        let host_nonce: &Nonce = frame.payload.try_into().map_err(|_| Status::BadLen)?;
+
+        let mut device_nonce: Nonce = [0; SESSION_NONCE_LEN];
+        let rng = RNG.try_get().ok_or(Status::NotReady)?;
+        rng.lock().await.fill_bytes(&mut device_nonce).await;
+
+        let open = SESSIONS.lock(|sessions| {
+            sessions
+                .borrow_mut()
+                .accept(&psk, host_nonce, &device_nonce)
+        });
+        data.extend_from_slice(&open).map_err(|_| Status::Internal)
+    }
+}
+
+/// Answer a `CMD_SECURE` frame. Frames that do not open get a plain
+/// UNAUTHORIZED (NOT_READY with no session) and never reach a handler.
+///
+/// The request is decrypted on a copy of the keys; only taking its counter
+/// runs in the critical section.
+pub async fn dispatch(
+    link: &'static Link,
+    frame: &Frame<'_>,
+) -> Result<Vec<u8, MAX_FRAME>, BuildError> {
+    let keys = SESSIONS.lock(|sessions| sessions.borrow().keys());
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let opened = match keys.open_request(frame, &mut buf) {
+        Ok(opened) => opened,
+        Err(err) => return refused(frame, err),
+    };
+    if let Err(err) = SESSIONS.lock(|sessions| sessions.borrow_mut().claim(&opened)) {
+        return refused(frame, err);
+    }
+
+    // Sealed under the session it came in, even if a handshake replaced it since
+    let resp = commands::registry(link).dispatch(&opened.request).await?;
+    opened.seal_response(&resp)
+}
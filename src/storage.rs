@@ -0,0 +1,60 @@
+//! Settings kept in the last flash sectors, which the linker scripts leave
+//! out of the image.
+//!
+//! From the end of the 2 MiB the linker scripts assume:
+//! - `NODE_SECTOR`: node address and groups (`node`)
+//! - `KEY_SECTOR`: authentication key, written by the provisioning tool (`auth`)
+//! - `COUNTER_SECTORS`: two copies of the authentication counter bound (`auth`)
+//!
+//! Each record sits at the start of its own sector, so rewriting one never
+//! erases another.
+use core::cell::RefCell;
+
+use embassy_rp::Peri;
+use embassy_rp::flash::{Blocking, ERASE_SIZE, Error, Flash};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+/// Flash size the linker scripts assume (2 MiB).
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// Offset of the sector `n` sectors before the end of flash.
+const fn sector_from_end(n: usize) -> u32 {
+    (FLASH_SIZE - n * ERASE_SIZE) as u32
+}
+
+pub const NODE_SECTOR: u32 = sector_from_end(1);
+pub const KEY_SECTOR: u32 = sector_from_end(2);
+pub const COUNTER_SECTORS: [u32; 2] = [sector_from_end(3), sector_from_end(4)];
+
+type SettingsFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+
+static STORAGE: Mutex<CriticalSectionRawMutex, RefCell<Option<SettingsFlash>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Take the flash. Call before any other function here.
+pub fn init(flash: Peri<'static, FLASH>) {
+    let flash = SettingsFlash::new_blocking(flash);
+    STORAGE.lock(|storage| storage.replace(Some(flash)));
+}
+
+/// Read the start of the sector at `offset`.
+pub fn read(offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+    STORAGE.lock(|storage| {
+        let mut storage = storage.borrow_mut();
+        let flash = storage.as_mut().ok_or(Error::Other)?;
+        flash.blocking_read(offset, buf)
+    })
+}
+
+/// Erase the sector at `offset` and write `record` to its start. Blocks for
+/// the erase (tens of ms).
+pub fn replace(offset: u32, record: &[u8]) -> Result<(), Error> {
+    STORAGE.lock(|storage| {
+        let mut storage = storage.borrow_mut();
+        let flash = storage.as_mut().ok_or(Error::Other)?;
+        flash.blocking_erase(offset, offset + ERASE_SIZE as u32)?;
+        flash.blocking_write(offset, record)
+    })
+}
@@ -1,9 +1,16 @@
-import serial
+"""Reboot the device into BOOTSEL with a 1200-baud touch.
+
+Usage: python baud_touch_1200.py [PORT]   (default COM8)
+"""
+import sys
 import time
 
+import serial
+
 def main():
-    print("Start 1200-baud touch")
-    ser = serial.Serial("COM8", 1200)
+    port = sys.argv[1] if len(sys.argv) > 1 else "COM8"
+    print(f"Start 1200-baud touch on {port}")
+    ser = serial.Serial(port, 1200)
     ser.setDTR(False)
     time.sleep(0.2)
     ser.close()
@@ -11,4 +18,4 @@ def main():
     print("Done")
 
 if __name__ == "__main__":
-    main()
\ No newline at end of file
+    main()
@@ -0,0 +1,116 @@
+"""Command table, payload codecs, status codes, event classes and feature bits.
+
+@generated by `protocol-gen` from crates/protocol/protocol.toml: edit that file and
+run `cargo protocol-gen` instead of changing this one.
+"""
+
+import struct
+from enum import IntEnum
+from typing import NamedTuple, Optional
+
+
+class Status(IntEnum):
+    """Response status byte (first payload byte). Mirrors `protocol::Status`."""
+
+    OK = 0x00  # Command accepted / data follows
+    BAD_LEN = 0x01  # Payload length outside command limits
+    BAD_CMD = 0x02  # Unknown command code
+    BAD_ARG = 0x03  # Payload value out of range
+    BUSY = 0x04  # Device busy, retry later
+    NOT_READY = 0x05  # Subsystem not initialised
+    INTERNAL = 0x06  # Unexpected firmware failure
+    UNAUTHORIZED = 0x07  # Command requires authentication
+    BAD_FRAME = 0x08  # NAK: request failed to parse
+
+
+class Cmd(IntEnum):
+    """CMD codes."""
+
+    PING = 0x01  # Device health check
+    CHASE = 0x02  # Start the LED chase pattern; ACKed at once, `CHASE_FINISHED` event when done
+    CLEAR_LINK_STATS = 0x03  # Reset the link-quality counters of the link it arrives on
+    SET_ADDRESS = 0x04  # Store a new node address and optionally group membership
+    SUBSCRIBE = 0x05  # Enable event classes (`[CLASSES u16 LE]` bitmask, see below)
+    UNSUBSCRIBE = 0x06  # Disable event classes (same payload)
+    FRAGMENT = 0x10  # One fragment of a large request
+    FRAGMENT_ABORT = 0x11  # Drop a partly received fragmented request
+    SESSION_OPEN = 0x12  # Start an encrypted session (16-byte host nonce)
+    SECURE = 0x13  # Encrypted request inside a session
+    GET_DEVICE_ID = 0x20  # Query unique device identifier
+    GET_LINK_STATS = 0x21  # Read link-quality counters of the link it arrives on
+    GET_CAPABILITIES = 0x22  # Protocol version, framings, optional features, buffer sizes and the command table
+    GET_ADDRESS = 0x23  # Read the node address and group membership
+
+
+class CommandInfo(NamedTuple):
+    getter: bool
+    min_payload: int
+    max_payload: int
+
+
+# Request limits the device checks before running a handler.
+COMMANDS = {
+    Cmd.PING: CommandInfo(False, 0, 253),
+    Cmd.CHASE: CommandInfo(False, 0, 2),
+    Cmd.CLEAR_LINK_STATS: CommandInfo(False, 0, 0),
+    Cmd.SET_ADDRESS: CommandInfo(False, 1, 3),
+    Cmd.SUBSCRIBE: CommandInfo(False, 2, 2),
+    Cmd.UNSUBSCRIBE: CommandInfo(False, 2, 2),
+    Cmd.SESSION_OPEN: CommandInfo(True, 16, 16),
+    Cmd.GET_DEVICE_ID: CommandInfo(True, 0, 0),
+    Cmd.GET_LINK_STATS: CommandInfo(True, 0, 0),
+    Cmd.GET_CAPABILITIES: CommandInfo(True, 0, 0),
+    Cmd.GET_ADDRESS: CommandInfo(True, 0, 0),
+}
+
+# Unsolicited events: CMD = 0x80 + class.
+EVENT_CLASSES = {0: "BUTTON", 1: "THRESHOLD", 2: "FAULT", 3: "CHASE_FINISHED"}
+
+# (struct format, field names) of each event payload.
+EVENT_FIELDS = {
+    "BUTTON": ("<B?", ("index", "pressed")),
+    "THRESHOLD": ("<B?h", ("channel", "above", "value")),
+    "FAULT": ("<B", ("code",)),
+    "CHASE_FINISHED": ("<", ()),
+}
+
+# GET_CAPABILITIES feature bits.
+FEATURE_FLAGS = {
+    0x01: "ARQ",
+    0x02: "FRAGMENTATION",
+    0x04: "AUTH",
+    0x08: "EVENTS",
+    0x10: "PIPELINING",
+    0x20: "SESSION",
+}
+
+
+def decode_fields(fmt: str, names: tuple, data: bytes) -> dict:
+    if len(data) != struct.calcsize(fmt):
+        raise ValueError(f"expected {struct.calcsize(fmt)} bytes, got {len(data)}")
+    return dict(zip(names, struct.unpack(fmt, data)))
+
+
+def encode_set_address(node: int, groups: Optional[int] = None) -> bytes:
+    """0x04 SET_ADDRESS request."""
+    if not 1 <= node <= 0xEF:
+        raise ValueError("node must be 1..=0xEF")
+    out = struct.pack("<B", node)
+    if groups is not None:
+        out += struct.pack("<H", groups)
+    return out
+
+
+def decode_get_device_id(data: bytes) -> dict:
+    """0x20 GET_DEVICE_ID data."""
+    return decode_fields("<8s", ("id",), data)
+
+
+def decode_get_link_stats(data: bytes) -> dict:
+    """0x21 GET_LINK_STATS data."""
+    return decode_fields("<IIIII", ("frames_ok", "crc_errors", "resyncs", "overflows", "bytes_discarded"), data)
+
+
+def decode_get_address(data: bytes) -> dict:
+    """0x23 GET_ADDRESS data."""
+    return decode_fields("<BH", ("node", "groups"), data)
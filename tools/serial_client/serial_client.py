@@ -1,6 +1,140 @@
+import hashlib
+import hmac
 import serial
 import time
-from typing import Union, Optional
+from serial.tools import list_ports
+from typing import NamedTuple, Union, Optional
+
+# Generated from crates/protocol/protocol.toml (`cargo protocol-gen`)
+from protocol_schema import (
+    COMMANDS,
+    EVENT_CLASSES,
+    EVENT_FIELDS,
+    FEATURE_FLAGS,
+    Cmd,
+    Status,
+    decode_fields,
+    decode_get_link_stats,
+)
+
+
+# Default USB identity. Mirrors `USB_VID`/`USB_PID` in .cargo/config.toml.
+USB_VID = 0xC0DE
+USB_PID = 0xCAFE
+# USB interface of each CDC port: the command link, then logs and telemetry
+COMMAND_INTERFACE = 0
+LOG_INTERFACE = 2
+
+STX = 0xA5
+STX_SEQ = 0xA6  # sequenced frame: [STX_SEQ, LEN, SEQ, ADDR, CMD, ...]
+
+# ADDR values. Mirrors `protocol::address`: node addresses are 0x01..=0xEF.
+BROADCAST = 0x00  # every device executes, none replies
+GROUP_BASE = 0xF0  # groups 0..15 are 0xF0..0xFF; members execute, none replies
+
+
+def group_address(group: int) -> int:
+    """ADDR for group 0..15."""
+    if not 0 <= group < 16:
+        raise ValueError("Group must be 0..15")
+    return GROUP_BASE + group
+
+
+# Reason byte following BAD_FRAME. Mirrors `protocol::ParseError::code`.
+NAK_REASONS = {
+    0x01: "LEN_TOO_SMALL",
+    0x02: "LEN_TOO_BIG",
+    0x03: "CRC_MISMATCH",
+    0x04: "BAD_ENCODING",
+}
+
+# Unsolicited events: CMD = EVENT_FIRST + class. Mirrors `protocol::event`.
+EVENT_FIRST = 0x80
+EVENT_LAST = 0xBF
+CMD_SUBSCRIBE = Cmd.SUBSCRIBE
+CMD_UNSUBSCRIBE = Cmd.UNSUBSCRIBE
+
+
+def is_event_cmd(cmd: int) -> bool:
+    return EVENT_FIRST <= cmd <= EVENT_LAST
+
+
+def event_mask(*classes: str) -> bytes:
+    """SUBSCRIBE / UNSUBSCRIBE payload for class names, e.g. event_mask("BUTTON", "FAULT")."""
+    bits = {name: bit for bit, name in EVENT_CLASSES.items()}
+    mask = 0
+    for name in classes:
+        mask |= 1 << bits[name]
+    return mask.to_bytes(2, "little")
+
+
+def status_name(code: int) -> str:
+    """Name for a status byte, or UNKNOWN(0x..) for codes this client does not know."""
+    try:
+        return Status(code).name
+    except ValueError:
+        return f"UNKNOWN(0x{code:02X})"
+
+
+def decode_link_stats(data: bytes) -> dict:
+    """Decode GET_LINK_STATS (0x21) data: five u32 little-endian counters."""
+    return decode_get_link_stats(data)
+
+
+FRAMING_FLAGS = {0x01: "STX_LEN", 0x02: "COBS"}
+
+
+def decode_event(resp: "Response") -> dict:
+    """Decode an event frame (see `is_event_cmd`) into a dict with its class and fields."""
+    name = EVENT_CLASSES.get(resp.cmd - EVENT_FIRST)
+    if name is None:
+        raise ValueError(f"Unknown event CMD=0x{resp.cmd:02X}")
+    fmt, names = EVENT_FIELDS[name]
+    return {"class": name, **decode_fields(fmt, names, resp.data)}
+
+
+def decode_capabilities(data: bytes) -> dict:
+    """Decode GET_CAPABILITIES (0x22) data. Mirrors `protocol::capabilities::Capabilities`."""
+    if len(data) < 13 or len(data) < 13 + 2 * data[12]:
+        raise ValueError("Truncated GET_CAPABILITIES data")
+    count = data[12]
+    return {
+        "version": (data[0], data[1]),
+        "framings": [name for bit, name in FRAMING_FLAGS.items() if data[2] & bit],
+        "features": [name for bit, name in FEATURE_FLAGS.items() if data[3] & bit],
+        "max_payload": int.from_bytes(data[4:6], "little"),
+        "stream_buf_cap": int.from_bytes(data[6:8], "little"),
+        "max_message": int.from_bytes(data[8:12], "little"),
+        "commands": {
+            data[13 + 2 * i]: "getter" if data[14 + 2 * i] else "setter"
+            for i in range(count)
+        },
+    }
+
+
+class Response(NamedTuple):
+    addr: int
+    cmd: int
+    status: Optional[int]
+    data: bytes
+    seq: Optional[int] = None
+
+    @property
+    def ok(self) -> bool:
+        return self.status == Status.OK
+
+    def __str__(self) -> str:
+        if is_event_cmd(self.cmd):
+            return f"EVENT ADDR=0x{self.addr:02X} CMD=0x{self.cmd:02X} DATA={self.data.hex(' ').upper()}"
+        status = status_name(self.status) if self.status is not None else "<none>"
+        text = f"ADDR=0x{self.addr:02X} CMD=0x{self.cmd:02X} STATUS={status}"
+        if self.seq is not None:
+            text = f"SEQ={self.seq} " + text
+        if self.status == Status.BAD_FRAME and self.data:
+            return text + f" REASON={NAK_REASONS.get(self.data[0], hex(self.data[0]))}"
+        if self.data:
+            text += f" DATA={self.data.hex(' ').upper()}"
+        return text
 
 
 def crc16_modbus(data: bytes) -> int:
@@ -16,6 +150,34 @@ def crc16_modbus(data: bytes) -> int:
     return crc & 0xFFFF
 
 
+# Authenticated requests. Mirrors `protocol::auth`.
+TAG_LEN = 8  # truncated HMAC-SHA256
+KEY_LEN = 32
+OPEN_CMDS = (0x01, 0x22)  # PING and GET_CAPABILITIES also run unsigned
+
+
+def sign_payload(key: bytes, counter: int, addr: int, cmd: int, payload: bytes) -> bytes:
+    """`payload` + COUNTER (u32 LE) + the first TAG_LEN bytes of HMAC-SHA256(key, ADDR, CMD, payload, COUNTER)."""
+    trailer = counter.to_bytes(4, "little")
+    tag = hmac.new(key, bytes([addr, cmd]) + payload + trailer, hashlib.sha256).digest()[:TAG_LEN]
+    return payload + trailer + tag
+
+
+def last_counter(resp: "Response") -> Optional[int]:
+    """The device's LAST_COUNTER from an UNAUTHORIZED response, else None."""
+    if resp.status == Status.UNAUTHORIZED and len(resp.data) == 4:
+        return int.from_bytes(resp.data, "little")
+    return None
+
+
+def key_record(key: bytes) -> bytes:
+    """Flash key record `[b"KEY1", KEY, CRC16 LE]` for `storage::KEY_SECTOR` (0x101FE000 on 2 MiB flash)."""
+    if len(key) != KEY_LEN:
+        raise ValueError("Key must be 32 bytes")
+    body = b"KEY1" + key
+    return body + crc16_modbus(body).to_bytes(2, "little")
+
+
 def parse_u8(x: Union[int, bytes, str]) -> int:
     """
     Accepts:
@@ -97,35 +259,222 @@ def data_to_2_bytes(data: Union[bytes, bytearray, int, str, None]) -> bytes:
     raise TypeError("Unsupported DATA type")
 
 
+def parse_response(frame: bytes) -> Response:
+    """
+    Decode one response frame [STX, LEN, ADDR, CMD, STATUS, (BYTECOUNT, DATA...), CRCL, CRCH]
+    or its sequenced form [STX_SEQ, LEN, SEQ, ADDR, ...].
+    Raises ValueError if the frame is malformed or the CRC does not match.
+    """
+    if len(frame) < 6 or frame[0] not in (STX, STX_SEQ):
+        raise ValueError("Not a frame (missing STX or too short)")
+    header = 3 if frame[0] == STX_SEQ else 2
+    length = frame[1]
+    total = header + length + 2
+    if length < 2 or len(frame) < total:
+        raise ValueError("Truncated frame")
+    crc = crc16_modbus(frame[: total - 2])
+    if frame[total - 2] != (crc & 0xFF) or frame[total - 1] != (crc >> 8):
+        raise ValueError("CRC mismatch")
+
+    seq = frame[2] if header == 3 else None
+    addr, cmd = frame[header], frame[header + 1]
+    payload = frame[header + 2 : total - 2]
+    if is_event_cmd(cmd):
+        # Events carry no status byte; the payload is the event data
+        return Response(addr, cmd, None, bytes(payload), seq)
+    status = payload[0] if payload else None
+    data = b""
+    if status == Status.OK and len(payload) >= 2:
+        # Getter: [STATUS, BYTECOUNT, DATA...]
+        data = bytes(payload[2 : 2 + payload[1]])
+    elif status == Status.BAD_FRAME:
+        # NAK: [BAD_FRAME, REASON]
+        data = bytes(payload[1:2])
+    elif status == Status.UNAUTHORIZED:
+        # [UNAUTHORIZED, LAST_COUNTER (u32 LE)], see `last_counter()`
+        data = bytes(payload[1:5])
+    return Response(addr, cmd, status, data, seq)
+
+
+def cobs_encode(data: bytes) -> bytes:
+    """COBS-encode data (no trailing 0x00 delimiter). Mirrors `protocol::cobs::encode`."""
+    out = bytearray([0])
+    code_at, code = 0, 1
+    for i, b in enumerate(data):
+        if b:
+            out.append(b)
+            code += 1
+        if b == 0 or (code == 0xFF and i + 1 < len(data)):
+            out[code_at] = code
+            code_at, code = len(out), 1
+            out.append(0)
+    out[code_at] = code
+    return bytes(out)
+
+
+def cobs_decode(packet: bytes) -> bytes:
+    """Decode one COBS packet (without its 0x00 delimiter)."""
+    out = bytearray()
+    i = 0
+    while i < len(packet):
+        code = packet[i]
+        if code == 0 or i + code > len(packet):
+            raise ValueError("Invalid COBS packet")
+        out += packet[i + 1 : i + code]
+        i += code
+        if code != 0xFF and i < len(packet):
+            out.append(0)
+    return bytes(out)
+
+
+def parse_cobs_response(packet: bytes) -> Response:
+    """Decode one COBS response packet: COBS([ADDR, CMD, PAYLOAD..., CRCL, CRCH]) 0x00."""
+    body = cobs_decode(packet.rstrip(b"\x00"))
+    if len(body) < 4:
+        raise ValueError("Not a frame (too short)")
+    # Same body and CRC as an STX frame, so rebuild one and reuse its parser
+    data, crc = body[:-2], body[-2:]
+    if crc16_modbus(data).to_bytes(2, "little") != crc:
+        raise ValueError("CRC mismatch")
+    head = bytes([STX, len(data)]) + data
+    return parse_response(head + crc16_modbus(head).to_bytes(2, "little"))
+
+
+def usb_interface(info) -> Optional[int]:
+    """USB interface of a port, from locations such as '1-1.2:1.0' (Linux) or '1-2:x.2' (Windows)."""
+    try:
+        return int((info.location or "").rsplit(".", 1)[1])
+    except (IndexError, ValueError):
+        return None
+
+
+def find_ports(serial_number: Optional[str] = None, vid: int = USB_VID, pid: int = USB_PID, interface: Optional[int] = COMMAND_INTERFACE) -> list:
+    """(port, serial number) of every attached device, or only the one whose serial number (chip ID) matches.
+
+    Each device has two ports; `interface` picks the command port (default), the log port
+    (`LOG_INTERFACE`) or, with None, both.
+    """
+    found = []
+    for info in list_ports.comports():
+        if (info.vid, info.pid) != (vid, pid):
+            continue
+        if serial_number is not None and (info.serial_number or "").upper() != serial_number.upper():
+            continue
+        if interface is not None and usb_interface(info) not in (interface, None):
+            continue
+        found.append((info.device, info.serial_number))
+    return found
+
+
+def find_port(serial_number: str, vid: int = USB_VID, pid: int = USB_PID, interface: int = COMMAND_INTERFACE) -> str:
+    """Port of the device with this serial number."""
+    found = find_ports(serial_number, vid, pid, interface)
+    if not found:
+        raise LookupError(f"No device with serial number {serial_number}")
+    return found[0][0]
+
+
+def read_log(port: str, telemetry=None) -> None:
+    """Print the log port's lines; `@name v1 v2 ...` telemetry samples go to `telemetry(name, values)` if given."""
+    with serial.Serial(port=port, timeout=None) as ser:
+        for raw in ser:
+            line = raw.decode("utf-8", "replace").rstrip("\r\n")
+            if telemetry is not None and line.startswith("@"):
+                name, *values = line[1:].split()
+                telemetry(name, [int(v) for v in values])
+            else:
+                print(line)
+
+
 class CommandSender:
-    def __init__(self, port: str, baudrate: int = 115200, timeout: float = 1.0, stx: int = 0xA5):
+    def __init__(self, port: str, baudrate: int = 115200, timeout: float = 1.0, stx: int = STX, cobs: bool = False, key: Optional[bytes] = None):
         self.ser = serial.Serial(port=port, baudrate=baudrate, timeout=timeout)
         self.stx = stx & 0xFF
+        self.cobs = cobs  # must match `link::FRAMING` in the firmware
+        self.key = key  # sign every request when set (device has a key provisioned)
+        self.counter = 0  # last counter used; raise it with `resync()`
 
         # Pico USB CDC often benefits from a short settle time after opening
         time.sleep(2)
 
-    def build_frame(self, addr: Union[int, bytes, str], cmd: Union[int, bytes, str], data: Union[bytes, bytearray, int, str, None]) -> bytes:
+    @classmethod
+    def for_serial(cls, serial_number: str, baudrate: int = 115200, **kwargs) -> "CommandSender":
+        """Open the device with this serial number, wherever it enumerated."""
+        return cls(find_port(serial_number), baudrate, **kwargs)
+
+    def build_frame(self, addr: Union[int, bytes, str], cmd: Union[int, bytes, str], data: Union[bytes, bytearray, int, str, None], seq: Optional[int] = None, raw: bool = False) -> bytes:
         addr_u8 = parse_u8(addr)
         cmd_u8 = parse_u8(cmd)
 
-        payload = data_to_2_bytes(data)
+        payload = bytes(data or b"") if raw else data_to_2_bytes(data)
+        if self.key is not None:
+            self.counter += 1
+            payload = sign_payload(self.key, self.counter, addr_u8, cmd_u8, payload)
 
         # LEN counts bytes from ADDR through end of PAYLOAD
         length = 2 + len(payload)  # ADDR + CMD + payload
         if not (2 <= length <= 255):
             raise ValueError("Frame LEN out of range (2..255). Payload too large?")
 
-        head = bytes([self.stx, length, addr_u8, cmd_u8]) + payload
+        if self.cobs:
+            # COBS([ADDR, CMD, PAYLOAD, CRCL, CRCH]) 0x00 (no SEQ in COBS mode)
+            body = bytes([addr_u8, cmd_u8]) + payload
+            return cobs_encode(body + crc16_modbus(body).to_bytes(2, "little")) + b"\x00"
+
+        if seq is None:
+            head = bytes([self.stx, length, addr_u8, cmd_u8]) + payload
+        else:
+            # Sequenced: the device replays its cached response if SEQ repeats
+            head = bytes([STX_SEQ, length, seq & 0xFF, addr_u8, cmd_u8]) + payload
         crc = crc16_modbus(head)
         crcl, crch = (crc & 0xFF), ((crc >> 8) & 0xFF)  # Modbus CRC order: low byte then high byte
         return head + bytes([crcl, crch])
 
-    def send(self, addr: Union[int, bytes, str], cmd: Union[int, bytes, str], data: Union[bytes, bytearray, int, str, None] = b"") -> bytes:
-        frame = self.build_frame(addr, cmd, data)
+    def send(self, addr: Union[int, bytes, str], cmd: Union[int, bytes, str], data: Union[bytes, bytearray, int, str, None] = b"", seq: Optional[int] = None) -> bytes:
+        frame = self.build_frame(addr, cmd, data, seq)
         self.ser.write(frame)
         return frame  # return what we sent (useful for logging)
 
+    def send_command(self, addr: Union[int, bytes, str], cmd: Cmd, payload: bytes = b"", seq: Optional[int] = None) -> bytes:
+        """Send an encoded payload as-is, e.g. send_command(0x01, Cmd.SET_ADDRESS, encode_set_address(0x2A))."""
+        info = COMMANDS.get(cmd)
+        if info is not None and not info.min_payload <= len(payload) <= info.max_payload:
+            raise ValueError(f"{Cmd(cmd).name} takes {info.min_payload}..={info.max_payload} payload bytes")
+        frame = self.build_frame(addr, cmd, payload, seq, raw=True)
+        self.ser.write(frame)
+        return frame
+
+    def resync(self, resp: "Response") -> bool:
+        """After UNAUTHORIZED, continue above the device's counter. True if the request is worth resending."""
+        last = last_counter(resp)
+        if last is None or last < self.counter:
+            return False
+        self.counter = last
+        return True
+
+    def wait_event(self, name: str, timeout: float = 5.0) -> Optional[dict]:
+        """Read STX/LEN frames until event `name` arrives (subscribe to it first).
+        Responses and other events read on the way are dropped. None on timeout."""
+        deadline = time.monotonic() + timeout
+        buf = b""
+        while time.monotonic() < deadline:
+            buf += self.ser.read(self.ser.in_waiting or 1)
+            while len(buf) >= 2:
+                if buf[0] not in (STX, STX_SEQ):
+                    buf = buf[1:]
+                    continue
+                total = (3 if buf[0] == STX_SEQ else 2) + buf[1] + 2
+                if len(buf) < total:
+                    break
+                frame, buf = buf[:total], buf[total:]
+                try:
+                    resp = parse_response(frame)
+                except ValueError:
+                    continue
+                if is_event_cmd(resp.cmd) and EVENT_CLASSES.get(resp.cmd - EVENT_FIRST) == name:
+                    return decode_event(resp)
+        return None
+
     def read_any(self, max_bytes: int = 256) -> bytes:
         """Read up to max_bytes (whatever is available until timeout)."""
         return self.ser.read(max_bytes)
@@ -137,16 +486,21 @@ class CommandSender:
 if __name__ == "__main__":
     comm = CommandSender("COM8", 115200)
 
-    # Example 1: CHASE with empty payload (no DATA)
+    # Example 1: CHASE with empty payload (no DATA). The ACK comes at once;
+    # CHASE_FINISHED marks the end of the run.
     ADDR = 0x01
     CMD = 0x02
     DATA = 0x00
+    comm.send(ADDR, CMD_SUBSCRIBE, event_mask("CHASE_FINISHED"))
+    comm.read_any()
     for _ in range(2):
         tx = comm.send(ADDR, CMD, DATA)
         print("TX:", tx.hex(" ").upper())
-        rx = comm.read_any()
+        rx = comm.read_any(7)  # the ACK frame
         print("RX:", rx.hex(" ").upper() if rx else "<no response>")
-        time.sleep(1)
+        if rx:
+            print("   ", parse_response(rx))
+        print("   ", comm.wait_event("CHASE_FINISHED") or "<no CHASE_FINISHED>")
 
     # Example 2: send a DATA value (1..255) as 2 bytes
     # tx = comm.send(b"0x01", b"0x10", 25)  # payload becomes 00 19